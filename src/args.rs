@@ -1,14 +1,50 @@
-use clap::{App, AppSettings, Arg, ArgMatches};
-use rzm::{Error, Result};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use rzm::{Error, ErrorMode, InfoSections, Result, TraceFormat, ZConfig, colour_from_name,
+          colour_names, error_mode_from_name, error_mode_names, trace_format_from_name,
+          trace_format_names};
 use std::borrow::Cow;
 use std::env;
 use std::ffi::OsString;
-use std::path::Path;
+use std::num::NonZeroU64;
+use std::path::{Path, PathBuf};
 
 const ZFILE: &'static str = "ZFILE";
 const STACK_SIZE: &'static str = "stacksize";
 const DEFAULT_STACK_SIZE: &'static str = "61440";
 const START_PC: &'static str = "startpc";
+const TRANSCRIPT: &str = "transcript";
+const TRANSCRIPT_STYLES: &str = "transcript-styles";
+const FOREGROUND: &str = "foreground";
+const BACKGROUND: &str = "background";
+const ERRORS: &str = "errors";
+const DEBUG: &str = "debug";
+const SNAPSHOT_INTERVAL: &str = "snapshot-interval";
+const WATCH: &str = "watch";
+const WATCH_LOG: &str = "watch-log";
+const DEBUG_INFO: &str = "debug-info";
+const TRACE: &str = "trace";
+const TRACE_FORMAT: &str = "trace-format";
+const TRACE_RANGE: &str = "trace-range";
+const TRACE_ROUTINE: &str = "trace-routine";
+const PROFILE: &str = "profile";
+const PROFILE_FOLDED: &str = "profile-folded";
+const COVERAGE: &str = "coverage";
+const COVERAGE_DATA: &str = "DATA";
+const DISASM: &str = "disasm";
+const INFO: &str = "info";
+const HEADER: &str = "header";
+const OBJECTS: &str = "objects";
+const DICTIONARY: &str = "dictionary";
+const ABBREVIATIONS: &str = "abbreviations";
+const JSON: &str = "json";
+
+/// What to do with the story file.
+pub enum Command {
+  Play,
+  Disasm,
+  Info,
+  Coverage,
+}
 
 pub struct Args<'a> {
   matches: ArgMatches<'a>,
@@ -20,8 +56,67 @@ impl<'a> Args<'a> {
     Ok(Args { matches: matches })
   }
 
+  pub fn command(&self) -> Command {
+    match self.matches.subcommand_name() {
+      Some(DISASM) => Command::Disasm,
+      Some(INFO) => Command::Info,
+      Some(COVERAGE) => Command::Coverage,
+      _ => Command::Play,
+    }
+  }
+
   pub fn zfile(&self) -> Cow<Path> {
-    Cow::Borrowed(Path::new(self.matches.value_of(ZFILE).unwrap()))
+    // The subcommands take the story file too.
+    let matches = self.matches.subcommand().1.unwrap_or(&self.matches);
+    Cow::Borrowed(Path::new(matches.value_of(ZFILE).unwrap()))
+  }
+
+  /// The sections that `info` should show. All of them, unless some are asked
+  /// for.
+  pub fn info_sections(&self) -> InfoSections {
+    let matches = match self.matches.subcommand_matches(INFO) {
+      Some(matches) => matches,
+      None => return InfoSections::all(),
+    };
+    let sections = InfoSections {
+      header: matches.is_present(HEADER),
+      objects: matches.is_present(OBJECTS),
+      dictionary: matches.is_present(DICTIONARY),
+      abbreviations: matches.is_present(ABBREVIATIONS),
+    };
+    if sections.header || sections.objects || sections.dictionary || sections.abbreviations {
+      sections
+    } else {
+      InfoSections::all()
+    }
+  }
+
+  pub fn json(&self) -> bool {
+    self.matches.subcommand_matches(INFO).map(|matches| matches.is_present(JSON)).unwrap_or(false)
+  }
+
+  /// The watchpoints given on the command line, as written.
+  pub fn watches(&self) -> Vec<&str> {
+    self.matches.values_of(WATCH).map(|values| values.collect()).unwrap_or_default()
+  }
+
+  /// The address ranges to trace, as written.
+  pub fn trace_ranges(&self) -> Vec<&str> {
+    self.matches.values_of(TRACE_RANGE).map(|values| values.collect()).unwrap_or_default()
+  }
+
+  /// The routines to trace, as written.
+  pub fn trace_routines(&self) -> Vec<&str> {
+    self.matches.values_of(TRACE_ROUTINE).map(|values| values.collect()).unwrap_or_default()
+  }
+
+  /// The coverage files that `coverage` should report on.
+  pub fn coverage_files(&self) -> Vec<PathBuf> {
+    self.matches
+      .subcommand_matches(COVERAGE)
+      .and_then(|matches| matches.values_of(COVERAGE_DATA))
+      .map(|values| values.map(PathBuf::from).collect())
+      .unwrap_or_default()
   }
 
   pub fn start_pc(&self) -> Result<Option<usize>> {
@@ -44,17 +139,111 @@ impl<'a> Args<'a> {
   // }
 }
 
+impl<'a> ZConfig for Args<'a> {
+  fn stack_size(&self) -> Option<usize> {
+    self.matches.value_of(STACK_SIZE).and_then(|s| s.parse::<usize>().ok())
+  }
+
+  fn transcript_path(&self) -> Option<PathBuf> {
+    self.matches.value_of(TRANSCRIPT).map(PathBuf::from)
+  }
+
+  fn transcript_markup(&self) -> bool {
+    self.matches.is_present(TRANSCRIPT_STYLES)
+  }
+
+  fn default_foreground(&self) -> Option<u16> {
+    self.matches.value_of(FOREGROUND).and_then(colour_from_name)
+  }
+
+  fn default_background(&self) -> Option<u16> {
+    self.matches.value_of(BACKGROUND).and_then(colour_from_name)
+  }
+
+  fn error_mode(&self) -> ErrorMode {
+    self.matches.value_of(ERRORS).and_then(error_mode_from_name).unwrap_or(ErrorMode::WarnOnce)
+  }
+
+  fn debug(&self) -> bool {
+    self.matches.is_present(DEBUG)
+  }
+
+  fn snapshot_interval(&self) -> Result<Option<u64>> {
+    match self.matches.value_of(SNAPSHOT_INTERVAL) {
+      // 0 would never take a snapshot.
+      Some(s) => {
+        s.parse::<NonZeroU64>()
+          .map(|interval| Some(interval.get()))
+          .map_err(|e| Error::ParseIntError(SNAPSHOT_INTERVAL, e))
+      }
+      None => Ok(None),
+    }
+  }
+
+  fn watch_log_path(&self) -> Option<PathBuf> {
+    self.matches.value_of(WATCH_LOG).map(PathBuf::from)
+  }
+
+  fn debug_info_path(&self) -> Option<PathBuf> {
+    // disasm and coverage take it too.
+    let matches = self.matches.subcommand().1.unwrap_or(&self.matches);
+    matches.value_of(DEBUG_INFO).map(PathBuf::from)
+  }
+
+  fn trace_path(&self) -> Option<PathBuf> {
+    self.matches.value_of(TRACE).map(PathBuf::from)
+  }
+
+  fn trace_format(&self) -> TraceFormat {
+    self.matches
+      .value_of(TRACE_FORMAT)
+      .and_then(trace_format_from_name)
+      .unwrap_or(TraceFormat::Text)
+  }
+
+  fn profile(&self) -> bool {
+    self.matches.is_present(PROFILE)
+  }
+
+  fn profile_folded_path(&self) -> Option<PathBuf> {
+    self.matches.value_of(PROFILE_FOLDED).map(PathBuf::from)
+  }
+
+  fn coverage_path(&self) -> Option<PathBuf> {
+    self.matches.value_of(COVERAGE).map(PathBuf::from)
+  }
+}
+
+fn debug_info_arg<'a, 'b>() -> Arg<'a, 'b> {
+  Arg::with_name(DEBUG_INFO)
+    .long(DEBUG_INFO)
+    .takes_value(true)
+    .multiple(false)
+    .number_of_values(1)
+    .value_name("FILE")
+    .help("Inform debugging information (gameinfo.dbg) for names of routines, variables and \
+           objects")
+}
+
 fn parse_from<'a, I, T>(itr: I) -> Result<ArgMatches<'a>>
   where I: IntoIterator<Item = T>,
-        T: Into<OsString> {
+        T: Into<OsString> + Clone {
+  let colours = colour_names();
+  let error_modes = error_mode_names();
+  let trace_formats = trace_format_names();
   App::new("rzm")
     // App configuration
     .about("Rust implementation of the ZMachine.")
     .author(crate_authors!())
     .version(crate_version!())
+    .after_help("EXIT STATUS:\n    0  The game quit\n    1  Error while running the game\n    2  Bad \
+                 command line\n    3  Could not read the story file\n    4  Unsupported story \
+                 file version\n    5  Could not read the debugging information\n    6  Could not \
+                 read the coverage file")
     .setting(AppSettings::StrictUtf8)
     .setting(AppSettings::UnifiedHelpMessage)
     .setting(AppSettings::VersionlessSubcommands)
+    .setting(AppSettings::SubcommandsNegateReqs)
 
     // Arguments.
     .arg(Arg::with_name(ZFILE)
@@ -75,6 +264,159 @@ fn parse_from<'a, I, T>(itr: I) -> Result<ArgMatches<'a>>
       .multiple(false)
       .number_of_values(1)
       .help("Byte address to execute first. Mostly for debugging."))
+    .arg(Arg::with_name(TRANSCRIPT)
+      .long(TRANSCRIPT)
+      .takes_value(true)
+      .multiple(false)
+      .number_of_values(1)
+      .value_name("FILE")
+      .help("File to append transcripts to (default: transcript.txt)"))
+    .arg(Arg::with_name(TRANSCRIPT_STYLES)
+      .long(TRANSCRIPT_STYLES)
+      .help("Mark text styles in the transcript with <b>, <i>, <rev> and <tt> tags"))
+    .arg(Arg::with_name(FOREGROUND)
+      .long(FOREGROUND)
+      .visible_alias("fg")
+      .takes_value(true)
+      .multiple(false)
+      .number_of_values(1)
+      .possible_values(&colours)
+      .help("Default text colour (default: the terminal's)"))
+    .arg(Arg::with_name(BACKGROUND)
+      .long(BACKGROUND)
+      .visible_alias("bg")
+      .takes_value(true)
+      .multiple(false)
+      .number_of_values(1)
+      .possible_values(&colours)
+      .help("Default background colour (default: the terminal's)"))
+    .arg(Arg::with_name(ERRORS)
+      .long(ERRORS)
+      .takes_value(true)
+      .multiple(false)
+      .number_of_values(1)
+      .possible_values(&error_modes)
+      .help("What to do about recoverable errors in the story file (default: once)"))
+    .arg(Arg::with_name(DEBUG)
+      .long(DEBUG)
+      .help("Run the game in the debugger, stopped before the first instruction"))
+    .arg(Arg::with_name(SNAPSHOT_INTERVAL)
+      .long(SNAPSHOT_INTERVAL)
+      .takes_value(true)
+      .multiple(false)
+      .number_of_values(1)
+      .value_name("N")
+      .requires(DEBUG)
+      .help("Instructions between the debugger's snapshots, for going back in time (default: \
+             10000)"))
+    .arg(Arg::with_name(WATCH)
+      .long(WATCH)
+      .takes_value(true)
+      .multiple(true)
+      .number_of_values(1)
+      .value_name("WATCH")
+      .help("Report writes to a global (G10), bytes of memory (4f05 or 4f05+4), an attribute \
+             (attr:12:3) or an object's parent (parent:12)"))
+    .arg(Arg::with_name(WATCH_LOG)
+      .long(WATCH_LOG)
+      .takes_value(true)
+      .multiple(false)
+      .number_of_values(1)
+      .value_name("FILE")
+      .help("File to append watchpoint reports to (default: stderr)"))
+    .arg(debug_info_arg())
+    .arg(Arg::with_name(TRACE)
+      .long(TRACE)
+      .takes_value(true)
+      .multiple(false)
+      .number_of_values(1)
+      .value_name("FILE")
+      .help("Write a trace of each instruction run, with its operands and results, to FILE"))
+    .arg(Arg::with_name(TRACE_FORMAT)
+      .long(TRACE_FORMAT)
+      .takes_value(true)
+      .multiple(false)
+      .number_of_values(1)
+      .possible_values(&trace_formats)
+      .requires(TRACE)
+      .help("How to write the trace (default: text)"))
+    .arg(Arg::with_name(TRACE_RANGE)
+      .long(TRACE_RANGE)
+      .takes_value(true)
+      .multiple(true)
+      .number_of_values(1)
+      .value_name("START-END")
+      .requires(TRACE)
+      .help("Only trace instructions from START up to END (in hex)"))
+    .arg(Arg::with_name(TRACE_ROUTINE)
+      .long(TRACE_ROUTINE)
+      .takes_value(true)
+      .multiple(true)
+      .number_of_values(1)
+      .value_name("ROUTINE")
+      .requires(TRACE)
+      .help("Only trace the instructions of a routine (its address in hex, or its name)"))
+    .arg(Arg::with_name(PROFILE)
+      .long(PROFILE)
+      .help("Count the instructions run, by opcode and by routine, and report them on stderr \
+             when the game is over"))
+    .arg(Arg::with_name(PROFILE_FOLDED)
+      .long(PROFILE_FOLDED)
+      .takes_value(true)
+      .multiple(false)
+      .number_of_values(1)
+      .value_name("FILE")
+      .help("Write the profile's call stacks to FILE as folded stacks, for flame graphs"))
+    .arg(Arg::with_name(COVERAGE)
+      .long(COVERAGE)
+      .takes_value(true)
+      .multiple(false)
+      .number_of_values(1)
+      .value_name("FILE")
+      .help("Record which routines, instructions and branches run in FILE, adding to what it \
+             already holds"))
+
+    // Subcommands.
+    .subcommand(SubCommand::with_name(DISASM)
+      .about("Print a listing of the story file's code")
+      .arg(Arg::with_name(ZFILE)
+        .required(true)
+        .multiple(false)
+        .index(1))
+      .arg(debug_info_arg()))
+    .subcommand(SubCommand::with_name(INFO)
+      .about("Show what's in the story file (everything, unless sections are chosen)")
+      .arg(Arg::with_name(ZFILE)
+        .required(true)
+        .multiple(false)
+        .index(1))
+      .arg(Arg::with_name(HEADER)
+        .long(HEADER)
+        .help("Show the header"))
+      .arg(Arg::with_name(OBJECTS)
+        .long(OBJECTS)
+        .help("Show the objects and the object tree"))
+      .arg(Arg::with_name(DICTIONARY)
+        .long(DICTIONARY)
+        .help("Show the dictionary"))
+      .arg(Arg::with_name(ABBREVIATIONS)
+        .long(ABBREVIATIONS)
+        .help("Show the abbreviations"))
+      .arg(Arg::with_name(JSON)
+        .long(JSON)
+        .help("Write JSON instead of text")))
+    .subcommand(SubCommand::with_name(COVERAGE)
+      .about("Report how much of the story file's code the coverage files say has run")
+      .arg(Arg::with_name(ZFILE)
+        .required(true)
+        .multiple(false)
+        .index(1))
+      .arg(Arg::with_name(COVERAGE_DATA)
+        .required(true)
+        .multiple(true)
+        .index(2)
+        .help("Coverage files written by --coverage, merged for the report"))
+      .arg(debug_info_arg()))
 
     // Process it.
     .get_matches_from_safe(itr)
@@ -4,9 +4,12 @@ extern crate clap;
 extern crate log;
 extern crate ncurses;
 extern crate rand;
+extern crate unicode_width;
 
 mod result;
 mod zmachine;
 
 pub use result::{Error, Result};
-pub use zmachine::ZMachine;
+pub use zmachine::{ErrorMode, InfoSections, TraceFormat, ZConfig, ZMachine, colour_from_name,
+                   colour_names, error_mode_from_name, error_mode_names, trace_format_from_name,
+                   trace_format_names};
@@ -5,33 +5,97 @@ extern crate rzm;
 
 mod args;
 
-use args::Args;
+use args::{Args, Command};
 use rzm::{Error, Result, ZMachine};
 use std::fs::File;
+use std::io::{self, Write};
+use std::process;
+
+// Exit codes.
+const EXIT_RUNTIME_ERROR: i32 = 1;
+const EXIT_USAGE: i32 = 2;
+const EXIT_BAD_STORY_FILE: i32 = 3;
+const EXIT_UNSUPPORTED_VERSION: i32 = 4;
+const EXIT_BAD_DEBUG_INFO: i32 = 5;
+const EXIT_BAD_COVERAGE_FILE: i32 = 6;
 
 fn real_main() -> Result<()> {
   let args = Args::parse()?;
   let path = args.zfile();
-  let f = File::open(path)?;
-  let mut zmachine = ZMachine::from_reader(f)?;
-  zmachine.run(args.start_pc()?)?;
+  let f = File::open(&path).map_err(|err| Error::CouldNotOpenStoryFile(path.to_path_buf(), err))?;
+  let mut zmachine = ZMachine::from_reader(f, &args)?;
+  match args.command() {
+    Command::Play => {
+      for watch in args.watches() {
+        zmachine.add_watch(watch)?;
+      }
+      for range in args.trace_ranges() {
+        zmachine.add_trace_range(range)?;
+      }
+      for routine in args.trace_routines() {
+        zmachine.add_trace_routine(routine)?;
+      }
+      // If this fails, the terminal has already been put back.
+      zmachine.run(args.start_pc()?)?;
+    }
+    Command::Disasm => {
+      let stdout = io::stdout();
+      zmachine.disassemble(&mut stdout.lock())?;
+    }
+    Command::Info => {
+      let info = zmachine.info(args.info_sections())?;
+      let stdout = io::stdout();
+      let mut out = stdout.lock();
+      if args.json() {
+        writeln!(out, "{}", info.to_json())?;
+      } else {
+        info.write_text(&mut out)?;
+      }
+    }
+    Command::Coverage => {
+      let stdout = io::stdout();
+      zmachine.coverage_report(&args.coverage_files(), &mut stdout.lock())?;
+    }
+  }
   Ok(())
 }
 
+fn exit_code(err: &Error) -> i32 {
+  match *err {
+    Error::Clap(_) |
+    Error::ParseIntError(..) |
+    Error::BadWatchpoint(..) |
+    Error::BadTraceFilter(..) => EXIT_USAGE,
+    Error::CouldNotOpenStoryFile(..) |
+    Error::CouldNotReadHeader |
+    Error::ZFileTooShort => EXIT_BAD_STORY_FILE,
+    Error::UnsupportedVersion(_) => EXIT_UNSUPPORTED_VERSION,
+    Error::CouldNotOpenDebugInfo(..) |
+    Error::BadDebugInfo(_) => EXIT_BAD_DEBUG_INFO,
+    Error::BadCoverageFile(..) => EXIT_BAD_COVERAGE_FILE,
+    _ => EXIT_RUNTIME_ERROR,
+  }
+}
+
 fn main() {
   env_logger::init().unwrap();
 
   // A shell that calls a "real main" function and reports errors.
   // A convenience so that I can try!() inside the "main" function.
-  // TODO: make this return non-zero on failure.
   match real_main() {
     Ok(_) => (),
     Err(err) => {
       match err {
         // Clap gets special attention. ('-h' for example is better handled by clap::Error::exit())
-        Error::Clap(ce) => clap::Error::exit(&ce),
-        _ => println!("{:?}", err),
+        Error::Clap(ref ce) if !ce.use_stderr() => ce.exit(),
+        Error::Clap(ref ce) => {
+          let _ = writeln!(io::stderr(), "{}", ce);
+        }
+        _ => {
+          let _ = writeln!(io::stderr(), "rzm: {}", err);
+        }
       }
+      process::exit(exit_code(&err));
     }
   }
 }
@@ -1,6 +1,8 @@
 use clap;
+use std::fmt;
 use std::io;
 use std::num;
+use std::path::PathBuf;
 use std::result;
 
 #[derive(Debug)]
@@ -9,10 +11,41 @@ pub enum Error {
   Clap(clap::Error),
   IO(io::Error),
 
+  CouldNotOpenStoryFile(PathBuf, io::Error),
+  CouldNotOpenDebugInfo(PathBuf, io::Error),
+  BadDebugInfo(String),
   CouldNotReadHeader,
   ParseIntError(&'static str, num::ParseIntError),
-  UnknownOpcode(&'static str, u8, usize),
+  UnsupportedVersion(u8),
+  BadWatchpoint(String, String),
+  CouldNotCreateTrace(PathBuf, io::Error),
+  BadTraceFilter(String, String),
+  BadCoverageFile(PathBuf, String),
   ZFileTooShort,
+  NoScreen,
+  EndOfInput,
+  NoCallStack,
+  ReplayDiverged(u64),
+
+  // Problems with the story file found while running it.
+  AbbrevInAbbrev,
+  AddressOutOfRange(usize),
+  BadAttribute(u16),
+  BadGlobal(u8),
+  BadLocal(u8),
+  BadPropertySize(u16, u16),
+  MemoryStreamsTooDeep,
+  MissingOperand,
+  NoObject(&'static str),
+  NotARoutine(usize),
+  PropertyNotFound(u16),
+  ReadOnlyAddress(usize),
+  StackOverflow,
+  StackUnderflow,
+  UnknownOpcode(&'static str, u8, usize),
+  // Any of the above (or an IO error) while running the instruction at a PC,
+  // with the instruction's first byte.
+  Instruction(usize, u8, Box<Error>),
 
   Restart,
   Quitting,
@@ -20,6 +53,78 @@ pub enum Error {
 
 pub type Result<T> = result::Result<T, Error>;
 
+impl fmt::Display for Error {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      Error::BoardParseError => write!(f, "Could not parse the board"),
+      Error::Clap(ref err) => write!(f, "{}", err),
+      Error::IO(ref err) => write!(f, "{}", err),
+
+      Error::CouldNotOpenStoryFile(ref path, ref err) => {
+        write!(f, "Could not open the story file {}: {}", path.display(), err)
+      }
+      Error::CouldNotOpenDebugInfo(ref path, ref err) => {
+        write!(f, "Could not open the debug information file {}: {}", path.display(), err)
+      }
+      Error::BadDebugInfo(ref why) => write!(f, "Could not read the debug information: {}", why),
+      Error::CouldNotReadHeader => write!(f, "The story file is too short to have a header"),
+      Error::ParseIntError(name, ref err) => write!(f, "Bad value for --{}: {}", name, err),
+      Error::UnsupportedVersion(version) => {
+        write!(f, "Version {} story files are not supported", version)
+      }
+      Error::BadWatchpoint(ref watch, ref why) => write!(f, "Bad watchpoint {}: {}", watch, why),
+      Error::CouldNotCreateTrace(ref path, ref err) => {
+        write!(f, "Could not create the trace file {}: {}", path.display(), err)
+      }
+      Error::BadTraceFilter(ref filter, ref why) => {
+        write!(f, "Bad trace filter {}: {}", filter, why)
+      }
+      Error::BadCoverageFile(ref path, ref why) => {
+        write!(f, "Could not read the coverage file {}: {}", path.display(), why)
+      }
+      Error::ZFileTooShort => write!(f, "The story file is shorter than its header says"),
+      Error::NoScreen => write!(f, "There is no screen to read input from"),
+      Error::EndOfInput => write!(f, "The terminal stopped sending input"),
+      Error::NoCallStack => write!(f, "There are no frames on the call stack"),
+      Error::ReplayDiverged(instruction) => {
+        write!(f,
+               "Going back in time, instruction {} asked for different input than it did before",
+               instruction)
+      }
+
+      Error::AbbrevInAbbrev => write!(f, "An abbreviation used another abbreviation"),
+      Error::AddressOutOfRange(addr) => write!(f, "Address {:#x} is outside the story file", addr),
+      Error::BadAttribute(attr) => write!(f, "Attribute {} does not exist", attr),
+      Error::BadGlobal(global) => write!(f, "There is no global variable {}", global),
+      Error::BadLocal(local) => write!(f, "The routine has no local variable {}", local),
+      Error::BadPropertySize(prop, size) => {
+        write!(f, "Property {} has {} bytes, which is too long to read", prop, size)
+      }
+      Error::MemoryStreamsTooDeep => {
+        write!(f, "Output stream 3 was selected more than 16 times without being closed")
+      }
+      Error::MissingOperand => write!(f, "An instruction is missing an operand"),
+      Error::NoObject(opcode) => write!(f, "{} was given object 0", opcode),
+      Error::NotARoutine(addr) => write!(f, "There is no routine at {:#x}", addr),
+      Error::PropertyNotFound(prop) => write!(f, "Property {} does not exist", prop),
+      Error::ReadOnlyAddress(addr) => {
+        write!(f, "Address {:#x} is outside dynamic memory and cannot be written", addr)
+      }
+      Error::StackOverflow => write!(f, "The stack is full"),
+      Error::StackUnderflow => write!(f, "There is nothing on the stack to pop"),
+      Error::UnknownOpcode(kind, opcode, pc) => {
+        write!(f, "Unknown {} opcode {:#x} at PC {:#x}", kind, opcode, pc)
+      }
+      Error::Instruction(pc, first_byte, ref err) => {
+        write!(f, "{} (PC = {:#x}, opcode byte {:#04x})", err, pc, first_byte)
+      }
+
+      Error::Restart => write!(f, "The game restarted"),
+      Error::Quitting => write!(f, "The game quit"),
+    }
+  }
+}
+
 impl From<clap::Error> for Error {
   fn from(err: clap::Error) -> Error {
     Error::Clap(err)
@@ -31,3 +136,18 @@ impl From<io::Error> for Error {
     Error::IO(err)
   }
 }
+
+#[cfg(test)]
+mod test {
+  use super::Error;
+
+  #[test]
+  fn test_display() {
+    assert_eq!("Version 6 story files are not supported",
+               Error::UnsupportedVersion(6).to_string());
+    let err = Error::Instruction(0x4f05, 0xe1, Box::new(Error::ReadOnlyAddress(0x5000)));
+    assert_eq!("Address 0x5000 is outside dynamic memory and cannot be written \
+                (PC = 0x4f05, opcode byte 0xe1)",
+               err.to_string());
+  }
+}
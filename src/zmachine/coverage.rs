@@ -0,0 +1,335 @@
+use result::{Error, Result};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+use zmachine::debug_info::DebugInfo;
+use zmachine::disasm;
+use zmachine::vm::VM;
+
+// Code coverage: which routines were entered, which instructions ran and
+// which ways each branch went. The data is kept in a text file so that
+// several runs (one for each test script, say) can add to the same file, or
+// be merged afterwards:
+//
+//     rzm coverage 1
+//     story 88.840726.a129
+//     routine 420
+//     instruction 423
+//     branch 42a taken
+//     branch 42a not-taken
+//
+// Addresses are in hex. The story line stops data from one story file being
+// used with another (or another release of the same one).
+//
+// The report compares this with the code that the disassembler can find (see
+// disasm), so code it can't find isn't counted.
+
+const FILE_HEADER: &str = "rzm coverage 1";
+
+#[derive(Clone,Debug,Default,PartialEq)]
+pub struct Coverage {
+  story: String,
+  routines: BTreeSet<usize>,
+  instructions: BTreeSet<usize>,
+  // Whether each branch has been taken, and whether it has been not taken.
+  branches: BTreeMap<usize, (bool, bool)>,
+}
+
+/// How a story file is told apart from others: its release, serial number and
+/// checksum.
+pub fn story_id(release: u16, serial: &str, checksum: u16) -> String {
+  format!("{}.{}.{:04x}", release, serial, checksum)
+}
+
+impl Coverage {
+  pub fn new(story: String) -> Coverage {
+    Coverage { story, ..Coverage::default() }
+  }
+
+  /// Read a coverage file written for the story `story`.
+  pub fn from_file<P>(path: P, story: &str) -> Result<Coverage>
+    where P: AsRef<Path> {
+    let path = path.as_ref();
+    let bad = |why: String| Error::BadCoverageFile(path.to_path_buf(), why);
+    let mut text = String::new();
+    File::open(path)
+      .and_then(|mut f| f.read_to_string(&mut text))
+      .map_err(|err| bad(err.to_string()))?;
+    let coverage = Coverage::parse(&text).map_err(&bad)?;
+    if coverage.story != story {
+      return Err(bad(format!("it is for story {}, not {}", coverage.story, story)));
+    }
+    Ok(coverage)
+  }
+
+  fn parse(text: &str) -> ::std::result::Result<Coverage, String> {
+    let mut lines = text.lines();
+    if lines.next() != Some(FILE_HEADER) {
+      return Err("not a coverage file".to_string());
+    }
+    let mut coverage = Coverage::default();
+    for line in lines {
+      let words = line.split_whitespace().collect::<Vec<_>>();
+      match words.as_slice() {
+        [] => {}
+        ["story", story] => coverage.story = story.to_string(),
+        ["routine", addr] => {
+          coverage.routines.insert(disasm::parse_addr(addr)?);
+        }
+        ["instruction", addr] => {
+          coverage.instructions.insert(disasm::parse_addr(addr)?);
+        }
+        ["branch", addr, "taken"] => coverage.branch(disasm::parse_addr(addr)?, true),
+        ["branch", addr, "not-taken"] => coverage.branch(disasm::parse_addr(addr)?, false),
+        _ => return Err(format!("can't read \"{}\"", line)),
+      }
+    }
+    Ok(coverage)
+  }
+
+  pub fn write<W>(&self, out: &mut W) -> Result<()>
+    where W: Write {
+    writeln!(out, "{}", FILE_HEADER)?;
+    writeln!(out, "story {}", self.story)?;
+    for addr in &self.routines {
+      writeln!(out, "routine {:x}", addr)?;
+    }
+    for addr in &self.instructions {
+      writeln!(out, "instruction {:x}", addr)?;
+    }
+    for (addr, &(taken, not_taken)) in &self.branches {
+      if taken {
+        writeln!(out, "branch {:x} taken", addr)?;
+      }
+      if not_taken {
+        writeln!(out, "branch {:x} not-taken", addr)?;
+      }
+    }
+    Ok(())
+  }
+
+  /// Add what another run covered.
+  pub fn merge(&mut self, other: &Coverage) {
+    self.routines.extend(other.routines.iter().cloned());
+    self.instructions.extend(other.instructions.iter().cloned());
+    for (addr, &(taken, not_taken)) in &other.branches {
+      let branch = self.branches.entry(*addr).or_insert((false, false));
+      branch.0 |= taken;
+      branch.1 |= not_taken;
+    }
+  }
+
+  /// The instruction at `pc` ran. `branch_taken` is which way it went, if it
+  /// is a branch.
+  pub fn ran(&mut self, pc: usize, branch_taken: Option<bool>) {
+    self.instructions.insert(pc);
+    if let Some(taken) = branch_taken {
+      self.branch(pc, taken);
+    }
+  }
+
+  /// The routine whose header is at `addr` was called.
+  pub fn entered(&mut self, addr: usize) {
+    self.routines.insert(addr);
+  }
+
+  fn branch(&mut self, pc: usize, taken: bool) {
+    let branch = self.branches.entry(pc).or_insert((false, false));
+    if taken {
+      branch.0 = true;
+    } else {
+      branch.1 = true;
+    }
+  }
+
+  /// Write how much of each routine was covered, and which instructions
+  /// weren't. `start_pc` is where the main routine starts.
+  pub fn write_report<T, W>(&self,
+                            vm: &mut T,
+                            start_pc: usize,
+                            names: &DebugInfo,
+                            out: &mut W)
+                            -> Result<()>
+    where T: VM,
+          W: Write {
+    let entered = self.routines.iter().cloned().collect::<Vec<_>>();
+    let routines = disasm::find_routines(vm, start_pc, &entered);
+
+    let mut lines = Vec::new();
+    let mut not_run = Vec::new();
+    let mut totals = [(0, 0); 3];
+    for routine in routines.values() {
+      let run = routine.instructions
+        .iter()
+        .filter(|instruction| self.instructions.contains(&instruction.addr))
+        .count();
+      let branches = routine.instructions
+        .iter()
+        .filter(|instruction| instruction.branch.is_some())
+        .map(|instruction| match self.branches.get(&instruction.addr) {
+          Some(&(taken, not_taken)) => taken as usize + not_taken as usize,
+          None => 0,
+        })
+        .collect::<Vec<_>>();
+      let entered = (routine.main && run > 0) || self.routines.contains(&routine.addr);
+      let counts = [(entered as usize, 1),
+                    (run, routine.instructions.len()),
+                    (branches.iter().sum(), 2 * branches.len())];
+      for (total, count) in totals.iter_mut().zip(counts.iter()) {
+        total.0 += count.0;
+        total.1 += count.1;
+      }
+
+      let name = routine_text(routine, names);
+      lines.push(format!("{:>11} {}  {:>11} {}  {}",
+                         fraction(counts[1]),
+                         percent(counts[1]),
+                         fraction(counts[2]),
+                         percent(counts[2]),
+                         name));
+      if !entered {
+        not_run.push(format!("  {}: never called", name));
+      } else if run < routine.instructions.len() {
+        let mut ranges = Vec::new();
+        let mut range: Option<(usize, usize)> = None;
+        for instruction in &routine.instructions {
+          if self.instructions.contains(&instruction.addr) {
+            ranges.extend(range.take());
+          } else {
+            range = Some((range.map_or(instruction.addr, |r| r.0), instruction.addr));
+          }
+        }
+        ranges.extend(range);
+        let ranges = ranges.iter()
+          .map(|&(start, end)| if start == end {
+            format!("{:x}", start)
+          } else {
+            format!("{:x}-{:x}", start, end)
+          })
+          .collect::<Vec<_>>();
+        not_run.push(format!("  {}: {}", name, ranges.join(", ")));
+      }
+    }
+
+    writeln!(out,
+             "Routines: {} {}\nInstructions: {} {}\nBranch directions: {} {}\n",
+             fraction(totals[0]),
+             percent(totals[0]),
+             fraction(totals[1]),
+             percent(totals[1]),
+             fraction(totals[2]),
+             percent(totals[2]))?;
+    writeln!(out, "instructions        branches            routine")?;
+    for line in lines {
+      writeln!(out, "{}", line)?;
+    }
+    if !not_run.is_empty() {
+      writeln!(out, "\nNot run:")?;
+      for line in not_run {
+        writeln!(out, "{}", line)?;
+      }
+    }
+    Ok(())
+  }
+}
+
+fn routine_text(routine: &disasm::Routine, names: &DebugInfo) -> String {
+  match (routine.main, names.routine_name(routine.addr)) {
+    (true, _) => format!("main routine ({:04x})", routine.addr),
+    (false, Some(name)) => format!("{} ({:04x})", name, routine.addr),
+    (false, None) => format!("{:04x}", routine.addr),
+  }
+}
+
+fn fraction((count, total): (usize, usize)) -> String {
+  format!("{}/{}", count, total)
+}
+
+// Nothing out of nothing is all of it.
+fn percent((count, total): (usize, usize)) -> String {
+  if total == 0 {
+    format!("{:5.1}%", 100.0)
+  } else {
+    format!("{:5.1}%", 100.0 * count as f64 / total as f64)
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::Coverage;
+  use zmachine::debug_info::DebugInfo;
+  use zmachine::ops::testvm::TestVM;
+
+  #[test]
+  fn test_read_write() {
+    let mut coverage = Coverage::new("1.000101.abcd".to_string());
+    coverage.entered(0x120);
+    coverage.ran(0x100, None);
+    coverage.ran(0x123, Some(true));
+    coverage.ran(0x123, Some(false));
+
+    let mut out = Vec::new();
+    coverage.write(&mut out).unwrap();
+    let text = String::from_utf8(out).unwrap();
+    assert_eq!("rzm coverage 1\n\
+                story 1.000101.abcd\n\
+                routine 120\n\
+                instruction 100\n\
+                instruction 123\n\
+                branch 123 taken\n\
+                branch 123 not-taken\n",
+               text);
+    assert_eq!(Ok(coverage), Coverage::parse(&text));
+    assert!(Coverage::parse("instruction 100\n").is_err());
+    assert!(Coverage::parse("rzm coverage 1\ninstruction 10g\n").is_err());
+  }
+
+  #[test]
+  fn test_merge() {
+    let mut first = Coverage::new("1".to_string());
+    first.ran(0x100, None);
+    first.ran(0x123, Some(true));
+    let mut second = Coverage::new("1".to_string());
+    second.entered(0x120);
+    second.ran(0x123, Some(false));
+    second.ran(0x128, None);
+
+    first.merge(&second);
+    let mut expected = Coverage::new("1".to_string());
+    expected.entered(0x120);
+    expected.ran(0x100, None);
+    expected.ran(0x123, Some(true));
+    expected.ran(0x123, Some(false));
+    expected.ran(0x128, None);
+    assert_eq!(expected, first);
+  }
+
+  #[test]
+  fn test_report() {
+    let mut vm = TestVM::with_story();
+    let mut coverage = Coverage::new("1".to_string());
+    coverage.ran(0x100, None);
+
+    let mut out = Vec::new();
+    coverage.write_report(&mut vm, 0x100, &DebugInfo::new(), &mut out).unwrap();
+    assert_eq!("Routines: 1/2  50.0%\n\
+                Instructions: 1/4  25.0%\n\
+                Branch directions: 0/2   0.0%\n\n\
+                instructions        branches            routine\n\
+                \x20       1/2  50.0%          0/0 100.0%  main routine (0100)\n\
+                \x20       0/2   0.0%          0/2   0.0%  0120\n\n\
+                Not run:\n  \
+                main routine (0100): 105\n  \
+                0120: never called\n",
+               String::from_utf8(out).unwrap());
+
+    coverage.entered(0x120);
+    coverage.ran(0x123, Some(false));
+    let mut out = Vec::new();
+    coverage.write_report(&mut vm, 0x100, &DebugInfo::new(), &mut out).unwrap();
+    assert!(String::from_utf8(out).unwrap().ends_with("Not run:\n  \
+                                                       main routine (0100): 105\n  \
+                                                       0120: 128\n"));
+  }
+}
@@ -0,0 +1,413 @@
+use result::{Error, Result};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+// The names from an Inform 6 debugging information file (gameinfo.dbg), so
+// that listings and the debugger can say "Main" and "location" instead of
+// "0420" and "G10".
+//
+// Inform writes two kinds of file. Older compilers write the binary format
+// from the Inform Technical Manual (section 12.5), and newer ones write XML.
+// Only the names are kept: routines (with their locals), globals, objects,
+// properties and attributes. Source lines, arrays and actions are skipped.
+
+// Binary record types.
+const EOF_DBR: u8 = 0;
+const FILE_DBR: u8 = 1;
+const CLASS_DBR: u8 = 2;
+const OBJECT_DBR: u8 = 3;
+const GLOBAL_DBR: u8 = 4;
+const ATTR_DBR: u8 = 5;
+const PROP_DBR: u8 = 6;
+const FAKE_ACTION_DBR: u8 = 7;
+const ACTION_DBR: u8 = 8;
+const HEADER_DBR: u8 = 9;
+const LINEREF_DBR: u8 = 10;
+const ROUTINE_DBR: u8 = 11;
+const ARRAY_DBR: u8 = 12;
+const MAP_DBR: u8 = 13;
+const ROUTINE_END_DBR: u8 = 14;
+
+// A source position: file number (1 byte), line (2 bytes), character (1 byte).
+const LINE_SIZE: usize = 4;
+
+#[derive(Clone,Debug,Default)]
+pub struct DebugInfo {
+  routines: BTreeMap<usize, (String, Vec<String>)>,
+  globals: BTreeMap<u8, String>,
+  objects: BTreeMap<u16, String>,
+  properties: BTreeMap<u16, String>,
+  attributes: BTreeMap<u16, String>,
+}
+
+impl DebugInfo {
+  /// No names at all.
+  pub fn new() -> DebugInfo {
+    DebugInfo::default()
+  }
+
+  /// Read a debugging information file. `globals` is the address of the
+  /// story file's global variables (which the XML format uses to say where
+  /// each global is).
+  pub fn from_file<P>(path: P, globals: usize) -> Result<DebugInfo>
+    where P: AsRef<Path> {
+    let path = path.as_ref();
+    let mut bytes = Vec::new();
+    File::open(path)
+      .and_then(|mut f| f.read_to_end(&mut bytes))
+      .map_err(|err| Error::CouldNotOpenDebugInfo(path.to_path_buf(), err))?;
+    DebugInfo::from_bytes(&bytes, globals)
+  }
+
+  pub fn from_bytes(bytes: &[u8], globals: usize) -> Result<DebugInfo> {
+    if bytes.starts_with(&[0xde, 0xbf]) {
+      read_binary(bytes)
+    } else if bytes.starts_with(b"<") {
+      read_xml(&String::from_utf8_lossy(bytes), globals)
+    } else {
+      Err(Error::BadDebugInfo("not an Inform debugging information file".to_string()))
+    }
+  }
+
+  /// The name of the routine whose header is at `addr`.
+  pub fn routine_name(&self, addr: usize) -> Option<&str> {
+    self.routines.get(&addr).map(|(name, _)| name.as_str())
+  }
+
+  pub fn local_name(&self, routine: usize, idx: u8) -> Option<&str> {
+    self.routines
+      .get(&routine)
+      .and_then(|(_, locals)| locals.get(idx as usize))
+      .map(|name| name.as_str())
+  }
+
+  pub fn global_name(&self, idx: u8) -> Option<&str> {
+    self.globals.get(&idx).map(|name| name.as_str())
+  }
+
+  pub fn object_name(&self, number: u16) -> Option<&str> {
+    self.objects.get(&number).map(|name| name.as_str())
+  }
+
+  pub fn property_name(&self, number: u16) -> Option<&str> {
+    self.properties.get(&number).map(|name| name.as_str())
+  }
+
+  pub fn attribute_name(&self, number: u16) -> Option<&str> {
+    self.attributes.get(&number).map(|name| name.as_str())
+  }
+
+  /// The address of the routine with this name. Inform's names aren't case
+  /// sensitive, so neither is this. (Nor are the others.)
+  pub fn routine_named(&self, name: &str) -> Option<usize> {
+    self.routines
+      .iter()
+      .find(|(_, (routine, _))| routine.eq_ignore_ascii_case(name))
+      .map(|(addr, _)| *addr)
+  }
+
+  pub fn global_named(&self, name: &str) -> Option<u8> {
+    find_named(&self.globals, name)
+  }
+
+  pub fn object_named(&self, name: &str) -> Option<u16> {
+    find_named(&self.objects, name)
+  }
+
+  pub fn attribute_named(&self, name: &str) -> Option<u16> {
+    find_named(&self.attributes, name)
+  }
+}
+
+fn find_named<K>(names: &BTreeMap<K, String>, name: &str) -> Option<K>
+  where K: Copy {
+  names.iter().find(|&(_, n)| n.eq_ignore_ascii_case(name)).map(|(k, _)| *k)
+}
+
+struct Reader<'a> {
+  bytes: &'a [u8],
+  pos: usize,
+}
+
+impl<'a> Reader<'a> {
+  fn bytes(&mut self, count: usize) -> Result<&'a [u8]> {
+    if self.pos + count > self.bytes.len() {
+      return Err(Error::BadDebugInfo("the file stops in the middle of a record".to_string()));
+    }
+    let bytes = &self.bytes[self.pos..self.pos + count];
+    self.pos += count;
+    Ok(bytes)
+  }
+
+  fn u8(&mut self) -> Result<u8> {
+    Ok(self.bytes(1)?[0])
+  }
+
+  fn u16(&mut self) -> Result<u16> {
+    let bytes = self.bytes(2)?;
+    Ok((bytes[0] as u16) << 8 | bytes[1] as u16)
+  }
+
+  fn address(&mut self) -> Result<usize> {
+    let bytes = self.bytes(3)?;
+    Ok((bytes[0] as usize) << 16 | (bytes[1] as usize) << 8 | bytes[2] as usize)
+  }
+
+  fn string(&mut self) -> Result<String> {
+    let len = match self.bytes[self.pos..].iter().position(|b| *b == 0) {
+      Some(len) => len,
+      None => return Err(Error::BadDebugInfo("a name is missing its end".to_string())),
+    };
+    let string = String::from_utf8_lossy(self.bytes(len)?).into_owned();
+    self.pos += 1;
+    Ok(string)
+  }
+
+  fn skip(&mut self, count: usize) -> Result<()> {
+    self.bytes(count).map(|_| ())
+  }
+}
+
+fn read_binary(bytes: &[u8]) -> Result<DebugInfo> {
+  let mut info = DebugInfo::new();
+  // Routine addresses are from the start of the code area, which the map
+  // at the end gives.
+  let mut routines = Vec::new();
+  let mut code_area = 0;
+
+  // Skip the magic number and the file and compiler versions.
+  let mut reader = Reader {
+    bytes,
+    pos: 6,
+  };
+  loop {
+    match reader.u8()? {
+      EOF_DBR => break,
+      FILE_DBR => {
+        reader.skip(1)?;
+        reader.string()?;
+        reader.string()?;
+      }
+      CLASS_DBR => {
+        reader.string()?;
+        reader.skip(2 * LINE_SIZE)?;
+      }
+      OBJECT_DBR => {
+        let number = reader.u16()?;
+        info.objects.insert(number, reader.string()?);
+        reader.skip(2 * LINE_SIZE)?;
+      }
+      GLOBAL_DBR => {
+        let number = reader.u8()?;
+        info.globals.insert(number, reader.string()?);
+      }
+      ATTR_DBR => {
+        let number = reader.u16()?;
+        info.attributes.insert(number, reader.string()?);
+      }
+      PROP_DBR => {
+        let number = reader.u16()?;
+        info.properties.insert(number, reader.string()?);
+      }
+      FAKE_ACTION_DBR | ACTION_DBR | ARRAY_DBR => {
+        reader.skip(2)?;
+        reader.string()?;
+      }
+      HEADER_DBR => reader.skip(64)?,
+      LINEREF_DBR => {
+        reader.skip(2)?;
+        let count = reader.u16()? as usize;
+        reader.skip(count * (LINE_SIZE + 2))?;
+      }
+      ROUTINE_DBR => {
+        reader.skip(2 + LINE_SIZE)?;
+        let start = reader.address()?;
+        let name = reader.string()?;
+        let mut locals = Vec::new();
+        loop {
+          let local = reader.string()?;
+          if local.is_empty() {
+            break;
+          }
+          locals.push(local);
+        }
+        routines.push((start, name, locals));
+      }
+      MAP_DBR => {
+        loop {
+          let name = reader.string()?;
+          if name.is_empty() {
+            break;
+          }
+          let addr = reader.address()?;
+          if name == "code area" {
+            code_area = addr;
+          }
+        }
+      }
+      ROUTINE_END_DBR => reader.skip(2 + LINE_SIZE + 3)?,
+      record => return Err(Error::BadDebugInfo(format!("unknown record type {}", record))),
+    }
+  }
+
+  for (start, name, locals) in routines {
+    info.routines.insert(code_area + start, (name, locals));
+  }
+  Ok(info)
+}
+
+fn read_xml(text: &str, globals: usize) -> Result<DebugInfo> {
+  let mut info = DebugInfo::new();
+  for routine in elements(text, "routine") {
+    if let (Some(name), Some(addr)) = (child_text(routine, "identifier"),
+                                       child_number(routine, "address")) {
+      let locals = elements(routine, "local-variable")
+        .into_iter()
+        .filter_map(|local| child_text(local, "identifier"))
+        .collect();
+      info.routines.insert(addr, (name, locals));
+    }
+  }
+  for global in elements(text, "global-variable") {
+    if let (Some(name), Some(addr)) = (child_text(global, "identifier"),
+                                       child_number(global, "address")) {
+      if addr >= globals && (addr - globals) / 2 < 240 {
+        info.globals.insert(((addr - globals) / 2) as u8, name);
+      }
+    }
+  }
+  read_numbered(text, "object", &mut info.objects);
+  read_numbered(text, "property", &mut info.properties);
+  read_numbered(text, "attribute", &mut info.attributes);
+  Ok(info)
+}
+
+// Objects, properties and attributes all look the same.
+fn read_numbered(text: &str, tag: &str, names: &mut BTreeMap<u16, String>) {
+  for element in elements(text, tag) {
+    if let (Some(name), Some(number)) = (child_text(element, "identifier"),
+                                         child_number(element, "value")) {
+      names.insert(number as u16, name);
+    }
+  }
+}
+
+// What's inside each <tag>...</tag>. This is nowhere near a real XML parser,
+// but Inform's files are regular enough not to need one.
+fn elements<'a>(text: &'a str, tag: &str) -> Vec<&'a str> {
+  let open = format!("<{}", tag);
+  let close = format!("</{}>", tag);
+  let mut found = Vec::new();
+  let mut rest = text;
+  while let Some(start) = rest.find(&open) {
+    let after = &rest[start + open.len()..];
+    // <tag> or <tag attr="...">, but not <tag-something>.
+    if !after.starts_with('>') && !after.starts_with(' ') {
+      rest = after;
+      continue;
+    }
+    let body_start = match after.find('>') {
+      Some(end) => end + 1,
+      None => break,
+    };
+    let body = &after[body_start..];
+    match body.find(&close) {
+      Some(end) => {
+        found.push(&body[..end]);
+        rest = &body[end + close.len()..];
+      }
+      None => break,
+    }
+  }
+  found
+}
+
+fn child_text(element: &str, tag: &str) -> Option<String> {
+  elements(element, tag).first().map(|text| unescape(text.trim()))
+}
+
+fn child_number(element: &str, tag: &str) -> Option<usize> {
+  child_text(element, tag).and_then(|text| text.parse::<usize>().ok())
+}
+
+fn unescape(text: &str) -> String {
+  text.replace("&lt;", "<")
+    .replace("&gt;", ">")
+    .replace("&quot;", "\"")
+    .replace("&apos;", "'")
+    .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod test {
+  use super::DebugInfo;
+
+  #[test]
+  fn test_binary() {
+    let mut bytes = vec![0xde, 0xbf, 0x00, 0x00, 0x06, 0x4c];
+    // GLOBAL_DBR 0x10 "location"
+    bytes.extend_from_slice(&[4, 0x10]);
+    bytes.extend_from_slice(b"location\0");
+    // ATTR_DBR 3 "light"
+    bytes.extend_from_slice(&[5, 0, 3]);
+    bytes.extend_from_slice(b"light\0");
+    // OBJECT_DBR 12 "lamp", then two source lines.
+    bytes.extend_from_slice(&[3, 0, 12]);
+    bytes.extend_from_slice(b"lamp\0");
+    bytes.extend_from_slice(&[0; 8]);
+    // ROUTINE_DBR 1, a source line, code offset 0x20, "Main" with a local.
+    bytes.extend_from_slice(&[11, 0, 1, 0, 0, 0, 0, 0x00, 0x00, 0x20]);
+    bytes.extend_from_slice(b"Main\0x\0\0");
+    // ROUTINE_END_DBR 1
+    bytes.extend_from_slice(&[14, 0, 1, 0, 0, 0, 0, 0x00, 0x00, 0x30]);
+    // MAP_DBR with the code area at 0x400.
+    bytes.push(13);
+    bytes.extend_from_slice(b"code area\0");
+    bytes.extend_from_slice(&[0x00, 0x04, 0x00, 0]);
+    bytes.push(0);
+
+    let info = DebugInfo::from_bytes(&bytes, 0).unwrap();
+    assert_eq!(Some("location"), info.global_name(0x10));
+    assert_eq!(Some("light"), info.attribute_name(3));
+    assert_eq!(Some("lamp"), info.object_name(12));
+    assert_eq!(Some("Main"), info.routine_name(0x420));
+    assert_eq!(Some("x"), info.local_name(0x420, 0));
+    assert_eq!(None, info.local_name(0x420, 1));
+    assert_eq!(Some(0x420), info.routine_named("main"));
+    assert_eq!(Some(0x10), info.global_named("Location"));
+
+    // A file that stops early is an error, not a panic.
+    assert!(DebugInfo::from_bytes(&bytes[..20], 0).is_err());
+  }
+
+  #[test]
+  fn test_xml() {
+    let xml = "<?xml version=\"1.0\"?>\n\
+               <inform-story-file version=\"1.0\" content-creator=\"Inform\">\n\
+               <global-variable><identifier>location</identifier>\
+               <address>704</address></global-variable>\n\
+               <object><identifier>lamp</identifier><value>12</value></object>\n\
+               <property><identifier>name</identifier><value>1</value></property>\n\
+               <attribute><identifier>light</identifier><value>3</value></attribute>\n\
+               <routine><identifier artificial=\"true\">Main</identifier><value>528</value>\
+               <address>1056</address>\
+               <local-variable><identifier>x</identifier><index>1</index></local-variable>\
+               <local-variable><identifier>a&amp;b</identifier><index>2</index></local-variable>\
+               </routine>\n\
+               </inform-story-file>\n";
+    let info = DebugInfo::from_bytes(xml.as_bytes(), 0x2a0).unwrap();
+    assert_eq!(Some("location"), info.global_name(0x10));
+    assert_eq!(Some("lamp"), info.object_name(12));
+    assert_eq!(Some("name"), info.property_name(1));
+    assert_eq!(Some("light"), info.attribute_name(3));
+    assert_eq!(Some("Main"), info.routine_name(0x420));
+    assert_eq!(Some("a&b"), info.local_name(0x420, 1));
+  }
+
+  #[test]
+  fn test_not_debug_info() {
+    assert!(DebugInfo::from_bytes(b"Zork", 0).is_err());
+  }
+}
@@ -0,0 +1,927 @@
+use result::{Error, Result};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{BufRead, Write};
+use zmachine::debug_info::DebugInfo;
+use zmachine::disasm;
+use zmachine::info;
+use zmachine::ops::decoder;
+use zmachine::vm::{Frame, Input, Timeline, VM, VariableRef, ZObjectTable};
+use zmachine::watch::{Watch, Watchpoints};
+
+// An interactive debugger. The interpreter asks it before each instruction
+// whether to stop there. When it does, commands are read from a plain prompt
+// on stdin/stdout. The screen is suspended while the prompt is up, so the
+// game's windows come back as they were when the game carries on.
+//
+// The debugger can also go back in time, to an earlier instruction or turn
+// (see Timeline). The interpreter does the going back when the prompt asks
+// for it, then stops there.
+
+const HELP: &str = "break ADDR          Stop before the instruction at ADDR (b)
+break routine ADDR  Stop before the first instruction of the routine at ADDR
+delete ADDR         Remove the breakpoint at ADDR (d)
+breakpoints         List the breakpoints
+step [N]            Run N instructions, 1 if not given (s)
+next                Run one instruction, stepping over calls (n)
+continue            Run until the next breakpoint or watchpoint (c)
+rstep [N]           Go back N instructions, 1 if not given (rs)
+turns               List what has been typed, by turn
+turn N              Go back (or forward) to where the game read the input for turn N,
+                    or to the start of the game for 0
+print VAR           Show sp, a local (L00-L0e) or a global (G00-Gef) (p)
+locals              Show the current routine's locals
+globals             Show all of the globals
+stack               Show the current routine's evaluation stack
+backtrace           Show the routines on the call stack (bt)
+object N            Show object N (o)
+watch WATCH         Stop when something is written: a global (G10), bytes of memory
+                    (4f05 or 4f05+4), an attribute (attr:12:3) or a parent (parent:12)
+unwatch N           Remove watchpoint N
+watches             List the watchpoints
+disasm [ADDR] [N]   List N instructions, 10 if not given, from ADDR or the PC (x)
+quit                Stop the game (q)
+
+Addresses are in hex. An empty line repeats the last step, next, continue or
+rstep. With --debug-info, routines, variables, objects and attributes can also
+be given by name.
+
+After going back, the game runs forward again with the same input and random
+numbers until it gets to where it was, without showing its output again. The
+screen isn't wound back.";
+
+const DEFAULT_DISASM_COUNT: usize = 10;
+
+#[derive(Clone,Debug,PartialEq)]
+enum Command {
+  Break(usize),
+  BreakRoutine(usize),
+  Delete(usize),
+  Breakpoints,
+  Step(usize),
+  Next,
+  Continue,
+  ReverseStep(usize),
+  Turns,
+  Turn(usize),
+  Print(VariableRef),
+  Locals,
+  Globals,
+  Stack,
+  Backtrace,
+  Object(u16),
+  Watch(Watch),
+  Unwatch(usize),
+  Watches,
+  Disasm(Option<usize>, usize),
+  Help,
+  Quit,
+}
+
+// How to carry on after the prompt.
+#[derive(Clone,Copy,Debug,PartialEq)]
+enum Resume {
+  Continue,
+  // Stop after this many more instructions.
+  Step(usize),
+  // Stop at the first instruction that isn't in a call deeper than this.
+  Over(usize),
+  // Go back to the instruction with this number, and stop there.
+  Rewind(u64),
+}
+
+/// What the debugger can see of the game while it is stopped.
+pub struct Context<'a, T>
+  where T: VM + 'a {
+  pub vm: &'a mut T,
+  /// The instruction about to run.
+  pub pc: usize,
+  /// The call stack, innermost first.
+  pub frames: &'a [Frame],
+  pub watches: &'a mut Watchpoints,
+  /// What has happened so far.
+  pub timeline: &'a Timeline,
+  /// Used to show and read routines, variables and objects.
+  pub names: &'a DebugInfo,
+}
+
+pub struct Debugger {
+  breakpoints: BTreeSet<usize>,
+  resume: Resume,
+  last_command: Option<Command>,
+  // Things to tell the user when we next stop.
+  reports: Vec<String>,
+}
+
+impl Debugger {
+  /// A debugger that stops before the first instruction.
+  pub fn new() -> Debugger {
+    Debugger {
+      breakpoints: BTreeSet::new(),
+      resume: Resume::Step(1),
+      last_command: None,
+      reports: Vec::new(),
+    }
+  }
+
+  /// Stop before the next instruction and show `text` (a watchpoint hit, say).
+  pub fn report(&mut self, text: String) {
+    self.reports.push(text);
+    self.resume = Resume::Step(1);
+  }
+
+  /// Whether to stop before the instruction at `pc`. `depth` is the number of
+  /// frames on the call stack.
+  pub fn should_stop(&mut self, pc: usize, depth: usize) -> bool {
+    let stop = match self.resume {
+      Resume::Continue => false,
+      Resume::Step(count) => {
+        self.resume = Resume::Step(count.saturating_sub(1));
+        count <= 1
+      }
+      Resume::Over(over_depth) => depth <= over_depth,
+      Resume::Rewind(_) => true,
+    };
+    stop || self.breakpoints.contains(&pc)
+  }
+
+  /// The instruction to go back to, if the prompt asked for one. The
+  /// debugger stops again once the interpreter has got there.
+  pub fn take_rewind(&mut self) -> Option<u64> {
+    match self.resume {
+      Resume::Rewind(instruction) => {
+        self.resume = Resume::Step(1);
+        Some(instruction)
+      }
+      _ => None,
+    }
+  }
+
+  /// Show the instruction about to run and read commands until one of them
+  /// lets the game carry on.
+  pub fn prompt<T, R, W>(&mut self,
+                         context: &mut Context<T>,
+                         input: &mut R,
+                         out: &mut W)
+                         -> Result<()>
+    where T: VM,
+          R: BufRead,
+          W: Write {
+    for report in self.reports.drain(..) {
+      write!(out, "{}", report)?;
+    }
+    let routine = current_routine(context.frames);
+    write_instructions(context.vm, context.pc, context.pc, 1, context.names, routine, out)?;
+    loop {
+      write!(out, "(rzm) ")?;
+      out.flush()?;
+      let mut line = String::new();
+      if input.read_line(&mut line)? == 0 {
+        // Nobody is left to ask.
+        writeln!(out)?;
+        return Err(Error::Quitting);
+      }
+
+      let command = if line.trim().is_empty() {
+        match self.last_command.clone() {
+          Some(command) => command,
+          None => continue,
+        }
+      } else {
+        match parse_command(&line, context.names, routine) {
+          Ok(command) => command,
+          Err(message) => {
+            writeln!(out, "{}", message)?;
+            continue;
+          }
+        }
+      };
+
+      match self.run_command(context, command.clone(), out) {
+        Ok(Some(resume)) => {
+          self.last_command = Some(command);
+          self.resume = resume;
+          return Ok(());
+        }
+        Ok(None) => {}
+        Err(Error::Quitting) => return Err(Error::Quitting),
+        // Anything else is a bad address or the like. Keep asking.
+        Err(err) => writeln!(out, "{}", err)?,
+      }
+    }
+  }
+
+  fn run_command<T, W>(&mut self,
+                       context: &mut Context<T>,
+                       command: Command,
+                       out: &mut W)
+                       -> Result<Option<Resume>>
+    where T: VM,
+          W: Write {
+    let vm = &mut *context.vm;
+    let watches = &mut *context.watches;
+    let (pc, frames) = (context.pc, context.frames);
+    let (timeline, names) = (context.timeline, context.names);
+    let routine = current_routine(frames);
+    match command {
+      Command::Break(addr) => {
+        self.breakpoints.insert(addr);
+        writeln!(out, "Breakpoint at {:x}", addr)?;
+      }
+      Command::BreakRoutine(addr) => {
+        let start = disasm::routine_start(vm, addr)?;
+        self.breakpoints.insert(start);
+        writeln!(out,
+                 "Breakpoint at {:x}, the start of {}",
+                 start,
+                 routine_text(addr, names))?;
+      }
+      Command::Delete(addr) => {
+        if self.breakpoints.remove(&addr) {
+          writeln!(out, "Deleted the breakpoint at {:x}", addr)?;
+        } else {
+          writeln!(out, "There is no breakpoint at {:x}", addr)?;
+        }
+      }
+      Command::Breakpoints => {
+        if self.breakpoints.is_empty() {
+          writeln!(out, "No breakpoints")?;
+        }
+        for addr in &self.breakpoints {
+          writeln!(out, "{:5x}", addr)?;
+        }
+      }
+      Command::Step(count) => return Ok(Some(Resume::Step(count))),
+      Command::Next => return Ok(Some(Resume::Over(frames.len()))),
+      Command::Continue => return Ok(Some(Resume::Continue)),
+      Command::ReverseStep(count) => {
+        if timeline.now() == 0 {
+          writeln!(out, "This is the start of the game")?;
+        } else {
+          return Ok(Some(Resume::Rewind(timeline.now().saturating_sub(count as u64))));
+        }
+      }
+      Command::Turns => write_turns(timeline, out)?,
+      Command::Turn(number) => {
+        match timeline.turn_start(number) {
+          Some(instruction) => return Ok(Some(Resume::Rewind(instruction))),
+          None => writeln!(out, "There is no turn {} yet", number)?,
+        }
+      }
+      Command::Print(variable) => {
+        let frame = current_frame(frames)?;
+        match variable {
+          VariableRef::Stack if frame.eval_stack.is_empty() => {
+            writeln!(out, "The stack is empty")?
+          }
+          VariableRef::Stack => {
+            // Reading the stack pops it, so put it back.
+            let value = vm.read_variable(variable)?;
+            vm.write_variable(variable, value)?;
+            write_variable(variable, value, names, routine, out)?;
+          }
+          VariableRef::Local(idx) if idx as usize >= frame.locals.len() => {
+            writeln!(out,
+                     "This routine has no {}",
+                     disasm::variable_text(variable, names, routine))?
+          }
+          _ => {
+            let value = vm.read_variable(variable)?;
+            write_variable(variable, value, names, routine, out)?;
+          }
+        }
+      }
+      Command::Locals => {
+        let frame = current_frame(frames)?;
+        if frame.locals.is_empty() {
+          writeln!(out, "This routine has no locals")?;
+        }
+        for idx in 0..frame.locals.len() as u8 {
+          let value = vm.read_variable(VariableRef::Local(idx))?;
+          write_variable(VariableRef::Local(idx), value, names, routine, out)?;
+        }
+      }
+      Command::Globals => {
+        for row in 0..30u8 {
+          let values = (0..8u8)
+            .map(|col| vm.read_variable(VariableRef::Global(row * 8 + col)))
+            .collect::<Result<Vec<_>>>()?;
+          let values = values.iter().map(|value| format!("{:04x}", value)).collect::<Vec<_>>();
+          writeln!(out, "G{:02x}: {}", row * 8, values.join(" "))?;
+        }
+      }
+      Command::Stack => {
+        let frame = current_frame(frames)?;
+        if frame.eval_stack.is_empty() {
+          writeln!(out, "The stack is empty")?;
+        } else {
+          // Top of the stack last, as it was pushed.
+          let values =
+            frame.eval_stack.iter().map(|value| format!("{:04x}", value)).collect::<Vec<_>>();
+          writeln!(out, "{}", values.join(" "))?;
+        }
+      }
+      Command::Backtrace => write_backtrace(pc, frames, names, out)?,
+      Command::Object(number) => {
+        let num_objects = vm.object_table()?.num_objects();
+        if number == 0 || number > num_objects {
+          writeln!(out, "There is no object {} (there are {})", number, num_objects)?;
+        } else {
+          info::write_object(&info::object(vm, number)?, out)?;
+        }
+      }
+      Command::Watch(watch) => {
+        let number = watches.add(vm, watch)?;
+        writeln!(out, "Watchpoint {}: {}", number, watch.describe(names))?;
+      }
+      Command::Unwatch(number) => {
+        if watches.remove(number) {
+          writeln!(out, "Deleted watchpoint {}", number)?;
+        } else {
+          writeln!(out, "There is no watchpoint {}", number)?;
+        }
+      }
+      Command::Watches => {
+        let list = watches.list();
+        if list.is_empty() {
+          writeln!(out, "No watchpoints")?;
+        }
+        for (number, watch) in list {
+          writeln!(out, "{:3}  {}", number, watch.describe(names))?;
+        }
+      }
+      Command::Disasm(addr, count) => {
+        write_instructions(vm, pc, addr.unwrap_or(pc), count, names, routine, out)?
+      }
+      Command::Help => writeln!(out, "{}", HELP)?,
+      Command::Quit => return Err(Error::Quitting),
+    }
+    Ok(None)
+  }
+}
+
+// There should always be at least the main routine's frame.
+fn current_frame(frames: &[Frame]) -> Result<&Frame> {
+  frames.first().ok_or(Error::NoCallStack)
+}
+
+fn current_routine(frames: &[Frame]) -> usize {
+  frames.first().map_or(0, |frame| frame.routine)
+}
+
+/// Write the routines on the call stack, innermost first, with where each one
+/// has got to. `pc` is the instruction the innermost one is running.
+pub fn write_backtrace<W>(pc: usize, frames: &[Frame], names: &DebugInfo, out: &mut W) -> Result<()>
+  where W: Write {
+  let mut pc = pc;
+  for (i, frame) in frames.iter().enumerate() {
+    let routine = if frame.routine == 0 {
+      "the main routine".to_string()
+    } else {
+      routine_text(frame.routine, names)
+    };
+    let mut line = format!("  #{:<3}{:5x} in {}", i, pc, routine);
+    if !frame.locals.is_empty() {
+      let locals = frame.locals
+        .iter()
+        .enumerate()
+        .map(|(idx, local)| match names.local_name(frame.routine, idx as u8) {
+          Some(name) => format!("{}={:04x}", name, local),
+          None => format!("{:04x}", local),
+        })
+        .collect::<Vec<_>>();
+      line.push_str(&format!(" ({})", locals.join(", ")));
+    }
+    if !frame.eval_stack.is_empty() {
+      line.push_str(&format!(", {} on the stack", frame.eval_stack.len()));
+    }
+    writeln!(out, "{}", line)?;
+    // The routine that called this one is where it returns to.
+    pc = frame.return_pc;
+  }
+  Ok(())
+}
+
+// The input so far, one turn to a line, with the instruction that read it.
+fn write_turns<W>(timeline: &Timeline, out: &mut W) -> Result<()>
+  where W: Write {
+  let turns = timeline.turns();
+  if turns.is_empty() {
+    writeln!(out, "Nothing has been typed yet")?;
+  } else {
+    writeln!(out, " turn  instruction     pc  input")?;
+  }
+  for (number, event) in turns.iter().enumerate() {
+    let input = match event.input {
+      Input::Line(ref text, _) => format!("\"{}\"", text),
+      Input::Key(key) => format!("key {}", key),
+      Input::Seed(seed) => format!("seed {}", seed),
+    };
+    writeln!(out,
+             "{:5}  {:11}  {:5x}  {}",
+             number + 1,
+             event.instruction,
+             event.pc,
+             input)?;
+  }
+  writeln!(out,
+           "Now at instruction {}, in turn {}",
+           timeline.now(),
+           timeline.turn())?;
+  Ok(())
+}
+
+// A routine by its name, with its address, or just by its address.
+fn routine_text(addr: usize, names: &DebugInfo) -> String {
+  match names.routine_name(addr) {
+    Some(name) => format!("{} ({:04x})", name, addr),
+    None => format!("routine {:04x}", addr),
+  }
+}
+
+fn write_variable<W>(variable: VariableRef,
+                     value: u16,
+                     names: &DebugInfo,
+                     routine: usize,
+                     out: &mut W)
+                     -> Result<()>
+  where W: Write {
+  writeln!(out,
+           "{} = {:04x} ({})",
+           disasm::variable_text(variable, names, routine),
+           value,
+           value as i16)?;
+  Ok(())
+}
+
+// The instruction at the PC is marked with an arrow.
+fn write_instructions<T, W>(vm: &mut T,
+                            pc: usize,
+                            addr: usize,
+                            count: usize,
+                            names: &DebugInfo,
+                            routine: usize,
+                            out: &mut W)
+                            -> Result<()>
+  where T: VM,
+        W: Write {
+  let labels = BTreeMap::new();
+  let mut addr = addr;
+  for _ in 0..count {
+    let marker = if addr == pc { "=>" } else { "  " };
+    match decoder::decode(vm, addr) {
+      Ok(instruction) => {
+        writeln!(out,
+                 "{} {:5x}:  {}",
+                 marker,
+                 addr,
+                 disasm::instruction_text(vm, &instruction, &labels, names, routine))?;
+        addr = instruction.next_addr();
+      }
+      Err(err) => {
+        writeln!(out, "{} {:5x}:  ; {}", marker, addr, err)?;
+        break;
+      }
+    }
+  }
+  Ok(())
+}
+
+// `routine` is the current routine, for the names of its locals.
+fn parse_command(line: &str,
+                 names: &DebugInfo,
+                 routine: usize)
+                 -> ::std::result::Result<Command, String> {
+  let mut words = line.split_whitespace();
+  let name = words.next().unwrap_or("");
+  let args = words.collect::<Vec<_>>();
+  match name {
+    "b" | "break" => {
+      if args.len() == 2 && args[0] == "routine" {
+        parse_routine(args[1], names).map(Command::BreakRoutine)
+      } else if let Some(addr) = args.first().and_then(|word| names.routine_named(word)) {
+        no_args(&args[1..], Command::BreakRoutine(addr))
+      } else {
+        one_arg(&args, "break ADDR").and_then(disasm::parse_addr).map(Command::Break)
+      }
+    }
+    "d" | "delete" => {
+      one_arg(&args, "delete ADDR").and_then(disasm::parse_addr).map(Command::Delete)
+    }
+    "breakpoints" => no_args(&args, Command::Breakpoints),
+    "s" | "step" => {
+      match args.len() {
+        0 => Ok(Command::Step(1)),
+        1 => parse_number(args[0]).map(|count| Command::Step(count.max(1))),
+        _ => Err("Usage: step [N]".to_string()),
+      }
+    }
+    "n" | "next" => no_args(&args, Command::Next),
+    "c" | "continue" => no_args(&args, Command::Continue),
+    "rs" | "rstep" | "reverse-step" => {
+      match args.len() {
+        0 => Ok(Command::ReverseStep(1)),
+        1 => parse_number(args[0]).map(|count| Command::ReverseStep(count.max(1))),
+        _ => Err("Usage: rstep [N]".to_string()),
+      }
+    }
+    "turns" => no_args(&args, Command::Turns),
+    "turn" => one_arg(&args, "turn N").and_then(parse_number).map(Command::Turn),
+    "p" | "print" => {
+      one_arg(&args, "print VAR")
+        .and_then(|word| parse_variable(word, names, routine))
+        .map(Command::Print)
+    }
+    "locals" => no_args(&args, Command::Locals),
+    "globals" => no_args(&args, Command::Globals),
+    "stack" => no_args(&args, Command::Stack),
+    "bt" | "backtrace" => no_args(&args, Command::Backtrace),
+    "o" | "object" => {
+      one_arg(&args, "object N")
+        .and_then(|word| match names.object_named(word) {
+          Some(number) => Ok(number as usize),
+          None => parse_number(word),
+        })
+        .and_then(|number| if number <= 0xffff {
+          Ok(Command::Object(number as u16))
+        } else {
+          Err(format!("There is no object {}", number))
+        })
+    }
+    "w" | "watch" => {
+      one_arg(&args, "watch WATCH").and_then(|word| Watch::parse(word, names)).map(Command::Watch)
+    }
+    "unwatch" => one_arg(&args, "unwatch N").and_then(parse_number).map(Command::Unwatch),
+    "watches" => no_args(&args, Command::Watches),
+    "x" | "disasm" => {
+      match args.len() {
+        0 => Ok(Command::Disasm(None, DEFAULT_DISASM_COUNT)),
+        1 => {
+          disasm::parse_addr(args[0]).map(|addr| Command::Disasm(Some(addr), DEFAULT_DISASM_COUNT))
+        }
+        2 => {
+          let addr = disasm::parse_addr(args[0])?;
+          parse_number(args[1]).map(|count| Command::Disasm(Some(addr), count))
+        }
+        _ => Err("Usage: disasm [ADDR] [N]".to_string()),
+      }
+    }
+    "h" | "help" | "?" => Ok(Command::Help),
+    "q" | "quit" => no_args(&args, Command::Quit),
+    _ => Err(format!("Unknown command \"{}\". Try \"help\".", name)),
+  }
+}
+
+fn no_args(args: &[&str], command: Command) -> ::std::result::Result<Command, String> {
+  if args.is_empty() {
+    Ok(command)
+  } else {
+    Err(format!("\"{}\" doesn't take anything after it", args[0]))
+  }
+}
+
+fn one_arg<'a>(args: &[&'a str], usage: &str) -> ::std::result::Result<&'a str, String> {
+  if args.len() == 1 {
+    Ok(args[0])
+  } else {
+    Err(format!("Usage: {}", usage))
+  }
+}
+
+// In hex, like the listings, with or without a 0x.
+fn parse_routine(word: &str, names: &DebugInfo) -> ::std::result::Result<usize, String> {
+  names.routine_named(word).map(Ok).unwrap_or_else(|| disasm::parse_addr(word))
+}
+
+fn parse_number(word: &str) -> ::std::result::Result<usize, String> {
+  word.parse::<usize>().map_err(|_| format!("\"{}\" is not a number", word))
+}
+
+// Variables are named as in the listings: sp, L00-L0e and G00-Gef, or by
+// their names. Locals hide globals with the same name, as they do in Inform.
+fn parse_variable(word: &str,
+                  names: &DebugInfo,
+                  routine: usize)
+                  -> ::std::result::Result<VariableRef, String> {
+  let lower = word.to_lowercase();
+  if lower == "sp" {
+    return Ok(VariableRef::Stack);
+  }
+  // The first letter may not be one byte long.
+  let mut chars = lower.chars();
+  if chars.next() == Some('l') {
+    match u8::from_str_radix(chars.as_str(), 16) {
+      Ok(idx) if idx < 15 => return Ok(VariableRef::Local(idx)),
+      _ => {}
+    }
+  }
+  (0..15u8)
+    .find(|idx| {
+      names.local_name(routine, *idx).is_some_and(|name| name.eq_ignore_ascii_case(word))
+    })
+    .map(VariableRef::Local)
+    .or_else(|| disasm::parse_global(word, names).ok().map(VariableRef::Global))
+    .ok_or(format!("\"{}\" is not a variable (sp, L00-L0e or G00-Gef)", word))
+}
+
+#[cfg(test)]
+mod test {
+  use super::{Command, Context, Debugger, Resume, parse_command, write_backtrace};
+  use result::Error;
+  use zmachine::debug_info::DebugInfo;
+  use zmachine::ops::testvm::TestVM;
+  use zmachine::vm::{Frame, Input, Timeline, VariableRef};
+  use zmachine::watch::{Watch, Watchpoints};
+
+  fn parse(line: &str) -> Result<Command, String> {
+    parse_command(line, &DebugInfo::new(), 0)
+  }
+
+  #[test]
+  fn test_parse_command() {
+    assert_eq!(Ok(Command::Break(0x4f05)), parse("break 4f05\n"));
+    assert_eq!(Ok(Command::Break(0x4f05)), parse("b 0x4f05"));
+    assert_eq!(Ok(Command::BreakRoutine(0x120)), parse("break routine 120"));
+    assert_eq!(Ok(Command::Step(1)), parse("s"));
+    assert_eq!(Ok(Command::Step(5)), parse("step 5"));
+    assert_eq!(Ok(Command::Print(VariableRef::Stack)), parse("p sp"));
+    assert_eq!(Ok(Command::Print(VariableRef::Local(14))), parse("print L0e"));
+    assert_eq!(Ok(Command::Print(VariableRef::Global(0x10))), parse("print g10"));
+    assert_eq!(Ok(Command::Object(12)), parse("object 12"));
+    assert_eq!(Ok(Command::Disasm(None, 10)), parse("x"));
+    assert_eq!(Ok(Command::Disasm(Some(0x100), 3)), parse("disasm 100 3"));
+    assert_eq!(Ok(Command::Watch(Watch::Global(0x10))), parse("watch G10"));
+    assert_eq!(Ok(Command::Unwatch(2)), parse("unwatch 2"));
+    assert_eq!(Ok(Command::ReverseStep(1)), parse("rs"));
+    assert_eq!(Ok(Command::ReverseStep(20)), parse("reverse-step 20"));
+    assert_eq!(Ok(Command::Turns), parse("turns"));
+    assert_eq!(Ok(Command::Turn(3)), parse("turn 3"));
+
+    assert!(parse("print L0f").is_err());
+    assert!(parse("print Gf0").is_err());
+    // Words that start with more than one byte aren't split in the middle.
+    assert!(parse("print é12").is_err());
+    assert!(parse("x é12").is_err());
+    assert!(parse("break").is_err());
+    assert!(parse("continue now").is_err());
+    assert!(parse("frobnicate").is_err());
+  }
+
+  #[test]
+  fn test_should_stop() {
+    let mut debugger = Debugger::new();
+    // It stops before the first instruction.
+    assert!(debugger.should_stop(0x100, 1));
+
+    debugger.resume = Resume::Step(2);
+    assert!(!debugger.should_stop(0x101, 1));
+    assert!(debugger.should_stop(0x102, 1));
+
+    // Stepping over a call doesn't stop inside it.
+    debugger.resume = Resume::Over(1);
+    assert!(!debugger.should_stop(0x200, 2));
+    assert!(!debugger.should_stop(0x203, 3));
+    assert!(debugger.should_stop(0x105, 1));
+
+    // Breakpoints stop it whatever it is doing.
+    debugger.resume = Resume::Continue;
+    debugger.breakpoints.insert(0x203);
+    assert!(!debugger.should_stop(0x200, 2));
+    assert!(debugger.should_stop(0x203, 3));
+  }
+
+  fn prompt(vm: &mut TestVM, debugger: &mut Debugger, frames: &[Frame], input: &str) -> String {
+    prompt_at(vm, debugger, frames, &Timeline::new(10), input)
+  }
+
+  fn prompt_at(vm: &mut TestVM,
+               debugger: &mut Debugger,
+               frames: &[Frame],
+               timeline: &Timeline,
+               input: &str)
+               -> String {
+    let mut out = Vec::new();
+    let mut watches = Watchpoints::new();
+    let names = DebugInfo::new();
+    let mut context = Context {
+      vm,
+      pc: 0x100,
+      frames,
+      watches: &mut watches,
+      timeline,
+      names: &names,
+    };
+    let _ = debugger.prompt(&mut context, &mut input.as_bytes(), &mut out);
+    String::from_utf8(out).unwrap()
+  }
+
+  #[test]
+  fn test_prompt() {
+    let mut vm = TestVM::new();
+    // rtrue
+    vm.heap[0x100] = 0xb0;
+    vm.locals[1] = 0xfffe;
+    vm.globals[3] = 0x1234;
+    vm.stack.push(7);
+    let frames = [Frame {
+                    return_pc: 0x4e21,
+                    routine: 0x4e00,
+                    locals: vec![0, 0xfffe],
+                    eval_stack: vec![7],
+                    result_location: VariableRef::Stack,
+                  }];
+    let mut debugger = Debugger::new();
+
+    let out = prompt(&mut vm,
+                     &mut debugger,
+                     &frames,
+                     "print L01\nprint G03\np sp\np L02\nstack\nb 104\nnext\n");
+    assert_eq!("=>   100:  RTRUE\n\
+                (rzm) L01 = fffe (-2)\n\
+                (rzm) G03 = 1234 (4660)\n\
+                (rzm) sp = 0007 (7)\n\
+                (rzm) This routine has no L02\n\
+                (rzm) 0007\n\
+                (rzm) Breakpoint at 104\n\
+                (rzm) ",
+               out);
+    // Looking at the stack left it alone.
+    assert_eq!(vec![7], vm.stack);
+    assert_eq!(Resume::Over(1), debugger.resume);
+
+    // An empty line does the last next, step or continue again.
+    debugger.resume = Resume::Continue;
+    prompt(&mut vm, &mut debugger, &frames, "\n");
+    assert_eq!(Resume::Over(1), debugger.resume);
+
+    // Reports are shown once, the next time it stops.
+    debugger.resume = Resume::Continue;
+    debugger.report("Watchpoint 1 (G03) changed at 100\n".to_string());
+    assert!(debugger.should_stop(0x100, 1));
+    let out = prompt(&mut vm, &mut debugger, &frames, "watch G03\nwatches\ncontinue\n");
+    assert_eq!("Watchpoint 1 (G03) changed at 100\n\
+                =>   100:  RTRUE\n\
+                (rzm) Watchpoint 1: G03\n\
+                (rzm)   1  G03\n\
+                (rzm) ",
+               out);
+    assert!(debugger.reports.is_empty());
+  }
+
+  #[test]
+  fn test_prompt_no_frames() {
+    let mut vm = TestVM::new();
+    vm.heap[0x100] = 0xb0;
+    let mut debugger = Debugger::new();
+
+    let out = prompt(&mut vm, &mut debugger, &[], "locals\nstack\ncontinue\n");
+    assert_eq!("=>   100:  RTRUE\n\
+                (rzm) There are no frames on the call stack\n\
+                (rzm) There are no frames on the call stack\n\
+                (rzm) ",
+               out);
+    assert_eq!(Resume::Continue, debugger.resume);
+  }
+
+  #[test]
+  fn test_prompt_quit() {
+    let mut vm = TestVM::new();
+    let frames = [Frame {
+                    return_pc: 0,
+                    routine: 0,
+                    locals: vec![],
+                    eval_stack: vec![],
+                    result_location: VariableRef::Stack,
+                  }];
+    let mut debugger = Debugger::new();
+    let mut out = Vec::new();
+    let mut watches = Watchpoints::new();
+    let mut input = "quit\n".as_bytes();
+    let names = DebugInfo::new();
+    let timeline = Timeline::new(10);
+    let mut context = Context {
+      vm: &mut vm,
+      pc: 0x100,
+      frames: &frames,
+      watches: &mut watches,
+      timeline: &timeline,
+      names: &names,
+    };
+    match debugger.prompt(&mut context, &mut input, &mut out) {
+      Err(Error::Quitting) => {}
+      _ => panic!("Expected to quit"),
+    }
+    // So does running out of input.
+    let mut input = "".as_bytes();
+    match debugger.prompt(&mut context, &mut input, &mut out) {
+      Err(Error::Quitting) => {}
+      _ => panic!("Expected to quit"),
+    }
+  }
+
+  #[test]
+  fn test_prompt_rewind() {
+    let mut vm = TestVM::new();
+    vm.heap[0x100] = 0xb0;
+    let frames = [Frame {
+                    return_pc: 0,
+                    routine: 0,
+                    locals: vec![],
+                    eval_stack: vec![],
+                    result_location: VariableRef::Stack,
+                  }];
+    let mut debugger = Debugger::new();
+    let mut timeline = Timeline::new(10);
+
+    let out = prompt_at(&mut vm, &mut debugger, &frames, &timeline, "rstep\nturns\nturn 1\n");
+    assert_eq!("=>   100:  RTRUE\n\
+                (rzm) This is the start of the game\n\
+                (rzm) Nothing has been typed yet\n\
+                Now at instruction 0, in turn 1\n\
+                (rzm) There is no turn 1 yet\n\
+                (rzm) \n",
+               out);
+    assert_eq!(None, debugger.take_rewind());
+
+    for _ in 0..5 {
+      timeline.ran();
+    }
+    timeline.record(0x4f05, Input::Line("look".to_string(), 13));
+    for _ in 0..5 {
+      timeline.ran();
+    }
+    let out = prompt_at(&mut vm, &mut debugger, &frames, &timeline, "turns\nrs 3\n");
+    assert_eq!("=>   100:  RTRUE\n\
+                (rzm)  turn  instruction     pc  input\n    \
+                1            5   4f05  \"look\"\n\
+                Now at instruction 10, in turn 2\n\
+                (rzm) ",
+               out);
+    assert_eq!(Some(7), debugger.take_rewind());
+    // It stops again once it gets there.
+    assert!(debugger.should_stop(0x100, 1));
+
+    prompt_at(&mut vm, &mut debugger, &frames, &timeline, "turn 0\n");
+    assert_eq!(Some(0), debugger.take_rewind());
+  }
+
+  #[test]
+  fn test_backtrace() {
+    let frames = [Frame {
+                    return_pc: 0x4e21,
+                    routine: 0x4ef0,
+                    locals: vec![1, 0],
+                    eval_stack: vec![7, 8],
+                    result_location: VariableRef::Stack,
+                  },
+                  Frame {
+                    return_pc: 0,
+                    routine: 0,
+                    locals: vec![],
+                    eval_stack: vec![],
+                    result_location: VariableRef::Stack,
+                  }];
+    let mut out = Vec::new();
+    write_backtrace(0x4f05, &frames, &DebugInfo::new(), &mut out).unwrap();
+    assert_eq!("  #0   4f05 in routine 4ef0 (0001, 0000), 2 on the stack\n\
+                \x20 #1   4e21 in the main routine\n",
+               String::from_utf8(out).unwrap());
+
+    let mut out = Vec::new();
+    write_backtrace(0x4f05, &frames, &names(), &mut out).unwrap();
+    assert_eq!("  #0   4f05 in Count (4ef0) (n=0001, 0000), 2 on the stack\n\
+                \x20 #1   4e21 in the main routine\n",
+               String::from_utf8(out).unwrap());
+  }
+
+  fn names() -> DebugInfo {
+    let xml = "<inform-story-file>\
+               <global-variable><identifier>location</identifier>\
+               <address>32</address></global-variable>\
+               <global-variable><identifier>n</identifier>\
+               <address>34</address></global-variable>\
+               <object><identifier>lamp</identifier><value>12</value></object>\
+               <routine><identifier>Count</identifier><address>20208</address>\
+               <local-variable><identifier>n</identifier><index>1</index></local-variable>\
+               </routine></inform-story-file>";
+    DebugInfo::from_bytes(xml.as_bytes(), 0).unwrap()
+  }
+
+  #[test]
+  fn test_parse_names() {
+    let names = names();
+    let parse = |line| parse_command(line, &names, 0x4ef0);
+    assert_eq!(Ok(Command::BreakRoutine(0x4ef0)), parse("break count"));
+    assert_eq!(Ok(Command::BreakRoutine(0x4ef0)), parse("break routine Count"));
+    assert_eq!(Ok(Command::Break(0xadd)), parse("break add"));
+    // The local n hides the global n.
+    assert_eq!(Ok(Command::Print(VariableRef::Local(0))), parse("print n"));
+    assert_eq!(Ok(Command::Print(VariableRef::Global(0x10))), parse("print location"));
+    assert_eq!(Ok(Command::Object(12)), parse("object lamp"));
+    assert_eq!(Ok(Command::Watch(Watch::Parent(12))), parse("watch parent:lamp"));
+    assert!(parse("print torch").is_err());
+
+    // Outside Count, n is the global.
+    assert_eq!(Ok(Command::Print(VariableRef::Global(0x11))),
+               parse_command("print n", &names, 0));
+  }
+}
@@ -0,0 +1,472 @@
+use result::{Error, Result};
+use std::cmp;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Write;
+use zmachine::debug_info::DebugInfo;
+use zmachine::ops::{Operand, decode_at};
+use zmachine::ops::decoder::{self, BranchTarget, Instruction};
+use zmachine::vm::{RawPtr, VM, VariableRef};
+
+// A txd-style disassembler. Starting from the main routine, it decodes each
+// routine up to the last instruction that can be reached in it, and follows
+// calls to constant addresses to find more routines. Routines that are only
+// called indirectly (through a variable or a property) are not found.
+//
+// Names from a debugging information file are used where there are any: for
+// routines, variables, and constants that an object instruction uses as an
+// object, attribute or property.
+
+/// A routine's code, as far as it can be followed.
+pub struct Routine {
+  pub addr: usize,
+  pub main: bool,
+  pub locals: Vec<u16>,
+  pub instructions: Vec<Instruction>,
+  labels: BTreeSet<usize>,
+  // Why decoding stopped early, if it did.
+  error: Option<Error>,
+}
+
+impl Routine {
+  fn new(addr: usize, main: bool) -> Routine {
+    Routine {
+      addr,
+      main,
+      locals: Vec::new(),
+      instructions: Vec::new(),
+      labels: BTreeSet::new(),
+      error: None,
+    }
+  }
+
+  fn called_routines(&self, version: u8) -> Vec<usize> {
+    self.instructions
+      .iter()
+      .filter(|instruction| instruction.name.starts_with("call"))
+      .filter_map(|instruction| match instruction.operands.first() {
+        Some(&Operand::LargeConstant(packed)) if packed != 0 => {
+          Some(unpack(version, packed))
+        }
+        _ => None,
+      })
+      .collect()
+  }
+}
+
+/// Write a listing of every routine that can be found from `start_pc`.
+pub fn disassemble<T, W>(vm: &mut T,
+                         start_pc: usize,
+                         names: &DebugInfo,
+                         out: &mut W)
+                         -> Result<()>
+  where T: VM,
+        W: Write {
+  for routine in find_routines(vm, start_pc, &[]).values() {
+    write_routine(vm, routine, names, out)?;
+  }
+  Ok(())
+}
+
+/// Every routine that can be found from `start_pc` and from the routines at
+/// `others` (which might only be called indirectly), by address. The main
+/// routine's address is `start_pc`.
+pub fn find_routines<T>(vm: &mut T, start_pc: usize, others: &[usize]) -> BTreeMap<usize, Routine>
+  where T: VM {
+  let version = vm.version();
+  let mut routines = BTreeMap::new();
+  let mut pending = others.iter().map(|addr| (*addr, false)).collect::<Vec<_>>();
+  pending.push((start_pc, true));
+  while let Some((addr, main)) = pending.pop() {
+    if routines.contains_key(&addr) {
+      continue;
+    }
+    let routine = walk_routine(vm, addr, main);
+    pending.extend(routine.called_routines(version).into_iter().map(|addr| (addr, false)));
+    routines.insert(addr, routine);
+  }
+  routines
+}
+
+// In v1-3, packed addresses are in words. In v4 and v5, they are in 4 bytes.
+fn unpack(version: u8, packed: u16) -> usize {
+  if version <= 3 {
+    packed as usize * 2
+  } else {
+    packed as usize * 4
+  }
+}
+
+// The main routine has no header. The PC starts at its first instruction.
+fn walk_routine<T>(vm: &mut T, addr: usize, main: bool) -> Routine
+  where T: VM {
+  let mut routine = Routine::new(addr, main);
+  let mut pc = addr;
+  if !main {
+    match read_locals(vm, addr) {
+      Ok(locals) => {
+        pc = first_instruction(vm.version(), addr, locals.len());
+        routine.locals = locals;
+      }
+      Err(err) => {
+        routine.error = Some(err);
+        return routine;
+      }
+    }
+  }
+
+  // Keep going until an instruction that doesn't carry on to the next one,
+  // unless something further on branches back in.
+  let mut furthest = pc;
+  loop {
+    let instruction = match decoder::decode(vm, pc) {
+      Ok(instruction) => instruction,
+      Err(err) => {
+        routine.error = Some(err);
+        break;
+      }
+    };
+    let targets = match instruction.branch_target() {
+      Some(BranchTarget::Address(target)) => Some(target),
+      _ => instruction.jump_target(),
+    };
+    if let Some(target) = targets {
+      routine.labels.insert(target);
+      furthest = cmp::max(furthest, target);
+    }
+    pc = instruction.next_addr();
+    let done = instruction.ends_flow() && pc > furthest;
+    routine.instructions.push(instruction);
+    if done {
+      break;
+    }
+  }
+  routine
+}
+
+/// The address of a routine's first instruction, just after its header.
+pub fn routine_start<T>(vm: &T, addr: usize) -> Result<usize>
+  where T: VM {
+  let locals = read_locals(vm, addr)?;
+  Ok(first_instruction(vm.version(), addr, locals.len()))
+}
+
+fn first_instruction(version: u8, addr: usize, num_locals: usize) -> usize {
+  addr + 1 + if version <= 4 { num_locals * 2 } else { 0 }
+}
+
+// The routine header: the number of locals and, before v5, their initial
+// values.
+fn read_locals<T>(vm: &T, addr: usize) -> Result<Vec<u16>>
+  where T: VM {
+  let count = vm.read_memory_u8(RawPtr::new(addr))?;
+  if count > 15 {
+    return Err(Error::NotARoutine(addr));
+  }
+  if vm.version() >= 5 {
+    return Ok(vec![0; count as usize]);
+  }
+  (0..count as usize).map(|i| vm.read_memory(RawPtr::new(addr + 1 + 2 * i))).collect()
+}
+
+fn write_routine<T, W>(vm: &mut T,
+                       routine: &Routine,
+                       names: &DebugInfo,
+                       out: &mut W)
+                       -> Result<()>
+  where T: VM,
+        W: Write {
+  let locals = routine.locals
+    .iter()
+    .enumerate()
+    .map(|(idx, l)| match names.local_name(routine.addr, idx as u8) {
+      Some(name) => format!("{}={:04x}", name, l),
+      None => format!("{:04x}", l),
+    })
+    .collect::<Vec<_>>();
+  let name = names.routine_name(routine.addr).map(|name| format!(" {}", name));
+  writeln!(out,
+           "{} {:04x}{}, {} locals ({})\n",
+           if routine.main { "Main routine" } else { "Routine" },
+           routine.addr,
+           name.unwrap_or_default(),
+           locals.len(),
+           locals.join(", "))?;
+
+  let labels = routine.labels
+    .iter()
+    .enumerate()
+    .map(|(i, addr)| (*addr, format!("L{:04}", i + 1)))
+    .collect::<BTreeMap<_, _>>();
+  for instruction in &routine.instructions {
+    let label = labels.get(&instruction.addr).map(|l| format!("{}:", l)).unwrap_or(String::new());
+    let text = instruction_text(vm, instruction, &labels, names, routine.addr);
+    writeln!(out, "{:7}{:5x}:  {}", label, instruction.addr, text)?;
+  }
+  if let Some(ref err) = routine.error {
+    writeln!(out, "       ; {}", err)?;
+  }
+  writeln!(out)?;
+  Ok(())
+}
+
+/// How an instruction is shown in a listing. Branch and jump targets that have
+/// a label are shown by it, and by their address otherwise. `routine` is the
+/// address of the routine the instruction is in, for the names of its locals.
+pub fn instruction_text<T>(vm: &mut T,
+                           instruction: &Instruction,
+                           labels: &BTreeMap<usize, String>,
+                           names: &DebugInfo,
+                           routine: usize)
+                           -> String
+  where T: VM {
+  let version = vm.version();
+  let kinds = operand_kinds(instruction.name);
+  let operands = instruction.operands
+    .iter()
+    .enumerate()
+    .map(|(idx, operand)| if idx == 0 && instruction.variable {
+      variable_operand_text(operand, names, routine)
+    } else {
+      operand_text(operand, kinds.get(idx).cloned(), names, routine)
+    })
+    .collect::<Vec<_>>();
+  let mut parts = Vec::new();
+
+  match (instruction.name, instruction.operands.first()) {
+    (name, Some(&Operand::LargeConstant(packed))) if name.starts_with("call") => {
+      let addr = unpack(version, packed);
+      let target = names.routine_name(addr)
+        .map(|name| name.to_string())
+        .unwrap_or(format!("{:04x}", addr));
+      parts.push(format!("{} ({})", target, operands[1..].join(",")));
+    }
+    ("jump", _) => {
+      if let Some(target) = instruction.jump_target() {
+        parts.push(address_text(target, labels));
+      }
+    }
+    ("print_paddr", Some(&Operand::LargeConstant(packed))) => {
+      parts.push(operands.join(","));
+      if let Ok(string) = decode_at(vm, RawPtr::new(unpack(version, packed))) {
+        parts.push(format!("\"{}\"", quote(&string)));
+      }
+    }
+    _ if !operands.is_empty() => parts.push(operands.join(",")),
+    _ => {}
+  }
+
+  if let Some(variable) = instruction.store {
+    parts.push(format!("-> {}", variable_text(variable, names, routine)));
+  }
+  if let (Some(branch), Some(target)) = (instruction.branch, instruction.branch_target()) {
+    let target = match target {
+      BranchTarget::ReturnFalse => "RFALSE".to_string(),
+      BranchTarget::ReturnTrue => "RTRUE".to_string(),
+      BranchTarget::Address(addr) => address_text(addr, labels),
+    };
+    parts.push(format!("[{}] {}", if branch.on_true { "TRUE" } else { "FALSE" }, target));
+  }
+  if let Some(ref string) = instruction.text {
+    parts.push(format!("\"{}\"", quote(string)));
+  }
+
+  let name = instruction.name.to_uppercase();
+  if parts.is_empty() {
+    name
+  } else {
+    format!("{:15} {}", name, parts.join(" "))
+  }
+}
+
+fn address_text(addr: usize, labels: &BTreeMap<usize, String>) -> String {
+  labels.get(&addr).cloned().unwrap_or(format!("{:04x}", addr))
+}
+
+/// Read an address as the listings show them: in hex, with or without 0x.
+pub fn parse_addr(word: &str) -> ::std::result::Result<usize, String> {
+  usize::from_str_radix(word.trim_start_matches("0x"), 16)
+    .map_err(|_| format!("\"{}\" is not an address", word))
+}
+
+/// Read a global variable as the listings show them, G00 to Gef, or by its
+/// name in `names`.
+pub fn parse_global(word: &str, names: &DebugInfo) -> ::std::result::Result<u8, String> {
+  // The first letter may not be one byte long.
+  let mut chars = word.chars();
+  let kind = chars.next();
+  match (kind, u8::from_str_radix(chars.as_str(), 16)) {
+    (Some('G'), Ok(idx)) |
+    (Some('g'), Ok(idx)) if idx < 240 => Ok(idx),
+    _ => names.global_named(word).ok_or(format!("\"{}\" is not a global (G00-Gef)", word)),
+  }
+}
+
+// What an object instruction's constant operands are, so that they can be
+// shown by name.
+#[derive(Clone,Copy)]
+enum Kind {
+  Object,
+  Attribute,
+  Property,
+}
+
+fn operand_kinds(name: &str) -> &'static [Kind] {
+  match name {
+    "jin" | "insert_obj" => &[Kind::Object, Kind::Object],
+    "test_attr" | "set_attr" | "clear_attr" => &[Kind::Object, Kind::Attribute],
+    "get_prop" | "get_prop_addr" | "get_next_prop" | "put_prop" => {
+      &[Kind::Object, Kind::Property]
+    }
+    "remove_obj" | "get_parent" | "get_child" | "get_sibling" | "print_obj" => &[Kind::Object],
+    _ => &[],
+  }
+}
+
+fn operand_text(operand: &Operand,
+                kind: Option<Kind>,
+                names: &DebugInfo,
+                routine: usize)
+                -> String {
+  let val = match *operand {
+    Operand::LargeConstant(val) => val,
+    Operand::SmallConstant(val) => val as u16,
+    Operand::Variable(variable) => return variable_text(variable, names, routine),
+    Operand::Omitted => return String::new(),
+  };
+  let name = match kind {
+    Some(Kind::Object) => names.object_name(val),
+    Some(Kind::Attribute) => names.attribute_name(val),
+    Some(Kind::Property) => names.property_name(val),
+    None => None,
+  };
+  match (name, operand) {
+    (Some(name), _) => name.to_string(),
+    (None, &Operand::LargeConstant(_)) => format!("#{:04x}", val),
+    (None, _) => format!("#{:02x}", val),
+  }
+}
+
+// The operand of an instruction like inc or store that is the number of a
+// variable. One that is read from a variable is shown in brackets, as txd
+// does.
+fn variable_operand_text(operand: &Operand, names: &DebugInfo, routine: usize) -> String {
+  match *operand {
+    Operand::SmallConstant(val) => variable_text(VariableRef::decode(val), names, routine),
+    Operand::LargeConstant(val) if val <= 0xff => {
+      variable_text(VariableRef::decode(val as u8), names, routine)
+    }
+    Operand::Variable(variable) => format!("[{}]", variable_text(variable, names, routine)),
+    _ => operand_text(operand, None, names, routine),
+  }
+}
+
+/// How a variable is shown: by name if it has one, and otherwise as sp, Lxx
+/// or Gxx. `routine` is the address of the routine it is used in.
+pub fn variable_text(variable: VariableRef, names: &DebugInfo, routine: usize) -> String {
+  let name = match variable {
+    VariableRef::Stack => None,
+    VariableRef::Local(idx) => names.local_name(routine, idx),
+    VariableRef::Global(idx) => names.global_name(idx),
+  };
+  match (name, variable) {
+    (Some(name), _) => name.to_string(),
+    (None, VariableRef::Stack) => "sp".to_string(),
+    (None, VariableRef::Local(idx)) => format!("L{:02x}", idx),
+    (None, VariableRef::Global(idx)) => format!("G{:02x}", idx),
+  }
+}
+
+// Newlines are shown as ^, as Inform writes them.
+fn quote(string: &str) -> String {
+  string.replace('\n', "^")
+}
+
+#[cfg(test)]
+mod test {
+  use std::collections::BTreeMap;
+  use zmachine::debug_info::DebugInfo;
+  use zmachine::ops::decoder::decode;
+  use zmachine::ops::testvm::TestVM;
+  use super::{disassemble, instruction_text, parse_addr, parse_global};
+
+  #[test]
+  fn test_disassemble() {
+    let mut vm = TestVM::with_story();
+
+    let mut out = Vec::new();
+    disassemble(&mut vm, 0x100, &DebugInfo::new(), &mut out).unwrap();
+    assert_eq!("Main routine 0100, 0 locals ()\n\n\
+                \x20        100:  CALL            0120 () -> sp\n\
+                \x20        105:  QUIT\n\n\
+                Routine 0120, 1 locals (0005)\n\n\
+                L0001:   123:  DEC_CHK         L00,#00 [FALSE] L0001\n\
+                \x20        128:  RTRUE\n\n",
+               String::from_utf8(out).unwrap());
+  }
+
+  #[test]
+  fn test_disassemble_names() {
+    let mut vm = TestVM::with_story();
+    let xml = "<inform-story-file>\
+               <routine><identifier>Count</identifier><address>288</address>\
+               <local-variable><identifier>n</identifier><index>1</index></local-variable>\
+               </routine></inform-story-file>";
+    let names = DebugInfo::from_bytes(xml.as_bytes(), 0).unwrap();
+
+    let mut out = Vec::new();
+    disassemble(&mut vm, 0x100, &names, &mut out).unwrap();
+    assert_eq!("Main routine 0100, 0 locals ()\n\n\
+                \x20        100:  CALL            Count () -> sp\n\
+                \x20        105:  QUIT\n\n\
+                Routine 0120 Count, 1 locals (n=0005)\n\n\
+                L0001:   123:  DEC_CHK         n,#00 [FALSE] L0001\n\
+                \x20        128:  RTRUE\n\n",
+               String::from_utf8(out).unwrap());
+  }
+
+  #[test]
+  fn test_variable_operands() {
+    let xml = "<inform-story-file>\
+               <global-variable><identifier>score</identifier><address>48</address>\
+               </global-variable></inform-story-file>";
+    let names = DebugInfo::from_bytes(xml.as_bytes(), 0).unwrap();
+    let text = |code: &[u8]| {
+      let mut vm = TestVM::new();
+      vm.heap[0x200..0x200 + code.len()].copy_from_slice(code);
+      let instruction = decode(&mut vm, 0x200).unwrap();
+      instruction_text(&mut vm, &instruction, &BTreeMap::new(), &names, 0x100)
+    };
+    assert_eq!("INC             sp", text(&[0x95, 0x00]));
+    assert_eq!("STORE           G10,#05", text(&[0x0d, 0x20, 0x05]));
+    assert_eq!("STORE           score,#05", text(&[0x0d, 0x28, 0x05]));
+    assert_eq!("LOAD            [L01] -> sp", text(&[0xae, 0x02, 0x00]));
+    assert_eq!("PULL            L00", text(&[0xe9, 0x7f, 0x01]));
+    assert_eq!("DEC             L02", text(&[0x86, 0x00, 0x03]));
+    // Other instructions' constants are still values.
+    assert_eq!("PUSH            #01", text(&[0xe8, 0x7f, 0x01]));
+  }
+
+  #[test]
+  fn test_parse_addr() {
+    assert_eq!(Ok(0x4f05), parse_addr("4f05"));
+    assert_eq!(Ok(0x4f05), parse_addr("0x4f05"));
+    assert!(parse_addr("").is_err());
+    assert!(parse_addr("lamp").is_err());
+    assert!(parse_addr("é12").is_err());
+  }
+
+  #[test]
+  fn test_parse_global() {
+    let xml = "<inform-story-file>\
+               <global-variable><identifier>score</identifier><address>16</address>\
+               </global-variable></inform-story-file>";
+    let names = DebugInfo::from_bytes(xml.as_bytes(), 0).unwrap();
+    assert_eq!(Ok(0x10), parse_global("G10", &names));
+    assert_eq!(Ok(0xef), parse_global("gef", &names));
+    assert_eq!(Ok(8), parse_global("score", &names));
+    assert_eq!(Err("\"Gf0\" is not a global (G00-Gef)".to_string()),
+               parse_global("Gf0", &names));
+    assert!(parse_global("é12", &names).is_err());
+    assert!(parse_global("lamp", &names).is_err());
+  }
+}
@@ -0,0 +1,449 @@
+use result::Result;
+use std::io::Write;
+use zmachine::json::Json;
+use zmachine::ops::decode_at;
+use zmachine::vm::{BytePtr, Memory, RawPtr, VM, ZObject, ZObjectTable, ZPropertyTable,
+                   attribute_mask, num_attributes};
+use zmachine::vm::Dictionary;
+
+// What's inside a story file, like infodump shows: the header, the objects,
+// the dictionary and the abbreviations. Each section is gathered into plain
+// structs, which can then be written as text or as JSON.
+
+/// Which sections to show.
+#[derive(Clone,Copy,Debug)]
+pub struct Sections {
+  pub header: bool,
+  pub objects: bool,
+  pub dictionary: bool,
+  pub abbreviations: bool,
+}
+
+impl Sections {
+  pub fn all() -> Sections {
+    Sections {
+      header: true,
+      objects: true,
+      dictionary: true,
+      abbreviations: true,
+    }
+  }
+}
+
+pub struct Header {
+  version: u8,
+  release: u16,
+  serial: String,
+  checksum: u16,
+  flags1: u8,
+  flags2: u16,
+  high_memory: BytePtr,
+  static_memory: BytePtr,
+  initial_pc: BytePtr,
+  dictionary: BytePtr,
+  objects: BytePtr,
+  globals: BytePtr,
+  abbreviations: BytePtr,
+  file_length: u32,
+  standard: (u8, u8),
+}
+
+pub struct Object {
+  number: u16,
+  name: String,
+  parent: u16,
+  sibling: u16,
+  child: u16,
+  attributes: Vec<u8>,
+  properties: Vec<(u16, Vec<u8>)>,
+}
+
+pub struct DictionaryEntry {
+  word: String,
+  data: Vec<u8>,
+}
+
+pub fn header(memory: &Memory) -> Header {
+  Header {
+    version: memory.version(),
+    release: memory.release(),
+    serial: memory.serial(),
+    checksum: memory.checksum(),
+    flags1: memory.flag1(),
+    flags2: memory.flag2(),
+    high_memory: memory.high_memory_ptr(),
+    static_memory: memory.static_memory_ptr(),
+    initial_pc: memory.starting_pc(),
+    dictionary: memory.dictionary_table_ptr(),
+    objects: memory.property_table_ptr(),
+    globals: memory.global_base_ptr(),
+    abbreviations: memory.abbrev_table_ptr(),
+    file_length: memory.file_length(),
+    standard: memory.standard_revision(),
+  }
+}
+
+pub fn objects<T>(vm: &mut T) -> Result<Vec<Object>>
+  where T: VM {
+  let num_objects = vm.object_table()?.num_objects();
+  (1..num_objects + 1).map(|number| object(vm, number)).collect()
+}
+
+pub fn object<T>(vm: &mut T, number: u16) -> Result<Object>
+  where T: VM {
+  let object_table = vm.object_table()?;
+  let object = object_table.object_with_number(number);
+  let property_table = object.property_table()?;
+
+  // The name is preceded by its length in words, which may be 0.
+  let name_ptr = property_table.name_ptr();
+  let name_len = vm.read_memory_u8(RawPtr::new(addr(name_ptr) - 1))?;
+  let name = if name_len == 0 {
+    String::new()
+  } else {
+    decode_at(vm, name_ptr)?
+  };
+
+  let attrs = object.attributes()?;
+  let attributes = (0..num_attributes(vm.version()))
+    .filter(|&attr| attrs & attribute_mask(attr) != 0)
+    .map(|attr| attr as u8)
+    .collect();
+
+  let mut properties = Vec::new();
+  let mut property_number = property_table.next_property(0)?;
+  while property_number != 0 {
+    if let Some((size, ptr)) = property_table.find_property(property_number)? {
+      let start = addr(ptr);
+      let data = (0..size as usize)
+        .map(|i| vm.read_memory_u8(RawPtr::new(start + i)))
+        .collect::<Result<Vec<_>>>()?;
+      properties.push((property_number, data));
+    }
+    property_number = property_table.next_property(property_number)?;
+  }
+
+  Ok(Object {
+    number,
+    name,
+    parent: object.parent()?,
+    sibling: object.sibling()?,
+    child: object.child()?,
+    attributes,
+    properties,
+  })
+}
+
+pub fn dictionary<T>(vm: &mut T, dict: &Dictionary) -> Result<Vec<DictionaryEntry>>
+  where T: VM {
+  // The encoded word is 4 bytes before v4, and 6 after.
+  let word_len = if vm.version() <= 3 { 4 } else { 6 };
+  let mut entries = Vec::new();
+  for number in 1..dict.num_entries() + 1 {
+    let ptr = addr(dict.entry_ptr(number));
+    let word = decode_at(vm, RawPtr::new(ptr))?;
+    let data = (word_len..dict.entry_length() as usize)
+      .map(|i| vm.read_memory_u8(RawPtr::new(ptr + i)))
+      .collect::<Result<Vec<_>>>()?;
+    entries.push(DictionaryEntry {
+      word,
+      data,
+    });
+  }
+  Ok(entries)
+}
+
+pub fn abbreviations<T>(vm: &mut T) -> Result<Vec<String>>
+  where T: VM {
+  // v1 has no abbreviations, and v2 only has the first 32.
+  let num_tables = match vm.version() {
+    1 => 0,
+    2 => 1,
+    _ => 3,
+  };
+  let mut abbreviations = Vec::new();
+  // Tables are numbered from 1, like the Z-characters that use them.
+  for table in 1..num_tables + 1 {
+    for index in 0..32 {
+      let addr = vm.abbrev_addr(table, index)?;
+      abbreviations.push(decode_at(vm, addr)?);
+    }
+  }
+  Ok(abbreviations)
+}
+
+/// Everything that was asked for, gathered up.
+pub struct Info {
+  pub header: Option<Header>,
+  pub objects: Option<Vec<Object>>,
+  pub dictionary: Option<(Vec<char>, Vec<DictionaryEntry>)>,
+  pub abbreviations: Option<Vec<String>>,
+}
+
+impl Info {
+  pub fn write_text<W>(&self, out: &mut W) -> Result<()>
+    where W: Write {
+    if let Some(ref header) = self.header {
+      write_header(header, out)?;
+    }
+    if let Some(ref objects) = self.objects {
+      write_objects(objects, out)?;
+    }
+    if let Some((ref separators, ref entries)) = self.dictionary {
+      write_dictionary(separators, entries, out)?;
+    }
+    if let Some(ref abbreviations) = self.abbreviations {
+      write_abbreviations(abbreviations, out)?;
+    }
+    Ok(())
+  }
+
+  pub fn to_json(&self) -> Json {
+    let mut json = Json::object();
+    if let Some(ref header) = self.header {
+      json = json.with("header", header_json(header));
+    }
+    if let Some(ref objects) = self.objects {
+      json = json.with("objects", objects.iter().map(object_json).collect::<Vec<_>>());
+    }
+    if let Some((ref separators, ref entries)) = self.dictionary {
+      let separators = separators.iter().map(|ch| ch.to_string()).collect::<Vec<_>>();
+      let entries = entries.iter()
+        .map(|entry| {
+          Json::object()
+            .with("word", entry.word.as_str())
+            .with("data", entry.data.clone())
+        })
+        .collect::<Vec<_>>();
+      json = json.with("dictionary",
+                       Json::object().with("separators", separators).with("entries", entries));
+    }
+    if let Some(ref abbreviations) = self.abbreviations {
+      json = json.with("abbreviations", abbreviations.clone());
+    }
+    json
+  }
+}
+
+fn addr(ptr: BytePtr) -> usize {
+  RawPtr::from(ptr).ptr()
+}
+
+fn write_header<W>(header: &Header, out: &mut W) -> Result<()>
+  where W: Write {
+  writeln!(out, "Story file header\n")?;
+  writeln!(out, "    Z-code version:           {}", header.version)?;
+  writeln!(out, "    Release number:           {}", header.release)?;
+  writeln!(out, "    Serial number:            {}", header.serial)?;
+  writeln!(out, "    Checksum:                 {:04x}", header.checksum)?;
+  writeln!(out, "    Flags 1:                  {:02x}", header.flags1)?;
+  writeln!(out, "    Flags 2:                  {:04x}", header.flags2)?;
+  writeln!(out, "    Size of resident memory:  {:04x}", addr(header.high_memory))?;
+  writeln!(out, "    Start PC:                 {:04x}", addr(header.initial_pc))?;
+  writeln!(out, "    Dictionary address:       {:04x}", addr(header.dictionary))?;
+  writeln!(out, "    Object table address:     {:04x}", addr(header.objects))?;
+  writeln!(out, "    Global variables address: {:04x}", addr(header.globals))?;
+  writeln!(out, "    Size of dynamic memory:   {:04x}", addr(header.static_memory))?;
+  writeln!(out, "    Abbreviations address:    {:04x}", addr(header.abbreviations))?;
+  writeln!(out, "    File size:                {:05x}", header.file_length)?;
+  writeln!(out,
+           "    Standard revision:        {}.{}\n",
+           header.standard.0,
+           header.standard.1)?;
+  Ok(())
+}
+
+fn header_json(header: &Header) -> Json {
+  Json::object()
+    .with("version", header.version)
+    .with("release", header.release)
+    .with("serial", header.serial.as_str())
+    .with("checksum", header.checksum)
+    .with("flags1", header.flags1)
+    .with("flags2", header.flags2)
+    .with("high_memory", addr(header.high_memory))
+    .with("initial_pc", addr(header.initial_pc))
+    .with("dictionary", addr(header.dictionary))
+    .with("objects", addr(header.objects))
+    .with("globals", addr(header.globals))
+    .with("static_memory", addr(header.static_memory))
+    .with("abbreviations", addr(header.abbreviations))
+    .with("file_length", header.file_length)
+    .with("standard", format!("{}.{}", header.standard.0, header.standard.1))
+}
+
+fn write_objects<W>(objects: &[Object], out: &mut W) -> Result<()>
+  where W: Write {
+  writeln!(out, "Objects ({})\n", objects.len())?;
+  for object in objects {
+    write_object(object, out)?;
+  }
+
+  // Then the tree, starting from each object that has no parent.
+  writeln!(out, "\nObject tree\n")?;
+  let mut shown = vec![false; objects.len()];
+  for object in objects.iter().filter(|object| object.parent == 0) {
+    write_tree(objects, object.number, 0, &mut shown, out)?;
+  }
+  writeln!(out)?;
+  Ok(())
+}
+
+pub fn write_object<W>(object: &Object, out: &mut W) -> Result<()>
+  where W: Write {
+  writeln!(out, "{:4}. \"{}\"", object.number, object.name)?;
+  let attributes = object.attributes.iter().map(|a| a.to_string()).collect::<Vec<_>>();
+  writeln!(out, "      Attributes: {}", attributes.join(", "))?;
+  writeln!(out,
+           "      Parent: {}  Sibling: {}  Child: {}",
+           object.parent,
+           object.sibling,
+           object.child)?;
+  writeln!(out, "      Properties:")?;
+  for &(number, ref data) in &object.properties {
+    writeln!(out, "        [{:2}] {}", number, hex_bytes(data))?;
+  }
+  Ok(())
+}
+
+fn write_tree<W>(objects: &[Object],
+                 number: u16,
+                 depth: usize,
+                 shown: &mut [bool],
+                 out: &mut W)
+                 -> Result<()>
+  where W: Write {
+  let object = match objects.get(number as usize - 1) {
+    Some(object) => object,
+    None => return Ok(()),
+  };
+  // A bad tree could have loops, through children or siblings. Each object is
+  // only shown once, so meeting one again means the tree is broken.
+  if shown[number as usize - 1] {
+    writeln!(out,
+             "{:1$}[{2:3}] \"{3}\" (shown already: the tree has a loop)",
+             "",
+             depth * 4,
+             object.number,
+             object.name)?;
+    return Ok(());
+  }
+  shown[number as usize - 1] = true;
+  writeln!(out, "{:1$}[{2:3}] \"{3}\"", "", depth * 4, object.number, object.name)?;
+  let mut child = object.child;
+  while child != 0 && child as usize <= objects.len() {
+    let looped = shown[child as usize - 1];
+    write_tree(objects, child, depth + 1, shown, out)?;
+    if looped {
+      break;
+    }
+    child = objects[child as usize - 1].sibling;
+  }
+  Ok(())
+}
+
+fn object_json(object: &Object) -> Json {
+  let properties = object.properties
+    .iter()
+    .map(|&(number, ref data)| Json::object().with("number", number).with("data", data.clone()))
+    .collect::<Vec<_>>();
+  Json::object()
+    .with("number", object.number)
+    .with("name", object.name.as_str())
+    .with("parent", object.parent)
+    .with("sibling", object.sibling)
+    .with("child", object.child)
+    .with("attributes", object.attributes.clone())
+    .with("properties", properties)
+}
+
+fn write_dictionary<W>(separators: &[char], entries: &[DictionaryEntry], out: &mut W) -> Result<()>
+  where W: Write {
+  let separators = separators.iter().map(|ch| ch.to_string()).collect::<Vec<_>>();
+  writeln!(out, "Dictionary ({} entries)\n", entries.len())?;
+  writeln!(out, "    Word separators: {}\n", separators.join(" "))?;
+  for (i, entry) in entries.iter().enumerate() {
+    writeln!(out, "[{:4}] {:10} {}", i + 1, entry.word, hex_bytes(&entry.data))?;
+  }
+  writeln!(out)?;
+  Ok(())
+}
+
+fn write_abbreviations<W>(abbreviations: &[String], out: &mut W) -> Result<()>
+  where W: Write {
+  writeln!(out, "Abbreviations ({})\n", abbreviations.len())?;
+  for (i, abbreviation) in abbreviations.iter().enumerate() {
+    writeln!(out, "[{:2}] \"{}\"", i, abbreviation)?;
+  }
+  writeln!(out)?;
+  Ok(())
+}
+
+fn hex_bytes(bytes: &[u8]) -> String {
+  bytes.iter().map(|b| format!("{:02x}", b)).collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod test {
+  use super::{Info, Object};
+
+  fn object(number: u16, name: &str, parent: u16, sibling: u16, child: u16) -> Object {
+    Object {
+      number,
+      name: name.to_string(),
+      parent,
+      sibling,
+      child,
+      attributes: vec![3, 17],
+      properties: vec![(18, vec![0x12, 0x34])],
+    }
+  }
+
+  fn info() -> Info {
+    Info {
+      header: None,
+      objects: Some(vec![object(1, "Forest", 0, 0, 2),
+                         object(2, "tree", 1, 3, 0),
+                         object(3, "leaf", 1, 0, 0)]),
+      dictionary: None,
+      abbreviations: Some(vec!["the ".to_string()]),
+    }
+  }
+
+  #[test]
+  fn test_tree_text() {
+    let mut out = Vec::new();
+    info().write_text(&mut out).unwrap();
+    let text = String::from_utf8(out).unwrap();
+    assert!(text.contains("   1. \"Forest\"\n      Attributes: 3, 17\n"));
+    assert!(text.contains("        [18] 12 34\n"));
+    assert!(text.contains("[  1] \"Forest\"\n    [  2] \"tree\"\n    [  3] \"leaf\"\n"));
+    assert!(text.contains("[ 0] \"the \"\n"));
+  }
+
+  #[test]
+  fn test_tree_loops() {
+    // The tree's siblings go round in a circle, and the leaf is its own child.
+    let mut info = info();
+    info.objects = Some(vec![object(1, "Forest", 0, 0, 2),
+                             object(2, "tree", 1, 3, 0),
+                             object(3, "leaf", 1, 2, 3)]);
+    let mut out = Vec::new();
+    info.write_text(&mut out).unwrap();
+    let text = String::from_utf8(out).unwrap();
+    assert!(text.contains("[  1] \"Forest\"\n    \
+                           [  2] \"tree\"\n    \
+                           [  3] \"leaf\"\n        \
+                           [  3] \"leaf\" (shown already: the tree has a loop)\n    \
+                           [  2] \"tree\" (shown already: the tree has a loop)\n\n"));
+  }
+
+  #[test]
+  fn test_json() {
+    let json = info().to_json().to_string();
+    assert!(json.starts_with("{\"objects\":[{\"number\":1,\"name\":\"Forest\",\"parent\":0,\
+                              \"sibling\":0,\"child\":2,\"attributes\":[3,17],\
+                              \"properties\":[{\"number\":18,\"data\":[18,52]}]},"));
+    assert!(json.ends_with("\"abbreviations\":[\"the \"]}"));
+  }
+}
@@ -0,0 +1,143 @@
+use std::fmt;
+
+// Just enough JSON to write out what we know about a story file. Objects keep
+// their keys in the order they were added.
+
+#[derive(Clone,Debug,PartialEq)]
+pub enum Json {
+  Bool(bool),
+  Number(i64),
+  String(String),
+  Array(Vec<Json>),
+  Object(Vec<(String, Json)>),
+}
+
+impl Json {
+  pub fn object() -> Json {
+    Json::Object(Vec::new())
+  }
+
+  /// Add a key to an object. (Does nothing to anything else.)
+  pub fn with<K, V>(mut self, key: K, value: V) -> Json
+    where K: Into<String>,
+          V: Into<Json> {
+    if let Json::Object(ref mut pairs) = self {
+      pairs.push((key.into(), value.into()));
+    }
+    self
+  }
+}
+
+impl From<bool> for Json {
+  fn from(val: bool) -> Json {
+    Json::Bool(val)
+  }
+}
+
+impl From<u8> for Json {
+  fn from(val: u8) -> Json {
+    Json::Number(val as i64)
+  }
+}
+
+impl From<u16> for Json {
+  fn from(val: u16) -> Json {
+    Json::Number(val as i64)
+  }
+}
+
+impl From<u32> for Json {
+  fn from(val: u32) -> Json {
+    Json::Number(val as i64)
+  }
+}
+
+impl From<usize> for Json {
+  fn from(val: usize) -> Json {
+    Json::Number(val as i64)
+  }
+}
+
+impl<'a> From<&'a str> for Json {
+  fn from(val: &'a str) -> Json {
+    Json::String(val.to_string())
+  }
+}
+
+impl From<String> for Json {
+  fn from(val: String) -> Json {
+    Json::String(val)
+  }
+}
+
+impl<T> From<Vec<T>> for Json
+  where T: Into<Json> {
+  fn from(vals: Vec<T>) -> Json {
+    Json::Array(vals.into_iter().map(Into::into).collect())
+  }
+}
+
+impl fmt::Display for Json {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      Json::Bool(val) => write!(f, "{}", val),
+      Json::Number(val) => write!(f, "{}", val),
+      Json::String(ref val) => write_string(f, val),
+      Json::Array(ref vals) => {
+        write!(f, "[")?;
+        for (i, val) in vals.iter().enumerate() {
+          if i > 0 {
+            write!(f, ",")?;
+          }
+          write!(f, "{}", val)?;
+        }
+        write!(f, "]")
+      }
+      Json::Object(ref pairs) => {
+        write!(f, "{{")?;
+        for (i, (key, val)) in pairs.iter().enumerate() {
+          if i > 0 {
+            write!(f, ",")?;
+          }
+          write_string(f, key)?;
+          write!(f, ":{}", val)?;
+        }
+        write!(f, "}}")
+      }
+    }
+  }
+}
+
+fn write_string(f: &mut fmt::Formatter, val: &str) -> fmt::Result {
+  write!(f, "\"")?;
+  for ch in val.chars() {
+    match ch {
+      '"' => write!(f, "\\\"")?,
+      '\\' => write!(f, "\\\\")?,
+      '\n' => write!(f, "\\n")?,
+      '\r' => write!(f, "\\r")?,
+      '\t' => write!(f, "\\t")?,
+      ch if (ch as u32) < 0x20 => write!(f, "\\u{:04x}", ch as u32)?,
+      ch => write!(f, "{}", ch)?,
+    }
+  }
+  write!(f, "\"")
+}
+
+#[cfg(test)]
+mod test {
+  use super::Json;
+
+  #[test]
+  fn test_display() {
+    let json = Json::object()
+      .with("name", "West \"of\" House\n")
+      .with("number", 12u16)
+      .with("flags", vec![true, false])
+      .with("empty", Json::object());
+    assert_eq!("{\"name\":\"West \\\"of\\\" House\\n\",\"number\":12,\"flags\":[true,false],\
+                \"empty\":{}}",
+               json.to_string());
+    assert_eq!("\"\\u0001\"", Json::from("\u{1}").to_string());
+  }
+}
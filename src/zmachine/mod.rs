@@ -1,5 +1,19 @@
+mod coverage;
+mod debug_info;
+mod debugger;
+mod disasm;
+mod info;
+mod json;
 mod ops;
+mod profile;
+mod trace;
 mod vm;
+mod watch;
 mod zconfig;
 
+pub use self::vm::{ErrorMode, colour_from_name, colour_names, error_mode_from_name,
+                   error_mode_names};
+pub use self::info::Sections as InfoSections;
 pub use self::vm::zvm::ZMachine;
+pub use self::trace::{TraceFormat, trace_format_from_name, trace_format_names};
+pub use self::zconfig::ZConfig;
@@ -1,11 +1,11 @@
 use result::Result;
-use zmachine::ops::Operand;
+use zmachine::ops::{Operand, check_object};
 use zmachine::vm::{VM, VariableRef, ZObject, ZObjectTable};
 
 const BRANCH_POLARITY_MASK: u8 = 0b10000000;
 const BRANCH_LENGTH_MASK: u8 = 0b01000000;
 
-fn fourteen_bit_signed(b1: u8, b2: u8) -> i16 {
+pub fn fourteen_bit_signed(b1: u8, b2: u8) -> i16 {
   // TODO: this is convoluted. Rewrite.
   let first = b1 & 0b00111111;
   let word: u16 = ((first as u16) << 8) + b2 as u16;
@@ -21,11 +21,11 @@ fn fourteen_bit_signed(b1: u8, b2: u8) -> i16 {
 
 pub fn branch_on_condition<T>(vm: &mut T, condition: bool) -> Result<()>
   where T: VM {
-  let first_label_byte = vm.read_pc_byte();
+  let first_label_byte = vm.read_pc_byte()?;
   let offset: i16;
   if first_label_byte & BRANCH_LENGTH_MASK == 0 {
     // two-byte, 14-bit signed offset
-    let second_label_byte = vm.read_pc_byte();
+    let second_label_byte = vm.read_pc_byte()?;
     offset = fourteen_bit_signed(first_label_byte, second_label_byte);
   } else {
     // one-byte, 6-bit unsigned offset
@@ -35,6 +35,7 @@ pub fn branch_on_condition<T>(vm: &mut T, condition: bool) -> Result<()>
   // Branch on false iff BRANCH_POLARITY_MASK is 0.
   let branch_on = (first_label_byte & BRANCH_POLARITY_MASK) != 0;
 
+  vm.set_branch_taken(condition == branch_on);
   if condition == branch_on {
     if offset == 0 {
       // return false from the current routine
@@ -139,9 +140,12 @@ pub fn jin_0x06<T>(vm: &mut T, lhs: Operand, rhs: Operand) -> Result<()>
   // TODO: test jin_0x06
   let child_number = lhs.value(vm)?;
   let parent_number = rhs.value(vm)?;
+  if !check_object(vm, child_number, "jin")? {
+    return branch_on_condition(vm, parent_number == 0);
+  }
   let object_table = vm.object_table()?;
   let child_obj = object_table.object_with_number(child_number);
-  let childs_parent_number = child_obj.parent();
+  let childs_parent_number = child_obj.parent()?;
 
   branch_on_condition(vm, parent_number == childs_parent_number)
 }
@@ -150,10 +154,14 @@ pub fn get_child_0x02<T>(vm: &mut T, object_number: Operand, variable: VariableR
   where T: VM {
   // TODO: test get_child_0x02
   let object_number = object_number.value(vm)?;
+  if !check_object(vm, object_number, "get_child")? {
+    vm.write_variable(variable, 0)?;
+    return branch_on_condition(vm, false);
+  }
   let object_table = vm.object_table()?;
 
   let obj = object_table.object_with_number(object_number);
-  let child_number = obj.child();
+  let child_number = obj.child()?;
 
   vm.write_variable(variable, child_number)?;
   branch_on_condition(vm, child_number != 0)
@@ -166,10 +174,14 @@ pub fn get_sibling_0x01<T>(vm: &mut T,
   where T: VM {
   // TODO: test get_sibling_0x01
   let object_number = object_number.value(vm)?;
+  if !check_object(vm, object_number, "get_sibling")? {
+    vm.write_variable(variable, 0)?;
+    return branch_on_condition(vm, false);
+  }
   let object_table = vm.object_table()?;
 
   let obj = object_table.object_with_number(object_number);
-  let sibling_number = obj.sibling();
+  let sibling_number = obj.sibling()?;
   vm.write_variable(variable, sibling_number)?;
   branch_on_condition(vm, sibling_number != 0)
 }
@@ -203,6 +215,7 @@ mod test {
     // offset - 2, plus 1 for the pc++ in branch_on_condition.
     // Other tests will be similar.
     assert_eq!(3, vm.current_pc());
+    assert_eq!(Some(true), vm.branch_taken);
 
     vm.set_jump_offset_byte(8, false);
     branch_on_condition(&mut vm, false);
@@ -211,6 +224,7 @@ mod test {
     vm.set_jump_offset_byte(12, true);
     branch_on_condition(&mut vm, false);
     assert_eq!(1, vm.current_pc());
+    assert_eq!(Some(false), vm.branch_taken);
 
     vm.set_jump_offset_byte(16, false);
     branch_on_condition(&mut vm, true);
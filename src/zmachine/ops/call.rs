@@ -5,7 +5,7 @@ use zmachine::vm::{PackedAddr, RawPtr, VM, VariableRef};
 
 pub fn call_0x00<T>(vm: &mut T, operands: [Operand; 4]) -> Result<()>
   where T: VM {
-  let result_location = VariableRef::decode(vm.read_pc_byte());
+  let result_location = VariableRef::decode(vm.read_pc_byte()?);
   let return_pc = vm.current_pc();
 
   let addr_value = operands[0].value(vm)?;
@@ -34,11 +34,11 @@ pub fn call_0x00<T>(vm: &mut T, operands: [Operand; 4]) -> Result<()>
     argument_values.push(arg_value);
   }
 
-  let num_locals = vm.read_pc_byte();
-  vm.new_frame(return_pc, num_locals, result_location)?;
+  let num_locals = vm.read_pc_byte()?;
+  vm.new_frame(return_pc, raw_addr.into(), num_locals, result_location)?;
   // The frame is set up. Now initialize the local variables from the code.
   for i in 0..num_locals {
-    let local_from_pc = vm.read_pc_word();
+    let local_from_pc = vm.read_pc_word()?;
     vm.write_local(i, local_from_pc)?;
   }
 
@@ -0,0 +1,551 @@
+use result::{Error, Result};
+use zmachine::ops::Operand;
+use zmachine::ops::branch::fourteen_bit_signed;
+use zmachine::ops::decode_at;
+use zmachine::vm::{RawPtr, VM, VariableRef};
+
+// Decoding of instructions (spec section 4), shared by the interpreter, which
+// only needs the operands (the opcodes read their own store variables, branch
+// offsets and text), and the tools, which want the whole instruction.
+
+/// How the opcode and operand types are encoded.
+#[derive(Clone,Copy,Debug,Eq,PartialEq)]
+pub enum Form {
+  Long,
+  Short,
+  Variable,
+  Extended,
+}
+
+/// Together with the opcode number, the operand count says which instruction
+/// it is.
+#[derive(Clone,Copy,Debug,Eq,PartialEq)]
+pub enum OperandCount {
+  Op0,
+  Op1,
+  Op2,
+  Var,
+  Ext,
+}
+
+impl OperandCount {
+  pub fn name(&self) -> &'static str {
+    match *self {
+      OperandCount::Op0 => "0OP",
+      OperandCount::Op1 => "1OP",
+      OperandCount::Op2 => "2OP",
+      OperandCount::Var => "VAR",
+      OperandCount::Ext => "EXT",
+    }
+  }
+}
+
+/// What follows the operands of an instruction, and whether its first operand
+/// is the number of a variable (as in `inc` or `store`) rather than a value.
+#[derive(Clone,Copy,Debug,Eq,PartialEq)]
+pub struct OpcodeInfo {
+  pub name: &'static str,
+  pub store: bool,
+  pub branch: bool,
+  pub text: bool,
+  pub variable: bool,
+}
+
+#[derive(Clone,Copy,Debug,Eq,PartialEq)]
+pub struct Branch {
+  pub on_true: bool,
+  pub offset: i16,
+}
+
+#[derive(Clone,Copy,Debug,Eq,PartialEq)]
+pub enum BranchTarget {
+  ReturnFalse,
+  ReturnTrue,
+  Address(usize),
+}
+
+#[derive(Clone,Debug)]
+pub struct Instruction {
+  pub addr: usize,
+  /// Length in bytes, including any inline text.
+  pub len: usize,
+  pub form: Form,
+  pub count: OperandCount,
+  pub opcode: u8,
+  pub name: &'static str,
+  pub operands: Vec<Operand>,
+  /// Whether the first operand is the number of a variable.
+  pub variable: bool,
+  pub store: Option<VariableRef>,
+  pub branch: Option<Branch>,
+  pub text: Option<String>,
+}
+
+impl Instruction {
+  pub fn next_addr(&self) -> usize {
+    self.addr + self.len
+  }
+
+  pub fn branch_target(&self) -> Option<BranchTarget> {
+    self.branch.map(|branch| match branch.offset {
+      0 => BranchTarget::ReturnFalse,
+      1 => BranchTarget::ReturnTrue,
+      offset => BranchTarget::Address(offset_addr(self.next_addr(), offset)),
+    })
+  }
+
+  /// Where a jump goes, if this is a jump to a constant address.
+  pub fn jump_target(&self) -> Option<usize> {
+    match (self.name, self.operands.first()) {
+      ("jump", Some(&Operand::LargeConstant(offset))) => {
+        Some(offset_addr(self.next_addr(), offset as i16))
+      }
+      _ => None,
+    }
+  }
+
+  /// True if execution never carries on to the next instruction.
+  pub fn ends_flow(&self) -> bool {
+    matches!(self.name,
+             "jump" | "print_ret" | "quit" | "restart" | "ret" | "ret_popped" | "rfalse" |
+             "rtrue" | "throw")
+  }
+}
+
+// Branch and jump offsets count from the end of the instruction, less 2.
+fn offset_addr(next_addr: usize, offset: i16) -> usize {
+  (next_addr as isize + offset as isize - 2) as usize
+}
+
+/// Somewhere to read the bytes of an instruction from.
+pub trait InstructionBytes {
+  fn next_byte(&mut self) -> Result<u8>;
+
+  fn next_word(&mut self) -> Result<u16> {
+    let high = self.next_byte()?;
+    let low = self.next_byte()?;
+    Ok(((high as u16) << 8) | low as u16)
+  }
+}
+
+pub fn operand_of_type<B>(bytes: &mut B, operand_type: u8) -> Result<Operand>
+  where B: InstructionBytes {
+  let operand = match operand_type {
+    0b00 => Operand::LargeConstant(bytes.next_word()?),
+    0b01 => Operand::SmallConstant(bytes.next_byte()?),
+    0b10 => Operand::Variable(VariableRef::decode(bytes.next_byte()?)),
+    // Only two bits, so this is 0b11.
+    _ => Operand::Omitted,
+  };
+  Ok(operand)
+}
+
+/// The operands of a variable or extended form instruction: a byte of operand
+/// types, then the operands.
+pub fn var_operands<B>(bytes: &mut B) -> Result<[Operand; 4]>
+  where B: InstructionBytes {
+  let operand_types = bytes.next_byte()?;
+  Ok([operand_of_type(bytes, (operand_types & 0b11000000) >> 6)?,
+      operand_of_type(bytes, (operand_types & 0b00110000) >> 4)?,
+      operand_of_type(bytes, (operand_types & 0b00001100) >> 2)?,
+      operand_of_type(bytes, operand_types & 0b00000011)?])
+}
+
+/// The two operands of a long form instruction, whose types are in the first
+/// byte.
+pub fn long_operands<B>(bytes: &mut B, first_byte: u8) -> Result<[Operand; 4]>
+  where B: InstructionBytes {
+  let first_type = if first_byte & 0b01000000 == 0 { 0b01 } else { 0b10 };
+  let second_type = if first_byte & 0b00100000 == 0 { 0b01 } else { 0b10 };
+  Ok([operand_of_type(bytes, first_type)?,
+      operand_of_type(bytes, second_type)?,
+      Operand::Omitted,
+      Operand::Omitted])
+}
+
+/// The operand of a short form instruction. Omitted means that it is a 0OP.
+pub fn short_operand<B>(bytes: &mut B, first_byte: u8) -> Result<Operand>
+  where B: InstructionBytes {
+  operand_of_type(bytes, (first_byte & 0b00110000) >> 4)
+}
+
+/// Decode the whole instruction at `addr`, without running it.
+pub fn decode<T>(vm: &mut T, addr: usize) -> Result<Instruction>
+  where T: VM {
+  let version = vm.version();
+  let (form, count, opcode, operands, info, store, branch, text_addr) = {
+    let mut bytes = MemoryBytes {
+      vm: &*vm,
+      addr,
+    };
+    let first_byte = bytes.next_byte()?;
+    let (form, count, opcode, mut operands) = match first_byte & 0b11000000 {
+      0b11000000 => {
+        let opcode = first_byte & 0b00011111;
+        let count = if first_byte & 0b00100000 == 0 {
+          OperandCount::Op2
+        } else {
+          OperandCount::Var
+        };
+        (Form::Variable, count, opcode, var_operands(&mut bytes)?.to_vec())
+      }
+      0b10000000 => {
+        let opcode = first_byte & 0b00001111;
+        match short_operand(&mut bytes, first_byte)? {
+          Operand::Omitted if opcode == 0x0e && version >= 5 => {
+            let opcode = bytes.next_byte()?;
+            (Form::Extended, OperandCount::Ext, opcode, var_operands(&mut bytes)?.to_vec())
+          }
+          Operand::Omitted => (Form::Short, OperandCount::Op0, opcode, vec![]),
+          operand => (Form::Short, OperandCount::Op1, opcode, vec![operand]),
+        }
+      }
+      _ => {
+        (Form::Long,
+         OperandCount::Op2,
+         first_byte & 0b00011111,
+         long_operands(&mut bytes, first_byte)?.to_vec())
+      }
+    };
+    let info = opcode_info(version, count, opcode)
+      .ok_or(Error::UnknownOpcode(count.name(), opcode, addr))?;
+    // call_vs2 and call_vn2 have a second byte of types, for up to 8 operands.
+    if count == OperandCount::Var && (info.name == "call_vs2" || info.name == "call_vn2") {
+      operands.extend(var_operands(&mut bytes)?.iter());
+    }
+    // The first omitted operand ends the list.
+    let operands = operands.into_iter().take_while(|o| *o != Operand::Omitted).collect::<Vec<_>>();
+
+    let store = if info.store {
+      Some(VariableRef::decode(bytes.next_byte()?))
+    } else {
+      None
+    };
+    let branch = if info.branch {
+      let first = bytes.next_byte()?;
+      let offset = if first & 0b01000000 == 0 {
+        fourteen_bit_signed(first, bytes.next_byte()?)
+      } else {
+        (first & 0b00111111) as i16
+      };
+      Some(Branch {
+        on_true: first & 0b10000000 != 0,
+        offset,
+      })
+    } else {
+      None
+    };
+    (form, count, opcode, operands, info, store, branch, bytes.addr)
+  };
+
+  let (text, len) = if info.text {
+    let text = decode_at(vm, RawPtr::new(text_addr))?;
+    let text_len = text_length(vm, text_addr)?;
+    (Some(text), text_addr + text_len - addr)
+  } else {
+    (None, text_addr - addr)
+  };
+
+  Ok(Instruction {
+    addr,
+    len,
+    form,
+    count,
+    opcode,
+    name: info.name,
+    operands,
+    variable: info.variable,
+    store,
+    branch,
+    text,
+  })
+}
+
+// The length in bytes of the encoded string at `addr`. The last word has the
+// top bit set.
+fn text_length<T>(vm: &T, addr: usize) -> Result<usize>
+  where T: VM {
+  let mut len = 0;
+  loop {
+    let word = vm.read_memory(RawPtr::new(addr + len))?;
+    len += 2;
+    if word & 0x8000 != 0 {
+      return Ok(len);
+    }
+  }
+}
+
+struct MemoryBytes<'a, T>
+  where T: VM + 'a {
+  vm: &'a T,
+  addr: usize,
+}
+
+impl<'a, T> InstructionBytes for MemoryBytes<'a, T>
+  where T: VM {
+  fn next_byte(&mut self) -> Result<u8> {
+    let byte = self.vm.read_memory_u8(RawPtr::new(self.addr))?;
+    self.addr += 1;
+    Ok(byte)
+  }
+}
+
+const STORE: u8 = 0b001;
+const BRANCH: u8 = 0b010;
+const TEXT: u8 = 0b100;
+const VARIABLE: u8 = 0b1000;
+
+fn op(name: &'static str, flags: u8) -> Option<OpcodeInfo> {
+  Some(OpcodeInfo {
+    name,
+    store: flags & STORE != 0,
+    branch: flags & BRANCH != 0,
+    text: flags & TEXT != 0,
+    variable: flags & VARIABLE != 0,
+  })
+}
+
+/// The operand count and opcode number of the instruction that starts with
+/// `first_byte`. `next_byte` is the byte after it, which only an extended
+/// instruction needs.
+pub fn opcode_number(version: u8, first_byte: u8, next_byte: u8) -> (OperandCount, u8) {
+  match first_byte & 0b11000000 {
+    0b11000000 if first_byte & 0b00100000 == 0 => (OperandCount::Op2, first_byte & 0b00011111),
+    0b11000000 => (OperandCount::Var, first_byte & 0b00011111),
+    // The operand type is 0b11 (omitted) for a 0OP.
+    0b10000000 if first_byte & 0b00110000 != 0b00110000 => {
+      (OperandCount::Op1, first_byte & 0b00001111)
+    }
+    0b10000000 if first_byte & 0b00001111 == 0x0e && version >= 5 => {
+      (OperandCount::Ext, next_byte)
+    }
+    0b10000000 => (OperandCount::Op0, first_byte & 0b00001111),
+    _ => (OperandCount::Op2, first_byte & 0b00011111),
+  }
+}
+
+/// The name of an instruction and what follows its operands (spec section
+/// 14), or None if there is no such instruction in `version`. Names are the
+/// ones Inform uses.
+pub fn opcode_info(version: u8, count: OperandCount, opcode: u8) -> Option<OpcodeInfo> {
+  use self::OperandCount::*;
+  match (count, opcode) {
+    (Op2, 0x01) => op("je", BRANCH),
+    (Op2, 0x02) => op("jl", BRANCH),
+    (Op2, 0x03) => op("jg", BRANCH),
+    (Op2, 0x04) => op("dec_chk", VARIABLE | BRANCH),
+    (Op2, 0x05) => op("inc_chk", VARIABLE | BRANCH),
+    (Op2, 0x06) => op("jin", BRANCH),
+    (Op2, 0x07) => op("test", BRANCH),
+    (Op2, 0x08) => op("or", STORE),
+    (Op2, 0x09) => op("and", STORE),
+    (Op2, 0x0a) => op("test_attr", BRANCH),
+    (Op2, 0x0b) => op("set_attr", 0),
+    (Op2, 0x0c) => op("clear_attr", 0),
+    (Op2, 0x0d) => op("store", VARIABLE),
+    (Op2, 0x0e) => op("insert_obj", 0),
+    (Op2, 0x0f) => op("loadw", STORE),
+    (Op2, 0x10) => op("loadb", STORE),
+    (Op2, 0x11) => op("get_prop", STORE),
+    (Op2, 0x12) => op("get_prop_addr", STORE),
+    (Op2, 0x13) => op("get_next_prop", STORE),
+    (Op2, 0x14) => op("add", STORE),
+    (Op2, 0x15) => op("sub", STORE),
+    (Op2, 0x16) => op("mul", STORE),
+    (Op2, 0x17) => op("div", STORE),
+    (Op2, 0x18) => op("mod", STORE),
+    (Op2, 0x19) if version >= 4 => op("call_2s", STORE),
+    (Op2, 0x1a) if version >= 5 => op("call_2n", 0),
+    (Op2, 0x1b) if version >= 5 => op("set_colour", 0),
+    (Op2, 0x1c) if version >= 5 => op("throw", 0),
+
+    (Op1, 0x00) => op("jz", BRANCH),
+    (Op1, 0x01) => op("get_sibling", STORE | BRANCH),
+    (Op1, 0x02) => op("get_child", STORE | BRANCH),
+    (Op1, 0x03) => op("get_parent", STORE),
+    (Op1, 0x04) => op("get_prop_len", STORE),
+    (Op1, 0x05) => op("inc", VARIABLE),
+    (Op1, 0x06) => op("dec", VARIABLE),
+    (Op1, 0x07) => op("print_addr", 0),
+    (Op1, 0x08) if version >= 4 => op("call_1s", STORE),
+    (Op1, 0x09) => op("remove_obj", 0),
+    (Op1, 0x0a) => op("print_obj", 0),
+    (Op1, 0x0b) => op("ret", 0),
+    (Op1, 0x0c) => op("jump", 0),
+    (Op1, 0x0d) => op("print_paddr", 0),
+    (Op1, 0x0e) => op("load", VARIABLE | STORE),
+    (Op1, 0x0f) if version <= 4 => op("not", STORE),
+    (Op1, 0x0f) => op("call_1n", 0),
+
+    (Op0, 0x00) => op("rtrue", 0),
+    (Op0, 0x01) => op("rfalse", 0),
+    (Op0, 0x02) => op("print", TEXT),
+    (Op0, 0x03) => op("print_ret", TEXT),
+    (Op0, 0x04) => op("nop", 0),
+    (Op0, 0x05) if version <= 3 => op("save", BRANCH),
+    (Op0, 0x05) if version == 4 => op("save", STORE),
+    (Op0, 0x06) if version <= 3 => op("restore", BRANCH),
+    (Op0, 0x06) if version == 4 => op("restore", STORE),
+    (Op0, 0x07) => op("restart", 0),
+    (Op0, 0x08) => op("ret_popped", 0),
+    (Op0, 0x09) if version <= 4 => op("pop", 0),
+    (Op0, 0x09) => op("catch", STORE),
+    (Op0, 0x0a) => op("quit", 0),
+    (Op0, 0x0b) => op("new_line", 0),
+    (Op0, 0x0c) if version == 3 => op("show_status", 0),
+    (Op0, 0x0d) if version >= 3 => op("verify", BRANCH),
+    (Op0, 0x0f) if version >= 5 => op("piracy", BRANCH),
+
+    (Var, 0x00) if version <= 3 => op("call", STORE),
+    (Var, 0x00) => op("call_vs", STORE),
+    (Var, 0x01) => op("storew", 0),
+    (Var, 0x02) => op("storeb", 0),
+    (Var, 0x03) => op("put_prop", 0),
+    (Var, 0x04) if version <= 4 => op("sread", 0),
+    (Var, 0x04) => op("aread", STORE),
+    (Var, 0x05) => op("print_char", 0),
+    (Var, 0x06) => op("print_num", 0),
+    (Var, 0x07) => op("random", STORE),
+    (Var, 0x08) => op("push", 0),
+    (Var, 0x09) => op("pull", VARIABLE),
+    (Var, 0x0a) if version >= 3 => op("split_window", 0),
+    (Var, 0x0b) if version >= 3 => op("set_window", 0),
+    (Var, 0x0c) if version >= 4 => op("call_vs2", STORE),
+    (Var, 0x0d) if version >= 4 => op("erase_window", 0),
+    (Var, 0x0e) if version >= 4 => op("erase_line", 0),
+    (Var, 0x0f) if version >= 4 => op("set_cursor", 0),
+    (Var, 0x10) if version >= 4 => op("get_cursor", 0),
+    (Var, 0x11) if version >= 4 => op("set_text_style", 0),
+    (Var, 0x12) if version >= 4 => op("buffer_mode", 0),
+    (Var, 0x13) if version >= 3 => op("output_stream", 0),
+    (Var, 0x14) if version >= 3 => op("input_stream", 0),
+    (Var, 0x15) if version >= 3 => op("sound_effect", 0),
+    (Var, 0x16) if version >= 4 => op("read_char", STORE),
+    (Var, 0x17) if version >= 4 => op("scan_table", STORE | BRANCH),
+    (Var, 0x18) if version >= 5 => op("not", STORE),
+    (Var, 0x19) if version >= 5 => op("call_vn", 0),
+    (Var, 0x1a) if version >= 5 => op("call_vn2", 0),
+    (Var, 0x1b) if version >= 5 => op("tokenise", 0),
+    (Var, 0x1c) if version >= 5 => op("encode_text", 0),
+    (Var, 0x1d) if version >= 5 => op("copy_table", 0),
+    (Var, 0x1e) if version >= 5 => op("print_table", 0),
+    (Var, 0x1f) if version >= 5 => op("check_arg_count", BRANCH),
+
+    (Ext, 0x00) => op("save", STORE),
+    (Ext, 0x01) => op("restore", STORE),
+    (Ext, 0x02) => op("log_shift", STORE),
+    (Ext, 0x03) => op("art_shift", STORE),
+    (Ext, 0x04) => op("set_font", STORE),
+    (Ext, 0x09) => op("save_undo", STORE),
+    (Ext, 0x0a) => op("restore_undo", STORE),
+    (Ext, 0x0b) => op("print_unicode", 0),
+    (Ext, 0x0c) => op("check_unicode", STORE),
+    (Ext, 0x0d) => op("set_true_colour", 0),
+
+    _ => None,
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use zmachine::ops::Operand;
+  use zmachine::ops::testvm::TestVM;
+  use zmachine::vm::VariableRef;
+  use super::{BranchTarget, Form, OperandCount, decode, opcode_number};
+
+  fn vm_with_code(code: &[u8]) -> TestVM {
+    let mut vm = TestVM::new();
+    vm.heap[100..100 + code.len()].copy_from_slice(code);
+    vm
+  }
+
+  #[test]
+  fn test_long_form() {
+    // add L00 #05 -> sp
+    let mut vm = vm_with_code(&[0x54, 0x01, 0x05, 0x00]);
+    let instruction = decode(&mut vm, 100).unwrap();
+    assert_eq!(Form::Long, instruction.form);
+    assert_eq!(OperandCount::Op2, instruction.count);
+    assert_eq!("add", instruction.name);
+    assert_eq!(vec![Operand::Variable(VariableRef::Local(0)), Operand::SmallConstant(5)],
+               instruction.operands);
+    assert_eq!(Some(VariableRef::Stack), instruction.store);
+    assert!(!instruction.variable);
+    assert_eq!(4, instruction.len);
+
+    // inc_chk #01 #05 [TRUE] +0x10: the first operand is L00.
+    let mut vm = vm_with_code(&[0x05, 0x01, 0x05, 0xd0]);
+    let instruction = decode(&mut vm, 100).unwrap();
+    assert_eq!("inc_chk", instruction.name);
+    assert!(instruction.variable);
+  }
+
+  #[test]
+  fn test_branches() {
+    // je G00 #01 [TRUE] +0x10
+    let mut vm = vm_with_code(&[0x41, 0x10, 0x01, 0xd0]);
+    let instruction = decode(&mut vm, 100).unwrap();
+    assert_eq!(Some(BranchTarget::Address(104 + 0x10 - 2)),
+               instruction.branch_target());
+
+    // jz sp [FALSE] rtrue
+    let mut vm = vm_with_code(&[0xa0, 0x00, 0x41]);
+    let instruction = decode(&mut vm, 100).unwrap();
+    assert!(!instruction.branch.unwrap().on_true);
+    assert_eq!(Some(BranchTarget::ReturnTrue), instruction.branch_target());
+
+    // jz sp [TRUE], 14 bit offset -3
+    let mut vm = vm_with_code(&[0xa0, 0x00, 0xbf, 0xfd]);
+    let instruction = decode(&mut vm, 100).unwrap();
+    assert_eq!(Some(BranchTarget::Address(104 - 3 - 2)),
+               instruction.branch_target());
+
+    // jump -4
+    let mut vm = vm_with_code(&[0x8c, 0xff, 0xfc]);
+    let instruction = decode(&mut vm, 100).unwrap();
+    assert_eq!(Some(103 - 4 - 2), instruction.jump_target());
+    assert!(instruction.ends_flow());
+  }
+
+  #[test]
+  fn test_var_form() {
+    // call 0x1234 #02 -> G01
+    let mut vm = vm_with_code(&[0xe0, 0x1f, 0x12, 0x34, 0x02, 0x11]);
+    let instruction = decode(&mut vm, 100).unwrap();
+    assert_eq!(Form::Variable, instruction.form);
+    assert_eq!("call", instruction.name);
+    assert_eq!(vec![Operand::LargeConstant(0x1234), Operand::SmallConstant(2)],
+               instruction.operands);
+    assert_eq!(Some(VariableRef::Global(1)), instruction.store);
+    assert_eq!(6, instruction.len);
+  }
+
+  #[test]
+  fn test_text() {
+    // print "hi", which is h=13, i=14, then a pad of 5.
+    let word = 0x8000 | (13 << 10) | (14 << 5) | 5;
+    let mut vm = vm_with_code(&[0xb2, (word >> 8) as u8, word as u8]);
+    let instruction = decode(&mut vm, 100).unwrap();
+    assert_eq!(OperandCount::Op0, instruction.count);
+    assert_eq!(Some("hi".to_string()), instruction.text);
+    assert_eq!(3, instruction.len);
+  }
+
+  #[test]
+  fn test_unknown() {
+    // There is no 0OP 0x0e before v5.
+    let mut vm = vm_with_code(&[0xbe]);
+    assert!(decode(&mut vm, 100).is_err());
+  }
+
+  #[test]
+  fn test_opcode_number() {
+    assert_eq!((OperandCount::Op2, 0x14), opcode_number(3, 0x54, 0));
+    assert_eq!((OperandCount::Op2, 0x01), opcode_number(3, 0xc1, 0));
+    assert_eq!((OperandCount::Var, 0x00), opcode_number(3, 0xe0, 0));
+    assert_eq!((OperandCount::Op1, 0x0c), opcode_number(3, 0x8c, 0));
+    assert_eq!((OperandCount::Op0, 0x00), opcode_number(3, 0xb0, 0));
+    assert_eq!((OperandCount::Op0, 0x0e), opcode_number(3, 0xbe, 0x0d));
+    assert_eq!((OperandCount::Ext, 0x0d), opcode_number(5, 0xbe, 0x0d));
+  }
+}
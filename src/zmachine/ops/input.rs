@@ -3,7 +3,7 @@ use std::cmp;
 use std::io::{self, Write};
 use zmachine::ops::Operand;
 use zmachine::ops::text::{decode_at, show_status_0x0c};
-use zmachine::vm::{BytePtr, RawPtr, VM};
+use zmachine::vm::{BytePtr, RawPtr, VM, VariableRef, char_to_zscii};
 
 enum CharType {
   WhiteSpace,
@@ -33,6 +33,7 @@ struct Token {
 
 struct Tokenizer {
   str: String,
+  text_start: u8,
   in_word: bool,
   word_start: u8,
   word_length: u8,
@@ -40,9 +41,12 @@ struct Tokenizer {
 }
 
 impl Tokenizer {
-  fn new() -> Tokenizer {
+  // `text_start` is the offset of the first character in the text buffer,
+  // which is where the token offsets are measured from.
+  fn new(text_start: u8) -> Tokenizer {
     Tokenizer {
       str: String::new(),
+      text_start,
       in_word: false,
       word_start: 0,
       word_length: 0,
@@ -50,18 +54,20 @@ impl Tokenizer {
     }
   }
 
-  fn tokenize<T>(&mut self, vm: &mut T, s: String)
+  fn tokenize<T>(&mut self, vm: &mut T, s: String) -> Result<()>
     where T: VM {
     self.str = s.clone();
 
     for (offset, ch) in s.chars().enumerate() {
       let ctype = CharType::char_type(ch);
       match ctype {
-        CharType::WhiteSpace => self.handle_whitespace(vm),
-        CharType::Separator => self.handle_separator(vm, offset as u8),
+        CharType::WhiteSpace => self.handle_whitespace(vm)?,
+        CharType::Separator => self.handle_separator(vm, offset as u8)?,
         CharType::WordChar => self.handle_wordchar(offset as u8),
       }
     }
+    // The input may end in the middle of a word.
+    self.maybe_push_word_token(vm)
   }
 
   fn tokens(self) -> Vec<Token> {
@@ -73,48 +79,53 @@ impl Tokenizer {
       self.word_length += 1
     } else {
       self.in_word = true;
-      self.word_start = offset + 1;  // Account for the length byte.
+      self.word_start = offset + self.text_start;
       self.word_length = 1;
     }
   }
 
-  fn handle_separator<T>(&mut self, vm: &mut T, offset: u8)
+  fn handle_separator<T>(&mut self, vm: &mut T, offset: u8) -> Result<()>
     where T: VM {
-    self.maybe_push_word_token(vm);
+    self.maybe_push_word_token(vm)?;
     self.tokens.push(Token {
       ptr: None,
-      offset: offset + 1, // Account for the length byte.
+      offset: offset + self.text_start,
       len: 1,
-    })
+    });
+    Ok(())
   }
 
-  fn handle_whitespace<T>(&mut self, vm: &mut T)
+  fn handle_whitespace<T>(&mut self, vm: &mut T) -> Result<()>
     where T: VM {
     // Finish off a word token if we're in one, then ignore whitespace.
-    self.maybe_push_word_token(vm);
+    self.maybe_push_word_token(vm)
   }
 
-  fn lookup_in_dictionary<T>(&self, vm: &mut T) -> Option<BytePtr>
+  fn lookup_in_dictionary<T>(&self, vm: &mut T) -> Result<Option<BytePtr>>
     where T: VM {
     // TODO: make this a binary search.
     // Truncate the match string to 6 characters to match what is in the dict.
     let len = cmp::min(self.word_length, 6);
-    let str = &self.str[(self.word_start - 1) as usize..(self.word_start - 1 + len) as usize];
+    let str: String = self.str
+      .chars()
+      .skip((self.word_start - self.text_start) as usize)
+      .take(len as usize)
+      .collect();
     for i in 0..vm.num_dict_entries() {
       let entry_number = i + 1;
       let entry_ptr = vm.dict_entry(entry_number);
-      let dict_str = decode_at(vm, entry_ptr).unwrap();
-      if str == dict_str.as_str() {
-        return Some(entry_ptr);
+      let dict_str = decode_at(vm, entry_ptr)?;
+      if str == dict_str {
+        return Ok(Some(entry_ptr));
       }
     }
-    None
+    Ok(None)
   }
 
-  fn maybe_push_word_token<T>(&mut self, vm: &mut T)
+  fn maybe_push_word_token<T>(&mut self, vm: &mut T) -> Result<()>
     where T: VM {
     if self.in_word {
-      let ptr = self.lookup_in_dictionary(vm);
+      let ptr = self.lookup_in_dictionary(vm)?;
       self.tokens.push(Token {
         ptr: ptr,
         offset: self.word_start,
@@ -122,56 +133,97 @@ impl Tokenizer {
       });
       self.in_word = false;
     }
+    Ok(())
   }
 }
 
 pub fn read_0x04<T>(vm: &mut T, operands: [Operand; 4]) -> Result<()>
   where T: VM {
+  let version = vm.version();
 
-  show_status_0x0c(vm)?;
+  // From v5, read stores the character that terminated input.
+  let result_location = if version >= 5 {
+    Some(VariableRef::decode(vm.read_pc_byte()?))
+  } else {
+    None
+  };
+
+  if version <= 3 {
+    show_status_0x0c(vm)?;
+  }
 
   // TODO: separators not getting tokenized correctly. "hi, sailor"
   io::stdout().flush()?;
 
-  // TODO: write a real readline. :-(
-  let buf = vm.read_line()?;
-
   let tbuf = BytePtr::new(operands[0].value(vm)?);
-  let pbuf = BytePtr::new(operands[1].value(vm)?);
+  let pbuf_addr = operands[1].value(vm)?;
+
+  // In v1-4, byte 0 of the text buffer is the max number of letters plus one
+  // (to leave room for the terminating zero), and the text starts at byte 1.
+  // From v5, byte 0 is the max number of letters, byte 1 receives the number
+  // of letters typed, and the text starts at byte 2 with no terminator.
+  let buffer_size = vm.read_memory_u8(tbuf)? as usize;
+  let (max_len, text_start) = if version >= 5 {
+    (buffer_size, 2)
+  } else {
+    (buffer_size.saturating_sub(1), 1)
+  };
+  let terminators = vm.terminating_chars()?;
+  let (line, terminator) = vm.read_line(max_len, &terminators)?;
+
+  // The game expects lower-case ZSCII.
+  let buf: String = line.chars().flat_map(|ch| ch.to_lowercase()).collect();
+
+  let mut ptr = tbuf.inc_by(text_start as u16);
+  let mut len = 0;
+  for ch in buf.chars() {
+    let zscii = char_to_zscii(ch).unwrap_or('?' as u16);
+    vm.write_memory_u8(ptr, zscii as u8)?;
+    ptr = ptr.inc_by(1);
+    len += 1;
+  }
+  if version >= 5 {
+    vm.write_memory_u8(tbuf.inc_by(1), len)?;
+  } else {
+    // null-terminated
+    vm.write_memory_u8(ptr, 0)?;
+  }
 
-  let tbuf_len = (vm.read_memory_u8(tbuf)? + 1) as usize;
+  // From v5, a parse buffer of 0 means the game will tokenise the text itself.
+  if pbuf_addr != 0 {
+    let pbuf = BytePtr::new(pbuf_addr);
 
-  let mut ptr = tbuf.inc_by(1);
-  for (pos, ch) in buf.chars().enumerate() {
-    if ch == '\n' {
-      // We don't do anything, since the newline is echoed by readline.
-    }
-    if pos >= tbuf_len - 2 || ch == '\n' {
-      // null-terminated
-      vm.write_memory_u8(ptr, 0)?;
-      break;
-    }
-    vm.write_memory_u8(ptr, ch as u8)?;
+    // TODO: split on the ., as well..
+    let mut tokenizer = Tokenizer::new(text_start);
+    tokenizer.tokenize(vm, buf)?;
+    let tokens = tokenizer.tokens();
+
+    // TODO: add code to respect the end of the tbuf and pbuf.
+    let mut ptr = pbuf.inc_by(1);
+    vm.write_memory_u8(ptr, tokens.len() as u8)?;
     ptr = ptr.inc_by(1);
+    for token in tokens {
+      let val = token.ptr.map(|p| RawPtr::from(p).into()).unwrap_or(0usize) as u16;
+      vm.write_memory(ptr, val)?;
+      ptr = ptr.inc_by(2);
+      vm.write_memory_u8(ptr, token.len)?;
+      ptr = ptr.inc_by(1);
+      vm.write_memory_u8(ptr, token.offset)?;
+      ptr = ptr.inc_by(1);
+    }
   }
 
-  // TODO: split on the ., as well..
-  let mut tokenizer = Tokenizer::new();
-  tokenizer.tokenize(vm, buf);
-  let tokens = tokenizer.tokens();
-
-  // TODO: add code to respect the end of the tbuf and pbuf.
-  let mut ptr = pbuf.inc_by(1);
-  vm.write_memory_u8(ptr, tokens.len() as u8)?;
-  ptr = ptr.inc_by(1);
-  for token in tokens {
-    let val = token.ptr.map(|p| RawPtr::from(p).into()).unwrap_or(0usize) as u16;
-    vm.write_memory(ptr, val)?;
-    ptr = ptr.inc_by(2);
-    vm.write_memory_u8(ptr, token.len)?;
-    ptr = ptr.inc_by(1);
-    vm.write_memory_u8(ptr, token.offset)?;
-    ptr = ptr.inc_by(1);
+  if let Some(variable) = result_location {
+    vm.write_variable(variable, terminator)?;
   }
   Ok(())
 }
+
+pub fn read_char_0x16<T>(vm: &mut T, operands: [Operand; 4], variable: VariableRef) -> Result<()>
+  where T: VM {
+  // The first operand is always 1 (the keyboard). Timed input (operands 2 and 3)
+  // is not supported, so we just wait for the key.
+  let _device = operands[0].value(vm)?;
+  let zscii = vm.read_char()?;
+  vm.write_variable(variable, zscii)
+}
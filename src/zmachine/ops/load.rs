@@ -1,11 +1,11 @@
-use result::Result;
+use result::{Error, Result};
 use zmachine::ops::Operand;
 use zmachine::vm::{BytePtr, VM, VariableRef};
 
 pub fn storew_0x01<T>(vm: &mut T, operands: [Operand; 4]) -> Result<()>
   where T: VM {
-  if operands[0..2].iter().any(|o| *o == Operand::Omitted) {
-    panic!("3 operands required: {:?}", operands);
+  if operands[0..3].contains(&Operand::Omitted) {
+    return Err(Error::MissingOperand);
   }
   let array_val = operands[0].value(vm)?;
   let word_index_val = operands[1].value(vm)?;
@@ -18,8 +18,8 @@ pub fn storew_0x01<T>(vm: &mut T, operands: [Operand; 4]) -> Result<()>
 
 pub fn storeb_0x02<T>(vm: &mut T, operands: [Operand; 4]) -> Result<()>
   where T: VM {
-  if operands[0..2].iter().any(|o| *o == Operand::Omitted) {
-    panic!("3 operands required: {:?}", operands);
+  if operands[0..3].contains(&Operand::Omitted) {
+    return Err(Error::MissingOperand);
   }
   let array_val = operands[0].value(vm)?;
   let word_index_val = operands[1].value(vm)?;
@@ -34,7 +34,7 @@ pub fn storeb_0x02<T>(vm: &mut T, operands: [Operand; 4]) -> Result<()>
 pub fn random_0x07<T>(vm: &mut T, operands: [Operand; 4], variable: VariableRef) -> Result<()>
   where T: VM {
   let range = operands[0].value(vm)?;
-  let value = vm.rand(range);
+  let value = vm.rand(range)?;
   vm.write_variable(variable, value)
 }
 
@@ -107,7 +107,7 @@ pub fn dec_0x06<T>(vm: &mut T, encoded_variable: Operand) -> Result<()>
 
 #[cfg(test)]
 mod test {
-  use result::Result;
+  use result::Error;
   use zmachine::ops::Operand;
   use zmachine::ops::testvm::TestVM;
   use zmachine::vm::{BytePtr, VM, VariableRef};
@@ -125,6 +125,17 @@ mod test {
                vm.read_memory(BytePtr::new(10 + 2 * 8)).unwrap());
   }
 
+  #[test]
+  fn test_storew_missing_operand() {
+    let mut vm = TestVM::new();
+    let result = super::storew_0x01(&mut vm,
+                                    [Operand::SmallConstant(10),
+                                     Operand::LargeConstant(8),
+                                     Operand::Omitted,
+                                     Operand::Omitted]);
+    assert!(matches!(result, Err(Error::MissingOperand)));
+  }
+
   #[test]
   fn test_store_0x0d() {
     let mut vm = TestVM::new();
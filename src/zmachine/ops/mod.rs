@@ -1,17 +1,22 @@
 use result::{Error, Result};
-use zmachine::vm::{VM, VariableRef};
+use zmachine::vm::{VM, VariableRef, num_attributes};
 
 mod binop;
 mod branch;
 mod call;
+pub mod decoder;
 mod input;
 mod load;
 mod properties;
 mod stackops;
+mod status_line;
 mod text;
+mod window;
 
 #[cfg(test)]
-mod testvm;
+pub mod testvm;
+
+pub use self::text::decode_at;
 
 #[derive(Clone,Copy,Debug,Eq,PartialEq)]
 pub enum Operand {
@@ -28,13 +33,33 @@ impl Operand {
       Operand::LargeConstant(val) => Ok(val),
       Operand::SmallConstant(val) => Ok(val as u16),
       Operand::Variable(variable) => runner.read_variable(variable),
-      Operand::Omitted => {
-        panic!("Cannot read Omitted operand: {:?}", *self);
-      }
+      Operand::Omitted => Err(Error::MissingOperand),
     }
   }
 }
 
+// Object 0 means "no object", so the object opcodes shouldn't be given it,
+// but many games do. Returns false (once the error has been reported) if the
+// opcode should do nothing instead.
+pub fn check_object<T>(vm: &mut T, object_number: u16, opcode: &'static str) -> Result<bool>
+  where T: VM {
+  if object_number == 0 {
+    vm.report_error(Error::NoObject(opcode))?;
+    return Ok(false);
+  }
+  Ok(true)
+}
+
+// Attributes are numbered 0-31 before v4, and 0-47 from then on.
+pub fn check_attribute<T>(vm: &mut T, attr_number: u16) -> Result<bool>
+  where T: VM {
+  if attr_number >= num_attributes(vm.version()) {
+    vm.report_error(Error::BadAttribute(attr_number))?;
+    return Ok(false);
+  }
+  Ok(true)
+}
+
 pub fn restart_0x07<T>(_: &mut T) -> Result<()>
   where T: VM {
   // TODO: move this somewhere like a 'meta' module.
@@ -42,7 +67,7 @@ pub fn restart_0x07<T>(_: &mut T) -> Result<()>
 }
 
 pub mod zeroops {
-  // 2do: restore_0x06, show_status_0x0c, verify_0x0d
+  // 2do: restore_0x06, verify_0x0d
 
   pub use super::call::nop_0x04;
   pub use super::call::quit_0x0a;
@@ -100,19 +125,34 @@ pub mod twoops {
   pub use super::properties::insert_obj_0x0e;
   pub use super::properties::set_attr_0x0b;
   pub use super::properties::test_attr_0x0a;
+  pub use super::text::set_colour_0x1b;
 }
 
 pub mod varops {
-  // 2do: split_window_0x0a, set_window_0x0b, output_stream_0x13, input_stream_0x14
+  // 2do: input_stream_0x14
 
   pub use super::call::call_0x00;
   pub use super::input::read_0x04;
+  pub use super::input::read_char_0x16;
   pub use super::load::random_0x07;
   pub use super::load::storeb_0x02;
   pub use super::load::storew_0x01;
   pub use super::properties::put_prop_0x03;
   pub use super::stackops::pull_0x09;
   pub use super::stackops::push_0x08;
+  pub use super::text::buffer_mode_0x12;
+  pub use super::text::output_stream_0x13;
   pub use super::text::print_char_0x05;
   pub use super::text::print_num_0x06;
+  pub use super::text::set_text_style_0x11;
+  pub use super::window::erase_line_0x0e;
+  pub use super::window::erase_window_0x0d;
+  pub use super::window::get_cursor_0x10;
+  pub use super::window::set_cursor_0x0f;
+  pub use super::window::set_window_0x0b;
+  pub use super::window::split_window_0x0a;
+}
+
+pub mod extops {
+  pub use super::text::set_true_colour_0x0d;
 }
@@ -15,11 +15,11 @@
 // of the opcode is undefined.
 //
 
-use result::Result;
-use zmachine::ops::Operand;
+use result::{Error, Result};
+use zmachine::ops::{Operand, check_attribute, check_object};
 use zmachine::ops::branch::branch_binop;
 use zmachine::vm::{BytePtr, RawPtr, VM, VariableRef, ZObject, ZObjectTable, ZPropertyStorage,
-                   ZPropertyTable};
+                   ZPropertyTable, attribute_mask};
 
 pub fn put_prop_0x03<T>(vm: &mut T, operands: [Operand; 4]) -> Result<()>
   where T: VM {
@@ -27,17 +27,23 @@ pub fn put_prop_0x03<T>(vm: &mut T, operands: [Operand; 4]) -> Result<()>
   let object_index = operands[0].value(vm)?;
   let property_number = operands[1].value(vm)?;
   let new_value = operands[2].value(vm)?;
+  if !check_object(vm, object_index, "put_prop")? {
+    return Ok(());
+  }
 
   let object_table = vm.object_table()?;
   let object = object_table.object_with_number(object_index);
-  let property_table = object.property_table();
-  Ok(property_table.set_property(property_number, new_value))
+  let property_table = object.property_table()?;
+  property_table.set_property(property_number, new_value).or_else(|err| vm.report_error(err))
 }
 
 pub fn insert_obj_0x0e<T>(vm: &mut T, object_op: Operand, dest_op: Operand) -> Result<()>
   where T: VM {
   let object_index = object_op.value(vm)?;
   let dest_index = dest_op.value(vm)?;
+  if !check_object(vm, object_index, "insert_obj")? {
+    return Ok(());
+  }
 
   vm.object_table()?.insert_obj(object_index, dest_index)
 }
@@ -45,6 +51,9 @@ pub fn insert_obj_0x0e<T>(vm: &mut T, object_op: Operand, dest_op: Operand) -> R
 pub fn remove_obj_0x09<T>(vm: &mut T, object_op: Operand) -> Result<()>
   where T: VM {
   let object_number = object_op.value(vm)?;
+  if !check_object(vm, object_number, "remove_obj")? {
+    return Ok(());
+  }
   vm.object_table()?.remove_object_from_parent(object_number)
 }
 
@@ -56,14 +65,19 @@ pub fn remove_obj_0x09<T>(vm: &mut T, object_op: Operand) -> Result<()>
 pub fn test_attr_0x0a<T>(vm: &mut T, object_number: Operand, attr_number: Operand) -> Result<()>
   where T: VM {
   let object_number = object_number.value(vm)?;
+  let attr_number = attr_number.value(vm)?;
+  if !check_object(vm, object_number, "test_attr")? || !check_attribute(vm, attr_number)? {
+    return branch_binop(vm,
+                        Operand::SmallConstant(0),
+                        Operand::SmallConstant(0),
+                        |l, _| l != 0);
+  }
+
   let object_table = vm.object_table()?;
   let obj = object_table.object_with_number(object_number);
-  let attrs = obj.attributes();
-
-  let attr_number = attr_number.value(vm)?;
+  let attrs = obj.attributes()?;
 
-  // attribute bits are 0..31 - the reverse of what I expect.
-  let mask = 1u32 << (31u8 - attr_number as u8);
+  let mask = attribute_mask(attr_number);
   let masked = attrs & mask;
   let val = masked != 0;
 
@@ -76,46 +90,37 @@ pub fn test_attr_0x0a<T>(vm: &mut T, object_number: Operand, attr_number: Operan
 pub fn set_attr_0x0b<T>(vm: &mut T, object_number: Operand, attr_number: Operand) -> Result<()>
   where T: VM {
   let object_number = object_number.value(vm)?;
-  let object_table = vm.object_table()?;
-  let obj = object_table.object_with_number(object_number);
-  let attrs = {
-    obj.attributes()
-  };
-
   let attr_number = attr_number.value(vm)?;
+  if !check_object(vm, object_number, "set_attr")? || !check_attribute(vm, attr_number)? {
+    return Ok(());
+  }
 
-  // attribute bits are 0..31 - the reverse of what I expect.
-  let mask = 1u32 << (31u8 - attr_number as u8);
-  let new_attrs = attrs | mask;
-  obj.set_attributes(new_attrs);
-  Ok(())
+  let object_table = vm.object_table()?;
+  object_table.object_with_number(object_number).set_attribute(attr_number, true)
 }
 
 pub fn clear_attr_0x0c<T>(vm: &mut T, object_number: Operand, attr_number: Operand) -> Result<()>
   where T: VM {
   let object_number = object_number.value(vm)?;
-  let object_table = vm.object_table()?;
-  let obj = object_table.object_with_number(object_number);
-  let attrs = {
-    obj.attributes()
-  };
-
   let attr_number = attr_number.value(vm)?;
+  if !check_object(vm, object_number, "clear_attr")? || !check_attribute(vm, attr_number)? {
+    return Ok(());
+  }
 
-  // attribute bits are 0..31 - the reverse of what I expect.
-  let mask = !(1u32 << (31u8 - attr_number as u8));
-  let new_attrs = attrs & mask;
-  obj.set_attributes(new_attrs);
-  Ok(())
+  let object_table = vm.object_table()?;
+  object_table.object_with_number(object_number).set_attribute(attr_number, false)
 }
 
 pub fn get_parent_0x03<T>(vm: &mut T, object_number: Operand, variable: VariableRef) -> Result<()>
   where T: VM {
   // TODO: test get_parent_0x03
   let object_number = object_number.value(vm)?;
+  if !check_object(vm, object_number, "get_parent")? {
+    return vm.write_variable(variable, 0);
+  }
   let obj = vm.object_table()?.object_with_number(object_number);
 
-  let parent_number = obj.parent();
+  let parent_number = obj.parent()?;
   vm.write_variable(variable, parent_number)
 }
 
@@ -128,7 +133,19 @@ pub fn get_prop_len_0x04<T>(vm: &mut T, prop_addr: Operand, variable: VariableRe
     // TODO: fix this abstraction violation. Storage of the length is impl. dependent.
     let ptr = BytePtr::new(addr - 1);
     let byte = vm.read_memory_u8(ptr)?;
-    byte / 32 + 1
+    if vm.version() <= 3 {
+      byte / 32 + 1
+    } else if byte & 0b10000000 != 0 {
+      // The second of two size bytes, where 0 means 64.
+      match byte & 0b00111111 {
+        0 => 64,
+        size => size,
+      }
+    } else if byte & 0b01000000 != 0 {
+      2
+    } else {
+      1
+    }
   };
   vm.write_variable(variable, val as u16)
 }
@@ -142,20 +159,28 @@ pub fn get_prop_0x11<T>(vm: &mut T,
   // TODO: test get_prop_0x11
   let object_number = object_number.value(vm)?;
   let property_number = property_number.value(vm)?;
+  if !check_object(vm, object_number, "get_prop")? {
+    return vm.write_variable(variable, 0);
+  }
 
   let property_value = {
     let object_table = vm.object_table()?;
     let property_table = object_table.object_with_number(object_number)
-      .property_table();
-    let property = property_table.find_property(property_number);
+      .property_table()?;
+    let property = property_table.find_property(property_number)?;
 
     match property {
-      None => object_table.default_property_value(property_number),
+      None => object_table.default_property_value(property_number)?,
       Some((size, ptr)) => {
         match size {
-          1 => property_table.storage().byte_property(ptr),
-          2 => property_table.storage().word_property(ptr),
-          _ => panic!("Bad size"),
+          1 => property_table.storage().byte_property(ptr)?,
+          2 => property_table.storage().word_property(ptr)?,
+          _ => {
+            // The spec leaves this undefined. Read the first word, like
+            // other interpreters do.
+            vm.report_error(Error::BadPropertySize(property_number, size))?;
+            property_table.storage().word_property(ptr)?
+          }
         }
       }
     }
@@ -172,12 +197,15 @@ pub fn get_prop_addr_0x12<T>(vm: &mut T,
   // TODO: test get_prop_0x11
   let object_number = object_number.value(vm)?;
   let property_number = property_number.value(vm)?;
+  if !check_object(vm, object_number, "get_prop_addr")? {
+    return vm.write_variable(variable, 0);
+  }
 
   let value = {
     let object_table = vm.object_table()?;
     let property_table = object_table.object_with_number(object_number)
-      .property_table();
-    let property = property_table.find_property(property_number);
+      .property_table()?;
+    let property = property_table.find_property(property_number)?;
     property.map(|(_, ptr)| RawPtr::from(ptr).into()).unwrap_or(0)
   };
   vm.write_variable(variable, value as u16)
@@ -191,12 +219,21 @@ pub fn get_next_prop_0x13<T>(vm: &mut T,
   where T: VM {
   let object_number = object_number.value(vm)?;
   let property_number = property_number.value(vm)?;
+  if !check_object(vm, object_number, "get_next_prop")? {
+    return vm.write_variable(variable, 0);
+  }
 
   let value = {
     let object_table = vm.object_table()?;
     let property_table = object_table.object_with_number(object_number)
-      .property_table();
-    property_table.next_property(property_number)
+      .property_table()?;
+    match property_table.next_property(property_number) {
+      Ok(value) => value,
+      Err(err) => {
+        vm.report_error(err)?;
+        0
+      }
+    }
   };
   info!(target: "pctrace", "get_next_prop: {}, {} => {}",
         object_number,
@@ -208,4 +245,45 @@ pub fn get_next_prop_0x13<T>(vm: &mut T,
 #[cfg(test)]
 mod tests {
   // TODO: test everything in this file.
+  use zmachine::ops::Operand;
+  use zmachine::ops::testvm::TestVM;
+  use zmachine::vm::{ErrorMode, ErrorPolicy, VM, VariableRef, ZObject, ZObjectTable};
+
+  #[test]
+  fn test_get_parent_of_nothing() {
+    let mut vm = TestVM::new();
+    vm.push_stack(99).unwrap();
+    super::get_parent_0x03(&mut vm, Operand::SmallConstant(0), VariableRef::Stack).unwrap();
+    assert_eq!(0, vm.pop_stack().unwrap());
+    assert_eq!(vec!["NoObject(\"get_parent\")"], vm.warnings);
+  }
+
+  #[test]
+  fn test_set_attr_of_nothing() {
+    let mut vm = TestVM::new();
+    super::set_attr_0x0b(&mut vm, Operand::SmallConstant(0), Operand::SmallConstant(3)).unwrap();
+    assert_eq!(1, vm.warnings.len());
+
+    vm.errors = ErrorPolicy::new(ErrorMode::Fatal);
+    assert!(super::set_attr_0x0b(&mut vm, Operand::SmallConstant(0), Operand::SmallConstant(3))
+      .is_err());
+  }
+
+  #[test]
+  fn test_attributes_by_version() {
+    // Objects have 32 attributes before v4, and 48 from then on.
+    let mut vm = TestVM::new();
+    vm.object_storage.borrow_mut().add_mock_object(0, 0, 0, 0);
+    super::set_attr_0x0b(&mut vm, Operand::SmallConstant(1), Operand::SmallConstant(40)).unwrap();
+    assert_eq!(vec!["BadAttribute(40)"], vm.warnings);
+
+    vm.version = 4;
+    vm.warnings.clear();
+    super::set_attr_0x0b(&mut vm, Operand::SmallConstant(1), Operand::SmallConstant(40)).unwrap();
+    super::set_attr_0x0b(&mut vm, Operand::SmallConstant(1), Operand::SmallConstant(47)).unwrap();
+    super::set_attr_0x0b(&mut vm, Operand::SmallConstant(1), Operand::SmallConstant(48)).unwrap();
+    assert_eq!(vec!["BadAttribute(48)"], vm.warnings);
+    let attributes = vm.object_table().unwrap().object_with_number(1).attributes().unwrap();
+    assert_eq!(0x000000000081, attributes);
+  }
 }
@@ -0,0 +1,132 @@
+use unicode_width::UnicodeWidthChar;
+
+// Layout of the v1-3 status line (spec section 8.2): the location name on the
+// left and the score/moves (or the time) on the right.
+//
+// All lengths here are display widths (terminal columns), not bytes or chars,
+// so that accented and wide characters in a location name line up properly.
+// The laid out line always fills exactly the requested width, however narrow
+// the screen or long the name.
+
+const ELLIPSIS: &str = "...";
+
+/// The right-hand field for a score game.
+pub fn score_field(score: i16, moves: u16) -> String {
+  format!("Score: {} Moves: {}", score, moves)
+}
+
+/// The right-hand field for a "time game": the hours and minutes on a 12-hour
+/// clock.
+pub fn time_field(hours: u16, minutes: u16) -> String {
+  let (hours, suffix) = match hours % 24 {
+    0 => (12, "AM"),
+    hours @ 1..=11 => (hours, "AM"),
+    12 => (12, "PM"),
+    hours => (hours - 12, "PM"),
+  };
+  format!("Time: {}:{:02} {}", hours, minutes, suffix)
+}
+
+/// Lay out " name<spaces>right " in exactly `width` columns.
+///
+/// There is always a space at each end and at least one between the name and
+/// the right-hand field. The name is shortened to make room. If there isn't
+/// even room for the right-hand field, it is left out and the name gets the
+/// whole line.
+pub fn layout(name: &str, right: &str, width: usize) -> String {
+  if width < 3 {
+    return spaces(width);
+  }
+
+  let right_width = str_width(right);
+  let (name_room, right) = if right_width + 3 <= width {
+    (width - right_width - 3, right)
+  } else {
+    (width - 2, "")
+  };
+
+  let name = ellipsize(name, name_room);
+  let gap = width - 2 - str_width(&name) - str_width(right);
+  format!(" {}{}{} ", name, spaces(gap), right)
+}
+
+/// Shorten `str` to at most `width` columns, ending with "..." if anything had
+/// to be removed (and there is room for it).
+pub fn ellipsize(str: &str, width: usize) -> String {
+  if str_width(str) <= width {
+    return str.to_string();
+  }
+
+  let ellipsis = if width > ELLIPSIS.len() { ELLIPSIS } else { "" };
+  let mut short = String::new();
+  let mut used = 0;
+  for ch in str.chars() {
+    let ch_width = char_width(ch);
+    if used + ch_width + ellipsis.len() > width {
+      break;
+    }
+    short.push(ch);
+    used += ch_width;
+  }
+  short.push_str(ellipsis);
+  short
+}
+
+fn str_width(str: &str) -> usize {
+  str.chars().map(char_width).sum()
+}
+
+// Control characters have no width of their own. They shouldn't be in a
+// location name, but we mustn't fall over if they are.
+fn char_width(ch: char) -> usize {
+  ch.width().unwrap_or(0)
+}
+
+fn spaces(count: usize) -> String {
+  (0..count).map(|_| ' ').collect()
+}
+
+#[cfg(test)]
+mod test {
+  use super::{ellipsize, layout, score_field, str_width, time_field};
+
+  #[test]
+  fn test_fields() {
+    assert_eq!("Score: -5 Moves: 12", score_field(-5, 12));
+    assert_eq!("Time: 12:00 AM", time_field(0, 0));
+    assert_eq!("Time: 9:05 AM", time_field(9, 5));
+    assert_eq!("Time: 12:30 PM", time_field(12, 30));
+    assert_eq!("Time: 11:59 PM", time_field(23, 59));
+  }
+
+  #[test]
+  fn test_ellipsize() {
+    assert_eq!("West of House", ellipsize("West of House", 13));
+    assert_eq!("West of...", ellipsize("West of House", 10));
+    assert_eq!("Wes", ellipsize("West of House", 3));
+    assert_eq!("", ellipsize("West of House", 0));
+
+    // Wide characters are two columns and are never split.
+    assert_eq!("東京", ellipsize("東京", 4));
+    assert_eq!("...", ellipsize("東京タワー", 4));
+    assert_eq!("東...", ellipsize("東京タワー", 6));
+    assert_eq!("Café...", ellipsize("Café de Flore", 7));
+  }
+
+  #[test]
+  fn test_layout() {
+    assert_eq!(" West of House     Score: 0 Moves: 1 ",
+               layout("West of House", "Score: 0 Moves: 1", 37));
+    assert_eq!(" West... Score: 0 Moves: 1 ",
+               layout("West of House", "Score: 0 Moves: 1", 27));
+    // No room for the score.
+    assert_eq!(" West of House ", layout("West of House", "Score: 0 Moves: 1", 15));
+    assert_eq!(" W... ", layout("West of House", "Score: 0 Moves: 1", 6));
+    assert_eq!("  ", layout("West of House", "Score: 0 Moves: 1", 2));
+    assert_eq!("", layout("West of House", "Score: 0 Moves: 1", 0));
+
+    for width in 0..60 {
+      assert_eq!(width, str_width(&layout("Café 東京", "Time: 12:30 PM", width)));
+    }
+  }
+}
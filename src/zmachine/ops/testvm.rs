@@ -1,8 +1,9 @@
 use byteorder::{BigEndian, ByteOrder};
-use result::Result;
-use zmachine::vm::{BytePtr, Memory, RawPtr, VM, VariableRef, WordPtr};
-use zmachine::vm::test::{MockObjectTable, MockObjectTableStorage, MockPropertyTable,
-                         MockPropertyTableStorage};
+use result::{Error, Result};
+use zmachine::vm::{BytePtr, ErrorMode, ErrorPolicy, RawPtr, VM, VariableRef, WordPtr};
+use std::cell::RefCell;
+use std::rc::Rc;
+use zmachine::vm::test::{MockObjectTable, MockObjectTableStorage, MockPropertyTable};
 
 /// All opcodes are implemented in terms of the VM trait.
 /// This means that we can test all of the opcodes without creating an
@@ -15,8 +16,14 @@ pub struct TestVM {
   pub globals: [u16; 240],
   pub pc: usize,
   pub pcbytes: Vec<u8>,
-  pub object_storage: MockObjectTableStorage,
-  pub property_storage: MockPropertyTableStorage,
+  pub branch_taken: Option<bool>,
+  pub object_storage: Rc<RefCell<MockObjectTableStorage>>,
+  pub version: u8,
+  pub screen_width: u16,
+  pub status_line: String,
+  pub main_window: String,
+  pub errors: ErrorPolicy,
+  pub warnings: Vec<String>,
 }
 
 impl TestVM {
@@ -28,8 +35,14 @@ impl TestVM {
       globals: [0; 240],
       pc: 0,
       pcbytes: Vec::new(),
-      object_storage: MockObjectTableStorage::new(),
-      property_storage: MockPropertyTableStorage::new(),
+      branch_taken: None,
+      object_storage: Rc::new(RefCell::new(MockObjectTableStorage::new())),
+      version: 3,
+      screen_width: 80,
+      status_line: String::new(),
+      main_window: String::new(),
+      errors: ErrorPolicy::new(ErrorMode::WarnAlways),
+      warnings: Vec::new(),
     }
   }
 
@@ -65,24 +78,45 @@ impl TestVM {
     BigEndian::write_u16(vec.as_mut_slice(), word as u16);
     self.set_pcbytes(vec);
   }
+
+  /// A VM holding a small story for tests that walk routines: a main
+  /// routine at 0x100 that calls a one-local routine at 0x120, which counts
+  /// its local down in a loop and returns.
+  pub fn with_story() -> TestVM {
+    let mut vm = TestVM::new();
+    let code = [// Main routine at 0x100: call 0x90 (packed) -> sp, then quit.
+                0xe0, 0x3f, 0x00, 0x90, 0x00,
+                0xba,
+                // Routine at 0x120, one local.
+                0x01, 0x00, 0x05,
+                // L0001: dec_chk L00 #00 [FALSE] L0001
+                0x04, 0x01, 0x00, 0x3f, 0xfd,
+                // rtrue
+                0xb0];
+    vm.heap[0x100..0x106].copy_from_slice(&code[0..6]);
+    vm.heap[0x120..0x129].copy_from_slice(&code[6..]);
+    vm
+  }
 }
 
 impl VM for TestVM {
   type ObjTable = MockObjectTable;
-  type ObjStorage = MockObjectTableStorage;
   type PropertyTable = MockPropertyTable;
-  type PropertyAccess = MockPropertyTableStorage;
 
-  fn read_pc_byte(&mut self) -> u8 {
+  fn version(&self) -> u8 {
+    self.version
+  }
+
+  fn read_pc_byte(&mut self) -> Result<u8> {
     let val = self.pcbytes[self.pc];
     self.pc += 1;
-    val
+    Ok(val)
   }
 
-  fn read_pc_word(&mut self) -> u16 {
+  fn read_pc_word(&mut self) -> Result<u16> {
     let val = BigEndian::read_u16(&self.pcbytes[self.pc..]);
     self.pc += 2;
-    val
+    Ok(val)
   }
 
   fn current_pc(&self) -> usize {
@@ -98,9 +132,14 @@ impl VM for TestVM {
     Ok(self.pc = ((self.pc as i32) + (offset as i32)) as usize)
   }
 
+  fn set_branch_taken(&mut self, taken: bool) {
+    self.branch_taken = Some(taken);
+  }
+
 
   fn new_frame(&mut self,
                ret_pc: usize,
+               _routine: usize,
                num_locals: u8,
                result_location: VariableRef)
                -> Result<()> {
@@ -138,6 +177,11 @@ impl VM for TestVM {
     Ok(())
   }
 
+  // The globals aren't in the heap, so they are given addresses after it.
+  fn global_addr(&self, global_idx: u8) -> Result<usize> {
+    Ok(self.heap.len() + global_idx as usize * 2)
+  }
+
   fn read_memory<T>(&self, ptr: T) -> Result<u16>
     where T: Into<RawPtr> {
     Ok(BigEndian::read_u16(&self.heap[ptr.into().ptr()..]))
@@ -154,60 +198,111 @@ impl VM for TestVM {
     Ok(self.heap[ptr.into().ptr()])
   }
 
+  fn write_memory_u8<T>(&mut self, ptr: T, val: u8) -> Result<()>
+    where T: Into<RawPtr> {
+    self.heap[ptr.into().ptr()] = val;
+    Ok(())
+  }
+
   fn object_table(&self) -> Result<Self::ObjTable> {
-    Ok(MockObjectTable::new())
+    Ok(MockObjectTable::new(self.object_storage.clone()))
+  }
+
+  fn terminating_chars(&self) -> Result<Vec<u16>> {
+    Ok(Vec::new())
+  }
+
+  fn num_dict_entries(&self) -> u16 {
+    unimplemented!()
+  }
+
+  fn dict_entry(&self, _number: u16) -> BytePtr {
+    unimplemented!()
+  }
+
+  fn rand(&mut self, _range: u16) -> Result<u16> {
+    unimplemented!()
+  }
+
+  fn report_error(&mut self, err: Error) -> Result<()> {
+    if let Some(err) = self.errors.check(err)? {
+      self.warnings.push(format!("{:?}", err));
+    }
+    Ok(())
+  }
+
+  fn write_status_line(&mut self, text: &str) -> Result<()> {
+    self.status_line = text.to_string();
+    Ok(())
+  }
+
+  fn screen_width(&self) -> u16 {
+    self.screen_width
   }
 
-  fn object_storage(&self) -> &Self::ObjStorage {
-    &self.object_storage
+  fn write_main_window_char(&mut self, ch: u16) -> Result<()> {
+    self.main_window.extend(::std::char::from_u32(ch as u32));
+    Ok(())
   }
 
-  fn object_storage_mut(&mut self) -> &mut Self::ObjStorage {
-    &mut self.object_storage
+  fn write_main_window(&mut self, text: &str) -> Result<()> {
+    self.main_window.push_str(text);
+    Ok(())
   }
 
-  fn property_storage(&self) -> &Self::PropertyAccess {
-    &self.property_storage
+  fn set_text_style(&mut self, _style: u16) -> Result<()> {
+    Ok(())
   }
 
-  fn property_storage_mut(&mut self) -> &mut Self::PropertyAccess {
-    &mut self.property_storage
+  fn set_buffer_mode(&mut self, _buffered: bool) -> Result<()> {
+    Ok(())
   }
 
-  // fn attributes(&mut self, object_number: u16) -> Result<u32> {
-  //   unimplemented!()
-  // }
+  fn split_window(&mut self, _lines: u16) -> Result<()> {
+    Ok(())
+  }
 
-  // fn set_attributes(&mut self, object_number: u16, attrs: u32) -> Result<()> {
-  //   unimplemented!()
-  // }
+  fn set_window(&mut self, _window: u16) -> Result<()> {
+    Ok(())
+  }
 
-  // fn parent_number(&self, object_number: u16) -> Result<u16> {
-  //   unimplemented!()
-  // }
+  fn erase_window(&mut self, _window: i16) -> Result<()> {
+    Ok(())
+  }
 
-  // fn child_number(&self, object_number: u16) -> Result<u16> {
-  //   unimplemented!()
-  // }
+  fn erase_line(&mut self) -> Result<()> {
+    Ok(())
+  }
 
-  // fn sibling_number(&self, object_number: u16) -> Result<u16> {
-  //   unimplemented!()
-  // }
+  fn set_cursor(&mut self, _line: u16, _column: u16) -> Result<()> {
+    Ok(())
+  }
 
-  // fn put_property(&mut self, object_number: u16, property_index: u16, value: u16) -> Result<()> {
-  //   unimplemented!()
-  // }
-  // fn insert_obj(&mut self, object_number: u16, dest_number: u16) -> Result<()> {
-  //   unimplemented!()
-  // }
-  // fn object_name(&self, object_number: u16) -> Result<RawPtr> {
-  //   unimplemented!()
-  // }
-  // fn get_property(&self, object_number: u16, property_number: u16) -> Result<u16> {
-  //   unimplemented!()
-  // }
+  fn cursor(&self) -> Result<(u16, u16)> {
+    Ok((1, 1))
+  }
+
+  fn set_colour(&mut self, _foreground: u16, _background: u16) -> Result<()> {
+    Ok(())
+  }
+
+  fn select_output_stream(&mut self, _stream: i16, _table: u16) -> Result<()> {
+    Ok(())
+  }
+
+  fn read_line(&mut self, _max_len: usize, _terminators: &[u16]) -> Result<(String, u16)> {
+    unimplemented!()
+  }
+
+  fn read_char(&mut self) -> Result<u16> {
+    unimplemented!()
+  }
 
   fn abbrev_addr(&self, abbrev_table: u8, abbrev_index: u8) -> Result<WordPtr> {
     unimplemented!()
   }
+
+  fn save(&self) -> Result<()> {
+    unimplemented!()
+  }
 }
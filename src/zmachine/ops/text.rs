@@ -1,6 +1,8 @@
-use result::Result;
-use zmachine::ops::Operand;
-use zmachine::vm::{BytePtr, PackedAddr, RawPtr, VM, ZObject, ZObjectTable, ZPropertyTable};
+use result::{Error, Result};
+use zmachine::ops::{Operand, check_object};
+use zmachine::ops::status_line;
+use zmachine::vm::{BytePtr, PackedAddr, RawPtr, VM, ZObject, ZObjectTable, ZPropertyTable,
+                   colour_from_true_colour};
 
 const ROW1: [char; 26] = ['a', 'b', 'c', 'd', 'e', 'f', 'g', 'h', 'i', 'j', 'k', 'l', 'm', 'n',
                           'o', 'p', 'q', 'r', 's', 't', 'u', 'v', 'w', 'x', 'y', 'z'];
@@ -9,6 +11,10 @@ const ROW2: [char; 26] = ['A', 'B', 'C', 'D', 'E', 'F', 'G', 'H', 'I', 'J', 'K',
 const ROW3: [char; 26] = ['@', '\n', '0', '1', '2', '3', '4', '5', '6', '7', '8', '9', '.', ',',
                           '!', '?', '_', '#', '\'', '"', '/', '\\', '-', ':', '(', ')'];
 
+// Flags 1 bit 1 (v3): the status line shows the time rather than the score.
+const FLAG1_INDEX: u16 = 0x01;
+const FLAG1_TIME_GAME: u8 = 0x02;
+
 // TODO: remove all of the BytePtrs from the API and replace with RawPtr.
 enum State {
   Normal,
@@ -21,8 +27,9 @@ enum TextSource {
   Memory(RawPtr, bool),
 }
 
-pub fn decode_at<T>(vm: &mut T, ptr: BytePtr) -> Result<String>
-  where T: VM {
+pub fn decode_at<T, P>(vm: &mut T, ptr: P) -> Result<String>
+  where T: VM,
+        P: Into<RawPtr> {
   let source = TextSource::Memory(ptr.into(), false);
   decode_text(vm, source)
 }
@@ -45,7 +52,7 @@ fn decode_text<T>(vm: &mut T, src: TextSource) -> Result<String>
   loop {
     // TODO: create a Trait for reading words to simplify this code.
     let word = if from_pc {
-      Ok(vm.read_pc_word())
+      vm.read_pc_word()
     } else {
       // text_ptr should always be Some() unless from_pc is true.
       let mut ptr = text_ptr.unwrap();
@@ -80,7 +87,7 @@ fn decode_text<T>(vm: &mut T, src: TextSource) -> Result<String>
                 }
                 0x01u16...0x03u16 => {
                   if in_abbrev {
-                    panic!("Attempted to read abbrev in abbrev");
+                    return Err(Error::AbbrevInAbbrev);
                   }
                   abbrev_set = Some(*ch);
                   row = ROW1;
@@ -117,15 +124,15 @@ fn decode_text<T>(vm: &mut T, src: TextSource) -> Result<String>
 pub fn print_0x02<T>(vm: &mut T) -> Result<()>
   where T: VM {
   let s = decode_text(vm, TextSource::PC)?;
-  vm.write_main_window(s.as_str());
+  vm.write_main_window(s.as_str())?;
   Ok(())
 }
 
 pub fn print_ret_0x03<T>(vm: &mut T) -> Result<()>
   where T: VM {
   let s = decode_text(vm, TextSource::PC)?;
-  vm.write_main_window(s.as_str());
-  vm.write_main_window("\n");
+  vm.write_main_window(s.as_str())?;
+  vm.write_main_window("\n")?;
   vm.ret_value(1)
 }
 
@@ -134,20 +141,22 @@ pub fn print_num_0x06<T>(vm: &mut T, operands: [Operand; 4]) -> Result<()>
   // We only care about the first operand.
   let value = operands[0].value(vm)?;
   let str = format!("{}", value);
-  vm.write_main_window(str.as_str());
+  vm.write_main_window(str.as_str())?;
   Ok(())
 }
 
 pub fn print_obj_0x0a<T>(vm: &mut T, operand: Operand) -> Result<()>
   where T: VM {
-  let object_table = vm.object_table()?;
-
   // TODO: test print_obj_0x0a
   let object_number = operand.value(vm)?;
+  if !check_object(vm, object_number, "print_obj")? {
+    return Ok(());
+  }
+  let object_table = vm.object_table()?;
   let obj = object_table.object_with_number(object_number);
-  let ptr = obj.property_table().name_ptr();
+  let ptr = obj.property_table()?.name_ptr();
   let str = decode_text(vm, TextSource::Memory(ptr.into(), false))?;
-  vm.write_main_window(str.as_str());
+  vm.write_main_window(str.as_str())?;
   Ok(())
 }
 
@@ -155,7 +164,7 @@ pub fn print_addr_0x07<T>(vm: &mut T, operand: Operand) -> Result<()>
   where T: VM {
   let addr = BytePtr::new(operand.value(vm)?);
   let str = decode_text(vm, TextSource::Memory(addr.into(), false))?;
-  vm.write_main_window(str.as_str());
+  vm.write_main_window(str.as_str())?;
   Ok(())
 }
 
@@ -163,7 +172,7 @@ pub fn print_paddr_0x0d<T>(vm: &mut T, operand: Operand) -> Result<()>
   where T: VM {
   let paddr = PackedAddr::new(operand.value(vm)?);
   let str = decode_text(vm, TextSource::Memory(paddr.into(), false))?;
-  vm.write_main_window(str.as_str());
+  vm.write_main_window(str.as_str())?;
   Ok(())
 }
 
@@ -171,12 +180,12 @@ pub fn print_char_0x05<T>(vm: &mut T, operands: [Operand; 4]) -> Result<()>
   where T: VM {
   let ch = operands[0].value(vm)?;
   match ch {
-    13 => vm.write_main_window_char('\n' as u16),
+    13 => vm.write_main_window_char('\n' as u16)?,
     32...126 => {
-      vm.write_main_window_char(ch as u8 as char as u16);
+      vm.write_main_window_char(ch as u8 as char as u16)?;
     }
     _ => {
-      vm.write_main_window("?");
+      vm.write_main_window("?")?;
     }
   }
   Ok(())
@@ -184,42 +193,86 @@ pub fn print_char_0x05<T>(vm: &mut T, operands: [Operand; 4]) -> Result<()>
 
 pub fn new_line_0x0b<T>(vm: &mut T) -> Result<()>
   where T: VM {
-  vm.write_main_window("\n");
+  vm.write_main_window("\n")?;
   Ok(())
 }
 
 pub fn show_status_0x0c<T>(vm: &mut T) -> Result<()>
   where T: VM {
   let object_number = vm.read_global(0)?;
-  let object_table = vm.object_table()?;
-  let object = object_table.object_with_number(object_number);
-  let property_table = object.property_table();
-  let name_ptr = property_table.name_ptr();
-  let name_str = decode_at(vm, name_ptr)?;
+  // Until the game sets the location, there isn't one to show.
+  let name_str = if object_number == 0 {
+    String::new()
+  } else {
+    let object_table = vm.object_table()?;
+    let object = object_table.object_with_number(object_number);
+    let name_ptr = object.property_table()?.name_ptr();
+    decode_at(vm, name_ptr)?
+  };
 
   let first_num = vm.read_global(1)?;
   let second_num = vm.read_global(2)?;
-  let score_str = format!("Score: {} Moves: {}", first_num, second_num);
+  let flag1 = vm.read_memory_u8(BytePtr::new(FLAG1_INDEX))?;
+  let right_str = if flag1 & FLAG1_TIME_GAME != 0 {
+    status_line::time_field(first_num, second_num)
+  } else {
+    status_line::score_field(first_num as i16, second_num)
+  };
 
-  // TODO: ellipsize this.
-  let width = vm.screen_width();
-  let mut spaces = String::new();
-  for _ in 0..(width as usize - name_str.len() - score_str.len() - 2) {
-    spaces.push(' ');
-  }
+  let width = vm.screen_width() as usize;
+  let str = status_line::layout(&name_str, &right_str, width);
+  vm.write_status_line(str.as_str())?;
+  Ok(())
+}
 
-  let str = format!(" {}{}{} ", name_str, spaces, score_str);
+pub fn set_text_style_0x11<T>(vm: &mut T, operands: [Operand; 4]) -> Result<()>
+  where T: VM {
+  let style = operands[0].value(vm)?;
+  vm.set_text_style(style)
+}
 
-  // TODO: time-based status line.
-  vm.write_status_line(str.as_str());
+pub fn buffer_mode_0x12<T>(vm: &mut T, operands: [Operand; 4]) -> Result<()>
+  where T: VM {
+  let flag = operands[0].value(vm)?;
+  vm.set_buffer_mode(flag != 0)
+}
+
+pub fn set_colour_0x1b<T>(vm: &mut T, foreground: Operand, background: Operand) -> Result<()>
+  where T: VM {
+  let foreground = foreground.value(vm)?;
+  let background = background.value(vm)?;
+  vm.set_colour(foreground, background)
+}
+
+pub fn set_true_colour_0x0d<T>(vm: &mut T, operands: [Operand; 4]) -> Result<()>
+  where T: VM {
+  // The v6 window operand is ignored.
+  let foreground = colour_from_true_colour(operands[0].value(vm)?);
+  let background = colour_from_true_colour(operands[1].value(vm)?);
+  vm.set_colour(foreground, background)
+}
+
+pub fn output_stream_0x13<T>(vm: &mut T, operands: [Operand; 4]) -> Result<()>
+  where T: VM {
+  // Only stream 3 has a table.
+  let stream = operands[0].value(vm)? as i16;
+  let table = if stream == 3 {
+    operands[1].value(vm)?
+  } else {
+    0
+  };
+  if stream != 0 {
+    vm.select_output_stream(stream, table)?;
+  }
   Ok(())
 }
 
 #[cfg(test)]
 mod test {
-  use super::{TextSource, decode_text};
+  use super::{TextSource, decode_text, show_status_0x0c};
+  use unicode_width::UnicodeWidthStr;
   use zmachine::ops::testvm::TestVM;
-  use zmachine::vm::BytePtr;
+  use zmachine::vm::{BytePtr, VM};
 
   // TODO: add test with ZSCII in it.
 
@@ -283,4 +336,44 @@ mod test {
 
   // TODO: test abbrevs!
   // TODO: test all of the print opcodes after writing an output abstraction.
+
+  #[test]
+  fn test_show_status() {
+    // The mock object table puts every object's name at 32. Use "quux" from
+    // test_long_string_from_heap.
+    let mut vm = TestVM::new();
+    vm.object_storage.borrow_mut().add_mock_object(0, 0, 0, 0);
+    vm.heap[32..36].copy_from_slice(&[0x5b, 0x5a, 0xf4, 0xa5]);
+    vm.write_global(0, 1).unwrap();
+    vm.write_global(1, -3i16 as u16).unwrap();
+    vm.write_global(2, 412).unwrap();
+
+    vm.screen_width = 30;
+    show_status_0x0c(&mut vm).unwrap();
+    assert_eq!(" quux    Score: -3 Moves: 412 ", vm.status_line);
+
+    // Time game.
+    vm.heap[1] = 0x02;
+    vm.write_global(1, 13).unwrap();
+    vm.write_global(2, 7).unwrap();
+    show_status_0x0c(&mut vm).unwrap();
+    assert_eq!(" quux           Time: 1:07 PM ", vm.status_line);
+  }
+
+  #[test]
+  fn test_show_status_widths() {
+    let mut vm = TestVM::new();
+    vm.object_storage.borrow_mut().add_mock_object(0, 0, 0, 0);
+    vm.heap[32..36].copy_from_slice(&[0x5b, 0x5a, 0xf4, 0xa5]);
+    vm.write_global(0, 1).unwrap();
+    vm.write_global(1, 350).unwrap();
+    vm.write_global(2, 65535).unwrap();
+
+    for width in 10..201 {
+      vm.screen_width = width;
+      show_status_0x0c(&mut vm).unwrap();
+      assert_eq!(width as usize, vm.status_line.width());
+      assert!(vm.status_line.starts_with(' ') && vm.status_line.ends_with(' '));
+    }
+  }
 }
@@ -0,0 +1,45 @@
+use result::Result;
+use zmachine::ops::Operand;
+use zmachine::vm::{BytePtr, VM};
+
+pub fn split_window_0x0a<T>(vm: &mut T, operands: [Operand; 4]) -> Result<()>
+  where T: VM {
+  let lines = operands[0].value(vm)?;
+  vm.split_window(lines)
+}
+
+pub fn set_window_0x0b<T>(vm: &mut T, operands: [Operand; 4]) -> Result<()>
+  where T: VM {
+  let window = operands[0].value(vm)?;
+  vm.set_window(window)
+}
+
+pub fn erase_window_0x0d<T>(vm: &mut T, operands: [Operand; 4]) -> Result<()>
+  where T: VM {
+  let window = operands[0].value(vm)? as i16;
+  vm.erase_window(window)
+}
+
+pub fn erase_line_0x0e<T>(vm: &mut T, operands: [Operand; 4]) -> Result<()>
+  where T: VM {
+  // Any value other than 1 does nothing (before v6).
+  if operands[0].value(vm)? == 1 {
+    vm.erase_line()?;
+  }
+  Ok(())
+}
+
+pub fn set_cursor_0x0f<T>(vm: &mut T, operands: [Operand; 4]) -> Result<()>
+  where T: VM {
+  let line = operands[0].value(vm)?;
+  let column = operands[1].value(vm)?;
+  vm.set_cursor(line, column)
+}
+
+pub fn get_cursor_0x10<T>(vm: &mut T, operands: [Operand; 4]) -> Result<()>
+  where T: VM {
+  let array = BytePtr::new(operands[0].value(vm)?);
+  let (line, column) = vm.cursor()?;
+  vm.write_memory(array, line)?;
+  vm.write_memory(array.inc_by(2), column)
+}
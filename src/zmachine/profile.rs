@@ -0,0 +1,313 @@
+use result::Result;
+use std::collections::HashMap;
+use std::io::Write;
+use std::mem;
+use zmachine::debug_info::DebugInfo;
+
+// A profiler. It is told the name of each instruction as it runs, and about
+// each call and return, so that it knows the call stack. It counts:
+//
+//   - instructions run, by opcode;
+//   - instructions run in each routine (exclusive) and in each routine or
+//     anything it called (inclusive);
+//   - calls to each routine;
+//   - instructions run with each call stack, for flame graphs.
+//
+// So that counting an instruction takes the same time however deep the call
+// stack is, each call stack is kept once, as the one below it plus the routine
+// called, and the profiler keeps track of which one it is in. A routine's
+// inclusive count is added up when it leaves the call stack, from how many
+// instructions had run when it went on.
+
+#[derive(Clone,Copy,Debug,Default,PartialEq)]
+struct Counts {
+  calls: u64,
+  exclusive: u64,
+  inclusive: u64,
+}
+
+// A call stack: the routine on top, and the call stack below it (None for the
+// main routine on its own).
+struct CallStack {
+  below: Option<usize>,
+  routine: usize,
+  instructions: u64,
+}
+
+#[derive(Default)]
+pub struct Profiler {
+  instructions: u64,
+  calls: u64,
+  opcodes: HashMap<&'static str, u64>,
+  routines: HashMap<usize, Counts>,
+  // Every call stack so far, by index. The first is the main routine's.
+  stacks: Vec<CallStack>,
+  // The call stack each (call stack, routine) call leads to.
+  calls_from: HashMap<(usize, usize), usize>,
+  // The call stack now.
+  stack: usize,
+  // For each routine on the call stack, how many times it is on it (more
+  // than once if it is recursive), and how many instructions had run when it
+  // first went on.
+  active: HashMap<usize, (u32, u64)>,
+}
+
+impl Profiler {
+  pub fn new() -> Profiler {
+    let mut profiler = Profiler {
+      stacks: vec![CallStack {
+                     below: None,
+                     routine: 0,
+                     instructions: 0,
+                   }],
+      ..Profiler::default()
+    };
+    // The game starts in the main routine, without calling it.
+    profiler.active.insert(0, (1, 0));
+    profiler
+  }
+
+  /// Count an instruction, run in the routine on top of the call stack.
+  pub fn count(&mut self, opcode: &'static str) {
+    self.instructions += 1;
+    *self.opcodes.entry(opcode).or_insert(0) += 1;
+
+    let stack = &mut self.stacks[self.stack];
+    stack.instructions += 1;
+    self.routines.entry(stack.routine).or_default().exclusive += 1;
+  }
+
+  /// The routine at `routine` has been called.
+  pub fn called(&mut self, routine: usize) {
+    self.calls += 1;
+    self.routines.entry(routine).or_default().calls += 1;
+    self.push(routine);
+  }
+
+  /// The routine on top of the call stack has returned.
+  pub fn returned(&mut self) {
+    let stack = &self.stacks[self.stack];
+    // The main routine never returns.
+    if let Some(below) = stack.below {
+      let routine = stack.routine;
+      self.stack = below;
+      self.leave(routine);
+    }
+  }
+
+  /// Carry on from another call stack (after going back in time, say).
+  /// `routines` are outermost (the main routine, 0) first.
+  pub fn set_call_stack(&mut self, routines: Vec<usize>) {
+    for (routine, (_, entered)) in mem::take(&mut self.active) {
+      self.add_inclusive(routine, entered);
+    }
+    self.stack = 0;
+    self.active.insert(0, (1, self.instructions));
+    for routine in routines.into_iter().skip(1) {
+      self.push(routine);
+    }
+  }
+
+  fn push(&mut self, routine: usize) {
+    let below = self.stack;
+    let stacks = &mut self.stacks;
+    self.stack = *self.calls_from.entry((below, routine)).or_insert_with(|| {
+      stacks.push(CallStack {
+        below: Some(below),
+        routine,
+        instructions: 0,
+      });
+      stacks.len() - 1
+    });
+    self.active.entry(routine).or_insert((0, self.instructions)).0 += 1;
+  }
+
+  // A recursive routine's inclusive count starts when it is first called and
+  // ends when its last frame returns.
+  fn leave(&mut self, routine: usize) {
+    let entered = match self.active.get_mut(&routine) {
+      Some(&mut (ref mut frames, entered)) => {
+        *frames -= 1;
+        if *frames > 0 {
+          return;
+        }
+        entered
+      }
+      None => return,
+    };
+    self.active.remove(&routine);
+    self.add_inclusive(routine, entered);
+  }
+
+  fn add_inclusive(&mut self, routine: usize, entered: u64) {
+    let ran = self.instructions - entered;
+    self.routines.entry(routine).or_default().inclusive += ran;
+  }
+
+  // The instructions run in a routine or anything it called, including the
+  // ones since it last went on the call stack if it is still on it.
+  fn inclusive(&self, routine: usize, counts: &Counts) -> u64 {
+    let running = self.active.get(&routine).map_or(0, |&(_, entered)| self.instructions - entered);
+    counts.inclusive + running
+  }
+
+  /// Write the report: the opcodes by how often they ran, then the routines by
+  /// how many instructions they ran themselves.
+  pub fn write_report<W>(&self, names: &DebugInfo, out: &mut W) -> Result<()>
+    where W: Write {
+    writeln!(out,
+             "Profile: {} instructions, {} calls\n",
+             self.instructions,
+             self.calls)?;
+
+    let mut opcodes = self.opcodes.iter().collect::<Vec<_>>();
+    opcodes.sort_by(|&(name_a, count_a), &(name_b, count_b)| {
+      count_b.cmp(count_a).then(name_a.cmp(name_b))
+    });
+    writeln!(out, "     count       %  opcode")?;
+    for (name, count) in opcodes {
+      writeln!(out, "{:10} {:6.1}%  {}", count, self.percent(*count), name)?;
+    }
+
+    let mut routines = self.routines.iter().collect::<Vec<_>>();
+    routines.sort_by(|&(addr_a, counts_a), &(addr_b, counts_b)| {
+      counts_b.exclusive.cmp(&counts_a.exclusive).then(addr_a.cmp(addr_b))
+    });
+    writeln!(out, "\n     calls   exclusive       %   inclusive       %  routine")?;
+    for (addr, counts) in routines {
+      let inclusive = self.inclusive(*addr, counts);
+      writeln!(out,
+               "{:10}  {:10} {:6.1}%  {:10} {:6.1}%  {}",
+               counts.calls,
+               counts.exclusive,
+               self.percent(counts.exclusive),
+               inclusive,
+               self.percent(inclusive),
+               routine_text(*addr, names))?;
+    }
+    Ok(())
+  }
+
+  /// Write the call stacks in the "folded" format that flame graph tools read:
+  /// one line for each, with its routines outermost first, separated by
+  /// semicolons, then the number of instructions run with it.
+  pub fn write_folded<W>(&self, names: &DebugInfo, out: &mut W) -> Result<()>
+    where W: Write {
+    let mut stacks = self.stacks
+      .iter()
+      .enumerate()
+      .filter(|&(_, stack)| stack.instructions > 0)
+      .map(|(idx, stack)| (self.routines_in(idx), stack.instructions))
+      .collect::<Vec<_>>();
+    stacks.sort();
+    for (routines, count) in stacks {
+      let frames = routines.iter()
+        .map(|&addr| match (addr, names.routine_name(addr)) {
+          (0, _) => "main".to_string(),
+          (_, Some(name)) => name.to_string(),
+          (_, None) => format!("{:04x}", addr),
+        })
+        .collect::<Vec<_>>();
+      writeln!(out, "{} {}", frames.join(";"), count)?;
+    }
+    Ok(())
+  }
+
+  // The routines in a call stack, outermost first.
+  fn routines_in(&self, stack: usize) -> Vec<usize> {
+    let mut routines = Vec::new();
+    let mut next = Some(stack);
+    while let Some(idx) = next {
+      routines.push(self.stacks[idx].routine);
+      next = self.stacks[idx].below;
+    }
+    routines.reverse();
+    routines
+  }
+
+  fn percent(&self, count: u64) -> f64 {
+    if self.instructions == 0 {
+      0.0
+    } else {
+      100.0 * count as f64 / self.instructions as f64
+    }
+  }
+}
+
+fn routine_text(addr: usize, names: &DebugInfo) -> String {
+  match (addr, names.routine_name(addr)) {
+    (0, _) => "(main routine)".to_string(),
+    (_, Some(name)) => format!("{} ({:04x})", name, addr),
+    (_, None) => format!("{:04x}", addr),
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::Profiler;
+  use zmachine::debug_info::DebugInfo;
+
+  fn profile() -> Profiler {
+    let mut profiler = Profiler::new();
+    profiler.count("call");
+    profiler.called(0x400);
+    profiler.count("add");
+    profiler.count("call");
+    // 0x400 calls itself.
+    profiler.called(0x400);
+    profiler.count("add");
+    profiler.count("ret");
+    profiler.returned();
+    profiler.count("ret");
+    profiler.returned();
+    profiler.count("call");
+    profiler.called(0x500);
+    profiler.count("rtrue");
+    profiler.returned();
+    profiler.count("quit");
+    profiler
+  }
+
+  #[test]
+  fn test_report() {
+    let mut out = Vec::new();
+    profile().write_report(&DebugInfo::new(), &mut out).unwrap();
+    assert_eq!("Profile: 9 instructions, 3 calls\n\n     \
+                count       %  opcode\n         \
+                3   33.3%  call\n         \
+                2   22.2%  add\n         \
+                2   22.2%  ret\n         \
+                1   11.1%  quit\n         \
+                1   11.1%  rtrue\n\n     \
+                calls   exclusive       %   inclusive       %  routine\n         \
+                2           5   55.6%           5   55.6%  0400\n         \
+                0           3   33.3%           9  100.0%  (main routine)\n         \
+                1           1   11.1%           1   11.1%  0500\n",
+               String::from_utf8(out).unwrap());
+  }
+
+  #[test]
+  fn test_folded() {
+    let mut out = Vec::new();
+    profile().write_folded(&DebugInfo::new(), &mut out).unwrap();
+    assert_eq!("main 3\nmain;0400 3\nmain;0400;0400 2\nmain;0500 1\n",
+               String::from_utf8(out).unwrap());
+  }
+
+  #[test]
+  fn test_set_call_stack() {
+    // Going back into 0x400's recursive call.
+    let mut profiler = profile();
+    profiler.set_call_stack(vec![0, 0x400, 0x400]);
+    profiler.count("add");
+    profiler.returned();
+    profiler.count("ret");
+
+    let mut out = Vec::new();
+    profiler.write_folded(&DebugInfo::new(), &mut out).unwrap();
+    assert_eq!("main 3\nmain;0400 4\nmain;0400;0400 3\nmain;0500 1\n",
+               String::from_utf8(out).unwrap());
+    assert_eq!(7, profiler.inclusive(0x400, &profiler.routines[&0x400]));
+    assert_eq!(11, profiler.inclusive(0, &profiler.routines[&0]));
+  }
+}
@@ -0,0 +1,323 @@
+use result::{Error, Result};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use zmachine::debug_info::DebugInfo;
+use zmachine::disasm;
+use zmachine::ops::decoder::{Form, Instruction, OperandCount};
+use zmachine::vm::VariableRef;
+
+// An execution trace: a record of each instruction as it runs, with the values
+// of its operands, what it stored and whether it branched. Two builds of the
+// interpreter that run the same game on the same input should write the same
+// trace, so diffing traces finds where they part ways.
+//
+// The text format is one line per instruction:
+//
+//      4f05:  add 0005 0002 -> sp=0007
+//      4f0b:  je 0001 0003 ?no
+//
+// A call's result isn't stored until the routine returns, so it is shown
+// without a value. Operands that can't be read are shown as ????.
+//
+// The binary format is the bytes "RZMT" and a version byte (1), then for each
+// instruction (words are big-endian, as in the story file):
+//
+//     3 bytes  address
+//     1 byte   form (high nibble: long, short, variable, extended) and operand
+//              count (low nibble: 0OP, 1OP, 2OP, VAR, EXT)
+//     1 byte   opcode number
+//     1 byte   flags (below)
+//     1 byte   number of operands, then a word for each (0 if unreadable)
+//     1 byte   store variable, if FLAG_STORE, then its value if FLAG_STORED
+
+const MAGIC: &[u8] = b"RZMT";
+const BINARY_VERSION: u8 = 1;
+
+const FLAG_STORE: u8 = 0x01;
+const FLAG_STORED: u8 = 0x02;
+const FLAG_BRANCH: u8 = 0x04;
+const FLAG_BRANCH_TAKEN: u8 = 0x08;
+
+#[derive(Clone,Copy,Debug,Eq,PartialEq)]
+pub enum TraceFormat {
+  Text,
+  Binary,
+}
+
+const TRACE_FORMAT_NAMES: [(&str, TraceFormat); 2] = [("text", TraceFormat::Text),
+                                                              ("binary", TraceFormat::Binary)];
+
+/// Look up a trace format by its name (for the command line).
+pub fn trace_format_from_name(name: &str) -> Option<TraceFormat> {
+  TRACE_FORMAT_NAMES.iter().find(|&&(n, _)| n == name).map(|&(_, format)| format)
+}
+
+pub fn trace_format_names() -> Vec<&'static str> {
+  TRACE_FORMAT_NAMES.iter().map(|&(name, _)| name).collect()
+}
+
+/// Which instructions to trace.
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum TraceFilter {
+  // From the first address up to, but not including, the second.
+  Range(usize, usize),
+  // The instructions of the routine whose header is at this address (but not
+  // of the routines it calls).
+  Routine(usize),
+}
+
+impl TraceFilter {
+  /// Read an address range, written as 4f00-5000 (in hex).
+  pub fn parse_range(spec: &str) -> ::std::result::Result<TraceFilter, String> {
+    let parts = spec.split('-').collect::<Vec<_>>();
+    match parts.as_slice() {
+      [start, end] => {
+        let start = disasm::parse_addr(start)?;
+        let end = disasm::parse_addr(end)?;
+        if start < end {
+          Ok(TraceFilter::Range(start, end))
+        } else {
+          Err("the range is empty".to_string())
+        }
+      }
+      _ => Err("not an address range (4f00-5000)".to_string()),
+    }
+  }
+
+  /// Read a routine, by the address of its header (in hex) or by its name in
+  /// `names`.
+  pub fn parse_routine(spec: &str,
+                       names: &DebugInfo)
+                       -> ::std::result::Result<TraceFilter, String> {
+    match names.routine_named(spec) {
+      Some(addr) => Ok(TraceFilter::Routine(addr)),
+      None => disasm::parse_addr(spec).map(TraceFilter::Routine),
+    }
+  }
+
+  fn matches(&self, pc: usize, routine: usize) -> bool {
+    match *self {
+      TraceFilter::Range(start, end) => start <= pc && pc < end,
+      TraceFilter::Routine(addr) => addr == routine,
+    }
+  }
+}
+
+/// What happened when an instruction ran.
+pub struct Record {
+  pub instruction: Instruction,
+  // The routine the instruction is in, for the names of its locals.
+  pub routine: usize,
+  pub operands: Vec<Option<u16>>,
+  // None if the instruction doesn't store, or hasn't yet.
+  pub stored: Option<u16>,
+  // None if the instruction doesn't branch, or didn't finish.
+  pub branch_taken: Option<bool>,
+}
+
+pub struct Tracer<W>
+  where W: Write {
+  out: W,
+  format: TraceFormat,
+  filters: Vec<TraceFilter>,
+}
+
+impl Tracer<BufWriter<File>> {
+  pub fn create<P>(path: P, format: TraceFormat) -> Result<Tracer<BufWriter<File>>>
+    where P: AsRef<Path> {
+    let path = path.as_ref();
+    let file = File::create(path)
+      .map_err(|err| Error::CouldNotCreateTrace(path.to_path_buf(), err))?;
+    Tracer::new(BufWriter::new(file), format)
+  }
+}
+
+impl<W> Tracer<W>
+  where W: Write {
+  pub fn new(mut out: W, format: TraceFormat) -> Result<Tracer<W>> {
+    if format == TraceFormat::Binary {
+      out.write_all(MAGIC)?;
+      out.write_all(&[BINARY_VERSION])?;
+    }
+    Ok(Tracer {
+      out,
+      format,
+      filters: Vec::new(),
+    })
+  }
+
+  /// Only trace what matches one of the filters. With none, everything is
+  /// traced.
+  pub fn add_filter(&mut self, filter: TraceFilter) {
+    self.filters.push(filter);
+  }
+
+  /// Whether to trace the instruction at `pc`, in the routine at `routine`.
+  pub fn wants(&self, pc: usize, routine: usize) -> bool {
+    self.filters.is_empty() || self.filters.iter().any(|filter| filter.matches(pc, routine))
+  }
+
+  pub fn write(&mut self, record: &Record, names: &DebugInfo) -> Result<()> {
+    match self.format {
+      TraceFormat::Text => write_text(record, names, &mut self.out),
+      TraceFormat::Binary => write_binary(record, &mut self.out),
+    }
+  }
+}
+
+fn write_text<W>(record: &Record, names: &DebugInfo, out: &mut W) -> Result<()>
+  where W: Write {
+  let instruction = &record.instruction;
+  let mut line = format!("{:5x}:  {}", instruction.addr, instruction.name);
+  for operand in &record.operands {
+    match *operand {
+      Some(value) => line.push_str(&format!(" {:04x}", value)),
+      None => line.push_str(" ????"),
+    }
+  }
+  if let Some(variable) = instruction.store {
+    line.push_str(&format!(" -> {}", disasm::variable_text(variable, names, record.routine)));
+    if let Some(value) = record.stored {
+      line.push_str(&format!("={:04x}", value));
+    }
+  }
+  if let Some(taken) = record.branch_taken {
+    line.push_str(if taken { " ?yes" } else { " ?no" });
+  }
+  writeln!(out, "{}", line)?;
+  Ok(())
+}
+
+fn write_binary<W>(record: &Record, out: &mut W) -> Result<()>
+  where W: Write {
+  let instruction = &record.instruction;
+  let form = match instruction.form {
+    Form::Long => 0,
+    Form::Short => 1,
+    Form::Variable => 2,
+    Form::Extended => 3,
+  };
+  let count = match instruction.count {
+    OperandCount::Op0 => 0,
+    OperandCount::Op1 => 1,
+    OperandCount::Op2 => 2,
+    OperandCount::Var => 3,
+    OperandCount::Ext => 4,
+  };
+  let mut flags = 0;
+  if instruction.store.is_some() {
+    flags |= FLAG_STORE;
+  }
+  if record.stored.is_some() {
+    flags |= FLAG_STORED;
+  }
+  if let Some(taken) = record.branch_taken {
+    flags |= FLAG_BRANCH;
+    if taken {
+      flags |= FLAG_BRANCH_TAKEN;
+    }
+  }
+
+  let addr = instruction.addr;
+  let mut bytes = vec![(addr >> 16) as u8,
+                       (addr >> 8) as u8,
+                       addr as u8,
+                       form << 4 | count,
+                       instruction.opcode,
+                       flags,
+                       record.operands.len() as u8];
+  for operand in &record.operands {
+    push_word(&mut bytes, operand.unwrap_or(0));
+  }
+  if let Some(variable) = instruction.store {
+    bytes.push(VariableRef::encode(variable));
+    if let Some(value) = record.stored {
+      push_word(&mut bytes, value);
+    }
+  }
+  out.write_all(&bytes)?;
+  Ok(())
+}
+
+fn push_word(bytes: &mut Vec<u8>, word: u16) {
+  bytes.push((word >> 8) as u8);
+  bytes.push(word as u8);
+}
+
+#[cfg(test)]
+mod test {
+  use super::{Record, TraceFilter, TraceFormat, Tracer};
+  use zmachine::debug_info::DebugInfo;
+  use zmachine::ops::decoder;
+  use zmachine::ops::testvm::TestVM;
+
+  fn record(vm: &mut TestVM, addr: usize, operands: Vec<Option<u16>>) -> Record {
+    Record {
+      instruction: decoder::decode(vm, addr).unwrap(),
+      routine: 0,
+      operands,
+      stored: None,
+      branch_taken: None,
+    }
+  }
+
+  fn story() -> TestVM {
+    let mut vm = TestVM::new();
+    // add G10 #02 -> sp
+    vm.heap[0x100..0x104].copy_from_slice(&[0x54, 0x20, 0x02, 0x00]);
+    // je L00 #03 [TRUE] RTRUE
+    vm.heap[0x104..0x108].copy_from_slice(&[0x41, 0x01, 0x03, 0xc1]);
+    vm
+  }
+
+  #[test]
+  fn test_text() {
+    let mut vm = story();
+    let mut tracer = Tracer::new(Vec::new(), TraceFormat::Text).unwrap();
+    let mut add = record(&mut vm, 0x100, vec![Some(5), Some(2)]);
+    add.stored = Some(7);
+    tracer.write(&add, &DebugInfo::new()).unwrap();
+    let mut je = record(&mut vm, 0x104, vec![None, Some(3)]);
+    je.branch_taken = Some(false);
+    tracer.write(&je, &DebugInfo::new()).unwrap();
+    assert_eq!("  100:  add 0005 0002 -> sp=0007\n  \
+                104:  je ???? 0003 ?no\n",
+               String::from_utf8(tracer.out).unwrap());
+  }
+
+  #[test]
+  fn test_binary() {
+    let mut vm = story();
+    let mut tracer = Tracer::new(Vec::new(), TraceFormat::Binary).unwrap();
+    let mut add = record(&mut vm, 0x100, vec![Some(5), Some(2)]);
+    add.stored = Some(7);
+    tracer.write(&add, &DebugInfo::new()).unwrap();
+    let mut je = record(&mut vm, 0x104, vec![Some(1), Some(3)]);
+    je.branch_taken = Some(true);
+    tracer.write(&je, &DebugInfo::new()).unwrap();
+    assert_eq!(vec![b'R', b'Z', b'M', b'T', 1,
+                    0x00, 0x01, 0x00, 0x02, 0x14, 0x03, 2, 0x00, 0x05, 0x00, 0x02, 0x00, 0x00,
+                    0x07,
+                    0x00, 0x01, 0x04, 0x02, 0x01, 0x0c, 2, 0x00, 0x01, 0x00, 0x03],
+               tracer.out);
+  }
+
+  #[test]
+  fn test_filters() {
+    assert_eq!(Ok(TraceFilter::Range(0x4f00, 0x5000)), TraceFilter::parse_range("4f00-5000"));
+    assert!(TraceFilter::parse_range("5000-4f00").is_err());
+    assert!(TraceFilter::parse_range("4f00").is_err());
+    assert_eq!(Ok(TraceFilter::Routine(0x420)),
+               TraceFilter::parse_routine("0x420", &DebugInfo::new()));
+
+    let mut tracer = Tracer::new(Vec::new(), TraceFormat::Text).unwrap();
+    assert!(tracer.wants(0x100, 0));
+    tracer.add_filter(TraceFilter::Range(0x4f00, 0x5000));
+    tracer.add_filter(TraceFilter::Routine(0x420));
+    assert!(tracer.wants(0x4f00, 0));
+    assert!(!tracer.wants(0x5000, 0));
+    assert!(tracer.wants(0x425, 0x420));
+    assert!(!tracer.wants(0x425, 0x400));
+  }
+}
@@ -0,0 +1,147 @@
+// Colours for set_colour and set_true_colour (spec section 8.3).
+//
+// Games name colours with small numbers: 0 means "leave it alone", 1 means
+// "the default colour", and 2-9 are the eight colours that every colour
+// terminal has. set_true_colour names them with 15-bit RGB values instead,
+// which we round to the nearest of those eight.
+
+pub const COLOUR_CURRENT: u16 = 0;
+pub const COLOUR_DEFAULT: u16 = 1;
+pub const COLOUR_BLACK: u16 = 2;
+pub const COLOUR_RED: u16 = 3;
+pub const COLOUR_GREEN: u16 = 4;
+pub const COLOUR_YELLOW: u16 = 5;
+pub const COLOUR_BLUE: u16 = 6;
+pub const COLOUR_MAGENTA: u16 = 7;
+pub const COLOUR_CYAN: u16 = 8;
+pub const COLOUR_WHITE: u16 = 9;
+
+// Special values for set_true_colour.
+const TRUE_COLOUR_DEFAULT: u16 = 0xffff;
+const TRUE_COLOUR_CURRENT: u16 = 0xfffe;
+
+const COLOUR_NAMES: [(&str, u16); 8] = [("black", COLOUR_BLACK),
+                                                 ("red", COLOUR_RED),
+                                                 ("green", COLOUR_GREEN),
+                                                 ("yellow", COLOUR_YELLOW),
+                                                 ("blue", COLOUR_BLUE),
+                                                 ("magenta", COLOUR_MAGENTA),
+                                                 ("cyan", COLOUR_CYAN),
+                                                 ("white", COLOUR_WHITE)];
+
+/// Look up a colour by its name (for the command line), case insensitively.
+pub fn colour_from_name(name: &str) -> Option<u16> {
+  let name = name.to_lowercase();
+  COLOUR_NAMES.iter().find(|&&(n, _)| n == name).map(|&(_, colour)| colour)
+}
+
+pub fn colour_names() -> Vec<&'static str> {
+  COLOUR_NAMES.iter().map(|&(name, _)| name).collect()
+}
+
+/// Convert a set_true_colour value to a colour number. Since we only have
+/// eight colours, each of the red, green and blue components is simply on
+/// or off. The "colour under the cursor" value (-3) is treated as current.
+pub fn colour_from_true_colour(true_colour: u16) -> u16 {
+  match true_colour {
+    TRUE_COLOUR_DEFAULT => COLOUR_DEFAULT,
+    TRUE_COLOUR_CURRENT => COLOUR_CURRENT,
+    _ if true_colour & 0x8000 != 0 => COLOUR_CURRENT,
+    _ => {
+      let red = true_colour & 0x1f >= 0x10;
+      let green = (true_colour >> 5) & 0x1f >= 0x10;
+      let blue = (true_colour >> 10) & 0x1f >= 0x10;
+      match (red, green, blue) {
+        (false, false, false) => COLOUR_BLACK,
+        (true, false, false) => COLOUR_RED,
+        (false, true, false) => COLOUR_GREEN,
+        (true, true, false) => COLOUR_YELLOW,
+        (false, false, true) => COLOUR_BLUE,
+        (true, false, true) => COLOUR_MAGENTA,
+        (false, true, true) => COLOUR_CYAN,
+        (true, true, true) => COLOUR_WHITE,
+      }
+    }
+  }
+}
+
+/// The current foreground and background colours. Both are always either
+/// COLOUR_DEFAULT or one of the eight real colours.
+#[derive(Clone,Copy,Debug,Eq,PartialEq)]
+pub struct Colours {
+  pub foreground: u16,
+  pub background: u16,
+}
+
+impl Colours {
+  pub fn new() -> Colours {
+    Colours {
+      foreground: COLOUR_DEFAULT,
+      background: COLOUR_DEFAULT,
+    }
+  }
+
+  /// The colours after a set_colour. Returns None if either colour is out of
+  /// range, in which case the colours should be left as they are.
+  pub fn with(&self, foreground: u16, background: u16) -> Option<Colours> {
+    Some(Colours {
+      foreground: resolve(foreground, self.foreground)?,
+      background: resolve(background, self.background)?,
+    })
+  }
+}
+
+fn resolve(requested: u16, current: u16) -> Option<u16> {
+  match requested {
+    COLOUR_CURRENT => Some(current),
+    COLOUR_DEFAULT..=COLOUR_WHITE => Some(requested),
+    _ => None,
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_colours_with() {
+    let colours = Colours::new();
+    let colours = colours.with(COLOUR_RED, COLOUR_CURRENT).unwrap();
+    assert_eq!(COLOUR_RED, colours.foreground);
+    assert_eq!(COLOUR_DEFAULT, colours.background);
+
+    let colours = colours.with(COLOUR_CURRENT, COLOUR_BLUE).unwrap();
+    assert_eq!(COLOUR_RED, colours.foreground);
+    assert_eq!(COLOUR_BLUE, colours.background);
+
+    let colours = colours.with(COLOUR_DEFAULT, COLOUR_CURRENT).unwrap();
+    assert_eq!(COLOUR_DEFAULT, colours.foreground);
+    assert_eq!(COLOUR_BLUE, colours.background);
+
+    assert_eq!(None, colours.with(10, COLOUR_BLACK));
+    assert_eq!(None, colours.with(0xffff, COLOUR_BLACK));
+  }
+
+  #[test]
+  fn test_true_colour() {
+    assert_eq!(COLOUR_BLACK, colour_from_true_colour(0x0000));
+    assert_eq!(COLOUR_WHITE, colour_from_true_colour(0x7fff));
+    assert_eq!(COLOUR_RED, colour_from_true_colour(0x001f));
+    assert_eq!(COLOUR_GREEN, colour_from_true_colour(0x03e0));
+    assert_eq!(COLOUR_BLUE, colour_from_true_colour(0x7c00));
+    assert_eq!(COLOUR_YELLOW, colour_from_true_colour(0x03ff));
+    // Dim components round down.
+    assert_eq!(COLOUR_BLACK, colour_from_true_colour(0x3def));
+    assert_eq!(COLOUR_DEFAULT, colour_from_true_colour(0xffff));
+    assert_eq!(COLOUR_CURRENT, colour_from_true_colour(0xfffe));
+    assert_eq!(COLOUR_CURRENT, colour_from_true_colour(0xfffd));
+  }
+
+  #[test]
+  fn test_colour_from_name() {
+    assert_eq!(Some(COLOUR_BLACK), colour_from_name("black"));
+    assert_eq!(Some(COLOUR_CYAN), colour_from_name("Cyan"));
+    assert_eq!(None, colour_from_name("mauve"));
+    assert_eq!(8, colour_names().len());
+  }
+}
@@ -1,6 +1,8 @@
+use result::Result;
 use zmachine::vm::{BytePtr, Memory};
+use zmachine::vm::zscii::zscii_to_char;
 
-#[derive(Debug)]
+#[derive(Clone,Debug)]
 pub struct Dictionary {
   separators: Vec<char>,
   entry_length: u8,
@@ -9,24 +11,37 @@ pub struct Dictionary {
 }
 
 impl Dictionary {
-  pub fn new(memory: &Memory) -> Dictionary {
+  pub fn new(memory: &Memory) -> Result<Dictionary> {
     let mut ptr = memory.dictionary_table_ptr();
-    let num_separators = memory.u8_at(ptr);
+    let num_separators = memory.read_u8(ptr)?;
 
-    let separators = Vec::<char>::new();
+    let separators = (0..num_separators as u16)
+      .map(|i| memory.read_u8(ptr.inc_by(1 + i)))
+      .map(|zscii| zscii.map(|zscii| zscii_to_char(zscii as u16).unwrap_or('?')))
+      .collect::<Result<_>>()?;
     ptr = ptr.inc_by(1u16 + num_separators as u16);
 
-    let entry_length = memory.u8_at(ptr);
+    let entry_length = memory.read_u8(ptr)?;
     ptr = ptr.inc_by(1);
-    let num_entries = memory.u16_at(ptr);
+    let num_entries = memory.read_u16(ptr)?;
     ptr = ptr.inc_by(2);
 
-    Dictionary {
+    Ok(Dictionary {
       separators: separators,
       entry_length: entry_length,
       num_entries: num_entries,
       entries_ptr: ptr,
-    }
+    })
+  }
+
+  pub fn separators(&self) -> &[char] {
+    &self.separators
+  }
+
+  /// The length in bytes of each entry: the encoded word, then data that is
+  /// up to the game.
+  pub fn entry_length(&self) -> u8 {
+    self.entry_length
   }
 
   pub fn num_entries(&self) -> u16 {
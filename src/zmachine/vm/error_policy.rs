@@ -0,0 +1,129 @@
+use result::{Error, Result};
+use std::collections::HashSet;
+
+// Many published games break the rules in small ways, like asking for the
+// parent of object 0. The spec (and other interpreters) recommend a sensible
+// way to carry on for most of these, so rather than stopping the game we can
+// do that, and tell the player as much (or as little) as they want to hear.
+// This is Frotz's -Z option.
+
+#[derive(Clone,Copy,Debug,Eq,PartialEq)]
+pub enum ErrorMode {
+  /// Carry on without saying anything.
+  Ignore,
+  /// Warn the first time each error happens.
+  WarnOnce,
+  /// Warn every time.
+  WarnAlways,
+  /// Stop the game, as for any other error.
+  Fatal,
+}
+
+const ERROR_MODE_NAMES: [(&str, ErrorMode); 4] = [("ignore", ErrorMode::Ignore),
+                                                           ("once", ErrorMode::WarnOnce),
+                                                           ("always", ErrorMode::WarnAlways),
+                                                           ("fatal", ErrorMode::Fatal)];
+
+/// Look up an error mode by its name (for the command line).
+pub fn error_mode_from_name(name: &str) -> Option<ErrorMode> {
+  ERROR_MODE_NAMES.iter().find(|&&(n, _)| n == name).map(|&(_, mode)| mode)
+}
+
+pub fn error_mode_names() -> Vec<&'static str> {
+  ERROR_MODE_NAMES.iter().map(|&(name, _)| name).collect()
+}
+
+/// True for the errors that the opcodes know how to recover from.
+pub fn is_recoverable(err: &Error) -> bool {
+  matches!(*err,
+           Error::BadAttribute(_) |
+           Error::BadPropertySize(..) |
+           Error::NoObject(_) |
+           Error::PropertyNotFound(_) |
+           Error::ReadOnlyAddress(_))
+}
+
+pub struct ErrorPolicy {
+  mode: ErrorMode,
+  // The errors already warned about, for WarnOnce, by their messages. (So
+  // object 0 given to get_parent and to get_child are told apart.)
+  reported: HashSet<String>,
+}
+
+impl ErrorPolicy {
+  pub fn new(mode: ErrorMode) -> ErrorPolicy {
+    ErrorPolicy {
+      mode,
+      reported: HashSet::new(),
+    }
+  }
+
+  pub fn mode(&self) -> ErrorMode {
+    self.mode
+  }
+
+  /// Decide what to do about `err`. If the game can't carry on, the error is
+  /// returned. Otherwise, returns the error if the player should be warned
+  /// about it, or None to carry on quietly.
+  pub fn check(&mut self, err: Error) -> Result<Option<Error>> {
+    if !is_recoverable(&err) {
+      return Err(err);
+    }
+    match self.mode {
+      ErrorMode::Ignore => Ok(None),
+      ErrorMode::WarnOnce => {
+        if self.reported.insert(err.to_string()) {
+          Ok(Some(err))
+        } else {
+          Ok(None)
+        }
+      }
+      ErrorMode::WarnAlways => Ok(Some(err)),
+      ErrorMode::Fatal => Err(err),
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use result::Error;
+  use super::{ErrorMode, ErrorPolicy, error_mode_from_name};
+
+  #[test]
+  fn test_error_mode_from_name() {
+    assert_eq!(Some(ErrorMode::WarnOnce), error_mode_from_name("once"));
+    assert_eq!(Some(ErrorMode::Fatal), error_mode_from_name("fatal"));
+    assert_eq!(None, error_mode_from_name("sometimes"));
+  }
+
+  #[test]
+  fn test_modes() {
+    let mut policy = ErrorPolicy::new(ErrorMode::Ignore);
+    assert!(policy.check(Error::NoObject("get_parent")).unwrap().is_none());
+
+    let mut policy = ErrorPolicy::new(ErrorMode::WarnAlways);
+    assert!(policy.check(Error::NoObject("get_parent")).unwrap().is_some());
+    assert!(policy.check(Error::NoObject("get_parent")).unwrap().is_some());
+
+    let mut policy = ErrorPolicy::new(ErrorMode::Fatal);
+    assert!(policy.check(Error::NoObject("get_parent")).is_err());
+  }
+
+  #[test]
+  fn test_warn_once() {
+    let mut policy = ErrorPolicy::new(ErrorMode::WarnOnce);
+    assert!(policy.check(Error::NoObject("get_parent")).unwrap().is_some());
+    assert!(policy.check(Error::NoObject("get_parent")).unwrap().is_none());
+    // Once per error, so the details count.
+    assert!(policy.check(Error::NoObject("get_child")).unwrap().is_some());
+    assert!(policy.check(Error::PropertyNotFound(3)).unwrap().is_some());
+    assert!(policy.check(Error::PropertyNotFound(3)).unwrap().is_none());
+  }
+
+  #[test]
+  fn test_unrecoverable() {
+    let mut policy = ErrorPolicy::new(ErrorMode::Ignore);
+    assert!(policy.check(Error::MissingOperand).is_err());
+    assert!(policy.check(Error::AddressOutOfRange(0x10000)).is_err());
+  }
+}
@@ -1,19 +1,41 @@
 use byteorder::{BigEndian, ByteOrder};
+use result::{Error, Result};
+use std::mem;
 use super::ptrs::{BytePtr, RawPtr};
 
+const VERSION_INDEX: u16 = 0x00;
 const FLAG1_INDEX: u16 = 0x01;
+const RELEASE_INDEX: u16 = 0x02;
+const HIGH_MEM_INDEX: u16 = 0x04;
 const STARTING_PC_INDEX: u16 = 0x06;
 const DICTIONARY_INDEX: u16 = 0x08;
 const PROPERTY_TABLE_INDEX: u16 = 0x0a;
 const GLOBAL_TABLE_INDEX: u16 = 0x0c;
 const STATIC_MEM_INDEX: u16 = 0x0e;
+const FLAG2_INDEX: u16 = 0x10;
+const SERIAL_INDEX: u16 = 0x12;
 const ABBREV_TABLE_INDEX: u16 = 0x18;
 const FILE_LENGTH_INDEX: u16 = 0x1a;
+const CHECKSUM_INDEX: u16 = 0x1c;
+const SCREEN_HEIGHT_LINES_INDEX: u16 = 0x20;
+const SCREEN_WIDTH_CHARS_INDEX: u16 = 0x21;
+const SCREEN_WIDTH_UNITS_INDEX: u16 = 0x22;
+const SCREEN_HEIGHT_UNITS_INDEX: u16 = 0x24;
+const FONT_WIDTH_INDEX: u16 = 0x26;
+const FONT_HEIGHT_INDEX: u16 = 0x27;
+const DEFAULT_BACKGROUND_INDEX: u16 = 0x2c;
+const DEFAULT_FOREGROUND_INDEX: u16 = 0x2d;
+const TERMINATING_CHARS_INDEX: u16 = 0x2e;
+const STANDARD_REVISION_INDEX: u16 = 0x32;
+const HEADER_SIZE: usize = 0x40;
 
 #[derive(Eq,PartialEq)]
 pub struct Memory {
   bytes: Vec<u8>,
   original_dynamic_bytes: Vec<u8>,
+  // The game's writes since they were last taken, as (address, length), if
+  // they are being recorded.
+  writes: Option<Vec<(usize, usize)>>,
 }
 
 impl From<Vec<u8>> for Memory {
@@ -24,25 +46,131 @@ impl From<Vec<u8>> for Memory {
 
 impl Memory {
   fn new(bytes: Vec<u8>) -> Memory {
-    let num_dynamic_bytes = BigEndian::read_u16(&bytes[STATIC_MEM_INDEX as usize..]);
-    let dynamic_bytes: Vec<u8> = From::from(&bytes[..num_dynamic_bytes as usize]);
+    let dynamic_bytes: Vec<u8> = From::from(&bytes[..dynamic_size(&bytes).min(bytes.len())]);
     Memory {
       bytes: bytes,
       original_dynamic_bytes: dynamic_bytes,
+      writes: None,
     }
   }
 
-  pub fn restore_dynamic_bytes(&mut self) {
-    let num_dynamic_bytes = self.u16_at(BytePtr::new(STATIC_MEM_INDEX)) as usize;
-    for i in 0..num_dynamic_bytes {
-      // TODO: is there a better way to do this?
-      self.bytes[i] = self.original_dynamic_bytes[i];
+  /// Keep a list of the game's writes from now on, for watchpoints.
+  pub fn record_writes(&mut self) {
+    if self.writes.is_none() {
+      self.writes = Some(Vec::new());
     }
   }
 
+  /// The game's writes since the last call, as (address, length). Writes to
+  /// the same value are there too.
+  pub fn take_writes(&mut self) -> Vec<(usize, usize)> {
+    self.writes.as_mut().map_or(Vec::new(), mem::take)
+  }
+
+  pub fn restore_dynamic_bytes(&mut self) {
+    let num_dynamic_bytes = self.original_dynamic_bytes.len();
+    self.bytes[..num_dynamic_bytes].copy_from_slice(&self.original_dynamic_bytes);
+  }
+
+  /// Put dynamic memory back as it was when `bytes` was copied from
+  /// dynamic_slice().
+  pub fn set_dynamic_bytes(&mut self, bytes: &[u8]) {
+    self.bytes[..bytes.len()].copy_from_slice(bytes);
+  }
+
   pub fn dynamic_slice(&self) -> &[u8] {
-    let num_dynamic_bytes = self.u16_at(BytePtr::new(STATIC_MEM_INDEX)) as usize;
-    &self.bytes[0..num_dynamic_bytes]
+    &self.bytes[..self.original_dynamic_bytes.len()]
+  }
+
+  /// True if the whole of dynamic memory, as described by the header, is
+  /// present.
+  pub fn is_complete(&self) -> bool {
+    dynamic_size(&self.bytes) <= self.bytes.len()
+  }
+
+  // The checked accessors are for reads and writes that the game asks for.
+  // The game may read anything in the file, but may only write to dynamic
+  // memory, and of the header, only to Flags 2 (spec section 1.1 and 11.1).
+  // The interpreter itself uses the unchecked accessors below.
+
+  pub fn read_u8<P>(&self, ptr: P) -> Result<u8>
+    where P: Into<RawPtr> {
+    let addr = self.check_read(ptr.into(), 1)?;
+    Ok(self.bytes[addr])
+  }
+
+  pub fn read_u16<P>(&self, ptr: P) -> Result<u16>
+    where P: Into<RawPtr> {
+    let addr = self.check_read(ptr.into(), 2)?;
+    Ok(BigEndian::read_u16(&self.bytes[addr..]))
+  }
+
+  pub fn write_u8<P>(&mut self, val: u8, ptr: P) -> Result<()>
+    where P: Into<RawPtr> {
+    let addr = ptr.into().ptr();
+    if self.check_write(addr, 1)? {
+      self.bytes[addr] = val;
+      self.wrote(addr, 1);
+    }
+    Ok(())
+  }
+
+  pub fn write_u16<P>(&mut self, val: u16, ptr: P) -> Result<()>
+    where P: Into<RawPtr> {
+    let addr = ptr.into().ptr();
+    if self.check_write(addr, 2)? {
+      BigEndian::write_u16(&mut self.bytes[addr..], val);
+      self.wrote(addr, 2);
+    }
+    Ok(())
+  }
+
+  pub fn read_u32<P>(&self, ptr: P) -> Result<u32>
+    where P: Into<RawPtr> {
+    let addr = self.check_read(ptr.into(), 4)?;
+    Ok(BigEndian::read_u32(&self.bytes[addr..]))
+  }
+
+  pub fn write_u32<P>(&mut self, val: u32, ptr: P) -> Result<()>
+    where P: Into<RawPtr> {
+    let addr = ptr.into().ptr();
+    if self.check_write(addr, 4)? {
+      BigEndian::write_u32(&mut self.bytes[addr..], val);
+      self.wrote(addr, 4);
+    }
+    Ok(())
+  }
+
+  fn check_read(&self, ptr: RawPtr, len: usize) -> Result<usize> {
+    let addr = ptr.ptr();
+    match addr.checked_add(len) {
+      Some(end) if end <= self.bytes.len() => Ok(addr),
+      _ => Err(Error::AddressOutOfRange(addr)),
+    }
+  }
+
+  fn wrote(&mut self, addr: usize, len: usize) {
+    if let Some(writes) = self.writes.as_mut() {
+      writes.push((addr, len));
+    }
+  }
+
+  // Returns false if the write should be quietly dropped.
+  fn check_write(&self, addr: usize, len: usize) -> Result<bool> {
+    match addr.checked_add(len) {
+      Some(end) if end <= self.original_dynamic_bytes.len() => (),
+      _ => return Err(Error::ReadOnlyAddress(addr)),
+    }
+    if addr < HEADER_SIZE {
+      let flag2 = FLAG2_INDEX as usize;
+      if addr < flag2 || addr + len > flag2 + 2 {
+        // Some games do this. Everything else in the header belongs to the
+        // interpreter, so don't let it change, but don't stop the game either.
+        warn!("Ignoring write of {} byte(s) to the header at {:#x}", len, addr);
+        return Ok(false);
+      }
+    }
+    Ok(true)
   }
 
   pub fn u8_at<P>(&self, ptr: P) -> u8
@@ -65,15 +193,8 @@ impl Memory {
     BigEndian::write_u16(&mut self.bytes[ptr.into().ptr()..], val);
   }
 
-  pub fn u32_at<P>(&self, ptr: P) -> u32
-    where P: Into<RawPtr> {
-    // TODO: test this.
-    BigEndian::read_u32(&self.bytes[ptr.into().ptr()..])
-  }
-
-  pub fn set_u32_at<P>(&mut self, ptr: P, attrs: u32)
-    where P: Into<RawPtr> {
-    BigEndian::write_u32(&mut self.bytes[ptr.into().ptr()..], attrs);
+  pub fn version(&self) -> u8 {
+    self.u8_at(BytePtr::new(VERSION_INDEX))
   }
 
   pub fn flag1(&self) -> u8 {
@@ -84,6 +205,45 @@ impl Memory {
     self.set_u8_at(val, BytePtr::new(FLAG1_INDEX));
   }
 
+  pub fn flag2(&self) -> u16 {
+    self.u16_at(BytePtr::new(FLAG2_INDEX))
+  }
+
+  pub fn set_flag2(&mut self, val: u16) {
+    self.set_u16_at(val, BytePtr::new(FLAG2_INDEX));
+  }
+
+  pub fn release(&self) -> u16 {
+    self.u16_at(BytePtr::new(RELEASE_INDEX))
+  }
+
+  /// The serial number, which is usually the compile date as YYMMDD.
+  pub fn serial(&self) -> String {
+    (0..6)
+      .map(|i| self.u8_at(BytePtr::new(SERIAL_INDEX + i)))
+      .map(|b| if (0x20..0x7f).contains(&b) { b as char } else { '?' })
+      .collect()
+  }
+
+  pub fn checksum(&self) -> u16 {
+    self.u16_at(BytePtr::new(CHECKSUM_INDEX))
+  }
+
+  /// The version of the standard that the interpreter claims to follow, as
+  /// (major, minor). Zero if it claims none.
+  pub fn standard_revision(&self) -> (u8, u8) {
+    (self.u8_at(BytePtr::new(STANDARD_REVISION_INDEX)),
+     self.u8_at(BytePtr::new(STANDARD_REVISION_INDEX + 1)))
+  }
+
+  pub fn high_memory_ptr(&self) -> BytePtr {
+    BytePtr::new(self.u16_at(BytePtr::new(HIGH_MEM_INDEX)))
+  }
+
+  pub fn static_memory_ptr(&self) -> BytePtr {
+    BytePtr::new(self.u16_at(BytePtr::new(STATIC_MEM_INDEX)))
+  }
+
   pub fn file_length(&self) -> u32 {
     self.u16_at(BytePtr::new(FILE_LENGTH_INDEX)) as u32 * 2
   }
@@ -104,32 +264,93 @@ impl Memory {
     BytePtr::new(self.u16_at(BytePtr::new(DICTIONARY_INDEX)))
   }
 
+  /// The extra characters that end input in `read` (v5+), as stored in the
+  /// game's table. (A 255 in the table means "any function key".)
+  pub fn terminating_chars(&self) -> Result<Vec<u16>> {
+    let mut chars = Vec::new();
+    if self.version() < 5 {
+      return Ok(chars);
+    }
+    let table_addr = self.u16_at(BytePtr::new(TERMINATING_CHARS_INDEX));
+    if table_addr == 0 {
+      return Ok(chars);
+    }
+
+    // A table with no end runs into the end of the file.
+    let mut ptr = RawPtr::from(BytePtr::new(table_addr));
+    loop {
+      match self.read_u8(ptr)? {
+        0 => break,
+        ch => chars.push(ch as u16),
+      }
+      ptr.inc_by(1);
+    }
+    Ok(chars)
+  }
+
+  /// Record the screen size (in characters) in the header, for v4+ games. In
+  /// v5 the screen is also measured in "units", which for us are characters.
+  pub fn set_screen_size(&mut self, rows: u16, cols: u16) {
+    let version = self.version();
+    if version < 4 {
+      return;
+    }
+    // A height of 255 would mean "infinite".
+    self.set_u8_at(rows.min(254) as u8, BytePtr::new(SCREEN_HEIGHT_LINES_INDEX));
+    self.set_u8_at(cols.min(255) as u8, BytePtr::new(SCREEN_WIDTH_CHARS_INDEX));
+    if version >= 5 {
+      self.set_u16_at(cols, BytePtr::new(SCREEN_WIDTH_UNITS_INDEX));
+      self.set_u16_at(rows, BytePtr::new(SCREEN_HEIGHT_UNITS_INDEX));
+      self.set_u8_at(1, BytePtr::new(FONT_WIDTH_INDEX));
+      self.set_u8_at(1, BytePtr::new(FONT_HEIGHT_INDEX));
+    }
+  }
+
+  /// Record the default colours (as colour numbers) in the header, for v5+
+  /// games.
+  pub fn set_default_colours(&mut self, foreground: u8, background: u8) {
+    self.set_u8_at(background, BytePtr::new(DEFAULT_BACKGROUND_INDEX));
+    self.set_u8_at(foreground, BytePtr::new(DEFAULT_FOREGROUND_INDEX));
+  }
+
   pub fn global_base_ptr(&self) -> BytePtr {
     BytePtr::new(self.u16_at(BytePtr::new(GLOBAL_TABLE_INDEX)))
   }
 
-  fn global_ptr(&self, global_idx: u8) -> BytePtr {
-    assert!(global_idx < 240, "Max global is 239: {}", global_idx);
+  pub fn global_ptr(&self, global_idx: u8) -> Result<BytePtr> {
+    if global_idx >= 240 {
+      return Err(Error::BadGlobal(global_idx));
+    }
     let base = self.global_base_ptr();
-    base.inc_by(global_idx as u16 * 2)
+    Ok(base.inc_by(global_idx as u16 * 2))
+  }
+
+  pub fn read_global(&self, global_idx: u8) -> Result<u16> {
+    let ptr = self.global_ptr(global_idx)?;
+    self.read_u16(ptr)
   }
 
-  pub fn read_global(&self, global_idx: u8) -> u16 {
-    let ptr = self.global_ptr(global_idx);
-    self.u16_at(ptr)
-    //    BigEndian::read_u16(&self.bytes[offset..])
+  pub fn write_global(&mut self, global_idx: u8, val: u16) -> Result<()> {
+    let ptr = self.global_ptr(global_idx)?;
+    self.write_u16(val, ptr)
   }
+}
 
-  pub fn write_global(&mut self, global_idx: u8, val: u16) {
-    let ptr = self.global_ptr(global_idx);
-    self.set_u16_at(val, ptr);
-    //    BigEndian::write_u16(&mut self.bytes[offset..], val);
+// The size of dynamic memory, from the header's static memory mark (or 0 if
+// there isn't even that much header).
+fn dynamic_size(bytes: &[u8]) -> usize {
+  let index = STATIC_MEM_INDEX as usize;
+  if bytes.len() < index + 2 {
+    0
+  } else {
+    BigEndian::read_u16(&bytes[index..]) as usize
   }
 }
 
 #[cfg(test)]
 mod test {
   use byteorder::{BigEndian, ByteOrder};
+  use result::Error;
   use super::Memory;
   use super::super::ptrs::{BytePtr, RawPtr};
 
@@ -154,10 +375,25 @@ mod test {
     assert_eq!(0x0805, memory.u16_at(BytePtr::new(4)));
   }
 
+  #[test]
+  fn test_is_complete() {
+    let mut bytes = vec![0; 0x40];
+    bytes[0x0e] = 0x01;
+    assert!(!Memory::from(bytes.clone()).is_complete());
+
+    bytes.resize(0x100, 0);
+    let memory = Memory::from(bytes);
+    assert!(memory.is_complete());
+    assert_eq!(0x100, memory.dynamic_slice().len());
+  }
+
   #[test]
   fn test_globals() {
     // 608 = 0x80 (global base) + 2 * 0xf0 (number of globals)
-    let mut memory = Memory::from(vec![0; 608]);
+    // All of it is dynamic memory.
+    let mut bytes = vec![0; 608];
+    BigEndian::write_u16(&mut bytes[super::STATIC_MEM_INDEX as usize..], 608);
+    let mut memory = Memory::from(bytes);
 
     // Set up the memory so that the global table is at 0x80 and has
     // the value 0x84 at global 2 (0x84)
@@ -167,30 +403,134 @@ mod test {
                          global_offset as u16);
     BigEndian::write_u16(&mut memory.bytes[global_offset + 2 * 2..], val);
 
-    assert_eq!(val, memory.read_global(2));
+    assert_eq!(val, memory.read_global(2).unwrap());
 
-    memory.write_global(0, 0x0809);
-    assert_eq!(0x0809, memory.read_global(0));
+    memory.write_global(0, 0x0809).unwrap();
+    assert_eq!(0x0809, memory.read_global(0).unwrap());
 
-    memory.write_global(239, 0x0708);
-    assert_eq!(0x0708, memory.read_global(239));
+    memory.write_global(239, 0x0708).unwrap();
+    assert_eq!(0x0708, memory.read_global(239).unwrap());
+  }
+
+  #[test]
+  fn test_checked_access() {
+    // Dynamic memory is 0x00-0x7f, static memory is 0x80-0xff.
+    let mut bytes = vec![0; 0x100];
+    bytes[0x0f] = 0x80;
+    bytes[0x90] = 0x12;
+    bytes[0x91] = 0x34;
+    let mut memory = Memory::from(bytes);
+
+    assert_eq!(0x1234, memory.read_u16(BytePtr::new(0x90)).unwrap());
+    assert_eq!(0x34, memory.read_u8(BytePtr::new(0x91)).unwrap());
+    assert!(matches!(memory.read_u16(BytePtr::new(0xff)), Err(Error::AddressOutOfRange(0xff))));
+    assert!(memory.read_u8(BytePtr::new(0x100)).is_err());
+
+    memory.write_u16(0x5678, BytePtr::new(0x7e)).unwrap();
+    assert_eq!(0x5678, memory.u16_at(BytePtr::new(0x7e)));
+    assert!(matches!(memory.write_u16(0x5678, BytePtr::new(0x7f)),
+                     Err(Error::ReadOnlyAddress(0x7f))));
+    assert!(memory.write_u8(1, BytePtr::new(0x90)).is_err());
+    assert_eq!(0x12, memory.u8_at(BytePtr::new(0x90)));
+
+    // Addresses so large that adding the length would overflow.
+    let huge = RawPtr::new(usize::MAX - 1);
+    assert!(matches!(memory.read_u16(huge), Err(Error::AddressOutOfRange(_))));
+    assert!(matches!(memory.write_u32(0, huge), Err(Error::ReadOnlyAddress(_))));
+
+    // Only Flags 2 may be changed in the header. Other writes are ignored.
+    memory.write_u16(0x0003, BytePtr::new(0x10)).unwrap();
+    assert_eq!(0x0003, memory.flag2());
+    memory.write_u8(0x20, BytePtr::new(0x0f)).unwrap();
+    assert_eq!(0x80, memory.u8_at(BytePtr::new(0x0f)));
+  }
+
+  #[test]
+  fn test_record_writes() {
+    let mut bytes = vec![0; 0x100];
+    bytes[0x0f] = 0x80;
+    let mut memory = Memory::from(bytes);
+    memory.write_u8(1, BytePtr::new(0x40)).unwrap();
+    assert!(memory.take_writes().is_empty());
+
+    memory.record_writes();
+    memory.write_u16(0, BytePtr::new(0x42)).unwrap();
+    memory.write_u32(0, BytePtr::new(0x44)).unwrap();
+    // Dropped and failed writes don't count.
+    memory.write_u8(0x20, BytePtr::new(0x0f)).unwrap();
+    assert!(memory.write_u8(1, BytePtr::new(0x90)).is_err());
+    assert_eq!(vec![(0x42, 2), (0x44, 4)], memory.take_writes());
+    assert!(memory.take_writes().is_empty());
+  }
+
+  #[test]
+  fn test_terminating_chars() {
+    let mut bytes = vec![0; 0x40];
+    bytes[0x00] = 5;
+    bytes[0x2f] = 0x40;
+    bytes.extend_from_slice(&[133, 134, 0]);
+    let memory = Memory::from(bytes.clone());
+    assert_eq!(vec![133, 134], memory.terminating_chars().unwrap());
+
+    // A table that runs off the end of the file is an error, even at the
+    // end of what a byte pointer can reach.
+    let mut long = vec![0; 0x40];
+    long[0x00] = 5;
+    long[0x2e] = 0xff;
+    long[0x2f] = 0xfe;
+    long.resize(0x10000, 1);
+    assert!(matches!(Memory::from(long).terminating_chars(),
+                     Err(Error::AddressOutOfRange(0x10000))));
+
+    let mut truncated = bytes.clone();
+    truncated.pop();
+    assert!(matches!(Memory::from(truncated).terminating_chars(),
+                     Err(Error::AddressOutOfRange(0x42))));
+
+    // Versions before 5 don't have the table.
+    bytes[0x00] = 3;
+    let memory = Memory::from(bytes);
+    assert!(memory.terminating_chars().unwrap().is_empty());
+  }
+
+  #[test]
+  fn test_screen_size() {
+    let mut bytes = vec![0; 0x40];
+    bytes[0x00] = 5;
+    let mut memory = Memory::from(bytes.clone());
+    memory.set_screen_size(50, 132);
+    assert_eq!(50, memory.u8_at(BytePtr::new(0x20)));
+    assert_eq!(132, memory.u8_at(BytePtr::new(0x21)));
+    assert_eq!(132, memory.u16_at(BytePtr::new(0x22)));
+    assert_eq!(50, memory.u16_at(BytePtr::new(0x24)));
+    assert_eq!(1, memory.u8_at(BytePtr::new(0x26)));
+    assert_eq!(1, memory.u8_at(BytePtr::new(0x27)));
+
+    memory.set_screen_size(300, 300);
+    assert_eq!(254, memory.u8_at(BytePtr::new(0x20)));
+    assert_eq!(255, memory.u8_at(BytePtr::new(0x21)));
+    assert_eq!(300, memory.u16_at(BytePtr::new(0x22)));
+
+    // v3 games don't get told.
+    bytes[0x00] = 3;
+    let mut memory = Memory::from(bytes);
+    memory.set_screen_size(50, 132);
+    assert_eq!(0, memory.u8_at(BytePtr::new(0x20)));
   }
 
   #[test]
-  #[should_panic]
   fn test_globals_overflow_read() {
     // 608 = 0x80 (global base) + 2 * 0xf0 (number of globals)
     let memory = Memory::from(vec![0; 608]);
 
-    memory.read_global(240);
+    assert!(matches!(memory.read_global(240), Err(Error::BadGlobal(240))));
   }
 
   #[test]
-  #[should_panic]
   fn test_globals_overflow_write() {
     // 608 = 0x80 (global base) + 2 * 0xf0 (number of globals)
     let mut memory = Memory::from(vec![0; 608]);
 
-    memory.write_global(240, 0);
+    assert!(matches!(memory.write_global(240, 0), Err(Error::BadGlobal(240))));
   }
 }
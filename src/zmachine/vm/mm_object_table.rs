@@ -1,32 +1,99 @@
+use result::{Error, Result};
 use std::cell::RefCell;
 use std::rc::Rc;
 use zmachine::vm::memory::Memory;
 use zmachine::vm::object_table::{ZObject, ZObjectTable, ZPropertyStorage, ZPropertyTable};
 use zmachine::vm::ptrs::{BytePtr, RawPtr};
 
+// The object table changed in v4: there are 63 property defaults rather than
+// 31, each object has 48 attributes rather than 32, and parents, siblings and
+// children are words rather than bytes, so that there can be more than 255
+// objects. Property sizes are stored differently too.
+
 pub struct MemoryMappedObjectTable {
   memory: Rc<RefCell<Memory>>,
   base_ptr: BytePtr,
+  version: u8,
 }
 
 impl MemoryMappedObjectTable {
   pub fn new(ptr: BytePtr, memory: Rc<RefCell<Memory>>) -> MemoryMappedObjectTable {
+    let version = memory.borrow().version();
     MemoryMappedObjectTable {
       memory: memory,
       base_ptr: ptr,
+      version,
     }
   }
+
+  fn num_defaults(&self) -> u16 {
+    if self.version <= 3 { 31 } else { 63 }
+  }
+
+  fn object_size(&self) -> u16 {
+    if self.version <= 3 { 9 } else { 14 }
+  }
 }
 
 pub struct MemoryMappedObject {
   memory: Rc<RefCell<Memory>>,
   ptr: BytePtr,
+  version: u8,
+}
+
+impl MemoryMappedObject {
+  fn read_relation(&self, offset: u16) -> Result<u16> {
+    let memory = self.memory.borrow();
+    if self.version <= 3 {
+      Ok(memory.read_u8(self.ptr.inc_by(4 + offset))? as u16)
+    } else {
+      memory.read_u16(self.ptr.inc_by(6 + offset * 2))
+    }
+  }
+
+  fn write_relation(&self, offset: u16, number: u16) -> Result<()> {
+    let mut memory = self.memory.borrow_mut();
+    if self.version <= 3 {
+      memory.write_u8(number as u8, self.ptr.inc_by(4 + offset))
+    } else {
+      memory.write_u16(number, self.ptr.inc_by(6 + offset * 2))
+    }
+  }
+
+  fn property_table_offset(&self) -> u16 {
+    if self.version <= 3 { 7 } else { 12 }
+  }
 }
 
 pub struct MemoryMappedPropertyTable {
   memory: Rc<RefCell<Memory>>,
   ptr: BytePtr,
   text_len: u8,
+  version: u8,
+}
+
+impl MemoryMappedPropertyTable {
+  // The number and size of the property at `ptr`, and where its data starts.
+  // A number of 0 ends the table.
+  fn property_at(&self, ptr: BytePtr) -> Result<(u16, u16, BytePtr)> {
+    let memory = self.memory.borrow();
+    let size_byte = memory.read_u8(ptr)?;
+    if self.version <= 3 {
+      return Ok(((size_byte & 0b00011111) as u16, (size_byte / 32 + 1) as u16, ptr.inc_by(1)));
+    }
+    let number = (size_byte & 0b00111111) as u16;
+    if size_byte & 0b10000000 == 0 {
+      let size = if size_byte & 0b01000000 == 0 { 1 } else { 2 };
+      Ok((number, size, ptr.inc_by(1)))
+    } else {
+      // The size is in a second byte, where 0 means 64.
+      let size = match memory.read_u8(ptr.inc_by(1))? & 0b00111111 {
+        0 => 64,
+        size => size as u16,
+      };
+      Ok((number, size, ptr.inc_by(2)))
+    }
+  }
 }
 
 impl ZObjectTable for MemoryMappedObjectTable {
@@ -36,61 +103,123 @@ impl ZObjectTable for MemoryMappedObjectTable {
   fn object_with_number(&self, object_number: u16) -> MemoryMappedObject {
     // TODO: check for 0.
     MemoryMappedObject {
-      // 31 * 2 to skip the defaults table.
+      // Skip the defaults table.
       // Subtract one from object_number because objects are 1-indexed.
       memory: self.memory.clone(),
-      ptr: self.base_ptr.inc_by(31 * 2 + (object_number - 1) * 9),
+      ptr: self.base_ptr
+        .inc_by(self.num_defaults() * 2 + (object_number - 1) * self.object_size()),
+      version: self.version,
     }
   }
 
-  fn default_property_value(&self, property_number: u16) -> u16 {
+  fn default_property_value(&self, property_number: u16) -> Result<u16> {
     // TODO: test this.
     let ptr = self.base_ptr.inc_by(2 * (property_number - 1));
-    self.memory.borrow().u16_at(ptr)
+    self.memory.borrow().read_u16(ptr)
+  }
+
+  fn num_objects(&self) -> u16 {
+    // Like infodump, assume that the objects end where the first property
+    // table starts. (Inform puts them straight after the objects.)
+    let memory = self.memory.borrow();
+    let first_object = self.base_ptr.inc_by(self.num_defaults() * 2);
+    let size = self.object_size();
+    let property_table_offset = size - 2;
+    let start = usize::from(RawPtr::from(first_object));
+    let mut end = usize::MAX;
+    let mut count = 0u16;
+    while start + (count as usize + 1) * size as usize <= end {
+      // Running off the end of memory ends the table too.
+      match memory.read_u16(first_object.inc_by(count * size + property_table_offset)) {
+        Ok(property_table) => end = end.min(property_table as usize),
+        Err(_) => break,
+      }
+      if start + (count as usize + 1) * size as usize > end {
+        break;
+      }
+      count += 1;
+    }
+    count
   }
 }
 
 impl ZObject for MemoryMappedObject {
   type PropertyTable = MemoryMappedPropertyTable;
 
-  fn attributes(&self) -> u32 {
-    self.memory.borrow().u32_at(self.ptr)
+  fn attributes(&self) -> Result<u64> {
+    let memory = self.memory.borrow();
+    let high = (memory.read_u32(self.ptr)? as u64) << 16;
+    if self.version <= 3 {
+      Ok(high)
+    } else {
+      Ok(high | memory.read_u16(self.ptr.inc_by(4))? as u64)
+    }
+  }
+
+  fn set_attributes(&self, attrs: u64) -> Result<()> {
+    let mut memory = self.memory.borrow_mut();
+    memory.write_u32((attrs >> 16) as u32, self.ptr)?;
+    if self.version <= 3 {
+      Ok(())
+    } else {
+      memory.write_u16(attrs as u16, self.ptr.inc_by(4))
+    }
   }
 
-  fn set_attributes(&self, attrs: u32) {
-    self.memory.borrow_mut().set_u32_at(self.ptr, attrs);
+  // Only the byte with the attribute in it is written, so that a watch on
+  // another attribute doesn't see it.
+  fn set_attribute(&self, attr_number: u16, set: bool) -> Result<()> {
+    let ptr = self.ptr.inc_by(attr_number / 8);
+    let bit = 0x80 >> (attr_number % 8);
+    let mut memory = self.memory.borrow_mut();
+    let byte = memory.read_u8(ptr)?;
+    memory.write_u8(if set { byte | bit } else { byte & !bit }, ptr)
   }
 
-  fn parent(&self) -> u16 {
-    self.memory.borrow().u8_at(self.ptr.inc_by(4)) as u16
+  fn parent(&self) -> Result<u16> {
+    self.read_relation(0)
   }
 
-  fn set_parent(&self, parent: u16) {
-    self.memory.borrow_mut().set_u8_at(parent as u8, self.ptr.inc_by(4));
+  fn set_parent(&self, parent: u16) -> Result<()> {
+    self.write_relation(0, parent)
   }
 
-  fn sibling(&self) -> u16 {
-    self.memory.borrow().u8_at(self.ptr.inc_by(5)) as u16
+  fn sibling(&self) -> Result<u16> {
+    self.read_relation(1)
   }
 
-  fn set_sibling(&self, sibling: u16) {
-    self.memory.borrow_mut().set_u8_at(sibling as u8, self.ptr.inc_by(5))
+  fn set_sibling(&self, sibling: u16) -> Result<()> {
+    self.write_relation(1, sibling)
   }
 
-  fn child(&self) -> u16 {
-    self.memory.borrow().u8_at(self.ptr.inc_by(6)) as u16
+  fn child(&self) -> Result<u16> {
+    self.read_relation(2)
   }
 
-  fn set_child(&self, child: u16) {
-    self.memory.borrow_mut().set_u8_at(child as u8, self.ptr.inc_by(6));
+  fn set_child(&self, child: u16) -> Result<()> {
+    self.write_relation(2, child)
   }
 
-  fn property_table(&self) -> MemoryMappedPropertyTable {
-    let ptr = BytePtr::new(self.memory.borrow().u16_at(self.ptr.inc_by(7)));
-    MemoryMappedPropertyTable {
+  fn property_table(&self) -> Result<MemoryMappedPropertyTable> {
+    let memory = self.memory.borrow();
+    let ptr = BytePtr::new(memory.read_u16(self.ptr.inc_by(self.property_table_offset()))?);
+    Ok(MemoryMappedPropertyTable {
       ptr: ptr,
       memory: self.memory.clone(),
-      text_len: self.memory.borrow().u8_at(ptr),
+      text_len: memory.read_u8(ptr)?,
+      version: self.version,
+    })
+  }
+
+  fn attribute_span(&self, attr_number: u16) -> (usize, usize) {
+    (usize::from(RawPtr::from(self.ptr.inc_by(attr_number / 8))), 1)
+  }
+
+  fn parent_span(&self) -> (usize, usize) {
+    if self.version <= 3 {
+      (usize::from(RawPtr::from(self.ptr.inc_by(4))), 1)
+    } else {
+      (usize::from(RawPtr::from(self.ptr.inc_by(6))), 2)
     }
   }
 }
@@ -100,17 +229,17 @@ pub struct MemoryWrapper {
 }
 
 impl ZPropertyStorage for MemoryWrapper {
-  fn byte_property(&self, ptr: BytePtr) -> u16 {
-    self.memory.borrow().u8_at(ptr) as u16
+  fn byte_property(&self, ptr: BytePtr) -> Result<u16> {
+    Ok(self.memory.borrow().read_u8(ptr)? as u16)
   }
-  fn word_property(&self, ptr: BytePtr) -> u16 {
-    self.memory.borrow().u16_at(ptr)
+  fn word_property(&self, ptr: BytePtr) -> Result<u16> {
+    self.memory.borrow().read_u16(ptr)
   }
-  fn set_byte_property(&mut self, value: u8, ptr: BytePtr) {
-    self.memory.borrow_mut().set_u8_at(value, ptr);
+  fn set_byte_property(&mut self, value: u8, ptr: BytePtr) -> Result<()> {
+    self.memory.borrow_mut().write_u8(value, ptr)
   }
-  fn set_word_property(&mut self, value: u16, ptr: BytePtr) {
-    self.memory.borrow_mut().set_u16_at(value, ptr);
+  fn set_word_property(&mut self, value: u16, ptr: BytePtr) -> Result<()> {
+    self.memory.borrow_mut().write_u16(value, ptr)
   }
 }
 
@@ -125,57 +254,48 @@ impl ZPropertyTable for MemoryMappedPropertyTable {
     self.ptr.inc_by(1)
   }
 
-  fn find_property(&self, number: u16) -> Option<(u16, BytePtr)> {
+  fn find_property(&self, number: u16) -> Result<Option<(u16, BytePtr)>> {
     // * 2 because it's a word count, +1 to skip the size byte as well as the text.
     let mut prop_ptr = self.ptr.inc_by(self.text_len as u16 * 2 + 1);
     loop {
-      let size_byte = self.memory.borrow().u8_at(prop_ptr);
-      let prop_num = (size_byte & 0b00011111u8) as u16;
+      let (prop_num, size, data_ptr) = self.property_at(prop_ptr)?;
       // Properties are sorted descending, and terminated by a 0 size_byte.
       if prop_num < number {
-        return None;
+        return Ok(None);
       }
 
-      let size = size_byte / 32 + 1;
       if prop_num == number {
-        return Some((size as u16, prop_ptr.inc_by(1)));
+        return Ok(Some((size, data_ptr)));
       }
 
-      // Add 1 (for the size byte) plus the length of the property.
-      prop_ptr = prop_ptr.inc_by(size as u16 + 1);
+      // Skip the size byte(s) and the data.
+      prop_ptr = data_ptr.inc_by(size);
     }
   }
 
-  fn next_property(&self, number: u16) -> u16 {
+  fn next_property(&self, number: u16) -> Result<u16> {
     // * 2 because text_len is a word count, +1 to skip the size byte as well as the text.
     let mut prop_ptr = self.ptr.inc_by(self.text_len as u16 * 2 + 1);
     info!(target: "pctrace", "start ptr: {:?}", prop_ptr);
     // TODO: oh, man, seriously test this.
     if number > 0 {
-      match self.find_property(number) {
-        None => panic!("Unknown property requested: {}", number),
-        Some((_, ptr)) => {
-          // Subtract one to get back to the size byte.
-          let val = usize::from(RawPtr::from(ptr)) - 1usize;
-          prop_ptr = BytePtr::new(val as u16);
-          let size_byte = self.memory.borrow().u8_at(prop_ptr);
-          let size = size_byte / 32 + 1;
-          prop_ptr = prop_ptr.inc_by(size as u16 + 1);
-        }
+      match self.find_property(number)? {
+        None => return Err(Error::PropertyNotFound(number)),
+        Some((size, ptr)) => prop_ptr = ptr.inc_by(size),
       }
     }
 
     // Now, prop_ptr should point to the next property.
     info!(target: "pctrace", "end ptr: {:?}", prop_ptr);
-    let size_byte = self.memory.borrow().u8_at(prop_ptr);
-    let prop_num = (size_byte & 0b00011111u8) as u16;
-    prop_num
-
+    let (prop_num, _, _) = self.property_at(prop_ptr)?;
+    Ok(prop_num)
   }
 }
 
 #[cfg(test)]
 mod tests {
+  use std::cell::RefCell;
+  use std::rc::Rc;
   use super::{MemoryMappedObject, MemoryMappedObjectTable, MemoryMappedPropertyTable};
   use zmachine::vm::memory::Memory;
   use zmachine::vm::object_table::{ZObject, ZObjectTable, ZPropertyTable};
@@ -185,21 +305,18 @@ mod tests {
   fn test_mm_object_table() {
     // This is a very simple data structure. We only really have to test that
     // objects are mapped to the correct place.
-    let object_table = MemoryMappedObjectTable::new(BytePtr::new(0));
+    let memory = Rc::new(RefCell::new(Memory::from(vec![0; 128])));
+    let object_table = MemoryMappedObjectTable::new(BytePtr::new(0), memory);
     let object = object_table.object_with_number(1);
-    assert_eq!(1, object.number);
     assert_eq!(BytePtr::new(62), object.ptr);
 
     let object = object_table.object_with_number(2);
-    assert_eq!(2, object.number);
     assert_eq!(BytePtr::new(71), object.ptr);
 
     let object = object_table.object_with_number(3);
-    assert_eq!(3, object.number);
     assert_eq!(BytePtr::new(80), object.ptr);
 
     let object = object_table.object_with_number(6);
-    assert_eq!(6, object.number);
     assert_eq!(BytePtr::new(107), object.ptr);
   }
 
@@ -209,34 +326,104 @@ mod tests {
     // fields can be read and written.
     // But now, we have to create a Memory object to map to.
     // This requires knowledge of the spec.
-    let mut memory = Memory::from(vec![0x00, 0x00, 0x00, 0x00 /* some padding */, 0x34,
-                                       0x56, 0x78, 0x9a /* attributes */,
-                                       0x12 /* parent */, 0x13 /* sibling */,
-                                       0x23 /* child */, 0x65, 0x43 /* property ptr */]);
-    let ptr = BytePtr::new(0x04);  // skip the padding
+    // The game can only write to dynamic memory past the header, so the
+    // object goes straight after the header, and ends dynamic memory.
+    let mut bytes = vec![0; 0x40];
+    bytes[0x0f] = 0x49;
+    bytes.extend_from_slice(&[0x34, 0x56, 0x78, 0x9a /* attributes */,
+                              0x12 /* parent */, 0x13 /* sibling */,
+                              0x23 /* child */, 0x65, 0x43 /* property ptr */]);
+    let memory = Memory::from(bytes);
+    let ptr = BytePtr::new(0x40);  // skip the header
     let obj = MemoryMappedObject {
-      number: 3,
+      memory: Rc::new(RefCell::new(memory)),
       ptr: ptr,
+      version: 3,
     };
 
-    assert_eq!(0x3456789a, obj.attributes(&memory));
-    assert_eq!(0x12, obj.parent(&memory));
-    assert_eq!(0x13, obj.sibling(&memory));
-    assert_eq!(0x23, obj.child(&memory));
+    // The attributes are the top 32 of 48 bits.
+    assert_eq!(0x3456789a0000, obj.attributes().unwrap());
+    assert_eq!(0x12, obj.parent().unwrap());
+    assert_eq!(0x13, obj.sibling().unwrap());
+    assert_eq!(0x23, obj.child().unwrap());
 
-    obj.set_attributes(0x55667788, &mut memory);
-    obj.set_parent(0x11, &mut memory);
-    obj.set_sibling(0x77, &mut memory);
-    obj.set_child(0xcc, &mut memory);
+    obj.set_attributes(0x556677880000).unwrap();
+    obj.set_parent(0x11).unwrap();
+    obj.set_sibling(0x77).unwrap();
+    obj.set_child(0xcc).unwrap();
 
-    assert_eq!(0x55667788, obj.attributes(&memory));
-    assert_eq!(0x11, obj.parent(&memory));
-    assert_eq!(0x77, obj.sibling(&memory));
-    assert_eq!(0xcc, obj.child(&memory));
+    assert_eq!(0x556677880000, obj.attributes().unwrap());
+    assert_eq!(0x11, obj.parent().unwrap());
+    assert_eq!(0x77, obj.sibling().unwrap());
+    assert_eq!(0xcc, obj.child().unwrap());
 
     // TODO: test property_table().
   }
 
+  #[test]
+  fn test_mm_set_attribute() {
+    let mut bytes = vec![0; 0x40];
+    bytes[0x0f] = 0x49;
+    bytes.extend_from_slice(&[0x80, 0, 0, 0, 0x12, 0x13, 0x23, 0x65, 0x43]);
+    let mut memory = Memory::from(bytes);
+    memory.record_writes();
+    let memory = Rc::new(RefCell::new(memory));
+    let obj = MemoryMappedObject {
+      memory: memory.clone(),
+      ptr: BytePtr::new(0x40),
+      version: 3,
+    };
+
+    // Only the byte with the attribute in it is written, even if it doesn't
+    // change.
+    obj.set_attribute(0, true).unwrap();
+    obj.set_attribute(9, true).unwrap();
+    obj.set_attribute(0, false).unwrap();
+    assert_eq!(0x004000000000, obj.attributes().unwrap());
+    assert_eq!(vec![(0x40, 1), (0x41, 1), (0x40, 1)], memory.borrow_mut().take_writes());
+    assert_eq!((0x41, 1), obj.attribute_span(9));
+    assert_eq!((0x44, 1), obj.parent_span());
+  }
+
+  #[test]
+  fn test_mm_objects_v4() {
+    // From v4, objects have 48 attributes and word-sized relations.
+    let mut bytes = vec![0; 0x40 + 63 * 2];
+    bytes[0x00] = 4;
+    bytes[0x0f] = 0xcc;
+    bytes.extend_from_slice(&[0x34, 0x56, 0x78, 0x9a, 0xbc, 0xde /* attributes */,
+                              0x01, 0x12 /* parent */, 0x01, 0x13 /* sibling */,
+                              0x01, 0x23 /* child */, 0x65, 0x43 /* property ptr */]);
+    let memory = Rc::new(RefCell::new(Memory::from(bytes)));
+    let object_table = MemoryMappedObjectTable::new(BytePtr::new(0x40), memory);
+    let obj = object_table.object_with_number(1);
+    assert_eq!(BytePtr::new(0xbe), obj.ptr);
+    assert_eq!(BytePtr::new(0xcc), object_table.object_with_number(2).ptr);
+
+    assert_eq!(0x3456789abcde, obj.attributes().unwrap());
+    assert_eq!(0x112, obj.parent().unwrap());
+    assert_eq!(0x113, obj.sibling().unwrap());
+    assert_eq!(0x123, obj.child().unwrap());
+
+    obj.set_attributes(0x000000000001).unwrap();
+    obj.set_child(0x2cc).unwrap();
+    assert_eq!(0x000000000001, obj.attributes().unwrap());
+    assert_eq!(0x113, obj.sibling().unwrap());
+    assert_eq!(0x2cc, obj.child().unwrap());
+  }
+
+  #[test]
+  fn test_num_objects() {
+    // Three objects, with the first property table straight after them.
+    let mut bytes = vec![0; 128];
+    for (i, property_table) in [0x59, 0x5f, 0x65].iter().enumerate() {
+      bytes[62 + i * 9 + 8] = *property_table;
+    }
+    let memory = Rc::new(RefCell::new(Memory::from(bytes)));
+    let object_table = MemoryMappedObjectTable::new(BytePtr::new(0), memory);
+    assert_eq!(3, object_table.num_objects());
+  }
+
   #[test]
   fn test_mm_property_table() {
     let blob = vec![// Leave 3 zeros at the front to test the ptr code.
@@ -285,20 +472,62 @@ mod tests {
                     0x00];
     let memory = Memory::from(blob);
     let ptr = BytePtr::new(3);
+    let text_len = memory.u8_at(ptr);
     let prop_table = MemoryMappedPropertyTable {
+      memory: Rc::new(RefCell::new(memory)),
       ptr: ptr,
-      text_len: memory.u8_at(ptr),
+      text_len,
+      version: 3,
     };
 
-    assert_eq!(None, prop_table.find_property(21, &memory));
-    assert_eq!(None, prop_table.find_property(9, &memory));
-    assert_eq!(None, prop_table.find_property(1, &memory));
+    assert_eq!(None, prop_table.find_property(21).unwrap());
+    assert_eq!(None, prop_table.find_property(9).unwrap());
+    assert_eq!(None, prop_table.find_property(1).unwrap());
 
     assert_eq!(Some((2, BytePtr::new(18))),
-               prop_table.find_property(12, &memory));
+               prop_table.find_property(12).unwrap());
     assert_eq!(Some((4, BytePtr::new(21))),
-               prop_table.find_property(7, &memory));
+               prop_table.find_property(7).unwrap());
     assert_eq!(Some((2, BytePtr::new(26))),
-               prop_table.find_property(3, &memory));
+               prop_table.find_property(3).unwrap());
+  }
+
+  #[test]
+  fn test_mm_property_table_v4() {
+    // No name, then property 40 of length 10, with two size bytes.
+    let mut blob = vec![0x00, 0x80 | 40, 0x80 | 10];
+    blob.extend_from_slice(&[0x01; 10]);
+    // Property 33 of length 64, which is stored as 0.
+    blob.extend_from_slice(&[0x80 | 33, 0x80]);
+    blob.extend_from_slice(&[0xff; 64]);
+    blob.extend_from_slice(&[// property 12 of length 2, val = 0xdde2
+                             0x40 | 12,
+                             0xdd,
+                             0xe2,
+
+                             // property 3 of length 1, val = 0x12
+                             3,
+                             0x12,
+
+                             // termination byte
+                             0x00]);
+    let prop_table = MemoryMappedPropertyTable {
+      memory: Rc::new(RefCell::new(Memory::from(blob))),
+      ptr: BytePtr::new(0),
+      text_len: 0,
+      version: 4,
+    };
+
+    assert_eq!(Some((10, BytePtr::new(3))), prop_table.find_property(40).unwrap());
+    assert_eq!(Some((64, BytePtr::new(15))), prop_table.find_property(33).unwrap());
+    assert_eq!(Some((2, BytePtr::new(80))), prop_table.find_property(12).unwrap());
+    assert_eq!(Some((1, BytePtr::new(83))), prop_table.find_property(3).unwrap());
+    assert_eq!(None, prop_table.find_property(20).unwrap());
+
+    assert_eq!(40, prop_table.next_property(0).unwrap());
+    assert_eq!(33, prop_table.next_property(40).unwrap());
+    assert_eq!(12, prop_table.next_property(33).unwrap());
+    assert_eq!(3, prop_table.next_property(12).unwrap());
+    assert_eq!(0, prop_table.next_property(3).unwrap());
   }
 }
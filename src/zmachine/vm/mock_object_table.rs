@@ -1,7 +1,9 @@
-use std::collections::HashMap;
+use result::Result;
+use std::cell::RefCell;
+use std::rc::Rc;
 
 use zmachine::vm::BytePtr;
-use zmachine::vm::object_table::{ZObject, ZObjectTable, ZPropertyAccess, ZPropertyTable};
+use zmachine::vm::object_table::{ZObject, ZObjectTable, ZPropertyStorage, ZPropertyTable};
 
 #[derive(Debug,Clone,Eq,PartialEq)]
 pub struct MockObjectTableStorage {
@@ -10,40 +12,31 @@ pub struct MockObjectTableStorage {
 
 #[derive(Debug)]
 pub struct MockObjectTable {
+  storage: Rc<RefCell<MockObjectTableStorage>>,
 }
 
-struct MockObject {
+pub struct MockObject {
+  storage: Rc<RefCell<MockObjectTableStorage>>,
   object_number: u16,
 }
 
 #[derive(Debug,Clone,Eq,PartialEq)]
 struct MockObjectRep {
-  attributes: u32,
+  attributes: u64,
   parent: u16,
   sibling: u16,
   child: u16,
 }
 
-pub struct MockPropertyTableStorage {
-  table: HashMap<(u16, u16), MockProperty>,
-}
-
-pub struct MockPropertyTable {
-  table: HashMap<u16, MockProperty>,
-}
+pub struct MockPropertyTableStorage {}
 
-#[derive(Clone, Copy)]
-struct MockProperty {
-  number: u16,
-  size: u16,
-  val: u16,
-}
+pub struct MockPropertyTable {}
 
 impl MockObjectTableStorage {
   pub fn new() -> MockObjectTableStorage {
     MockObjectTableStorage { objects: Vec::new() }
   }
-  pub fn add_mock_object(&mut self, attributes: u32, parent: u16, sibling: u16, child: u16) {
+  pub fn add_mock_object(&mut self, attributes: u64, parent: u16, sibling: u16, child: u16) {
     let obj = MockObjectRep {
       attributes: attributes,
       parent: parent,
@@ -53,139 +46,137 @@ impl MockObjectTableStorage {
     self.objects.push(obj);
   }
 
-  fn attributes(&self, object_number: u16) -> u32 {
-    let ref rep = self.objects[object_number as usize - 1];
-    rep.attributes
-  }
-  fn set_attributes(&mut self, object_number: u16, attrs: u32) {
-    let ref mut rep = self.objects[object_number as usize - 1];
-    rep.attributes = attrs;
-  }
-  fn parent(&self, object_number: u16) -> u16 {
-    let ref rep = self.objects[object_number as usize - 1];
-    rep.parent
-  }
-  fn set_parent(&mut self, object_number: u16, parent: u16) {
-    let ref mut rep = self.objects[object_number as usize - 1];
-    rep.parent = parent;
+  fn rep(&self, object_number: u16) -> &MockObjectRep {
+    &self.objects[object_number as usize - 1]
   }
-  fn sibling(&self, object_number: u16) -> u16 {
-    let ref rep = self.objects[object_number as usize - 1];
-    rep.sibling
-  }
-  fn set_sibling(&mut self, object_number: u16, sibling: u16) {
-    let ref mut rep = self.objects[object_number as usize - 1];
-    rep.sibling = sibling;
-  }
-  fn child(&self, object_number: u16) -> u16 {
-    let ref rep = self.objects[object_number as usize - 1];
-    rep.child
-  }
-  fn set_child(&mut self, object_number: u16, child: u16) {
-    let ref mut rep = self.objects[object_number as usize - 1];
-    rep.child = child;
+  fn rep_mut(&mut self, object_number: u16) -> &mut MockObjectRep {
+    &mut self.objects[object_number as usize - 1]
   }
 }
 
 impl MockObjectTable {
-  pub fn new() -> MockObjectTable {
-    MockObjectTable {}
+  pub fn new(storage: Rc<RefCell<MockObjectTableStorage>>) -> MockObjectTable {
+    MockObjectTable { storage }
   }
 }
 
 impl ZObjectTable for MockObjectTable {
   type ZObject = MockObject;
-  type DataAccess = MockObjectTableStorage;
   type PropertyTable = MockPropertyTable;
-  type PropertyAccess = MockPropertyTableStorage;
 
   fn object_with_number(&self, object_number: u16) -> MockObject {
-    MockObject { object_number: object_number }
+    MockObject {
+      storage: self.storage.clone(),
+      object_number,
+    }
   }
 
-  fn default_property_value(&self, property_number: u16, access: &Self::DataAccess) -> u16 {
-    43 + 2 * property_number
+  fn default_property_value(&self, property_number: u16) -> Result<u16> {
+    Ok(43 + 2 * property_number)
+  }
+
+  fn num_objects(&self) -> u16 {
+    self.storage.borrow().objects.len() as u16
   }
 }
 
 impl ZObject for MockObject {
-  type DataAccess = MockObjectTableStorage;
   type PropertyTable = MockPropertyTable;
 
-  fn attributes(&self, helper: &MockObjectTableStorage) -> u32 {
-    helper.attributes(self.object_number)
+  fn attributes(&self) -> Result<u64> {
+    Ok(self.storage.borrow().rep(self.object_number).attributes)
   }
 
-  fn set_attributes(&self, attrs: u32, helper: &mut MockObjectTableStorage) {
-    helper.set_attributes(self.object_number, attrs);
+  fn set_attributes(&self, attrs: u64) -> Result<()> {
+    self.storage.borrow_mut().rep_mut(self.object_number).attributes = attrs;
+    Ok(())
   }
 
-  fn parent(&self, helper: &MockObjectTableStorage) -> u16 {
-    helper.parent(self.object_number)
+  fn parent(&self) -> Result<u16> {
+    Ok(self.storage.borrow().rep(self.object_number).parent)
   }
 
-  fn set_parent(&self, parent: u16, helper: &mut MockObjectTableStorage) {
-    helper.set_parent(self.object_number, parent);
+  fn set_parent(&self, parent: u16) -> Result<()> {
+    self.storage.borrow_mut().rep_mut(self.object_number).parent = parent;
+    Ok(())
   }
 
-  fn sibling(&self, helper: &MockObjectTableStorage) -> u16 {
-    helper.sibling(self.object_number)
+  fn sibling(&self) -> Result<u16> {
+    Ok(self.storage.borrow().rep(self.object_number).sibling)
   }
-  fn set_sibling(&self, sibling: u16, helper: &mut MockObjectTableStorage) {
-    helper.set_sibling(self.object_number, sibling);
+
+  fn set_sibling(&self, sibling: u16) -> Result<()> {
+    self.storage.borrow_mut().rep_mut(self.object_number).sibling = sibling;
+    Ok(())
   }
 
-  fn child(&self, helper: &MockObjectTableStorage) -> u16 {
-    helper.child(self.object_number)
+  fn child(&self) -> Result<u16> {
+    Ok(self.storage.borrow().rep(self.object_number).child)
   }
 
-  fn set_child(&self, child: u16, helper: &mut MockObjectTableStorage) {
-    helper.set_child(self.object_number, child);
+  fn set_child(&self, child: u16) -> Result<()> {
+    self.storage.borrow_mut().rep_mut(self.object_number).child = child;
+    Ok(())
   }
 
-  fn property_table(&self, helper: &MockObjectTableStorage) -> MockPropertyTable {
+  fn property_table(&self) -> Result<MockPropertyTable> {
     // TODO: make this really work.
-    MockPropertyTable { table: HashMap::new() }
+    Ok(MockPropertyTable {})
+  }
+
+  // Mock objects aren't in memory.
+  fn attribute_span(&self, _: u16) -> (usize, usize) {
+    (0, 0)
   }
-}
 
-impl MockPropertyTableStorage {
-  pub fn new() -> MockPropertyTableStorage {
-    MockPropertyTableStorage { table: HashMap::new() }
+  fn parent_span(&self) -> (usize, usize) {
+    (0, 0)
   }
 }
 
-impl ZPropertyAccess for MockPropertyTableStorage {
-  fn byte_property(&self, ptr: BytePtr) -> u16 {
-    0
+impl ZPropertyStorage for MockPropertyTableStorage {
+  fn byte_property(&self, _: BytePtr) -> Result<u16> {
+    Ok(0)
   }
-  fn word_property(&self, ptr: BytePtr) -> u16 {
-    0
+  fn word_property(&self, _: BytePtr) -> Result<u16> {
+    Ok(0)
+  }
+  fn set_byte_property(&mut self, _: u8, _: BytePtr) -> Result<()> {
+    Ok(())
+  }
+  fn set_word_property(&mut self, _: u16, _: BytePtr) -> Result<()> {
+    Ok(())
   }
-  fn set_byte_property(&mut self, value: u8, ptr: BytePtr) {}
-  fn set_word_property(&mut self, value: u16, ptr: BytePtr) {}
 }
 
 impl ZPropertyTable for MockPropertyTable {
-  type PropertyAccess = MockPropertyTableStorage;
+  type Storage = MockPropertyTableStorage;
+
+  fn storage(&self) -> MockPropertyTableStorage {
+    MockPropertyTableStorage {}
+  }
 
-  fn name_ptr(&self, helper: &MockPropertyTableStorage) -> BytePtr {
+  fn name_ptr(&self) -> BytePtr {
     // TODO: implement a testable version of this.
     BytePtr::new(32)
   }
 
   // property numbers are 1-31. Returns the size and ptr to the property.
-  fn find_property(&self,
-                   number: u16,
-                   helper: &MockPropertyTableStorage)
-                   -> Option<(u16, BytePtr)> {
+  fn find_property(&self, _: u16) -> Result<Option<(u16, BytePtr)>> {
+    // TODO: implement a testable version of this.
+    Ok(None)
+  }
+
+  fn next_property(&self, _: u16) -> Result<u16> {
     // TODO: implement a testable version of this.
-    None
+    Ok(0)
   }
 }
 
 #[cfg(test)]
 mod tests {
+  use std::cell::RefCell;
+  use std::rc::Rc;
   use super::{MockObjectTable, MockObjectTableStorage};
   use zmachine::vm::object_table::{ZObject, ZObjectTable};
 
@@ -193,8 +184,8 @@ mod tests {
   fn test_mock_object_table() {
     // This is a very simple data structure. We only really have to test that
     // objects are mapped to the correct place.
-    let storage = MockObjectTableStorage::new();
-    let object_table = MockObjectTable::new();
+    let storage = Rc::new(RefCell::new(MockObjectTableStorage::new()));
+    let object_table = MockObjectTable::new(storage);
     let object = object_table.object_with_number(1);
     assert_eq!(1, object.object_number);
 
@@ -213,91 +204,24 @@ mod tests {
     let mut storage = MockObjectTableStorage::new();
     storage.add_mock_object(0x3456789a, 0x12, 0x13, 0x23);
 
-    let object_table = MockObjectTable::new();
+    let object_table = MockObjectTable::new(Rc::new(RefCell::new(storage)));
     let obj = object_table.object_with_number(1);
 
-    assert_eq!(0x3456789a, obj.attributes(&storage));
-    assert_eq!(0x12, obj.parent(&storage));
-    assert_eq!(0x13, obj.sibling(&storage));
-    assert_eq!(0x23, obj.child(&storage));
+    assert_eq!(0x3456789a, obj.attributes().unwrap());
+    assert_eq!(0x12, obj.parent().unwrap());
+    assert_eq!(0x13, obj.sibling().unwrap());
+    assert_eq!(0x23, obj.child().unwrap());
 
-    obj.set_attributes(0x55667788, &mut storage);
-    obj.set_parent(0x11, &mut storage);
-    obj.set_sibling(0x77, &mut storage);
-    obj.set_child(0xcc, &mut storage);
+    obj.set_attributes(0x55667788).unwrap();
+    obj.set_parent(0x11).unwrap();
+    obj.set_sibling(0x77).unwrap();
+    obj.set_child(0xcc).unwrap();
 
-    assert_eq!(0x55667788, obj.attributes(&storage));
-    assert_eq!(0x11, obj.parent(&storage));
-    assert_eq!(0x77, obj.sibling(&storage));
-    assert_eq!(0xcc, obj.child(&storage));
+    assert_eq!(0x55667788, obj.attributes().unwrap());
+    assert_eq!(0x11, obj.parent().unwrap());
+    assert_eq!(0x77, obj.sibling().unwrap());
+    assert_eq!(0xcc, obj.child().unwrap());
 
     // TODO: test property_table().
   }
-
-
-  // // TODO: fn test_mock_property_table() {}
-  // impl MockPropertyTable {
-  //   fn new(size: u16) -> MockPropertyTable {
-  //     MockPropertyTable { table: HashMap::new() }
-  //   }
-
-  //   fn add_property(&mut self, number: u16, size: u16, val: u16) {
-  //     self.table.insert(number, MockProperty::new(number, size, val));
-  //   }
-  // }
-
-  // impl MockProperty {
-  //   fn new(number: u16, size: u16, val: u16) -> MockProperty {
-  //     MockProperty {
-  //       number: number,
-  //       size: size,
-  //       val: val,
-  //     }
-  //   }
-  // }
-
-  //   impl ZPropertyTable for MockPropertyTable {
-  //     type Helper = Self;
-  //     type Ref = u16;
-
-  //     fn name_ptr(&self, helper: &Self::Helper) -> u16 {
-  //       0
-  //     }
-
-  //     fn find_property(&self, number: u16, helper: &Self::Helper) -> Option<(u16, Self::Ref)> {
-  //       Some((2, number))
-  //     }
-  //   }
-
-  //   impl ZPropertyAccess for MockPropertyTable {
-  //     type Ref = u16;
-
-  //     fn set_byte_property(&mut self, value: u8, ptr: u16) {
-  //       let property = self.table.get_mut(&ptr).unwrap();
-  //       property.val = value as u16;
-  //     }
-
-  //     fn set_word_property(&mut self, value: u16, ptr: u16) {
-  //       let property = self.table.get_mut(&ptr).unwrap();
-  //       property.val = value;
-  //     }
-  //   }
-
-  //   // test set property
-  //   fn test_mock_property_table() {
-  //     let mut table = MockPropertyTable::new(20);
-  //     table.add_property(17, 2, 0x1234);
-  //     table.add_property(22, 5, 0);
-  //     table.add_property(23, 1, 0x11);
-
-  //     let prop = table.find_property(17, &table);
-  //     assert!(prop.is_some());
-  //     let prop = table.find_property(22, &table);
-  //     assert!(prop.is_some());
-  //     let prop = table.find_property(23, &table);
-  //     assert!(prop.is_some());
-
-  //     let prop = table.find_property(3, &table);
-  //     assert!(prop.is_none());
-  //   }
 }
@@ -1,26 +1,44 @@
+mod colour;
 mod dictionary;
+mod error_policy;
 mod memory;
 mod mm_object_table;
 #[cfg(test)]
 mod mock_object_table;
 mod object_table;
+mod output;
+mod pager;
 mod pc;
 mod ptrs;
 mod quetzal;
+mod screen;
+mod screen_model;
+mod zkeys;
 mod zreadline;
+mod zscii;
 mod stack;
+mod timeline;
+mod transcript;
 mod vm;
+mod wrap;
 pub mod zvm;
 
+pub use self::colour::{colour_from_name, colour_from_true_colour, colour_names};
+pub use self::dictionary::Dictionary;
+pub use self::error_policy::{ErrorMode, ErrorPolicy, error_mode_from_name, error_mode_names};
 pub use self::memory::Memory;
 
-pub use self::object_table::{ZObject, ZObjectTable, ZPropertyStorage, ZPropertyTable};
+pub use self::object_table::{ZObject, ZObjectTable, ZPropertyStorage, ZPropertyTable,
+                             attribute_mask, num_attributes};
 pub use self::ptrs::BytePtr;
 pub use self::ptrs::PackedAddr;
 pub use self::ptrs::RawPtr;
 pub use self::ptrs::WordPtr;
+pub use self::stack::Frame;
+pub use self::timeline::{DEFAULT_SNAPSHOT_INTERVAL, Input, Timeline};
 pub use self::vm::VM;
 pub use self::vm::VariableRef;
+pub use self::zscii::char_to_zscii;
 
 #[cfg(test)]
 pub mod test {
@@ -1,4 +1,4 @@
-use result::Result;
+use result::{Error, Result};
 use zmachine::vm::BytePtr;
 
 // We create traits for ZObjectTable and ZObject to facilitate testability.
@@ -15,11 +15,13 @@ pub trait ZObjectTable {
   type PropertyTable: ZPropertyTable;
 
   fn object_with_number(&self, object_number: u16) -> Self::ZObject;
-  fn default_property_value(&self, property_number: u16) -> u16;
+  fn default_property_value(&self, property_number: u16) -> Result<u16>;
+  /// How many objects there are. The table doesn't say, so this is a guess.
+  fn num_objects(&self) -> u16;
 
   fn remove_object_from_parent(&self, object_number: u16) -> Result<()> {
     let obj = self.object_with_number(object_number);
-    let parent_number = obj.parent();
+    let parent_number = obj.parent()?;
 
     if parent_number == 0 {
       // object has no parent, so just return.
@@ -27,29 +29,30 @@ pub trait ZObjectTable {
     }
 
     let parent_obj = self.object_with_number(parent_number);
-    let mut current_number = parent_obj.child();
+    let mut current_number = parent_obj.child()?;
     if current_number == object_number {
       // object is first child
-      let new_child = obj.sibling();
-      parent_obj.set_child(new_child);
+      let new_child = obj.sibling()?;
+      parent_obj.set_child(new_child)?;
     } else {
       // object should be in sibling list
-      loop {
-        assert!(current_number != 0);
+      while current_number != 0 {
         let current_sibling = self.object_with_number(current_number);
-        let next_sibling = current_sibling.sibling();
+        let next_sibling = current_sibling.sibling()?;
         if next_sibling == object_number {
-          let new_next_sibling = obj.sibling();
-          current_sibling.set_sibling(new_next_sibling);
+          let new_next_sibling = obj.sibling()?;
+          current_sibling.set_sibling(new_next_sibling)?;
           break;
         } else {
           current_number = next_sibling;
         }
       }
+      // If it wasn't there, the table is already broken. Detaching the object
+      // from its parent is the best we can do.
     }
 
-    obj.set_sibling(0);
-    obj.set_parent(0);
+    obj.set_sibling(0)?;
+    obj.set_parent(0)?;
     return Ok(());
   }
 
@@ -57,11 +60,10 @@ pub trait ZObjectTable {
     let parent_obj = self.object_with_number(parent_number);
     let obj = self.object_with_number(object_number);
 
-    let new_sibling = parent_obj.child();
-    obj.set_parent(parent_number);
-    obj.set_sibling(new_sibling);
-    parent_obj.set_child(object_number);
-    Ok(())
+    let new_sibling = parent_obj.child()?;
+    obj.set_parent(parent_number)?;
+    obj.set_sibling(new_sibling)?;
+    parent_obj.set_child(object_number)
   }
 
   fn insert_obj(&self, object_number: u16, parent_number: u16) -> Result<()> {
@@ -80,25 +82,48 @@ pub trait ZObjectTable {
   }
 }
 
+/// How many attributes each object has: 32 before v4, and 48 from then on.
+pub fn num_attributes(version: u8) -> u16 {
+  if version <= 3 { 32 } else { 48 }
+}
+
+/// The bit for an attribute in ZObject::attributes(). Attribute 0 is the top
+/// bit, so before v4 the bottom 16 bits are unused.
+pub fn attribute_mask(attr_number: u16) -> u64 {
+  1u64 << (47 - attr_number)
+}
+
+// Objects live in the game's memory, so any of these can fail if the table
+// points outside it.
 pub trait ZObject {
   type PropertyTable;
 
-  fn attributes(&self) -> u32;
-  fn set_attributes(&self, attrs: u32);
-  fn parent(&self) -> u16;
-  fn set_parent(&self, parent: u16);
-  fn sibling(&self) -> u16;
-  fn set_sibling(&self, sibling: u16);
-  fn child(&self) -> u16;
-  fn set_child(&self, child: u16);
-  fn property_table(&self) -> Self::PropertyTable;
+  fn attributes(&self) -> Result<u64>;
+  fn set_attributes(&self, attrs: u64) -> Result<()>;
+  fn set_attribute(&self, attr_number: u16, set: bool) -> Result<()> {
+    let attrs = self.attributes()?;
+    let mask = attribute_mask(attr_number);
+    self.set_attributes(if set { attrs | mask } else { attrs & !mask })
+  }
+  fn parent(&self) -> Result<u16>;
+  fn set_parent(&self, parent: u16) -> Result<()>;
+  fn sibling(&self) -> Result<u16>;
+  fn set_sibling(&self, sibling: u16) -> Result<()>;
+  fn child(&self) -> Result<u16>;
+  fn set_child(&self, child: u16) -> Result<()>;
+  fn property_table(&self) -> Result<Self::PropertyTable>;
+
+  // Where an attribute and the parent are kept in memory, as (address,
+  // length), so that watchpoints can tell when the game writes to them.
+  fn attribute_span(&self, attr_number: u16) -> (usize, usize);
+  fn parent_span(&self) -> (usize, usize);
 }
 
 pub trait ZPropertyStorage {
-  fn byte_property(&self, ptr: BytePtr) -> u16;
-  fn word_property(&self, ptr: BytePtr) -> u16;
-  fn set_byte_property(&mut self, value: u8, ptr: BytePtr);
-  fn set_word_property(&mut self, value: u16, ptr: BytePtr);
+  fn byte_property(&self, ptr: BytePtr) -> Result<u16>;
+  fn word_property(&self, ptr: BytePtr) -> Result<u16>;
+  fn set_byte_property(&mut self, value: u8, ptr: BytePtr) -> Result<()>;
+  fn set_word_property(&mut self, value: u16, ptr: BytePtr) -> Result<()>;
 }
 
 pub trait ZPropertyTable {
@@ -108,42 +133,45 @@ pub trait ZPropertyTable {
 
   fn name_ptr(&self) -> BytePtr;
   // property numbers are 1-31. Returns the size and ptr to the property.
-  fn find_property(&self, number: u16) -> Option<(u16, BytePtr)>;
+  fn find_property(&self, number: u16) -> Result<Option<(u16, BytePtr)>>;
 
   // given a property number, return the next number of the property in the table.
   // Special cases:
   //   0: return the first property,
-  //   non-existing property: panic! (according to spec)
+  //   non-existing property: Error::PropertyNotFound (the spec says to halt)
   //   no next property: return 0
-  fn next_property(&self, number: u16) -> u16;
+  fn next_property(&self, number: u16) -> Result<u16>;
 
   // TODO: test set_property
-  fn set_property(&self, number: u16, value: u16) {
-    if let Some((size, ptr)) = self.find_property(number) {
+  fn set_property(&self, number: u16, value: u16) -> Result<()> {
+    if let Some((size, ptr)) = self.find_property(number)? {
       match size {
         1 => self.storage().set_byte_property(value as u8, ptr),
         2 => self.storage().set_word_property(value, ptr),
-        _ => {
-          panic!("Invalid size, {}, for property {}.", size, number);
-        }
+        _ => Err(Error::BadPropertySize(number, size)),
       }
     } else {
       // Not found, the spec says to halt. Ugh.
-      panic!("Property {} not found.", number);
+      Err(Error::PropertyNotFound(number))
     }
   }
 }
 
 #[cfg(test)]
 mod tests {
+  use std::cell::RefCell;
+  use std::rc::Rc;
   use super::ZObjectTable;
   use zmachine::vm::mock_object_table::{MockObjectTable, MockObjectTableStorage};
 
+  fn shared(storage: MockObjectTableStorage) -> Rc<RefCell<MockObjectTableStorage>> {
+    Rc::new(RefCell::new(storage))
+  }
+
   #[test]
   fn test_insert_0_obj() {
-    let mut storage = MockObjectTableStorage::new();
-    let table = MockObjectTable::new();
-    table.insert_obj(0, 0, &mut storage).unwrap();
+    let table = MockObjectTable::new(shared(MockObjectTableStorage::new()));
+    table.insert_obj(0, 0).unwrap();
   }
 
   #[test]
@@ -152,11 +180,12 @@ mod tests {
     storage.add_mock_object(0x11223344, 0, 0, 0);
     let storage2 = storage.clone();
 
-    let table = MockObjectTable::new();
+    let storage = shared(storage);
+    let table = MockObjectTable::new(storage.clone());
 
-    table.remove_object_from_parent(1, &mut storage);
+    table.remove_object_from_parent(1).unwrap();
 
-    assert_eq!(storage, storage2);
+    assert_eq!(*storage.borrow(), storage2);
   }
 
   #[test]
@@ -171,8 +200,9 @@ mod tests {
     storage.add_mock_object(0x11223344, 1, 4, 0); // 3
     storage.add_mock_object(0x11223344, 1, 0, 0); // 4
 
-    let table = MockObjectTable::new();
-    table.remove_object_from_parent(2, &mut storage).unwrap();
+    let storage = shared(storage);
+    let table = MockObjectTable::new(storage.clone());
+    table.remove_object_from_parent(2).unwrap();
 
     let mut storage2 = MockObjectTableStorage::new();
     // 1
@@ -184,7 +214,7 @@ mod tests {
     storage2.add_mock_object(0x11223344, 1, 4, 0); // 3
     storage2.add_mock_object(0x11223344, 1, 0, 0); // 4
 
-    assert_eq!(storage, storage2);
+    assert_eq!(*storage.borrow(), storage2);
   }
 
   #[test]
@@ -199,8 +229,9 @@ mod tests {
     storage.add_mock_object(0x11223344, 1, 4, 0); // 3
     storage.add_mock_object(0x11223344, 1, 0, 0); // 4
 
-    let table = MockObjectTable::new();
-    table.remove_object_from_parent(3, &mut storage).unwrap();
+    let storage = shared(storage);
+    let table = MockObjectTable::new(storage.clone());
+    table.remove_object_from_parent(3).unwrap();
 
     let mut storage2 = MockObjectTableStorage::new();
     // 1
@@ -212,7 +243,7 @@ mod tests {
     storage2.add_mock_object(0x11223344, 0, 0, 0); // 3
     storage2.add_mock_object(0x11223344, 1, 0, 0); // 4
 
-    assert_eq!(storage, storage2);
+    assert_eq!(*storage.borrow(), storage2);
   }
 
   #[test]
@@ -224,10 +255,11 @@ mod tests {
     storage.add_mock_object(0x11223344, 0, 0, 0); // 4
     storage.add_mock_object(0x11223344, 0, 0, 0); // 5
 
-    let table = MockObjectTable::new();
-    table.add_object_to_parent(2, 1, &mut storage);
-    table.add_object_to_parent(3, 1, &mut storage);
-    table.add_object_to_parent(4, 1, &mut storage);
+    let storage = shared(storage);
+    let table = MockObjectTable::new(storage.clone());
+    table.add_object_to_parent(2, 1).unwrap();
+    table.add_object_to_parent(3, 1).unwrap();
+    table.add_object_to_parent(4, 1).unwrap();
 
     let mut storage2 = MockObjectTableStorage::new();
     storage2.add_mock_object(0x11223344, 0, 0, 4); // 1
@@ -236,7 +268,7 @@ mod tests {
     storage2.add_mock_object(0x11223344, 1, 3, 0); // 4
     storage2.add_mock_object(0x11223344, 0, 0, 0); // 5
 
-    assert_eq!(storage, storage2);
+    assert_eq!(*storage.borrow(), storage2);
   }
 
   #[test]
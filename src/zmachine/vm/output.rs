@@ -0,0 +1,246 @@
+use result::{Error, Result};
+use super::colour::Colours;
+use super::screen::Screen;
+use super::screen_model::Window;
+use super::transcript::Transcript;
+use super::zscii::{char_to_zscii, zscii_to_char};
+
+// The output abstraction. All text that the game prints goes through Output,
+// which sends it to each of the active output streams (spec section 7) with
+// the current text style.
+//
+// * Stream 1 - the screen.
+// * Stream 2 - the transcript. This is controlled by bit 0 of Flags 2, since
+//   v3 games flip that bit themselves rather than using output_stream. Only
+//   text in the lower window is transcripted.
+// * Stream 3 - a table in memory. While one is selected, text goes only to
+//   it, as ZSCII. Selecting it again starts another table, up to 16 deep; the
+//   text is written to the game's memory when the table is closed.
+
+// Style bits for set_text_style (spec section 8.7).
+pub const STYLE_REVERSE: u16 = 0x01;
+pub const STYLE_BOLD: u16 = 0x02;
+pub const STYLE_ITALIC: u16 = 0x04;
+pub const STYLE_FIXED: u16 = 0x08;
+
+// Bits in Flags 2 that affect output.
+pub const FLAG2_TRANSCRIPT: u16 = 0x0001;
+pub const FLAG2_FIXED_PITCH: u16 = 0x0002;
+
+const MAX_MEMORY_STREAMS: usize = 16;
+
+#[derive(Clone,Copy,Debug,Eq,PartialEq)]
+pub struct TextStyle {
+  bits: u16,
+}
+
+impl TextStyle {
+  pub fn roman() -> TextStyle {
+    TextStyle { bits: 0 }
+  }
+
+  /// The style after a set_text_style with `bits`. Styles combine, except
+  /// that 0 (roman) turns them all off.
+  pub fn with(&self, bits: u16) -> TextStyle {
+    if bits == 0 {
+      TextStyle::roman()
+    } else {
+      TextStyle { bits: self.bits | (bits & 0x0f) }
+    }
+  }
+
+  pub fn is_reverse(&self) -> bool {
+    self.bits & STYLE_REVERSE != 0
+  }
+
+  pub fn is_bold(&self) -> bool {
+    self.bits & STYLE_BOLD != 0
+  }
+
+  pub fn is_italic(&self) -> bool {
+    self.bits & STYLE_ITALIC != 0
+  }
+
+  pub fn is_fixed(&self) -> bool {
+    self.bits & STYLE_FIXED != 0
+  }
+}
+
+pub struct Output {
+  screen: Screen,
+  transcript: Transcript,
+  screen_enabled: bool,
+  // The tables selected for stream 3, innermost last: each one's address and
+  // the text sent to it so far.
+  memory_streams: Vec<(u16, Vec<u8>)>,
+  // While the debugger goes back in time, the game says again what it has
+  // already said, which nobody needs to see twice.
+  replaying: bool,
+  style: TextStyle,
+  colours: Colours,
+}
+
+impl Output {
+  pub fn new(screen: Screen, transcript: Transcript) -> Output {
+    Output {
+      screen,
+      transcript,
+      screen_enabled: true,
+      memory_streams: Vec::new(),
+      replaying: false,
+      style: TextStyle::roman(),
+      colours: Colours::new(),
+    }
+  }
+
+  pub fn screen(&self) -> &Screen {
+    &self.screen
+  }
+
+  pub fn screen_mut(&mut self) -> &mut Screen {
+    &mut self.screen
+  }
+
+  pub fn set_screen_enabled(&mut self, enabled: bool) {
+    self.screen_enabled = enabled;
+  }
+
+  /// Send text to the table at `table` until it is closed (stream 3).
+  pub fn open_memory_stream(&mut self, table: u16) -> Result<()> {
+    if self.memory_streams.len() >= MAX_MEMORY_STREAMS {
+      return Err(Error::MemoryStreamsTooDeep);
+    }
+    self.memory_streams.push((table, Vec::new()));
+    Ok(())
+  }
+
+  /// Stop sending text to the innermost table. Returns its address and the
+  /// ZSCII text for it, or None if there wasn't one.
+  pub fn close_memory_stream(&mut self) -> Option<(u16, Vec<u8>)> {
+    self.memory_streams.pop()
+  }
+
+  pub fn set_replaying(&mut self, replaying: bool) {
+    self.replaying = replaying;
+  }
+
+  pub fn replaying(&self) -> bool {
+    self.replaying
+  }
+
+  pub fn set_text_style(&mut self, bits: u16) {
+    self.style = self.style.with(bits);
+    self.screen.set_style(self.style);
+  }
+
+  /// Change the colours, as with set_colour. Out of range colours are ignored.
+  pub fn set_colour(&mut self, foreground: u16, background: u16) {
+    match self.colours.with(foreground, background) {
+      Some(colours) => {
+        self.colours = colours;
+        self.screen.set_colours(colours);
+      }
+      None => warn!("Ignoring bad colours: {}, {}", foreground, background),
+    }
+  }
+
+  /// Print `text` to the active output streams. `flag2` is the current
+  /// value of Flags 2 from the header.
+  pub fn print(&mut self, text: &str, flag2: u16) -> Result<()> {
+    // This changes the game's memory, so it happens even when replaying.
+    if let Some(&mut (_, ref mut zscii)) = self.memory_streams.last_mut() {
+      zscii.extend(text.chars().filter_map(char_to_zscii).map(|code| code as u8));
+      return Ok(());
+    }
+    if self.replaying {
+      return Ok(());
+    }
+    if self.screen_enabled {
+      self.screen.print(text);
+    }
+    if flag2 & FLAG2_TRANSCRIPT != 0 && self.screen.current_window() == Window::Lower {
+      let style = effective_style(self.style, flag2);
+      self.transcript.print(text, style)?;
+    }
+    Ok(())
+  }
+
+  /// Copy the player's input, and the key that ended it, to the transcript.
+  /// (The screen has already echoed it.) Only a newline is shown for the key;
+  /// a function key that ends input leaves the cursor where it is.
+  pub fn echo_input(&mut self, text: &str, terminator: u16, flag2: u16) -> Result<()> {
+    if self.replaying {
+      return Ok(());
+    }
+    let end = match zscii_to_char(terminator) {
+      Some('\n') => "\n",
+      _ => "",
+    };
+    self.screen.record_input(text, end);
+    if flag2 & FLAG2_TRANSCRIPT != 0 {
+      self.transcript.print(text, TextStyle::roman())?;
+      self.transcript.print(end, TextStyle::roman())?;
+    }
+    Ok(())
+  }
+
+  pub fn close(&mut self) -> Result<()> {
+    self.screen.tear_down();
+    self.transcript.close()
+  }
+}
+
+// The game can force fixed-pitch output with a bit in Flags 2, regardless of
+// the style it has set.
+fn effective_style(style: TextStyle, flag2: u16) -> TextStyle {
+  if flag2 & FLAG2_FIXED_PITCH != 0 {
+    style.with(STYLE_FIXED)
+  } else {
+    style
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_text_style() {
+    let style = TextStyle::roman();
+    assert!(!style.is_bold() && !style.is_italic() && !style.is_reverse() && !style.is_fixed());
+
+    let style = style.with(STYLE_BOLD);
+    assert!(style.is_bold());
+    let style = style.with(STYLE_ITALIC | STYLE_REVERSE);
+    assert!(style.is_bold() && style.is_italic() && style.is_reverse());
+    assert!(!style.is_fixed());
+
+    assert_eq!(TextStyle::roman(), style.with(0));
+  }
+
+  #[test]
+  fn test_memory_streams() {
+    let mut output = Output::new(Screen::new(None, None), Transcript::new(None, false));
+    output.open_memory_stream(0x200).unwrap();
+    output.print("a\n", 0).unwrap();
+    output.open_memory_stream(0x300).unwrap();
+    output.print("é", 0).unwrap();
+    assert_eq!(Some((0x300, vec![170])), output.close_memory_stream());
+    output.print("b", 0).unwrap();
+    assert_eq!(Some((0x200, vec![b'a', 13, b'b'])), output.close_memory_stream());
+    assert_eq!(None, output.close_memory_stream());
+
+    for _ in 0..16 {
+      output.open_memory_stream(0x200).unwrap();
+    }
+    assert!(output.open_memory_stream(0x200).is_err());
+  }
+
+  #[test]
+  fn test_effective_style() {
+    let bold = TextStyle::roman().with(STYLE_BOLD);
+    assert_eq!(bold, effective_style(bold, FLAG2_TRANSCRIPT));
+    let forced = effective_style(bold, FLAG2_FIXED_PITCH);
+    assert!(forced.is_bold() && forced.is_fixed());
+  }
+}
@@ -0,0 +1,71 @@
+// Counts the lines scrolled through the lower window since the player last
+// typed something, so that we can stop with [MORE] before scrolling away text
+// that they haven't had a chance to read.
+
+pub struct Pager {
+  lines: u16,
+}
+
+impl Pager {
+  pub fn new() -> Pager {
+    Pager { lines: 0 }
+  }
+
+  /// The player has seen everything on the screen (they typed something, or
+  /// dismissed [MORE]).
+  pub fn reset(&mut self) {
+    self.lines = 0;
+  }
+
+  /// A line of the lower window has been finished.
+  pub fn line_done(&mut self) {
+    self.lines = self.lines.saturating_add(1);
+  }
+
+  /// True if writing more text would scroll away unseen text, in a lower
+  /// window with `page_length` lines (see `page_length`).
+  pub fn must_pause(&self, page_length: u16) -> bool {
+    page_length > 0 && self.lines >= page_length
+  }
+}
+
+/// How many new lines fit in the lower window before unseen text scrolls
+/// off: the screen, less the status line and upper window, less one line
+/// for the [MORE] prompt itself. 0 means the window is too small to page.
+pub fn page_length(screen_rows: u16, status_rows: u16, upper_rows: u16) -> u16 {
+  screen_rows.saturating_sub(status_rows).saturating_sub(upper_rows).saturating_sub(1)
+}
+
+#[cfg(test)]
+mod test {
+  use super::{Pager, page_length};
+
+  #[test]
+  fn test_page_length() {
+    assert_eq!(23, page_length(24, 0, 0));
+    assert_eq!(22, page_length(24, 1, 0));
+    assert_eq!(19, page_length(24, 1, 3));
+    assert_eq!(0, page_length(24, 1, 23));
+    assert_eq!(0, page_length(2, 1, 5));
+  }
+
+  #[test]
+  fn test_pager() {
+    let mut pager = Pager::new();
+    for _ in 0..4 {
+      assert!(!pager.must_pause(5));
+      pager.line_done();
+    }
+    pager.line_done();
+    assert!(pager.must_pause(5));
+    // A bigger upper window leaves less room.
+    assert!(pager.must_pause(3));
+
+    pager.reset();
+    assert!(!pager.must_pause(5));
+
+    // Windows too small to page never pause.
+    pager.line_done();
+    assert!(!pager.must_pause(0));
+  }
+}
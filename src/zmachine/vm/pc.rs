@@ -1,3 +1,4 @@
+use result::Result;
 use std::cell::RefCell;
 use std::rc::Rc;
 use super::memory::Memory;
@@ -30,28 +31,31 @@ impl PC {
     self.pc = RawPtr::new(p);
   }
 
-  pub fn next_byte(&mut self) -> u8 {
-    let result = self.memory.borrow().u8_at(self.pc);
+  pub fn next_byte(&mut self) -> Result<u8> {
+    let result = self.memory.borrow().read_u8(self.pc)?;
     self.pc.inc_by(1usize);
-    result
+    Ok(result)
   }
 
-  pub fn next_word(&mut self) -> u16 {
-    let result = self.memory.borrow().u16_at(self.pc);
+  pub fn next_word(&mut self) -> Result<u16> {
+    let result = self.memory.borrow().read_u16(self.pc)?;
     self.pc.inc_by(2usize);
-    result
+    Ok(result)
   }
 }
 
 #[cfg(test)]
 mod test {
+  use std::cell::RefCell;
+  use std::rc::Rc;
   use super::PC;
   use super::super::{BytePtr, PackedAddr};
   use super::super::memory::Memory;
 
   #[test]
   fn test_pc() {
-    let mut pc = PC::new(BytePtr::new(54));
+    let memory = Rc::new(RefCell::new(Memory::from(vec![0; 64])));
+    let mut pc = PC::new(BytePtr::new(54), memory);
     assert_eq!(54, pc.pc().ptr());
     pc.set_pc(BytePtr::new(88));
     assert_eq!(88, pc.pc().ptr());
@@ -63,11 +67,11 @@ mod test {
   fn test_memory() {
     let memory: Memory = From::from(vec![0, 1, 2, 3, 4, 5, 6, 7, 8, 9]);
 
-    let mut pc = PC::new(BytePtr::new(2));
-    assert_eq!(2, pc.next_byte(&memory));
+    let mut pc = PC::new(BytePtr::new(2), Rc::new(RefCell::new(memory)));
+    assert_eq!(2, pc.next_byte().unwrap());
     assert_eq!(3, pc.pc().ptr());
 
-    assert_eq!(0x0304, pc.next_word(&memory));
+    assert_eq!(0x0304, pc.next_word().unwrap());
     assert_eq!(5, pc.pc().ptr());
   }
 }
@@ -19,8 +19,10 @@ impl BytePtr {
     BytePtr { val: val }
   }
 
+  /// The pointer `addend` bytes on. This stops at 0xffff rather than wrapping
+  /// round to the header, so running off the end of memory is still caught.
   pub fn inc_by(&self, addend: u16) -> BytePtr {
-    BytePtr { val: self.val + addend }
+    BytePtr { val: self.val.saturating_add(addend) }
   }
 }
 
@@ -119,6 +121,12 @@ mod test {
     assert_eq!(10usize, usize::from(rp));
   }
 
+  #[test]
+  fn test_bp_inc_by() {
+    assert_eq!(BytePtr::new(0x4f07), BytePtr::new(0x4f05).inc_by(2));
+    assert_eq!(BytePtr::new(0xffff), BytePtr::new(0xfffe).inc_by(2));
+  }
+
   #[test]
   fn test_wp_convert() {
     let wp = WordPtr::new(25);
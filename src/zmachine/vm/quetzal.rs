@@ -4,6 +4,7 @@ use zmachine::vm::memory::Memory;
 use zmachine::vm::pc::PC;
 use zmachine::vm::ptrs::BytePtr;
 use zmachine::vm::stack::Stack;
+use zmachine::vm::VariableRef;
 
 struct Chunk {
   start_offset: usize,
@@ -77,23 +78,26 @@ impl Quetzal {
 
   fn write_stack(&mut self, stack: &Stack) -> Result<()> {
     let chunk = Chunk::start(new_id("Stks"), &mut self.bytes)?;
-    stack.map_frames(|old_pc, flags, retvar, locals, eval_stack| {
-        let return_and_flags = (old_pc << 8) + flags as u32;
-        &self.bytes.write_u32::<BigEndian>(return_and_flags);
-        &self.bytes.write_u8(retvar);
-        &self.bytes.write_u8(0);  // TODO: num arguments passed.
-        &self.bytes.write_u16::<BigEndian>(eval_stack.len() as u16);
-
-        for local in locals {
-          &self.bytes.write_u16::<BigEndian>(local);
-        }
-
-        for eval_word in eval_stack {
-          &self.bytes.write_u16::<BigEndian>(eval_word);
-        }
-
-        Ok(())
-      })?;
+    let mut frames = stack.frames().collect::<Vec<_>>();
+    // The oldest frame goes first.
+    frames.reverse();
+    for frame in frames {
+      let p_flag = 0u32;  // No Call_N implemented for v3.
+      let flags = (p_flag << 4) + (frame.locals.len() as u32 & 0x0f);
+      let return_and_flags = ((frame.return_pc as u32) << 8) + flags;
+      self.bytes.write_u32::<BigEndian>(return_and_flags)?;
+      self.bytes.write_u8(VariableRef::encode(frame.result_location))?;
+      self.bytes.write_u8(0)?;  // TODO: num arguments passed.
+      self.bytes.write_u16::<BigEndian>(frame.eval_stack.len() as u16)?;
+
+      for local in frame.locals {
+        self.bytes.write_u16::<BigEndian>(local)?;
+      }
+
+      for eval_word in frame.eval_stack {
+        self.bytes.write_u16::<BigEndian>(eval_word)?;
+      }
+    }
     chunk.end(&mut self.bytes)?;
     Ok(())
   }
@@ -112,4 +116,26 @@ impl Quetzal {
 }
 
 #[cfg(test)]
-mod tests {}
+mod tests {
+  use super::Quetzal;
+  use zmachine::vm::stack::Stack;
+  use zmachine::vm::VariableRef;
+
+  #[test]
+  fn test_write_stack() {
+    let mut stack = Stack::new(256);
+    stack.push_u16(0x0101).unwrap();
+    stack.new_frame(0x4f05, 0x2000, 2, VariableRef::Global(3)).unwrap();
+    stack.write_local(1, 0xbe55).unwrap();
+    stack.push_u16(0x1234).unwrap();
+
+    let mut q = Quetzal { bytes: Vec::new() };
+    q.write_stack(&stack).unwrap();
+    // The main routine's frame, then the call's.
+    assert_eq!(vec![0x53, 0x74, 0x6b, 0x73, 0x00, 0x00, 0x00, 0x18,
+                    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x01, 0x01,
+                    0x00, 0x4f, 0x05, 0x02, 0x13, 0x00, 0x00, 0x01,
+                    0x00, 0x00, 0xbe, 0x55, 0x12, 0x34],
+               q.bytes);
+  }
+}
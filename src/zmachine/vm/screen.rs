@@ -0,0 +1,574 @@
+use ncurses::{A_BOLD, A_NORMAL, A_REVERSE, A_UNDERLINE, COLOR_PAIR, COLOR_PAIRS, KEY_RESIZE,
+              LcCategory, WINDOW, WchResult, attr_t, def_prog_mode, doupdate, endwin, getmaxyx,
+              getyx, has_colors, init_pair, initscr, keypad, mvwaddstr, mvwprintw, newwin,
+              noecho, raw, redrawwin, refresh, reset_prog_mode, scrollok, setlocale, start_color,
+              stdscr, use_default_colors, waddstr, wattrset, wattron, wbkgdset, wclrtoeol,
+              wget_wch, wmove, wnoutrefresh, wrefresh, wresize, wsetscrreg};
+use super::colour::{COLOUR_BLACK, COLOUR_DEFAULT, COLOUR_WHITE, Colours};
+use super::output::TextStyle;
+use super::pager::{self, Pager};
+use super::screen_model::{ScreenModel, Window};
+use super::wrap::{Scrollback, WordWrapper};
+
+const MORE_PROMPT: &str = "[MORE]";
+
+// ncurses' idea of "whatever colour the terminal uses by default".
+const NCURSES_DEFAULT_COLOUR: i16 = -1;
+
+// The ncurses side of the output abstraction. Nothing outside of Screen and
+// ZReadline should need to know that we are using ncurses.
+//
+// v1-3 games get a one line status window at the top. Everything else is the
+// main window, which holds both the upper and lower windows (see
+// ScreenModel). The lower window is the main window's scrolling region.
+//
+// In buffer mode, text in the lower window is word wrapped. We stop one
+// column short of the edge so that ncurses never wraps a line itself. Either
+// way, we stop with [MORE] before the player loses text off the top.
+pub struct Screen {
+  status_window: Option<WINDOW>,
+  main_window: Option<WINDOW>,
+  num_rows: i32,
+  num_cols: i32,
+  version: u8,
+  model: ScreenModel,
+  buffered: bool,
+  wrapper: WordWrapper,
+  scrollback: Scrollback<attr_t>,
+  pager: Pager,
+  status_line: String,
+
+  style: TextStyle,
+  attrs: attr_t,
+  colours: Colours,
+  default_foreground: Option<u16>,
+  default_background: Option<u16>,
+  has_colours: bool,
+  // Colour pairs are allocated as they are needed. Pair n is stored at n - 1.
+  colour_pairs: Vec<(i16, i16)>,
+}
+
+impl Screen {
+  /// `default_foreground` and `default_background` are the colours used for
+  /// COLOUR_DEFAULT. If they are None, the terminal's own defaults are used.
+  pub fn new(default_foreground: Option<u16>, default_background: Option<u16>) -> Screen {
+    Screen {
+      status_window: None,
+      main_window: None,
+      num_rows: 0,
+      num_cols: 0,
+      version: 0,
+      model: ScreenModel::new(0, 0),
+      buffered: true,
+      wrapper: WordWrapper::new(1),
+      scrollback: Scrollback::new(),
+      pager: Pager::new(),
+      status_line: String::new(),
+      style: TextStyle::roman(),
+      attrs: A_NORMAL(),
+      colours: Colours::new(),
+      default_foreground,
+      default_background,
+      has_colours: false,
+      colour_pairs: Vec::new(),
+    }
+  }
+
+  pub fn init(&mut self, version: u8) {
+    // Use the user's locale so that ncurses will hand us multibyte input.
+    setlocale(LcCategory::all, "");
+    initscr();
+    raw();
+    noecho();
+    refresh();
+
+    if has_colors() {
+      start_color();
+      use_default_colors();
+      self.has_colours = true;
+    }
+
+    getmaxyx(stdscr(), &mut self.num_rows, &mut self.num_cols);
+    self.version = version;
+
+    let status_rows = if version <= 3 { 1 } else { 0 };
+    if status_rows > 0 {
+      let win = newwin(1, self.num_cols, 0, 0);
+      scrollok(win, false);
+      wattron(win, A_REVERSE());
+      self.status_window = Some(win);
+      wrefresh(win);
+    }
+
+    let main_rows = self.num_rows - status_rows;
+    self.model = ScreenModel::new(main_rows as u16, self.num_cols as u16);
+    self.wrapper.set_width(self.wrap_width());
+    let win = newwin(main_rows, self.num_cols, status_rows, 0);
+    scrollok(win, true);
+    keypad(win, true);
+    self.main_window = Some(win);
+    self.apply_attributes();
+    self.home_lower_cursor();
+    wrefresh(win);
+  }
+
+  pub fn tear_down(&mut self) {
+    if self.main_window.is_some() {
+      self.flush();
+      self.status_window = None;
+      self.main_window = None;
+      endwin();
+    }
+  }
+
+  /// Hand the terminal back for a while (to the debugger, say) without
+  /// losing the windows. Call resume() to get them back.
+  pub fn suspend(&mut self) {
+    if self.main_window.is_some() {
+      self.flush();
+      def_prog_mode();
+      endwin();
+    }
+  }
+
+  pub fn resume(&mut self) {
+    if let Some(w) = self.main_window {
+      reset_prog_mode();
+      // Whatever was written to the terminal meanwhile has to be painted over.
+      if let Some(sw) = self.status_window {
+        redrawwin(sw);
+        wnoutrefresh(sw);
+      }
+      redrawwin(w);
+      wnoutrefresh(w);
+      doupdate();
+    }
+  }
+
+  pub fn main_window(&self) -> Option<WINDOW> {
+    self.main_window
+  }
+
+  pub fn width(&self) -> u16 {
+    self.num_cols as u16
+  }
+
+  pub fn has_colours(&self) -> bool {
+    self.has_colours
+  }
+
+  /// The colours that COLOUR_DEFAULT stands for, as reported to the game in
+  /// the header. When we are using the terminal's defaults we can't know what
+  /// they are, so we claim white on black.
+  pub fn default_colours(&self) -> Colours {
+    Colours {
+      foreground: self.default_foreground.unwrap_or(COLOUR_WHITE),
+      background: self.default_background.unwrap_or(COLOUR_BLACK),
+    }
+  }
+
+  /// The size of the whole screen as (rows, columns).
+  pub fn size(&self) -> (u16, u16) {
+    (self.num_rows as u16, self.num_cols as u16)
+  }
+
+  pub fn write_status_line(&mut self, str: &str) {
+    self.status_line = str.to_string();
+    self.draw_status_line();
+  }
+
+  fn draw_status_line(&self) {
+    self.status_window.map(|w| {
+      self.main_window.map(|mw| {
+        let mut x = 0i32;
+        let mut y = 0i32;
+        getyx(mw, &mut y, &mut x);
+
+        // After a resize, the last status line may be too long until the game
+        // sends a new one.
+        let line: String = self.status_line.chars().take(self.num_cols as usize).collect();
+        wmove(w, 0, 0);
+        wclrtoeol(w);
+        mvwprintw(w, 0, 0, &line);
+        wmove(mw, y, x);
+        wrefresh(w);
+        wrefresh(mw);
+      })
+    });
+  }
+
+  pub fn current_window(&self) -> Window {
+    self.model.current()
+  }
+
+  pub fn print(&mut self, str: &str) {
+    if let Some(w) = self.main_window {
+      match self.model.current() {
+        Window::Lower => {
+          self.scrollback.push(self.attrs, str);
+          if self.buffered {
+            let (_, x) = cursor_of(w);
+            let text = self.wrapper.push(str, x as usize);
+            self.write_lower(w, &text);
+          } else {
+            self.write_lower(w, str);
+          }
+        }
+        Window::Upper => {
+          // The lower window's cursor is ncurses' cursor, so put it back.
+          let (y, x) = cursor_of(w);
+          for segment in self.model.upper_text(str) {
+            mvwaddstr(w, segment.row as i32, segment.col as i32, &segment.text);
+          }
+          wmove(w, y, x);
+        }
+      }
+      wrefresh(w);
+    }
+  }
+
+  /// Print any text held back by word wrapping.
+  pub fn flush(&mut self) {
+    if let Some(w) = self.main_window {
+      let (_, x) = cursor_of(w);
+      let text = self.wrapper.flush(x as usize);
+      if !text.is_empty() {
+        self.write_lower(w, &text);
+        wrefresh(w);
+      }
+    }
+  }
+
+  /// Print a message from the interpreter (not the game) in the lower window,
+  /// whichever window the game has selected.
+  pub fn print_message(&mut self, text: &str) {
+    self.flush();
+    if let Some(w) = self.main_window {
+      self.write_lower(w, text);
+      wrefresh(w);
+    }
+  }
+
+  /// Get ready to read from the keyboard: show everything, catch up with any
+  /// resize that happened while we weren't looking, and start a new page.
+  /// Returns true if the screen was resized.
+  pub fn prepare_for_input(&mut self) -> bool {
+    self.flush();
+    let mut rows = 0i32;
+    let mut cols = 0i32;
+    getmaxyx(stdscr(), &mut rows, &mut cols);
+    let resized = self.main_window.is_some() && (rows, cols) != (self.num_rows, self.num_cols);
+    if resized {
+      self.resize();
+    }
+    self.pager.reset();
+    resized
+  }
+
+  /// Turn word wrapping in the lower window on or off.
+  pub fn set_buffer_mode(&mut self, buffered: bool) {
+    self.flush();
+    self.buffered = buffered;
+  }
+
+  /// Remember a line that the player typed, and what ended it, so that it is
+  /// kept on reflow.
+  pub fn record_input(&mut self, text: &str, end: &str) {
+    self.scrollback.push(self.attrs, text);
+    self.scrollback.push(self.attrs, end);
+  }
+
+  /// Fit the windows to the terminal's new size and rewrap the lower window.
+  pub fn resize(&mut self) {
+    if let Some(w) = self.main_window {
+      getmaxyx(stdscr(), &mut self.num_rows, &mut self.num_cols);
+      let status_rows = if let Some(sw) = self.status_window {
+        wresize(sw, 1, self.num_cols);
+        1
+      } else {
+        0
+      };
+      let main_rows = self.num_rows - status_rows;
+      wresize(w, main_rows, self.num_cols);
+      self.model.resize(main_rows as u16, self.num_cols as u16);
+      self.wrapper.set_width(self.wrap_width());
+      self.set_scroll_region(w);
+      self.reflow(w);
+      wrefresh(w);
+      self.draw_status_line();
+    }
+  }
+
+  pub fn split_window(&mut self, lines: u16) {
+    self.flush();
+    self.model.split(lines);
+    if let Some(w) = self.main_window {
+      self.set_scroll_region(w);
+      // Keep the lower window's cursor out of the upper window.
+      let (y, _) = cursor_of(w);
+      let upper_height = self.model.upper_height() as i32;
+      if y < upper_height {
+        wmove(w, upper_height, 0);
+      }
+      if self.version <= 3 {
+        // In v3 the upper window is cleared whenever it is created.
+        self.clear_window(w, Window::Upper);
+      }
+      wrefresh(w);
+    }
+  }
+
+  pub fn set_window(&mut self, window: Window) {
+    self.flush();
+    self.model.select(window);
+  }
+
+  /// Erase a window as in erase_window: 0 and 1 erase the lower and upper
+  /// windows, -1 unsplits the screen and erases it all, and -2 erases it all
+  /// while leaving the windows alone.
+  pub fn erase_window(&mut self, window: i16) {
+    self.flush();
+    if let Some(w) = self.main_window {
+      match window {
+        -1 | -2 => {
+          if window == -1 {
+            self.model.unsplit();
+            self.set_scroll_region(w);
+          }
+          self.clear_window(w, Window::Upper);
+          self.clear_window(w, Window::Lower);
+          self.scrollback.clear();
+          self.model.home_upper_cursor();
+          self.home_lower_cursor();
+        }
+        0 => {
+          self.clear_window(w, Window::Lower);
+          self.scrollback.clear();
+          self.home_lower_cursor();
+        }
+        1 => {
+          let (y, x) = cursor_of(w);
+          self.clear_window(w, Window::Upper);
+          self.model.home_upper_cursor();
+          wmove(w, y, x);
+        }
+        _ => warn!("Cannot erase window {}.", window),
+      }
+      wrefresh(w);
+    }
+  }
+
+  /// Erase from the cursor to the end of the line in the current window.
+  pub fn erase_line(&mut self) {
+    self.flush();
+    if let Some(w) = self.main_window {
+      match self.model.current() {
+        Window::Lower => {
+          wclrtoeol(w);
+        }
+        Window::Upper => {
+          let (y, x) = cursor_of(w);
+          let (line, col) = self.model.upper_cursor();
+          wmove(w, line as i32 - 1, col as i32 - 1);
+          wclrtoeol(w);
+          wmove(w, y, x);
+        }
+      }
+      wrefresh(w);
+    }
+  }
+
+  /// Move the cursor in the upper window. Ignored for the lower window.
+  pub fn set_cursor(&mut self, line: u16, col: u16) {
+    if !self.model.set_cursor(line, col) {
+      warn!("Ignoring set_cursor in the lower window.");
+    }
+  }
+
+  /// The cursor position (1-based) in the current window.
+  pub fn cursor(&self) -> (u16, u16) {
+    match self.model.current() {
+      Window::Upper => self.model.upper_cursor(),
+      Window::Lower => {
+        let (y, x) = self.main_window.map(cursor_of).unwrap_or((0, 0));
+        let top = self.model.upper_height() as i32;
+        ((y - top + 1).max(1) as u16, (x + 1) as u16)
+      }
+    }
+  }
+
+  // Write text to the lower window, stopping for [MORE] if it would scroll
+  // away a screenful that the player hasn't seen. Lines that ncurses wraps
+  // count as well as newlines.
+  fn write_lower(&mut self, w: WINDOW, text: &str) {
+    let status_rows = if self.status_window.is_some() { 1 } else { 0 };
+    let page_length = pager::page_length(self.num_rows as u16,
+                                         status_rows,
+                                         self.model.upper_height());
+    let (_, mut col) = cursor_of(w);
+    let mut line = String::new();
+    for ch in text.chars() {
+      if line.is_empty() && self.pager.must_pause(page_length) {
+        self.show_more(w);
+      }
+      line.push(ch);
+      col += 1;
+      if ch == '\n' || col >= self.num_cols {
+        waddstr(w, &line);
+        line.clear();
+        col = 0;
+        self.pager.line_done();
+      }
+    }
+    waddstr(w, &line);
+  }
+
+  // Show [MORE] at the start of the current line and wait for a key. A resize
+  // is caught up with before the next input. If there is no more input (ERR),
+  // carry on, and the next read reports it.
+  fn show_more(&mut self, w: WINDOW) {
+    let (y, _) = cursor_of(w);
+    wattrset(w, A_REVERSE());
+    waddstr(w, MORE_PROMPT);
+    wattrset(w, self.attrs);
+    wrefresh(w);
+    while let Some(WchResult::KeyCode(KEY_RESIZE)) = wget_wch(w) {}
+    wmove(w, y, 0);
+    wclrtoeol(w);
+    self.pager.reset();
+  }
+
+  fn wrap_width(&self) -> usize {
+    (self.num_cols.max(2) - 1) as usize
+  }
+
+  // Redraw the lower window from the scrollback, wrapped to the current width.
+  fn reflow(&mut self, w: WINDOW) {
+    self.wrapper.clear();
+    self.clear_window(w, Window::Lower);
+    let (top, _) = self.model.window_rows(Window::Lower);
+    wmove(w, top as i32, 0);
+
+    let mut wrapper = WordWrapper::new(self.wrap_width());
+    for &(attrs, ref text) in self.scrollback.runs() {
+      wattrset(w, attrs);
+      let (_, x) = cursor_of(w);
+      let text = if self.buffered {
+        wrapper.push(text, x as usize)
+      } else {
+        text.clone()
+      };
+      waddstr(w, &text);
+    }
+    let (_, x) = cursor_of(w);
+    waddstr(w, &wrapper.flush(x as usize));
+    wattrset(w, self.attrs);
+  }
+
+  fn set_scroll_region(&self, w: WINDOW) {
+    let rows = self.model.rows() as i32;
+    let top = (self.model.upper_height() as i32).min(rows - 1);
+    wsetscrreg(w, top, rows - 1);
+  }
+
+  fn clear_window(&self, w: WINDOW, window: Window) {
+    let (top, bottom) = self.model.window_rows(window);
+    for row in top..bottom {
+      wmove(w, row as i32, 0);
+      wclrtoeol(w);
+    }
+  }
+
+  // After erasing the lower window, v5 games expect the cursor at its top
+  // and earlier games expect it at the bottom.
+  fn home_lower_cursor(&self) {
+    if let Some(w) = self.main_window {
+      let (top, bottom) = self.model.window_rows(Window::Lower);
+      let row = if self.version >= 5 { top } else { bottom.max(1) - 1 };
+      wmove(w, row as i32, 0);
+    }
+  }
+
+  /// Set the style used for subsequent text in the main window.
+  pub fn set_style(&mut self, style: TextStyle) {
+    self.flush();
+    self.style = style;
+    self.apply_attributes();
+  }
+
+  /// Set the colours used for subsequent text in the main window.
+  pub fn set_colours(&mut self, colours: Colours) {
+    self.flush();
+    self.colours = colours;
+    self.apply_attributes();
+  }
+
+  // Terminals are always fixed-pitch, so there is nothing to do for that style.
+  fn apply_attributes(&mut self) {
+    let mut attrs = A_NORMAL();
+    if self.style.is_bold() {
+      attrs |= A_BOLD();
+    }
+    if self.style.is_italic() {
+      // Few terminals do italics, so we follow the old tradition of underlining.
+      attrs |= A_UNDERLINE();
+    }
+    if self.style.is_reverse() {
+      attrs |= A_REVERSE();
+    }
+    let colour_attr = self.colour_attr();
+    self.attrs = attrs | colour_attr;
+    if let Some(w) = self.main_window {
+      wattrset(w, attrs | colour_attr);
+      // Also use the colours for the blanks left by scrolling and erasing.
+      wbkgdset(w, ' ' as u32 | colour_attr);
+    }
+  }
+
+  fn colour_attr(&mut self) -> attr_t {
+    if !self.has_colours {
+      return A_NORMAL();
+    }
+    let foreground = ncurses_colour(self.colours.foreground, self.default_foreground);
+    let background = ncurses_colour(self.colours.background, self.default_background);
+    if foreground == NCURSES_DEFAULT_COLOUR && background == NCURSES_DEFAULT_COLOUR {
+      // Pair 0 is always the terminal's defaults.
+      return COLOR_PAIR(0);
+    }
+
+    let pair = match self.colour_pairs.iter().position(|&p| p == (foreground, background)) {
+      Some(idx) => idx + 1,
+      None => {
+        if self.colour_pairs.len() + 1 >= COLOR_PAIRS() as usize {
+          warn!("Out of colour pairs.");
+          return A_NORMAL();
+        }
+        self.colour_pairs.push((foreground, background));
+        init_pair(self.colour_pairs.len() as i16, foreground, background);
+        self.colour_pairs.len()
+      }
+    };
+    COLOR_PAIR(pair as i16)
+  }
+}
+
+fn cursor_of(w: WINDOW) -> (i32, i32) {
+  let mut y = 0i32;
+  let mut x = 0i32;
+  getyx(w, &mut y, &mut x);
+  (y, x)
+}
+
+// The ncurses colours are the Z-machine colours, in the same order, but
+// starting from 0.
+fn ncurses_colour(colour: u16, default: Option<u16>) -> i16 {
+  let colour = if colour == COLOUR_DEFAULT {
+    match default {
+      Some(default) => default,
+      None => return NCURSES_DEFAULT_COLOUR,
+    }
+  } else {
+    colour
+  };
+  (colour - COLOUR_BLACK) as i16
+}
@@ -0,0 +1,256 @@
+// The layout of the upper and lower windows (spec section 8.7), kept apart
+// from ncurses so that it can be tested.
+//
+// The two windows share the screen area below the status line (if any). The
+// upper window is the top `upper_height` rows of it and the lower window is
+// everything else. The lower window scrolls and its cursor is ncurses' own
+// cursor. The upper window never scrolls, so we track its cursor here and
+// throw away any text that falls outside of it.
+//
+// Rows and columns are 1-based, as the game sees them, except in Segments,
+// which are 0-based ncurses coordinates.
+
+#[derive(Clone,Copy,Debug,Eq,PartialEq)]
+pub enum Window {
+  Lower,
+  Upper,
+}
+
+impl Window {
+  pub fn from_number(number: u16) -> Option<Window> {
+    match number {
+      0 => Some(Window::Lower),
+      1 => Some(Window::Upper),
+      _ => None,
+    }
+  }
+}
+
+/// A run of text to draw at `row`, `col` (0-based, relative to the window area).
+#[derive(Debug,Eq,PartialEq)]
+pub struct Segment {
+  pub row: u16,
+  pub col: u16,
+  pub text: String,
+}
+
+pub struct ScreenModel {
+  rows: u16,
+  cols: u16,
+  upper_height: u16,
+  current: Window,
+  upper_cursor: (u16, u16),
+}
+
+impl ScreenModel {
+  pub fn new(rows: u16, cols: u16) -> ScreenModel {
+    ScreenModel {
+      rows,
+      cols,
+      upper_height: 0,
+      current: Window::Lower,
+      upper_cursor: (1, 1),
+    }
+  }
+
+  pub fn rows(&self) -> u16 {
+    self.rows
+  }
+
+  pub fn upper_height(&self) -> u16 {
+    self.upper_height
+  }
+
+  pub fn current(&self) -> Window {
+    self.current
+  }
+
+  pub fn upper_cursor(&self) -> (u16, u16) {
+    self.upper_cursor
+  }
+
+  /// Change the size of the window area after the terminal is resized.
+  pub fn resize(&mut self, rows: u16, cols: u16) {
+    self.rows = rows;
+    self.cols = cols;
+    self.upper_height = self.upper_height.min(rows);
+    if self.upper_cursor.0 > self.upper_height.max(1) || self.upper_cursor.1 > cols.max(1) {
+      self.upper_cursor = (1, 1);
+    }
+  }
+
+  /// Give the upper window `lines` rows (as many as there are, at most).
+  pub fn split(&mut self, lines: u16) {
+    self.upper_height = lines.min(self.rows);
+    if self.upper_cursor.0 > self.upper_height {
+      self.upper_cursor = (1, 1);
+    }
+  }
+
+  /// Remove the upper window and select the lower one.
+  pub fn unsplit(&mut self) {
+    self.upper_height = 0;
+    self.current = Window::Lower;
+    self.upper_cursor = (1, 1);
+  }
+
+  /// Select a window. Selecting the upper window homes its cursor.
+  pub fn select(&mut self, window: Window) {
+    self.current = window;
+    if window == Window::Upper {
+      self.upper_cursor = (1, 1);
+    }
+  }
+
+  pub fn home_upper_cursor(&mut self) {
+    self.upper_cursor = (1, 1);
+  }
+
+  /// Move the upper window's cursor, keeping it inside the window. Returns
+  /// false (and does nothing) if the lower window is selected, since v4 and
+  /// v5 games can only position the cursor in the upper window.
+  pub fn set_cursor(&mut self, line: u16, col: u16) -> bool {
+    if self.current != Window::Upper {
+      return false;
+    }
+    let line = line.max(1).min(self.upper_height.max(1));
+    let col = col.max(1).min(self.cols.max(1));
+    self.upper_cursor = (line, col);
+    true
+  }
+
+  /// Lay out `text` in the upper window starting at its cursor, moving the
+  /// cursor past it. Text past the right edge or the bottom of the window is
+  /// dropped.
+  pub fn upper_text(&mut self, text: &str) -> Vec<Segment> {
+    let mut segments = Vec::new();
+    let (mut line, mut col) = self.upper_cursor;
+    let mut current: Option<Segment> = None;
+    for ch in text.chars() {
+      if ch == '\n' {
+        segments.extend(current.take());
+        line = line.saturating_add(1);
+        col = 1;
+        continue;
+      }
+      if line <= self.upper_height && col <= self.cols {
+        current.get_or_insert(Segment {
+            row: line - 1,
+            col: col - 1,
+            text: String::new(),
+          })
+          .text
+          .push(ch);
+      }
+      col = col.saturating_add(1);
+    }
+    segments.extend(current.take());
+
+    // Leave the cursor somewhere sensible if the text ran off the window.
+    self.upper_cursor = (line.min(self.upper_height.max(1)), col.min(self.cols.max(1)));
+    segments
+  }
+
+  /// The rows (0-based, end exclusive) covered by a window.
+  pub fn window_rows(&self, window: Window) -> (u16, u16) {
+    match window {
+      Window::Upper => (0, self.upper_height),
+      Window::Lower => (self.upper_height, self.rows),
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::{ScreenModel, Segment, Window};
+
+  fn segment(row: u16, col: u16, text: &str) -> Segment {
+    Segment {
+      row,
+      col,
+      text: text.to_string(),
+    }
+  }
+
+  #[test]
+  fn test_split_and_select() {
+    let mut model = ScreenModel::new(24, 80);
+    assert_eq!(0, model.upper_height());
+    assert_eq!(Window::Lower, model.current());
+
+    model.split(3);
+    assert_eq!((0, 3), model.window_rows(Window::Upper));
+    assert_eq!((3, 24), model.window_rows(Window::Lower));
+
+    model.split(100);
+    assert_eq!(24, model.upper_height());
+
+    model.split(2);
+    model.select(Window::Upper);
+    model.set_cursor(2, 5);
+    model.select(Window::Lower);
+    model.select(Window::Upper);
+    assert_eq!((1, 1), model.upper_cursor());
+
+    model.unsplit();
+    assert_eq!(0, model.upper_height());
+    assert_eq!(Window::Lower, model.current());
+  }
+
+  #[test]
+  fn test_set_cursor() {
+    let mut model = ScreenModel::new(24, 80);
+    model.split(3);
+    assert!(!model.set_cursor(2, 2));
+
+    model.select(Window::Upper);
+    assert!(model.set_cursor(2, 10));
+    assert_eq!((2, 10), model.upper_cursor());
+    model.set_cursor(10, 100);
+    assert_eq!((3, 80), model.upper_cursor());
+    model.set_cursor(0, 0);
+    assert_eq!((1, 1), model.upper_cursor());
+
+    // Shrinking the window below the cursor homes it.
+    model.set_cursor(3, 4);
+    model.split(2);
+    assert_eq!((1, 1), model.upper_cursor());
+  }
+
+  #[test]
+  fn test_resize() {
+    let mut model = ScreenModel::new(24, 80);
+    model.split(10);
+    model.select(Window::Upper);
+    model.set_cursor(5, 70);
+
+    model.resize(30, 100);
+    assert_eq!(10, model.upper_height());
+    assert_eq!((5, 70), model.upper_cursor());
+
+    model.resize(8, 60);
+    assert_eq!(8, model.upper_height());
+    assert_eq!((8, 8), model.window_rows(Window::Lower));
+    assert_eq!((1, 1), model.upper_cursor());
+  }
+
+  #[test]
+  fn test_upper_text() {
+    let mut model = ScreenModel::new(24, 10);
+    model.split(2);
+    model.select(Window::Upper);
+    model.set_cursor(1, 3);
+
+    assert_eq!(vec![segment(0, 2, "abc")], model.upper_text("abc"));
+    assert_eq!((1, 6), model.upper_cursor());
+
+    // Clipped at the right edge, then onto the next line.
+    assert_eq!(vec![segment(0, 5, "defgh"), segment(1, 0, "xy")],
+               model.upper_text("defghijk\nxy"));
+    assert_eq!((2, 3), model.upper_cursor());
+
+    // Nothing below the bottom of the window.
+    assert_eq!(vec![segment(1, 2, "z")], model.upper_text("z\nlost\nlost"));
+    assert_eq!((2, 5), model.upper_cursor());
+  }
+}
@@ -1,5 +1,5 @@
 use byteorder::{BigEndian, ByteOrder};
-use result::Result;
+use result::{Error, Result};
 use std::u16;
 use super::vm::VariableRef;
 
@@ -7,9 +7,10 @@ use super::vm::VariableRef;
 //    +------------------------------------------------------+
 //    | 0x00: fp (u16) to previous frame                     |
 //    | 0x02: pc (u32) to next instruction in previous frame |
-//    | 0x06: num locals (u8)                                |
-//    | 0x07: result location (u8)                           |
-//    | 0x08: L0 (two bytes each)                            |
+//    | 0x06: address of this frame's routine (u32)          |
+//    | 0x0a: num locals (u8)                                |
+//    | 0x0b: result location (u8)                           |
+//    | 0x0c: L0 (two bytes each)                            |
 //    |  ...  LN                                             |
 //    |       base of this frame's stack                     |
 //    |                                                      |
@@ -17,116 +18,159 @@ use super::vm::VariableRef;
 //    |                                                      |
 //
 const PC_OFFSET: usize = 0x02;
-const NUM_LOCALS_OFFSET: usize = 0x06;
-const RESULT_LOCATION_OFFSET: usize = 0x07;
-const FIRST_LOCAL_OFFSET: usize = 0x08;
+const ROUTINE_OFFSET: usize = 0x06;
+const NUM_LOCALS_OFFSET: usize = 0x0a;
+const RESULT_LOCATION_OFFSET: usize = 0x0b;
+const FIRST_LOCAL_OFFSET: usize = 0x0c;
+
+/// A routine on the call stack.
+#[derive(Clone,Debug,PartialEq)]
+pub struct Frame {
+  /// Where the routine returns to. (0 for the main routine.)
+  pub return_pc: usize,
+  /// The address of the routine's header. (0 for the main routine, which has
+  /// no header.)
+  pub routine: usize,
+  pub locals: Vec<u16>,
+  /// The routine's evaluation stack, bottom first.
+  pub eval_stack: Vec<u16>,
+  /// Where the routine's result goes.
+  pub result_location: VariableRef,
+}
+
+/// The frames on a Stack, innermost first.
+pub struct Frames<'a> {
+  stack: &'a Stack,
+  // The frame that comes next, and the offset of the first byte after it.
+  start_of_frame: Option<usize>,
+  end_of_frame: usize,
+}
+
+impl<'a> Iterator for Frames<'a> {
+  type Item = Frame;
+
+  fn next(&mut self) -> Option<Frame> {
+    let start_of_frame = self.start_of_frame?;
+    let bytes = &self.stack.stack;
+    let num_locals = bytes[start_of_frame + NUM_LOCALS_OFFSET] as usize;
+    let start_of_eval_stack = start_of_frame + FIRST_LOCAL_OFFSET + 2 * num_locals;
+    let words = |start: usize, end: usize| {
+      (start..end).step_by(2).map(|offset| BigEndian::read_u16(&bytes[offset..])).collect()
+    };
+    let frame = Frame {
+      return_pc: BigEndian::read_u32(&bytes[start_of_frame + PC_OFFSET..]) as usize,
+      routine: BigEndian::read_u32(&bytes[start_of_frame + ROUTINE_OFFSET..]) as usize,
+      locals: words(start_of_frame + FIRST_LOCAL_OFFSET, start_of_eval_stack),
+      eval_stack: words(start_of_eval_stack, self.end_of_frame),
+      result_location: VariableRef::decode(bytes[start_of_frame + RESULT_LOCATION_OFFSET]),
+    };
+
+    // The base frame is the one at the bottom of the stack.
+    self.start_of_frame = if start_of_frame == 0 {
+      None
+    } else {
+      Some(BigEndian::read_u16(&bytes[start_of_frame..]) as usize)
+    };
+    self.end_of_frame = start_of_frame;
+    Some(frame)
+  }
+}
 
+#[derive(Clone)]
 pub struct Stack {
   stack: Vec<u8>,
   sp: usize, // index of next empty location in stack
   fp: usize, // index of base of current frame (the saved fp)
   base_sp: usize, // index of bottom of current frame's stack
+  depth: usize, // number of frames, counting the base frame
 }
 
 impl Stack {
   pub fn new(size: usize) -> Stack {
     assert!(size < u16::MAX as usize, "Cannot to stack size > 0xffff");
 
+    assert!(size >= FIRST_LOCAL_OFFSET, "Stack size too small for the base frame");
+
+    // The base stack frame is all zeros, with no locals.
     let mut stack = Stack {
       stack: Vec::with_capacity(size),
-      sp: 0,
+      sp: FIRST_LOCAL_OFFSET,
       fp: 0,
-      base_sp: 0,
+      base_sp: FIRST_LOCAL_OFFSET,
+      depth: 1,
     };
     stack.stack.resize(size, 0);
-
-    // Initialize the base stack frame.
-    stack.push_u16(0);
-    stack.push_u32(0);
-    stack.push_u8(0);
-    stack.push_u8(0);
-    stack.base_sp = stack.sp;
     stack
   }
 
-  pub fn map_frames<T>(&self, mut f: T) -> Result<()>
-    where T: FnMut(u32, u8, u8, Vec<u16>, Vec<u16>) -> Result<()> {
-    for (start_of_frame, end_of_frame) in self.frame_ptrs() {
-      let return_pc = BigEndian::read_u32(&self.stack[start_of_frame + PC_OFFSET..]);
-
-      let p_flag = 0u8;  // No Call_N implemented for v3.
-      let num_locals = self.stack[start_of_frame + NUM_LOCALS_OFFSET];
-      let flags = (p_flag << 4) + (num_locals & 0x0f);
-
-      let encoded_result_variable = self.stack[start_of_frame + RESULT_LOCATION_OFFSET];
-
-      let start_of_eval_stack = start_of_frame + FIRST_LOCAL_OFFSET + 2 * num_locals as usize;
-      let eval_stack_words = ((end_of_frame - start_of_eval_stack) / 2) as u16;
-
-      // TODO: save/write num_args_passed
+  /// The frames on the call stack, innermost first.
+  pub fn frames<'a>(&'a self) -> Frames<'a> {
+    Frames {
+      stack: self,
+      start_of_frame: Some(self.fp),
+      end_of_frame: self.sp,
+    }
+  }
 
-      let mut locals = Vec::<u16>::new();
-      let local_offset = start_of_frame + FIRST_LOCAL_OFFSET;
-      for idx in 0..num_locals {
-        let local = BigEndian::read_u16(&self.stack[local_offset + 2 * idx as usize..]);
-        locals.push(local);
-      }
+  /// The number of frames on the call stack, counting the main routine's.
+  pub fn depth(&self) -> usize {
+    self.depth
+  }
 
-      let mut stack_words = Vec::<u16>::new();
-      for idx in 0..eval_stack_words {
-        let stack_word = BigEndian::read_u16(&self.stack[start_of_eval_stack + 2 * idx as usize..]);
-        stack_words.push(stack_word);
-      }
+  /// The address of the routine in the current frame (0 for the main
+  /// routine).
+  pub fn routine(&self) -> usize {
+    BigEndian::read_u32(&self.stack[self.fp + ROUTINE_OFFSET..]) as usize
+  }
 
-      f(return_pc,
-        flags,
-        encoded_result_variable,
-        locals,
-        stack_words)?;
+  /// The word `depth` down from the top of the current frame's stack, without
+  /// popping anything.
+  pub fn peek_u16(&self, depth: usize) -> Option<u16> {
+    let offset = self.sp.checked_sub(2 * (depth + 1))?;
+    if offset < self.base_sp {
+      return None;
     }
-    Ok(())
+    Some(BigEndian::read_u16(&self.stack[offset..]))
   }
 
-  // Return a vector of (start_of_frame, end_of_frame) pairs.
-  // start_of_frame is the offset of the beginning of the frame as described
-  // above. end_of_frame is the offset of the first byte in the stack *after*
-  // the frame.
-  fn frame_ptrs(&self) -> Vec<(usize, usize)> {
-    let mut ptrs = Vec::<(usize, usize)>::new();
-    let mut start_of_frame = self.fp;
-    let mut end_of_frame = self.sp;
-
-    loop {
-      ptrs.push((start_of_frame, end_of_frame));
-      let next_fp = BigEndian::read_u16(&self.stack[start_of_frame..]) as usize;
-      if start_of_frame == 0 {
-        break;
-      }
-      end_of_frame = start_of_frame;
-      start_of_frame = next_fp;
-    }
-    ptrs.reverse();
-    ptrs
+  /// The routines on the call stack, outermost (the main routine, 0) first.
+  pub fn routines(&self) -> Vec<usize> {
+    let mut routines = self.frames().map(|frame| frame.routine).collect::<Vec<_>>();
+    routines.reverse();
+    routines
   }
 
   // Allocate a new stack frame, adding it to the call stack.
   // Also allocate space for local variables, setting them all to zero.
-  pub fn new_frame(&mut self, pc: usize, num_locals: u8, result_location: VariableRef) {
+  pub fn new_frame(&mut self,
+                   pc: usize,
+                   routine: usize,
+                   num_locals: u8,
+                   result_location: VariableRef)
+                   -> Result<()> {
     let new_fp = self.sp;
     let old_fp = self.fp;
-    self.push_u16(old_fp as u16);
-    self.push_u32(pc as u32);
-    self.push_u8(num_locals);
-    self.push_u8(VariableRef::encode(result_location));
+    self.push_u16(old_fp as u16)?;
+    self.push_u32(pc as u32)?;
+    self.push_u32(routine as u32)?;
+    self.push_u8(num_locals)?;
+    self.push_u8(VariableRef::encode(result_location))?;
     for _ in 0..num_locals {
-      self.push_u16(0);
+      self.push_u16(0)?;
     }
 
     self.fp = new_fp;
     self.base_sp = self.sp;
+    self.depth += 1;
+    Ok(())
   }
 
-  pub fn pop_frame(&mut self) -> (usize, VariableRef) {
+  pub fn pop_frame(&mut self) -> Result<(usize, VariableRef)> {
+    // The main routine has nowhere to return to.
+    if self.fp == 0 {
+      return Err(Error::StackUnderflow);
+    }
+
     // Read these values before resetting the fp.
     let old_fp = BigEndian::read_u16(&self.stack[self.fp..]);
     let old_pc = BigEndian::read_u32(&self.stack[self.fp + PC_OFFSET..]);
@@ -135,58 +179,69 @@ impl Stack {
 
     self.fp = old_fp as usize;
     self.sp = old_sp;
+    self.depth -= 1;
 
     // Need to get the number of locals in the new frame to reset the base_sp.
     let num_locals = self.stack[self.fp + NUM_LOCALS_OFFSET];
     self.base_sp = self.fp + FIRST_LOCAL_OFFSET + 2 * num_locals as usize;
 
     // Return the old pc value and the result location.
-    (old_pc as usize, return_var)
+    Ok((old_pc as usize, return_var))
   }
 
-  fn offset_for_local(&self, local_idx: u8) -> usize {
+  fn offset_for_local(&self, local_idx: u8) -> Result<usize> {
     let num_locals = self.stack[self.fp + NUM_LOCALS_OFFSET];
-    assert!(local_idx < num_locals,
-            "Read non-existing local variable: {}.",
-            local_idx);
+    if local_idx >= num_locals {
+      return Err(Error::BadLocal(local_idx));
+    }
 
-    self.fp + FIRST_LOCAL_OFFSET + 2 * local_idx as usize
+    Ok(self.fp + FIRST_LOCAL_OFFSET + 2 * local_idx as usize)
   }
 
-  pub fn read_local(&self, local_idx: u8) -> u16 {
-    // TODO: cache the num_locals in the Stack struct
-    // let num_locals = self.stack[NUM_LOCALS_OFFSET as usize];
-    // assert!(local_idx < num_locals, "Read non-existing local variable.");
-    // let offset = FIRST_LOCAL_OFFSET + 2 * local_idx as usize;
-    let offset = self.offset_for_local(local_idx);
-    BigEndian::read_u16(&self.stack[offset..])
+  pub fn read_local(&self, local_idx: u8) -> Result<u16> {
+    let offset = self.offset_for_local(local_idx)?;
+    Ok(BigEndian::read_u16(&self.stack[offset..]))
   }
 
-  pub fn write_local(&mut self, local_idx: u8, val: u16) {
-    let offset = self.offset_for_local(local_idx);
+  pub fn write_local(&mut self, local_idx: u8, val: u16) -> Result<()> {
+    let offset = self.offset_for_local(local_idx)?;
     BigEndian::write_u16(&mut self.stack[offset..], val);
+    Ok(())
+  }
+
+  // The next `len` bytes of free space, moving the sp past them.
+  fn reserve(&mut self, len: usize) -> Result<&mut [u8]> {
+    let start = self.sp;
+    if start + len > self.stack.len() {
+      return Err(Error::StackOverflow);
+    }
+    self.sp += len;
+    Ok(&mut self.stack[start..start + len])
   }
 
-  fn push_u32(&mut self, val: u32) {
-    let mut slice = self.stack.as_mut_slice();
-    BigEndian::write_u32(&mut slice[self.sp..], val);
-    self.sp += 4;
+  fn push_u32(&mut self, val: u32) -> Result<()> {
+    BigEndian::write_u32(self.reserve(4)?, val);
+    Ok(())
   }
 
-  pub fn push_u16(&mut self, val: u16) {
-    let mut slice = self.stack.as_mut_slice();
-    BigEndian::write_u16(&mut slice[self.sp..], val);
-    self.sp += 2;
+  pub fn push_u16(&mut self, val: u16) -> Result<()> {
+    BigEndian::write_u16(self.reserve(2)?, val);
+    Ok(())
   }
 
-  pub fn pop_u16(&mut self) -> u16 {
+  /// Pop a word from the current frame's stack, which can't reach into the
+  /// frame below.
+  pub fn pop_u16(&mut self) -> Result<u16> {
+    if self.sp < self.base_sp + 2 {
+      return Err(Error::StackUnderflow);
+    }
     self.sp -= 2;
-    BigEndian::read_u16(&self.stack[self.sp..])
+    Ok(BigEndian::read_u16(&self.stack[self.sp..]))
   }
 
-  fn push_u8(&mut self, val: u8) {
-    self.stack[self.sp] = val;
-    self.sp += 1;
+  fn push_u8(&mut self, val: u8) -> Result<()> {
+    self.reserve(1)?[0] = val;
+    Ok(())
   }
 }
 
@@ -194,7 +249,8 @@ impl Stack {
 mod test {
   use byteorder::{BigEndian, ByteOrder};
   use std::u16;
-  use super::{FIRST_LOCAL_OFFSET, Stack};
+  use result::Error;
+  use super::{FIRST_LOCAL_OFFSET, Frame, Stack};
   use super::super::vm::VariableRef;
 
   #[test]
@@ -209,19 +265,37 @@ mod test {
 
     // We should be able to push 256 items into this stack with no error.
     for i in 0..((255 - stack.sp) as u8) {
-      stack.push_u8(i);
+      stack.push_u8(i).unwrap();
     }
   }
 
   #[test]
-  #[should_panic]
   fn test_overflow() {
     let mut stack = Stack::new(100);
+    for i in 0..((100 - stack.sp) as u8) {
+      stack.push_u8(i).unwrap();
+    }
 
     // Push one too many.
-    for i in 0..((101 - stack.sp) as u8) {
-      stack.push_u8(i);
-    }
+    assert!(matches!(stack.push_u8(0), Err(Error::StackOverflow)));
+    assert!(matches!(stack.new_frame(0x2345, 0x2000, 0, VariableRef::Stack),
+                     Err(Error::StackOverflow)));
+  }
+
+  #[test]
+  fn test_underflow() {
+    let mut stack = Stack::new(100);
+    assert!(matches!(stack.pop_u16(), Err(Error::StackUnderflow)));
+    assert!(matches!(stack.pop_frame(), Err(Error::StackUnderflow)));
+
+    // Popping can't reach into the frame below.
+    stack.push_u16(5).unwrap();
+    stack.new_frame(0x2345, 0x2000, 1, VariableRef::Stack).unwrap();
+    stack.push_u16(10).unwrap();
+    assert_eq!(10, stack.pop_u16().unwrap());
+    assert!(matches!(stack.pop_u16(), Err(Error::StackUnderflow)));
+    stack.pop_frame().unwrap();
+    assert_eq!(5, stack.pop_u16().unwrap());
   }
 
   #[test]
@@ -233,9 +307,9 @@ mod test {
   #[test]
   fn test_push_u8() {
     let mut stack = Stack::new(100);
-    stack.push_u8(5);
-    stack.push_u8(10);
-    stack.push_u8(15);
+    stack.push_u8(5).unwrap();
+    stack.push_u8(10).unwrap();
+    stack.push_u8(15).unwrap();
     assert_eq!(&stack.stack[stack.base_sp..stack.base_sp + 3],
                vec![5, 10, 15].as_slice());
   }
@@ -243,25 +317,25 @@ mod test {
   #[test]
   fn test_push_pop_u16() {
     let mut stack = Stack::new(100);
-    stack.push_u16(5);
-    stack.push_u16(10);
-    stack.push_u16(15);
+    stack.push_u16(5).unwrap();
+    stack.push_u16(10).unwrap();
+    stack.push_u16(15).unwrap();
     assert_eq!(&stack.stack[stack.base_sp..stack.base_sp + 6],
                vec![0, 5, 0, 10, 0, 15].as_slice());
 
-    assert_eq!(15, stack.pop_u16());
-    stack.push_u16(0xbcaa);
-    assert_eq!(0xbcaa, stack.pop_u16());
-    assert_eq!(10, stack.pop_u16());
-    assert_eq!(5, stack.pop_u16());
+    assert_eq!(15, stack.pop_u16().unwrap());
+    stack.push_u16(0xbcaa).unwrap();
+    assert_eq!(0xbcaa, stack.pop_u16().unwrap());
+    assert_eq!(10, stack.pop_u16().unwrap());
+    assert_eq!(5, stack.pop_u16().unwrap());
   }
 
   #[test]
   fn test_push_u32() {
     let mut stack = Stack::new(100);
-    stack.push_u32(5);
-    stack.push_u32(10);
-    stack.push_u32(15);
+    stack.push_u32(5).unwrap();
+    stack.push_u32(10).unwrap();
+    stack.push_u32(15).unwrap();
     assert_eq!(&stack.stack[stack.base_sp..stack.base_sp + 12],
                vec![0, 0, 0, 5, 0, 0, 0, 10, 0, 0, 0, 15].as_slice());
   }
@@ -269,12 +343,12 @@ mod test {
   #[test]
   fn test_push_mixed() {
     let mut stack = Stack::new(100);
-    stack.push_u8(5);
-    stack.push_u16(10);
-    stack.push_u32(15);
-    stack.push_u32(0xfedcba90);
-    stack.push_u16(20);
-    stack.push_u8(25);
+    stack.push_u8(5).unwrap();
+    stack.push_u16(10).unwrap();
+    stack.push_u32(15).unwrap();
+    stack.push_u32(0xfedcba90).unwrap();
+    stack.push_u16(20).unwrap();
+    stack.push_u8(25).unwrap();
     assert_eq!(&stack.stack[stack.base_sp..stack.base_sp + 14],
                vec![5, 0, 10, 0, 0, 0, 15, 0xfe, 0xdc, 0xba, 0x90, 0, 20, 25].as_slice());
   }
@@ -283,18 +357,21 @@ mod test {
   fn test_local() {
     let mut stack = Stack::new(100);
     let num_locals = 5;
-    stack.new_frame(0x2345, num_locals, VariableRef::Local(3));
+    stack.new_frame(0x2345, 0x2000, num_locals, VariableRef::Local(3)).unwrap();
 
     for i in 0..num_locals {
-      assert_eq!(0, stack.read_local(i));
+      assert_eq!(0, stack.read_local(i).unwrap());
     }
 
-    stack.write_local(0, 0xbe55);
+    stack.write_local(0, 0xbe55).unwrap();
+
+    assert_eq!(0xbe55, stack.read_local(0).unwrap());
 
-    assert_eq!(0xbe55, stack.read_local(0));
+    stack.write_local(4, 0xccee).unwrap();
+    assert_eq!(0xccee, stack.read_local(4).unwrap());
 
-    stack.write_local(4, 0xccee);
-    assert_eq!(0xccee, stack.read_local(4));
+    assert!(matches!(stack.read_local(5), Err(Error::BadLocal(5))));
+    assert!(matches!(stack.write_local(5, 0), Err(Error::BadLocal(5))));
   }
 
   #[test]
@@ -306,7 +383,7 @@ mod test {
     let old_fp = stack.fp;
     let result_location = VariableRef::Local(3);
 
-    stack.new_frame(0x8888, 5, result_location);
+    stack.new_frame(0x8888, 0x2000, 5, result_location).unwrap();
     // Check that the new values are as expected.
     assert_eq!(FIRST_LOCAL_OFFSET, stack.fp);
     assert_eq!(FIRST_LOCAL_OFFSET * 2 + 5 * 2, stack.sp);
@@ -317,14 +394,96 @@ mod test {
                BigEndian::read_u16(&stack.stack[stack.fp..]) as usize);
     assert_eq!(0x8888,
                BigEndian::read_u32(&stack.stack[stack.fp + super::PC_OFFSET..]));
+    assert_eq!(0x2000,
+               BigEndian::read_u32(&stack.stack[stack.fp + super::ROUTINE_OFFSET..]));
     assert_eq!(5, stack.stack[stack.fp + super::NUM_LOCALS_OFFSET]);
     assert_eq!(VariableRef::encode(result_location),
                stack.stack[stack.fp + super::RESULT_LOCATION_OFFSET]);
 
     // Check that stuff is restored after popping.
-    let (popped_pc, popped_location) = stack.pop_frame();
+    let (popped_pc, popped_location) = stack.pop_frame().unwrap();
     assert_eq!(stack.fp, old_fp);
     assert_eq!(0x8888, popped_pc);
     assert_eq!(result_location, popped_location);
   }
+
+  #[test]
+  fn test_depth() {
+    let mut stack = Stack::new(256);
+    assert_eq!(1, stack.depth());
+
+    stack.new_frame(0x8888, 0x2000, 2, VariableRef::Stack).unwrap();
+    stack.push_u16(0x1234).unwrap();
+    stack.new_frame(0x9999, 0x3000, 0, VariableRef::Stack).unwrap();
+    assert_eq!(3, stack.depth());
+
+    stack.pop_frame().unwrap();
+    assert_eq!(2, stack.depth());
+    assert_eq!(stack.frames().count(), stack.depth());
+  }
+
+  #[test]
+  fn test_routine_and_peek() {
+    let mut stack = Stack::new(256);
+    assert_eq!(0, stack.routine());
+    stack.push_u16(0x0101).unwrap();
+    stack.new_frame(0x8888, 0x2000, 2, VariableRef::Stack).unwrap();
+    assert_eq!(0x2000, stack.routine());
+    assert_eq!(None, stack.peek_u16(0));
+
+    stack.push_u16(0x1234).unwrap();
+    stack.push_u16(0x5678).unwrap();
+    assert_eq!(Some(0x5678), stack.peek_u16(0));
+    assert_eq!(Some(0x1234), stack.peek_u16(1));
+    assert_eq!(None, stack.peek_u16(2));
+
+    stack.pop_frame().unwrap();
+    assert_eq!(0, stack.routine());
+    assert_eq!(Some(0x0101), stack.peek_u16(0));
+  }
+
+  #[test]
+  fn test_routines() {
+    let mut stack = Stack::new(256);
+    assert_eq!(vec![0], stack.routines());
+
+    stack.new_frame(0x8888, 0x2000, 2, VariableRef::Stack).unwrap();
+    stack.push_u16(0x1234).unwrap();
+    stack.new_frame(0x9999, 0x3000, 0, VariableRef::Stack).unwrap();
+    assert_eq!(vec![0, 0x2000, 0x3000], stack.routines());
+  }
+
+  #[test]
+  fn test_frames_iterator() {
+    let mut stack = Stack::new(256);
+    stack.push_u16(0x0101).unwrap();
+    stack.new_frame(0x8888, 0x2000, 2, VariableRef::Stack).unwrap();
+    stack.write_local(1, 0xbe55).unwrap();
+    stack.push_u16(0x1234).unwrap();
+    stack.push_u16(0x5678).unwrap();
+    stack.new_frame(0x9999, 0x3000, 0, VariableRef::Global(3)).unwrap();
+
+    assert_eq!(vec![Frame {
+                      return_pc: 0x9999,
+                      routine: 0x3000,
+                      locals: vec![],
+                      eval_stack: vec![],
+                      result_location: VariableRef::Global(3),
+                    },
+                    Frame {
+                      return_pc: 0x8888,
+                      routine: 0x2000,
+                      locals: vec![0, 0xbe55],
+                      eval_stack: vec![0x1234, 0x5678],
+                      result_location: VariableRef::Stack,
+                    },
+                    Frame {
+                      return_pc: 0,
+                      routine: 0,
+                      locals: vec![],
+                      eval_stack: vec![0x0101],
+                      result_location: VariableRef::Stack,
+                    }],
+               stack.frames().collect::<Vec<_>>());
+  }
 }
@@ -0,0 +1,313 @@
+use rand::StdRng;
+use result::{Error, Result};
+use super::stack::Stack;
+
+// The debugger's record of the game so far, for going back in time. Every so
+// often, before an instruction runs, the state of the machine (dynamic memory,
+// the stack, the PC and the random number generator) is copied. Everything
+// else the game depends on comes from outside, so it is logged as it happens:
+// the lines and keys the player types, and any seed the operating system
+// picks for the random number generator.
+//
+// To go back to an earlier instruction, the interpreter puts back the latest
+// snapshot before it and runs the game forward from there, giving it the
+// logged input instead of asking for more. So it does just what it did the
+// first time. It carries on replaying the log until it gets back to where
+// the game had got to, then the player is asked again. If the game asks for
+// something other than what the log has next, it isn't doing what it did
+// before, and that is an error.
+//
+// Instructions are counted from the start of the game. A turn is the stretch
+// up to, and including, an instruction that read input, so turn 1 ends with
+// the first input.
+//
+// So that a long game doesn't use up memory, every other snapshot is dropped
+// when there are too many, and they are taken half as often from then on.
+
+pub const DEFAULT_SNAPSHOT_INTERVAL: u64 = 10000;
+const MAX_SNAPSHOTS: usize = 256;
+
+#[derive(Clone)]
+pub struct Snapshot {
+  pub instruction: u64,
+  pub pc: usize,
+  pub dynamic_bytes: Vec<u8>,
+  pub stack: Stack,
+  pub rng: StdRng,
+  // How many events had been logged.
+  events: usize,
+}
+
+/// Something from outside the game.
+#[derive(Clone,Debug,PartialEq)]
+pub enum Input {
+  // A line of text and the key that ended it.
+  Line(String, u16),
+  Key(u16),
+  Seed(usize),
+}
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Event {
+  // The instruction that asked for it, by number and address.
+  pub instruction: u64,
+  pub pc: usize,
+  pub input: Input,
+}
+
+pub struct Timeline {
+  interval: u64,
+  // The number of instructions run so far, and the most there have ever been.
+  now: u64,
+  end: u64,
+  snapshots: Vec<Snapshot>,
+  events: Vec<Event>,
+  // The next event to replay.
+  next_event: usize,
+}
+
+impl Timeline {
+  /// A timeline that copies the machine every `interval` instructions.
+  pub fn new(interval: u64) -> Timeline {
+    Timeline {
+      interval: interval.max(1),
+      now: 0,
+      end: 0,
+      snapshots: Vec::new(),
+      events: Vec::new(),
+      next_event: 0,
+    }
+  }
+
+  /// The number of instructions run so far.
+  pub fn now(&self) -> u64 {
+    self.now
+  }
+
+  /// Whether the game is going over what it did before (so it shouldn't be
+  /// shown again).
+  pub fn replaying(&self) -> bool {
+    self.now < self.end
+  }
+
+  /// Whether to copy the machine before the next instruction.
+  pub fn wants_snapshot(&self) -> bool {
+    !self.replaying() && self.now.is_multiple_of(self.interval) &&
+    self.snapshots.last().is_none_or(|snapshot| snapshot.instruction < self.now)
+  }
+
+  pub fn add_snapshot(&mut self,
+                      pc: usize,
+                      dynamic_bytes: Vec<u8>,
+                      stack: Stack,
+                      rng: StdRng) {
+    self.snapshots.push(Snapshot {
+      instruction: self.now,
+      pc,
+      dynamic_bytes,
+      stack,
+      rng,
+      events: self.next_event,
+    });
+    if self.snapshots.len() > MAX_SNAPSHOTS {
+      self.interval *= 2;
+      let interval = self.interval;
+      self.snapshots.retain(|snapshot| snapshot.instruction % interval == 0);
+    }
+  }
+
+  /// Count an instruction that has just run.
+  pub fn ran(&mut self) {
+    self.now += 1;
+    self.end = self.end.max(self.now);
+  }
+
+  /// The line (and the key that ended it) that the instruction about to run
+  /// read the first time, if it has run before.
+  pub fn replay_line(&mut self) -> Result<Option<(String, u16)>> {
+    match self.replay()? {
+      Some(Input::Line(text, terminator)) => Ok(Some((text, terminator))),
+      Some(_) => Err(Error::ReplayDiverged(self.now)),
+      None => Ok(None),
+    }
+  }
+
+  /// The key that the instruction about to run read the first time, if it
+  /// has run before.
+  pub fn replay_key(&mut self) -> Result<Option<u16>> {
+    match self.replay()? {
+      Some(Input::Key(key)) => Ok(Some(key)),
+      Some(_) => Err(Error::ReplayDiverged(self.now)),
+      None => Ok(None),
+    }
+  }
+
+  /// The seed that the instruction about to run was given the first time, if
+  /// it has run before.
+  pub fn replay_seed(&mut self) -> Result<Option<usize>> {
+    match self.replay()? {
+      Some(Input::Seed(seed)) => Ok(Some(seed)),
+      Some(_) => Err(Error::ReplayDiverged(self.now)),
+      None => Ok(None),
+    }
+  }
+
+  // The next logged input, which must be for the instruction about to run if
+  // the game is going over what it did before.
+  fn replay(&mut self) -> Result<Option<Input>> {
+    if !self.replaying() {
+      return Ok(None);
+    }
+    match self.events.get(self.next_event) {
+      Some(event) if event.instruction == self.now => {
+        self.next_event += 1;
+        Ok(Some(event.input.clone()))
+      }
+      _ => Err(Error::ReplayDiverged(self.now)),
+    }
+  }
+
+  /// Log the input given to the instruction at `pc`, which is about to run.
+  /// Anything logged after it didn't happen after all, if this is new.
+  pub fn record(&mut self, pc: usize, input: Input) {
+    let now = self.now;
+    self.end = now;
+    self.snapshots.retain(|snapshot| snapshot.instruction <= now);
+    self.events.truncate(self.next_event);
+    self.events.push(Event {
+      instruction: self.now,
+      pc,
+      input,
+    });
+    self.next_event += 1;
+  }
+
+  /// Go back (or forward, up to where the game has got to) to `instruction`.
+  /// This returns the snapshot to put back; the game must then be run until
+  /// now() is `instruction`.
+  pub fn rewind(&mut self, instruction: u64) -> Option<&Snapshot> {
+    let instruction = instruction.min(self.end);
+    let snapshot = self.snapshots.iter().rev().find(|s| s.instruction <= instruction)?;
+    self.now = snapshot.instruction;
+    self.next_event = snapshot.events;
+    Some(snapshot)
+  }
+
+  /// What the player typed (or pressed), oldest first.
+  pub fn turns(&self) -> Vec<&Event> {
+    self.events
+      .iter()
+      .filter(|event| !matches!(event.input, Input::Seed(_)))
+      .collect()
+  }
+
+  /// The instruction that read the input for turn `number`, or 0 (the start
+  /// of the game) for turn 0.
+  pub fn turn_start(&self, number: usize) -> Option<u64> {
+    if number == 0 {
+      Some(0)
+    } else {
+      self.turns().get(number - 1).map(|event| event.instruction)
+    }
+  }
+
+  /// The turn that the next instruction is part of.
+  pub fn turn(&self) -> usize {
+    self.turns().iter().filter(|event| event.instruction < self.now).count() + 1
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use rand::StdRng;
+  use result::Error;
+  use super::{Input, Timeline};
+  use zmachine::vm::stack::Stack;
+
+  fn run(timeline: &mut Timeline, count: u64) {
+    for _ in 0..count {
+      if timeline.wants_snapshot() {
+        let bytes = vec![timeline.now() as u8];
+        timeline.add_snapshot(0x100, bytes, Stack::new(32), StdRng::new().unwrap());
+      }
+      timeline.ran();
+    }
+  }
+
+  #[test]
+  fn test_replay() {
+    let mut timeline = Timeline::new(10);
+    run(&mut timeline, 5);
+    assert_eq!(None, timeline.replay_line().unwrap());
+    timeline.record(0x4f05, Input::Line("look".to_string(), 13));
+    run(&mut timeline, 10);
+    timeline.record(0x4f05, Input::Key(32));
+    run(&mut timeline, 10);
+    assert!(!timeline.replaying());
+    assert_eq!(3, timeline.turn());
+
+    // Back to instruction 12: the snapshot at 10, then replay.
+    assert_eq!(Some(10), timeline.rewind(12).map(|snapshot| snapshot.instruction));
+    assert_eq!(vec![10], timeline.rewind(12).unwrap().dynamic_bytes);
+    assert!(timeline.replaying());
+    assert_eq!(2, timeline.turn());
+    run(&mut timeline, 5);
+    assert_eq!(Some(32), timeline.replay_key().unwrap());
+    run(&mut timeline, 10);
+    assert!(!timeline.replaying());
+    assert_eq!(None, timeline.replay_key().unwrap());
+
+    assert_eq!(Some(0), timeline.turn_start(0));
+    assert_eq!(Some(5), timeline.turn_start(1));
+    assert_eq!(Some(15), timeline.turn_start(2));
+    assert_eq!(None, timeline.turn_start(3));
+  }
+
+  #[test]
+  fn test_new_input_replaces_old() {
+    let mut timeline = Timeline::new(10);
+    run(&mut timeline, 5);
+    timeline.record(0x4f05, Input::Seed(7));
+    timeline.record(0x4f05, Input::Line("look".to_string(), 13));
+    run(&mut timeline, 5);
+    assert_eq!(1, timeline.turns().len());
+
+    timeline.rewind(5);
+    run(&mut timeline, 5);
+    assert_eq!(Some(7), timeline.replay_seed().unwrap());
+    timeline.record(0x4f05, Input::Line("north".to_string(), 13));
+    assert!(!timeline.replaying());
+    assert_eq!(vec![Input::Line("north".to_string(), 13)],
+               timeline.turns().iter().map(|event| event.input.clone()).collect::<Vec<_>>());
+  }
+
+  #[test]
+  fn test_replay_diverged() {
+    let mut timeline = Timeline::new(10);
+    run(&mut timeline, 5);
+    timeline.record(0x4f05, Input::Line("look".to_string(), 13));
+    run(&mut timeline, 10);
+
+    // Asking for a key where a line was read.
+    timeline.rewind(8);
+    run(&mut timeline, 5);
+    assert!(timeline.replaying());
+    assert!(matches!(timeline.replay_key(), Err(Error::ReplayDiverged(5))));
+
+    // Asking for input where there wasn't any.
+    timeline.rewind(8);
+    run(&mut timeline, 3);
+    assert!(matches!(timeline.replay_line(), Err(Error::ReplayDiverged(3))));
+  }
+
+  #[test]
+  fn test_thinning() {
+    let mut timeline = Timeline::new(1);
+    run(&mut timeline, 1000);
+    assert!(timeline.snapshots.len() <= super::MAX_SNAPSHOTS);
+    assert_eq!(0, timeline.snapshots[0].instruction);
+    assert!(timeline.snapshots.iter().all(|snapshot| snapshot.instruction % 4 == 0));
+    // Going back lands on the latest snapshot before the instruction.
+    assert_eq!(Some(996), timeline.rewind(999).map(|snapshot| snapshot.instruction));
+  }
+}
@@ -0,0 +1,116 @@
+use result::Result;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use super::output::TextStyle;
+
+// Output stream 2: a copy of the lower window's text (and the player's
+// input) written to a file. The file is opened, in append mode, the first
+// time the game turns transcripting on.
+//
+// With markup turned on, style changes are recorded with simple tags:
+// <rev>, <b>, <i> and <tt>.
+
+const DEFAULT_TRANSCRIPT_PATH: &str = "transcript.txt";
+
+pub struct Transcript {
+  path: PathBuf,
+  markup: bool,
+  file: Option<File>,
+  style: TextStyle,
+}
+
+impl Transcript {
+  pub fn new(path: Option<PathBuf>, markup: bool) -> Transcript {
+    Transcript {
+      path: path.unwrap_or(PathBuf::from(DEFAULT_TRANSCRIPT_PATH)),
+      markup,
+      file: None,
+      style: TextStyle::roman(),
+    }
+  }
+
+  pub fn print(&mut self, text: &str, style: TextStyle) -> Result<()> {
+    if self.markup && style != self.style {
+      let tags = style_change_markup(self.style, style);
+      self.write(&tags)?;
+      self.style = style;
+    }
+    self.write(text)
+  }
+
+  /// Close any open markup and the file. A later print will reopen it.
+  pub fn close(&mut self) -> Result<()> {
+    if self.file.is_some() {
+      let roman = TextStyle::roman();
+      if self.markup && self.style != roman {
+        let tags = style_change_markup(self.style, roman);
+        self.write(&tags)?;
+        self.style = roman;
+      }
+      self.file = None;
+    }
+    Ok(())
+  }
+
+  fn write(&mut self, text: &str) -> Result<()> {
+    if self.file.is_none() {
+      let file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+      self.file = Some(file);
+    }
+    if let Some(ref mut file) = self.file {
+      file.write_all(text.as_bytes())?;
+    }
+    Ok(())
+  }
+}
+
+// The tags for each style, in the order that they are opened.
+fn style_tags(style: TextStyle) -> Vec<&'static str> {
+  let mut tags = Vec::new();
+  if style.is_reverse() {
+    tags.push("rev");
+  }
+  if style.is_bold() {
+    tags.push("b");
+  }
+  if style.is_italic() {
+    tags.push("i");
+  }
+  if style.is_fixed() {
+    tags.push("tt");
+  }
+  tags
+}
+
+// Close all of the tags for `old` (innermost first), then open the tags for `new`.
+fn style_change_markup(old: TextStyle, new: TextStyle) -> String {
+  let mut markup = String::new();
+  for tag in style_tags(old).iter().rev() {
+    markup.push_str(&format!("</{}>", tag));
+  }
+  for tag in style_tags(new) {
+    markup.push_str(&format!("<{}>", tag));
+  }
+  markup
+}
+
+#[cfg(test)]
+mod test {
+  use super::style_change_markup;
+  use zmachine::vm::output::{STYLE_BOLD, STYLE_FIXED, STYLE_ITALIC, STYLE_REVERSE, TextStyle};
+
+  #[test]
+  fn test_style_change_markup() {
+    let roman = TextStyle::roman();
+    let bold = roman.with(STYLE_BOLD);
+    let bold_italic = bold.with(STYLE_ITALIC);
+
+    assert_eq!("<b>", style_change_markup(roman, bold));
+    assert_eq!("</b>", style_change_markup(bold, roman));
+    assert_eq!("</b><b><i>", style_change_markup(bold, bold_italic));
+    assert_eq!("</i></b>", style_change_markup(bold_italic, roman));
+    assert_eq!("<rev><tt>",
+               style_change_markup(roman, roman.with(STYLE_REVERSE | STYLE_FIXED)));
+  }
+}
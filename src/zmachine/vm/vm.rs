@@ -1,4 +1,4 @@
-use result::Result;
+use result::{Error, Result};
 use super::ptrs::{BytePtr, RawPtr, WordPtr};
 use zmachine::vm::object_table::{ZObjectTable, ZPropertyTable};
 
@@ -24,23 +24,31 @@ pub trait VM: Sized {
   type ObjTable: ZObjectTable;
   type PropertyTable: ZPropertyTable;
 
+  /// The version of the story file being run.
+  fn version(&self) -> u8;
+
   /// Advance the PC past the next byte, returning that byte.
-  fn read_pc_byte(&mut self) -> u8;
+  fn read_pc_byte(&mut self) -> Result<u8>;
   /// Advance the PC past the next word, returning that word.
-  fn read_pc_word(&mut self) -> u16;
+  fn read_pc_word(&mut self) -> Result<u16>;
   /// Return the current value of the PC.
   fn current_pc(&self) -> usize;  // TODO: make this take a RawPtr.
   /// Set the PC to the supplied value.
   fn set_current_pc(&mut self, pc: usize) -> Result<()>;  // TODO: make RawPtr
   /// Add the `offset`, treated as a 14-bit signed int, to the PC.
   fn offset_pc(&mut self, offset: i16) -> Result<()>;
+  /// Note whether the instruction that is running took its branch, for traces
+  /// and coverage.
+  fn set_branch_taken(&mut self, taken: bool);
 
   /// Create a new stack frame.
   /// * `ret_pc` - the PC value to return when the frame is popped.
+  /// * `routine` - the address of the routine being called, for backtraces.
   /// * `num_locals` - the number of locals (<= 8) to allocate in the new frame.
   /// * `result_location` - the VariableRef to return when the frame is popped.
   fn new_frame(&mut self,
                ret_pc: usize,
+               routine: usize,
                num_locals: u8,
                result_location: VariableRef)
                -> Result<()>;
@@ -76,6 +84,9 @@ pub trait VM: Sized {
   /// `global_idx` is in the range [0, 239].
   /// NOTE: prefer write_variable().
   fn write_global(&mut self, global_idx: u8, val: u16) -> Result<()>;
+  /// Where the global at `global_idx` is kept in memory, so that watchpoints
+  /// can tell when it is written.
+  fn global_addr(&self, global_idx: u8) -> Result<usize>;
 
   /// Read the word at `ptr` in the vm's memory.
   fn read_memory<T>(&self, ptr: T) -> Result<u16> where T: Into<RawPtr>;
@@ -88,20 +99,54 @@ pub trait VM: Sized {
 
   fn object_table(&self) -> Result<Self::ObjTable>;
 
+  /// The contents of the terminating characters table (v5+), empty if the game has none.
+  fn terminating_chars(&self) -> Result<Vec<u16>>;
+
   fn num_dict_entries(&self) -> u16;
   fn dict_entry(&self, number: u16) -> BytePtr;
 
-  fn rand(&mut self, range: u16) -> u16;
-  fn write_status_line(&self, &str);
+  fn rand(&mut self, range: u16) -> Result<u16>;
+  fn write_status_line(&mut self, text: &str) -> Result<()>;
   fn screen_width(&self) -> u16;
-  fn write_main_window_char(&self, u16);
-  fn write_main_window(&self, &str);
-  fn read_line(&self) -> Result<String>;
+  fn write_main_window_char(&mut self, zscii: u16) -> Result<()>;
+  fn write_main_window(&mut self, text: &str) -> Result<()>;
+  /// Change the style of subsequent text, as with set_text_style.
+  fn set_text_style(&mut self, style: u16) -> Result<()>;
+  /// Turn word wrapping of the lower window on or off.
+  fn set_buffer_mode(&mut self, buffered: bool) -> Result<()>;
+  /// Give the upper window `lines` rows of the screen.
+  fn split_window(&mut self, lines: u16) -> Result<()>;
+  /// Select the lower (0) or upper (1) window for output.
+  fn set_window(&mut self, window: u16) -> Result<()>;
+  /// Erase a window: 0 or 1, or -1 to unsplit and clear the screen, or -2 to clear it.
+  fn erase_window(&mut self, window: i16) -> Result<()>;
+  /// Erase from the cursor to the end of its line.
+  fn erase_line(&mut self) -> Result<()>;
+  /// Move the cursor in the upper window (1-based).
+  fn set_cursor(&mut self, line: u16, column: u16) -> Result<()>;
+  /// The cursor's (line, column) in the current window (1-based).
+  fn cursor(&self) -> Result<(u16, u16)>;
+  /// Change the colours of subsequent text. Each colour is a set_colour
+  /// colour number: 0 for current, 1 for default, or 2-9.
+  fn set_colour(&mut self, foreground: u16, background: u16) -> Result<()>;
+  /// Turn an output stream on (positive) or off (negative). `table` is where
+  /// stream 3 writes to, when it is turned on.
+  fn select_output_stream(&mut self, stream: i16, table: u16) -> Result<()>;
+  /// Read a line of at most `max_len` characters from the keyboard. Input ends
+  /// with a newline or one of the ZSCII codes in `terminators`. Returns the
+  /// text (without the newline) and the ZSCII code that ended it.
+  fn read_line(&mut self, max_len: usize, terminators: &[u16]) -> Result<(String, u16)>;
+  /// Wait for a single keypress and return it as a ZSCII code.
+  fn read_char(&mut self) -> Result<u16>;
   /// Return the address as a WordPtr of the specified abbrev.
   fn abbrev_addr(&self, abbrev_table: u8, abbrev_index: u8) -> Result<WordPtr>;
 
   fn save(&self) -> Result<()>;
 
+  /// Report a problem with the story file that the caller can recover from
+  /// (see error_policy). Returns the error if the game should stop instead.
+  fn report_error(&mut self, err: Error) -> Result<()>;
+
   /// Read the value from the specified variable.
   fn read_variable(&mut self, variable: VariableRef) -> Result<u16> {
     match variable {
@@ -144,7 +189,6 @@ impl VariableRef {
       0x00 => VariableRef::Stack,
       0x01...0x0f => VariableRef::Local(encoded - 0x01),
       0x10...0xff => VariableRef::Global(encoded - 0x10),
-      _ => panic!("What is this number: {}", encoded),
     }
   }
 
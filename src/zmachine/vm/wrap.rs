@@ -0,0 +1,228 @@
+use std::collections::VecDeque;
+
+// Word wrapping for the lower window (spec section 8.7.2.4, "buffer mode").
+//
+// WordWrapper holds back the word being printed until it is complete, so
+// that it can decide whether the word fits on the current line. It doesn't
+// know anything about the screen: the caller tells it where the cursor is
+// and prints whatever it hands back.
+//
+// Scrollback remembers recent text (with its attributes), so that the lower
+// window can be rewrapped when the terminal changes size.
+
+// How many lines of text to keep for reflowing.
+const SCROLLBACK_LINES: usize = 200;
+
+pub struct WordWrapper {
+  width: usize,
+  word: String,
+  // Spaces before `word`. They are dropped if the word starts a new line.
+  spaces: usize,
+}
+
+impl WordWrapper {
+  pub fn new(width: usize) -> WordWrapper {
+    WordWrapper {
+      width: width.max(1),
+      word: String::new(),
+      spaces: 0,
+    }
+  }
+
+  pub fn set_width(&mut self, width: usize) {
+    self.width = width.max(1);
+  }
+
+  /// Add `text`, with the cursor at column `col`, and return the text to
+  /// print, with line breaks added. Any unfinished word is held back.
+  pub fn push(&mut self, text: &str, col: usize) -> String {
+    let mut out = String::new();
+    let mut col = col;
+    for ch in text.chars() {
+      match ch {
+        '\n' => {
+          self.place_word(&mut out, col);
+          self.spaces = 0;
+          out.push('\n');
+          col = 0;
+        }
+        ' ' => {
+          col = self.place_word(&mut out, col);
+          self.spaces += 1;
+        }
+        _ => self.word.push(ch),
+      }
+    }
+    out
+  }
+
+  /// Return the held back text (wrapped if needed), with the cursor at `col`.
+  pub fn flush(&mut self, col: usize) -> String {
+    let mut out = String::new();
+    let col = self.place_word(&mut out, col);
+    let spaces = self.spaces.min(self.width.saturating_sub(col));
+    out.extend((0..spaces).map(|_| ' '));
+    self.spaces = 0;
+    out
+  }
+
+  /// Throw away the held back text.
+  pub fn clear(&mut self) {
+    self.word.clear();
+    self.spaces = 0;
+  }
+
+  // Append the held back spaces and word to `out`, breaking the line first
+  // (and dropping the spaces) if they won't fit. Words that are longer than a
+  // whole line are broken wherever they have to be. Returns the new column.
+  fn place_word(&mut self, out: &mut String, col: usize) -> usize {
+    let chars: Vec<char> = self.word.drain(..).collect();
+    if chars.is_empty() {
+      return col;
+    }
+
+    let mut col = col;
+    if col > 0 && col + self.spaces + chars.len() > self.width {
+      out.push('\n');
+      col = 0;
+    } else {
+      out.extend((0..self.spaces).map(|_| ' '));
+      col += self.spaces;
+    }
+    self.spaces = 0;
+
+    for chunk in chars.chunks(self.width) {
+      if col >= self.width {
+        out.push('\n');
+        col = 0;
+      }
+      out.extend(chunk.iter());
+      col += chunk.len();
+    }
+    col
+  }
+}
+
+/// Recent text, as runs with the same attributes.
+pub struct Scrollback<A> {
+  runs: VecDeque<(A, String)>,
+  lines: usize,
+}
+
+impl<A> Scrollback<A>
+  where A: Copy + PartialEq {
+  pub fn new() -> Scrollback<A> {
+    Scrollback {
+      runs: VecDeque::new(),
+      lines: 0,
+    }
+  }
+
+  pub fn push(&mut self, attrs: A, text: &str) {
+    if text.is_empty() {
+      return;
+    }
+    self.lines += text.matches('\n').count();
+    let merged = match self.runs.back_mut() {
+      Some(&mut (last_attrs, ref mut last_text)) if last_attrs == attrs => {
+        last_text.push_str(text);
+        true
+      }
+      _ => false,
+    };
+    if !merged {
+      self.runs.push_back((attrs, text.to_string()));
+    }
+    self.trim();
+  }
+
+  pub fn clear(&mut self) {
+    self.runs.clear();
+    self.lines = 0;
+  }
+
+  pub fn runs(&self) -> &VecDeque<(A, String)> {
+    &self.runs
+  }
+
+  // Drop whole lines from the front until we are back under the limit.
+  fn trim(&mut self) {
+    while self.lines > SCROLLBACK_LINES {
+      let drop_run = {
+        let front = &mut self.runs.front_mut().unwrap().1;
+        match front.find('\n') {
+          Some(idx) => {
+            front.drain(..idx + 1);
+            self.lines -= 1;
+            front.is_empty()
+          }
+          None => true,
+        }
+      };
+      if drop_run {
+        self.runs.pop_front();
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::{SCROLLBACK_LINES, Scrollback, WordWrapper};
+
+  #[test]
+  fn test_wrap_at_spaces() {
+    let mut wrapper = WordWrapper::new(10);
+    let out = wrapper.push("The quick brown fox jumps", 0);
+    assert_eq!("The quick\nbrown fox", out);
+    assert_eq!("\njumps", wrapper.flush(9));
+  }
+
+  #[test]
+  fn test_word_held_back() {
+    let mut wrapper = WordWrapper::new(10);
+    assert_eq!("", wrapper.push("hel", 0));
+    assert_eq!("hello", wrapper.push("lo ", 0));
+    assert_eq!("\nworld\n", wrapper.push("world\n", 5));
+  }
+
+  #[test]
+  fn test_spaces() {
+    let mut wrapper = WordWrapper::new(10);
+    // Spaces at the start of a paragraph are kept...
+    assert_eq!("  indented", wrapper.push("  indented ", 0));
+    // ...but not at the start of a wrapped line.
+    assert_eq!("", wrapper.push("   next", 10));
+    assert_eq!("\nnext", wrapper.flush(10));
+
+    // Trailing spaces are printed when flushed, as long as they fit.
+    assert_eq!(">", wrapper.push("> ", 0));
+    assert_eq!(" ", wrapper.flush(1));
+    wrapper.push("  ", 9);
+    assert_eq!(" ", wrapper.flush(9));
+  }
+
+  #[test]
+  fn test_long_words() {
+    let mut wrapper = WordWrapper::new(4);
+    assert_eq!("ab", wrapper.push("ab abcdefghij", 0));
+    assert_eq!("\nabcd\nefgh\nij", wrapper.flush(2));
+  }
+
+  #[test]
+  fn test_scrollback() {
+    let mut scrollback = Scrollback::new();
+    scrollback.push(0, "one ");
+    scrollback.push(0, "two\n");
+    scrollback.push(1, "three");
+    scrollback.push(0, "");
+    assert_eq!(2, scrollback.runs().len());
+    assert_eq!((0, "one two\n".to_string()), scrollback.runs()[0]);
+
+    for _ in 0..SCROLLBACK_LINES {
+      scrollback.push(0, "line\n");
+    }
+    assert_eq!(SCROLLBACK_LINES, scrollback.lines);
+    assert_eq!((1, "three".to_string()), scrollback.runs()[0]);
+  }
+}
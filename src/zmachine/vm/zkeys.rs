@@ -0,0 +1,188 @@
+use ncurses::{KEY_A1, KEY_A3, KEY_B2, KEY_BACKSPACE, KEY_C1, KEY_C3, KEY_DC, KEY_DOWN, KEY_END,
+              KEY_ENTER, KEY_F0, KEY_HOME, KEY_LEFT, KEY_RESIZE, KEY_RIGHT, KEY_UP, WchResult};
+use super::zscii;
+
+// Translates keyboard input into ZSCII (spec section 3.8 and 10.7).
+//
+// ncurses hands us either a character or a key code (when keypad() is on).
+// Games only ever see ZSCII, so everything read from the keyboard, whether
+// by read_char or read, goes through here first.
+
+pub const ZSCII_DELETE: u16 = 8;
+pub const ZSCII_NEWLINE: u16 = 13;
+pub const ZSCII_ESCAPE: u16 = 27;
+pub const ZSCII_CURSOR_UP: u16 = 129;
+pub const ZSCII_CURSOR_DOWN: u16 = 130;
+pub const ZSCII_CURSOR_LEFT: u16 = 131;
+pub const ZSCII_CURSOR_RIGHT: u16 = 132;
+pub const ZSCII_F1: u16 = 133;
+pub const ZSCII_F12: u16 = 144;
+pub const ZSCII_KEYPAD_0: u16 = 145;
+pub const ZSCII_KEYPAD_9: u16 = 154;
+
+// In a terminating characters table, this stands for every function key.
+const ANY_FUNCTION_KEY: u16 = 255;
+
+const CTRL_A: i32 = 0x01;
+const CTRL_D: i32 = 0x04;
+const CTRL_E: i32 = 0x05;
+const ASCII_BS: i32 = 0x08;
+const ASCII_NL: i32 = '\n' as i32;
+const ASCII_CR: i32 = '\r' as i32;
+const ASCII_ESC: i32 = 0x1b;
+const ASCII_DEL: i32 = 0x7f;
+
+/// Map a value returned by `wgetch` (with keypad enabled) to a ZSCII input code.
+/// Returns None for keys that have no ZSCII equivalent.
+pub fn zscii_from_key(key: i32) -> Option<u16> {
+  match key {
+    ASCII_NL | ASCII_CR | KEY_ENTER => Some(ZSCII_NEWLINE),
+    ASCII_BS | ASCII_DEL | KEY_BACKSPACE | KEY_DC => Some(ZSCII_DELETE),
+    ASCII_ESC => Some(ZSCII_ESCAPE),
+    0x20..=0x7e => Some(key as u16),
+    KEY_UP => Some(ZSCII_CURSOR_UP),
+    KEY_DOWN => Some(ZSCII_CURSOR_DOWN),
+    KEY_LEFT => Some(ZSCII_CURSOR_LEFT),
+    KEY_RIGHT => Some(ZSCII_CURSOR_RIGHT),
+    _ if key > KEY_F0 && key <= KEY_F0 + (ZSCII_F12 - ZSCII_F1 + 1) as i32 => {
+      Some(ZSCII_F1 + (key - KEY_F0 - 1) as u16)
+    }
+    // ncurses only reports the corners and center of the keypad.
+    KEY_A1 => Some(ZSCII_KEYPAD_0 + 7),
+    KEY_A3 => Some(ZSCII_KEYPAD_0 + 9),
+    KEY_B2 => Some(ZSCII_KEYPAD_0 + 5),
+    KEY_C1 => Some(ZSCII_KEYPAD_0 + 1),
+    KEY_C3 => Some(ZSCII_KEYPAD_0 + 3),
+    _ => None,
+  }
+}
+
+/// A key as seen by the line editor: either something with a ZSCII code, or
+/// one of the editing keys that the game never sees. Resize isn't really a
+/// key, but ncurses reports it as one.
+#[derive(Clone,Copy,Debug,Eq,PartialEq)]
+pub enum Key {
+  Zscii(u16),
+  Home,
+  End,
+  ForwardDelete,
+  Resize,
+  Unknown,
+}
+
+/// Map a value returned by `wget_wch` (with keypad enabled) to a Key.
+pub fn key_from_input(input: WchResult) -> Key {
+  match input {
+    WchResult::KeyCode(KEY_HOME) => Key::Home,
+    WchResult::KeyCode(KEY_END) => Key::End,
+    WchResult::KeyCode(KEY_DC) => Key::ForwardDelete,
+    WchResult::KeyCode(KEY_RESIZE) => Key::Resize,
+    WchResult::KeyCode(code) => zscii_from_key(code).map(Key::Zscii).unwrap_or(Key::Unknown),
+    WchResult::Char(ch) if ch < 0x80 => {
+      match ch as i32 {
+        CTRL_A => Key::Home,
+        CTRL_E => Key::End,
+        CTRL_D => Key::ForwardDelete,
+        ch => zscii_from_key(ch).map(Key::Zscii).unwrap_or(Key::Unknown),
+      }
+    }
+    WchResult::Char(ch) => {
+      ::std::char::from_u32(ch)
+        .and_then(zscii::char_to_zscii)
+        .map(Key::Zscii)
+        .unwrap_or(Key::Unknown)
+    }
+  }
+}
+
+/// True if `zscii` is one of the function-key codes (cursor, function and keypad keys)
+/// that a game may use as a terminating character.
+pub fn is_function_key(zscii: u16) -> bool {
+  (ZSCII_CURSOR_UP..=ZSCII_KEYPAD_9).contains(&zscii)
+}
+
+/// True if `zscii` should end input, given the game's terminating characters table.
+pub fn is_terminator(zscii: u16, terminators: &[u16]) -> bool {
+  zscii == ZSCII_NEWLINE || terminators.contains(&zscii) ||
+  (terminators.contains(&ANY_FUNCTION_KEY) && is_function_key(zscii))
+}
+
+/// True if `zscii` can be typed into an input line.
+pub fn is_printable(zscii: u16) -> bool {
+  zscii != ZSCII_NEWLINE && zscii::zscii_to_char(zscii).is_some()
+}
+
+#[cfg(test)]
+mod test {
+  use ncurses::{KEY_A1, KEY_BACKSPACE, KEY_C3, KEY_DC, KEY_DOWN, KEY_END, KEY_ENTER, KEY_F0,
+                KEY_HOME, KEY_LEFT, KEY_RESIZE, KEY_RIGHT, KEY_UP, WchResult};
+  use super::*;
+
+  #[test]
+  fn test_printable() {
+    assert_eq!(Some('a' as u16), zscii_from_key('a' as i32));
+    assert_eq!(Some(' ' as u16), zscii_from_key(' ' as i32));
+    assert_eq!(Some('~' as u16), zscii_from_key('~' as i32));
+    assert_eq!(None, zscii_from_key(0x01));
+  }
+
+  #[test]
+  fn test_editing_keys() {
+    assert_eq!(Some(ZSCII_NEWLINE), zscii_from_key('\n' as i32));
+    assert_eq!(Some(ZSCII_NEWLINE), zscii_from_key('\r' as i32));
+    assert_eq!(Some(ZSCII_NEWLINE), zscii_from_key(KEY_ENTER));
+    assert_eq!(Some(ZSCII_DELETE), zscii_from_key(0x7f));
+    assert_eq!(Some(ZSCII_DELETE), zscii_from_key(0x08));
+    assert_eq!(Some(ZSCII_DELETE), zscii_from_key(KEY_BACKSPACE));
+    assert_eq!(Some(ZSCII_DELETE), zscii_from_key(KEY_DC));
+    assert_eq!(Some(ZSCII_ESCAPE), zscii_from_key(0x1b));
+  }
+
+  #[test]
+  fn test_function_keys() {
+    assert_eq!(Some(129), zscii_from_key(KEY_UP));
+    assert_eq!(Some(130), zscii_from_key(KEY_DOWN));
+    assert_eq!(Some(131), zscii_from_key(KEY_LEFT));
+    assert_eq!(Some(132), zscii_from_key(KEY_RIGHT));
+    assert_eq!(Some(133), zscii_from_key(KEY_F0 + 1));
+    assert_eq!(Some(144), zscii_from_key(KEY_F0 + 12));
+    assert_eq!(None, zscii_from_key(KEY_F0));
+    assert_eq!(None, zscii_from_key(KEY_F0 + 13));
+    assert_eq!(Some(152), zscii_from_key(KEY_A1));
+    assert_eq!(Some(148), zscii_from_key(KEY_C3));
+
+    assert!(is_function_key(129));
+    assert!(is_function_key(154));
+    assert!(!is_function_key(128));
+    assert!(!is_function_key(155));
+  }
+
+  #[test]
+  fn test_terminators() {
+    assert!(is_terminator(ZSCII_NEWLINE, &[]));
+    assert!(!is_terminator(ZSCII_F1, &[]));
+    assert!(is_terminator(ZSCII_F1, &[ZSCII_F1]));
+    assert!(!is_terminator(ZSCII_F1 + 1, &[ZSCII_F1]));
+    assert!(is_terminator(ZSCII_CURSOR_UP, &[255]));
+    assert!(is_terminator(ZSCII_KEYPAD_9, &[255]));
+    assert!(!is_terminator('a' as u16, &[255]));
+  }
+
+  #[test]
+  fn test_key_from_input() {
+    assert_eq!(Key::Zscii('x' as u16), key_from_input(WchResult::Char('x' as u32)));
+    assert_eq!(Key::Zscii(170), key_from_input(WchResult::Char('é' as u32)));
+    assert_eq!(Key::Unknown, key_from_input(WchResult::Char('€' as u32)));
+    assert_eq!(Key::Zscii(ZSCII_DELETE), key_from_input(WchResult::Char(0x7f)));
+    assert_eq!(Key::ForwardDelete, key_from_input(WchResult::KeyCode(KEY_DC)));
+    assert_eq!(Key::Home, key_from_input(WchResult::KeyCode(KEY_HOME)));
+    assert_eq!(Key::End, key_from_input(WchResult::KeyCode(KEY_END)));
+    assert_eq!(Key::Home, key_from_input(WchResult::Char(0x01)));
+    assert_eq!(Key::Resize, key_from_input(WchResult::KeyCode(KEY_RESIZE)));
+    assert_eq!(Key::Zscii(ZSCII_CURSOR_UP), key_from_input(WchResult::KeyCode(KEY_UP)));
+
+    assert!(is_printable(170));
+    assert!(!is_printable(ZSCII_NEWLINE));
+    assert!(!is_printable(ZSCII_CURSOR_LEFT));
+  }
+}
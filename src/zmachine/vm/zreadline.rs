@@ -1,54 +1,376 @@
 use ncurses::*;
+use result::{Error, Result};
+use super::zkeys::{self, Key, ZSCII_CURSOR_DOWN, ZSCII_CURSOR_LEFT, ZSCII_CURSOR_RIGHT,
+                   ZSCII_CURSOR_UP, ZSCII_DELETE, ZSCII_NEWLINE};
+use super::zscii;
 
-// For now, assume ncurses.
+// A line editor for the ncurses main window.
 //
-// Remember starting cursor position.
-// Get passed max string length.
+// The text being edited lives in a LineBuffer, which knows nothing about
+// ncurses so that the editing rules can be tested. ZReadline feeds it keys
+// and redraws the line from the starting cursor position after every change.
 //
-// On each character:
-//   update string,
-//   update display.
+// Every character in the buffer has a ZSCII equivalent (we refuse anything
+// else), so each one occupies exactly one column on the screen.
 
-pub struct ZReadline {
-  window: WINDOW,
+/// The contents of the input line and the cursor position within it.
+#[derive(Debug,Eq,PartialEq)]
+pub struct LineBuffer {
+  chars: Vec<char>,
+  cursor: usize,
+  max_len: usize,
 }
 
-const NL: i32 = '\n' as i32;
-const BS: i32 = 0x7fi32;
+impl LineBuffer {
+  pub fn new(max_len: usize) -> LineBuffer {
+    LineBuffer {
+      chars: Vec::new(),
+      cursor: 0,
+      max_len,
+    }
+  }
+
+  pub fn text(&self) -> String {
+    self.chars.iter().cloned().collect()
+  }
+
+  pub fn cursor(&self) -> usize {
+    self.cursor
+  }
+
+  pub fn len(&self) -> usize {
+    self.chars.len()
+  }
+
+  /// Replace the contents of the line (truncated to the max length) and
+  /// put the cursor at the end.
+  pub fn set_text(&mut self, text: &str) {
+    self.chars = text.chars().take(self.max_len).collect();
+    self.cursor = self.chars.len();
+  }
+
+  /// Insert `ch` at the cursor. Returns false if the line is already full.
+  pub fn insert(&mut self, ch: char) -> bool {
+    if self.chars.len() >= self.max_len {
+      return false;
+    }
+    self.chars.insert(self.cursor, ch);
+    self.cursor += 1;
+    true
+  }
+
+  /// Delete the character before the cursor.
+  pub fn backspace(&mut self) -> bool {
+    if self.cursor == 0 {
+      return false;
+    }
+    self.cursor -= 1;
+    self.chars.remove(self.cursor);
+    true
+  }
+
+  /// Delete the character under the cursor.
+  pub fn delete(&mut self) -> bool {
+    if self.cursor >= self.chars.len() {
+      return false;
+    }
+    self.chars.remove(self.cursor);
+    true
+  }
+
+  pub fn left(&mut self) {
+    if self.cursor > 0 {
+      self.cursor -= 1;
+    }
+  }
 
-impl ZReadline {
-  pub fn new(window: WINDOW) -> ZReadline {
+  pub fn right(&mut self) {
+    if self.cursor < self.chars.len() {
+      self.cursor += 1;
+    }
+  }
+
+  pub fn home(&mut self) {
+    self.cursor = 0;
+  }
+
+  pub fn end(&mut self) {
+    self.cursor = self.chars.len();
+  }
+}
+
+/// Previously entered lines, oldest first.
+///
+/// While browsing, `pos` is the index of the entry being shown, and `scratch`
+/// holds whatever the player had typed before they started browsing so that
+/// it can be restored when they come back down.
+pub struct History {
+  entries: Vec<String>,
+  pos: usize,
+  scratch: String,
+}
+
+impl History {
+  pub fn new() -> History {
+    History {
+      entries: Vec::new(),
+      pos: 0,
+      scratch: String::new(),
+    }
+  }
+
+  /// Record a completed line. Empty lines and immediate repeats are skipped.
+  pub fn add(&mut self, line: &str) {
+    if !line.is_empty() && self.entries.last().map(|l| l.as_str()) != Some(line) {
+      self.entries.push(line.to_string());
+    }
+    self.reset();
+  }
+
+  /// Stop browsing. The next `older()` starts from the most recent entry.
+  pub fn reset(&mut self) {
+    self.pos = self.entries.len();
+    self.scratch.clear();
+  }
+
+  /// Move to the previous entry, returning it. `current` is the line being
+  /// edited, which is saved if we are just starting to browse.
+  pub fn older(&mut self, current: &str) -> Option<&str> {
+    if self.pos == 0 {
+      return None;
+    }
+    if self.pos == self.entries.len() {
+      self.scratch = current.to_string();
+    }
+    self.pos -= 1;
+    Some(&self.entries[self.pos])
+  }
+
+  /// Move to the next entry, returning it, or the saved scratch line once
+  /// we move past the most recent entry.
+  pub fn newer(&mut self) -> Option<&str> {
+    if self.pos >= self.entries.len() {
+      return None;
+    }
+    self.pos += 1;
+    if self.pos == self.entries.len() {
+      Some(&self.scratch)
+    } else {
+      Some(&self.entries[self.pos])
+    }
+  }
+}
+
+pub struct ZReadline<'a> {
+  window: WINDOW,
+  history: &'a mut History,
+  // Called when the terminal changes size, to lay out the screen again.
+  on_resize: &'a mut dyn FnMut(),
+  start_y: i32,
+  start_x: i32,
+}
+
+impl<'a> ZReadline<'a> {
+  pub fn new(window: WINDOW,
+             history: &'a mut History,
+             on_resize: &'a mut dyn FnMut())
+             -> ZReadline<'a> {
     let mut startx = 0i32;
     let mut starty = 0i32;
     getyx(window, &mut starty, &mut startx);
 
-    ZReadline { window: window }
+    ZReadline {
+      window,
+      history,
+      on_resize,
+      start_y: starty,
+      start_x: startx,
+    }
+  }
+
+  /// Block until a key is pressed, and return it. The window waits for input,
+  /// so ncurses only gives up (ERR) when there is none left to wait for, as
+  /// when the terminal has gone away.
+  pub fn read_input(&mut self) -> Result<Key> {
+    let input = wget_wch(self.window).ok_or(Error::EndOfInput)?;
+    let key = zkeys::key_from_input(input);
+    if key == Key::Resize {
+      (self.on_resize)();
+    }
+    Ok(key)
   }
 
-  pub fn readline(self) -> String {
-    let mut input = String::new();
+  /// Block until a key with a ZSCII equivalent is pressed, and return its ZSCII code.
+  pub fn read_key(mut self) -> Result<u16> {
     loop {
-      let ch = wgetch(self.window);
-      match ch {
-        NL => {
-          input.push('\n');
-          waddch(self.window, ch as chtype);
-          return input;
+      if let Key::Zscii(zscii) = self.read_input()? {
+        return Ok(zscii);
+      }
+    }
+  }
+
+  /// Read a line of at most `max_len` characters. Input ends with a newline, or
+  /// with any of the ZSCII codes in `terminators` (the game's terminating
+  /// characters table). Returns the text and the ZSCII code that ended it.
+  pub fn readline(mut self, max_len: usize, terminators: &[u16]) -> Result<(String, u16)> {
+    let mut line = LineBuffer::new(max_len);
+    self.history.reset();
+    loop {
+      match self.read_input()? {
+        Key::Zscii(zscii) if zkeys::is_terminator(zscii, terminators) => {
+          let text = line.text();
+          line.end();
+          self.redraw(&line);
+          if zscii == ZSCII_NEWLINE {
+            waddch(self.window, '\n' as chtype);
+          }
+          self.history.add(&text);
+          return Ok((text, zscii));
+        }
+        Key::Zscii(ZSCII_DELETE) => {
+          line.backspace();
+        }
+        Key::ForwardDelete => {
+          line.delete();
         }
-        BS => {
-          if input.len() > 0 {
-            let mut x = 0i32;
-            let mut y = 0i32;
-            getyx(self.window, &mut y, &mut x);
-            input.pop();
-            mvwdelch(self.window, y, x - 1);
+        Key::Zscii(ZSCII_CURSOR_LEFT) => line.left(),
+        Key::Zscii(ZSCII_CURSOR_RIGHT) => line.right(),
+        Key::Home => line.home(),
+        Key::End => line.end(),
+        Key::Resize => {
+          // The screen has been redrawn up to the prompt, so start again from there.
+          getyx(self.window, &mut self.start_y, &mut self.start_x);
+        }
+        Key::Zscii(ZSCII_CURSOR_UP) => {
+          let text = line.text();
+          if let Some(entry) = self.history.older(&text) {
+            line.set_text(entry);
           }
         }
-        _ => {
-          input.push(ch as u8 as char);
-          waddch(self.window, ch as chtype);
+        Key::Zscii(ZSCII_CURSOR_DOWN) => {
+          if let Some(entry) = self.history.newer() {
+            line.set_text(entry);
+          }
         }
+        Key::Zscii(zscii) if zkeys::is_printable(zscii) => {
+          if let Some(ch) = zscii::zscii_to_char(zscii) {
+            line.insert(ch);
+          }
+        }
+        _ => {}
       }
+      self.redraw(&line);
+    }
+  }
+
+  // Redraw the whole line from its starting position, then place the cursor.
+  // If the line wraps off the bottom of the window, the window scrolls and
+  // the starting row moves up with it.
+  fn redraw(&mut self, line: &LineBuffer) {
+    let mut rows = 0i32;
+    let mut cols = 0i32;
+    getmaxyx(self.window, &mut rows, &mut cols);
+    let cols = if cols > 0 { cols } else { 1 };
+
+    wmove(self.window, self.start_y, self.start_x);
+    waddstr(self.window, &line.text());
+    wclrtobot(self.window);
+
+    let end_row = self.start_y + (self.start_x + line.len() as i32) / cols;
+    if end_row >= rows {
+      self.start_y -= end_row - (rows - 1);
+    }
+
+    let offset = self.start_x + line.cursor() as i32;
+    wmove(self.window, self.start_y + offset / cols, offset % cols);
+    wrefresh(self.window);
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::{History, LineBuffer};
+
+  #[test]
+  fn test_insert_and_max_len() {
+    let mut line = LineBuffer::new(5);
+    for ch in "hello world".chars() {
+      line.insert(ch);
     }
+    assert_eq!("hello", line.text());
+    assert_eq!(5, line.cursor());
+    assert!(!line.insert('!'));
+  }
+
+  #[test]
+  fn test_cursor_movement() {
+    let mut line = LineBuffer::new(20);
+    line.set_text("look");
+    line.home();
+    line.insert('x');
+    assert_eq!("xlook", line.text());
+    line.end();
+    line.insert('s');
+    assert_eq!("xlooks", line.text());
+
+    line.home();
+    line.left();
+    assert_eq!(0, line.cursor());
+    line.right();
+    line.right();
+    line.insert('-');
+    assert_eq!("xl-ooks", line.text());
+
+    line.end();
+    line.right();
+    assert_eq!(7, line.cursor());
+  }
+
+  #[test]
+  fn test_deletion() {
+    let mut line = LineBuffer::new(20);
+    line.set_text("take");
+    assert!(line.backspace());
+    assert_eq!("tak", line.text());
+    assert!(!line.delete());
+
+    line.home();
+    assert!(!line.backspace());
+    assert!(line.delete());
+    assert_eq!("ak", line.text());
+    assert_eq!(0, line.cursor());
+  }
+
+  #[test]
+  fn test_multibyte() {
+    let mut line = LineBuffer::new(4);
+    line.set_text("café au lait");
+    assert_eq!("café", line.text());
+    assert_eq!(4, line.cursor());
+    line.left();
+    line.backspace();
+    assert_eq!("caé", line.text());
+    line.delete();
+    assert_eq!("ca", line.text());
+  }
+
+  #[test]
+  fn test_history() {
+    let mut history = History::new();
+    assert_eq!(None, history.older(""));
+
+    history.add("north");
+    history.add("");
+    history.add("take lamp");
+    history.add("take lamp");
+    history.reset();
+
+    assert_eq!(None, history.newer());
+    assert_eq!(Some("take lamp"), history.older("inv"));
+    assert_eq!(Some("north"), history.older("take lamp"));
+    assert_eq!(None, history.older("north"));
+    assert_eq!(Some("take lamp"), history.newer());
+    assert_eq!(Some("inv"), history.newer());
+    assert_eq!(None, history.newer());
   }
 }
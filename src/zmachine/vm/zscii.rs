@@ -0,0 +1,64 @@
+// Conversion between ZSCII and Unicode (spec section 3.8).
+//
+// Codes 32-126 are plain ASCII. Codes 155-223 are the "extra characters"
+// from the default Unicode translation table (spec 3.8.5.3). We don't yet
+// support custom translation tables from the header extension.
+
+const EXTRA_CHARS: [char; 69] = ['ä', 'ö', 'ü', 'Ä', 'Ö', 'Ü', 'ß', '»', '«', 'ë', 'ï', 'ÿ', 'Ë',
+                                 'Ï', 'á', 'é', 'í', 'ó', 'ú', 'ý', 'Á', 'É', 'Í', 'Ó', 'Ú', 'Ý',
+                                 'à', 'è', 'ì', 'ò', 'ù', 'À', 'È', 'Ì', 'Ò', 'Ù', 'â', 'ê', 'î',
+                                 'ô', 'û', 'Â', 'Ê', 'Î', 'Ô', 'Û', 'å', 'Å', 'ø', 'Ø', 'ã', 'ñ',
+                                 'õ', 'Ã', 'Ñ', 'Õ', 'æ', 'Æ', 'ç', 'Ç', 'þ', 'ð', 'Þ', 'Ð', '£',
+                                 'œ', 'Œ', '¡', '¿'];
+const FIRST_EXTRA_CHAR: u16 = 155;
+
+/// Return the ZSCII code for `ch`, or None if it has no ZSCII representation.
+pub fn char_to_zscii(ch: char) -> Option<u16> {
+  match ch {
+    '\n' => Some(13),
+    ' '..='~' => Some(ch as u16),
+    _ => {
+      EXTRA_CHARS.iter()
+        .position(|extra| *extra == ch)
+        .map(|idx| FIRST_EXTRA_CHAR + idx as u16)
+    }
+  }
+}
+
+/// Return the Unicode character for the ZSCII code `zscii`, or None if it is
+/// not a printable ZSCII character.
+pub fn zscii_to_char(zscii: u16) -> Option<char> {
+  match zscii {
+    13 => Some('\n'),
+    32..=126 => Some(zscii as u8 as char),
+    155..=223 => Some(EXTRA_CHARS[(zscii - FIRST_EXTRA_CHAR) as usize]),
+    _ => None,
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::{char_to_zscii, zscii_to_char};
+
+  #[test]
+  fn test_ascii() {
+    assert_eq!(Some(97), char_to_zscii('a'));
+    assert_eq!(Some(13), char_to_zscii('\n'));
+    assert_eq!(Some('~'), zscii_to_char(126));
+    assert_eq!(None, char_to_zscii('\t'));
+    assert_eq!(None, zscii_to_char(127));
+  }
+
+  #[test]
+  fn test_extra_chars() {
+    assert_eq!(Some(155), char_to_zscii('ä'));
+    assert_eq!(Some(170), char_to_zscii('é'));
+    assert_eq!(Some(223), char_to_zscii('¿'));
+    assert_eq!(None, char_to_zscii('€'));
+
+    for zscii in 155..224 {
+      let ch = zscii_to_char(zscii).unwrap();
+      assert_eq!(Some(zscii), char_to_zscii(ch));
+    }
+  }
+}
@@ -1,21 +1,35 @@
-use ncurses::{A_REVERSE, WINDOW, endwin, getmaxyx, getyx, initscr, mvwprintw, newwin, noecho, raw,
-              refresh, scrollok, stdscr, waddch, wattron, wmove, wprintw, wrefresh};
-use rand::{Rng, SeedableRng, StdRng};
+use rand::{self, Rng, SeedableRng, StdRng};
 use result::{Error, Result};
 use std::cell::RefCell;
-use std::fs::File;
-use std::io::{Read, Write};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Read, Write};
+use std::mem;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
+use zmachine::coverage::{self, Coverage};
+use zmachine::debug_info::DebugInfo;
+use zmachine::debugger::{self, Context, Debugger};
+use zmachine::disasm;
+use zmachine::info::{self, Info, Sections};
 use zmachine::ops;
 use zmachine::ops::Operand;
-use zmachine::vm::{BytePtr, RawPtr, VM, VariableRef, WordPtr};
+use zmachine::ops::decoder::{self, InstructionBytes};
+use zmachine::profile::Profiler;
+use zmachine::vm::{BytePtr, DEFAULT_SNAPSHOT_INTERVAL, ErrorMode, ErrorPolicy, Input, RawPtr,
+                   Timeline, VM, VariableRef, WordPtr};
 use zmachine::vm::dictionary::Dictionary;
 use zmachine::vm::memory::Memory;
 use zmachine::vm::mm_object_table::{MemoryMappedObjectTable, MemoryMappedPropertyTable};
+use zmachine::vm::output::{FLAG2_TRANSCRIPT, Output};
 use zmachine::vm::pc::PC;
 use zmachine::vm::quetzal::Quetzal;
+use zmachine::vm::screen::Screen;
+use zmachine::vm::screen_model::Window;
 use zmachine::vm::stack::Stack;
-use zmachine::vm::zreadline::ZReadline;
+use zmachine::vm::transcript::Transcript;
+use zmachine::vm::zreadline::{History, ZReadline};
+use zmachine::trace::{Record, TraceFilter, Tracer};
+use zmachine::watch::{Watch, Watchpoints};
 use zmachine::zconfig::{ZConfig, ZDefaults};
 
 const HEADER_SIZE: usize = 64;
@@ -26,45 +40,102 @@ pub struct ZMachine {
   stack: Stack,
   dict: Dictionary,
 
-  status_window: Option<WINDOW>,
-  main_window: Option<WINDOW>,
-  num_rows: i32,
-  num_cols: i32,
+  output: Output,
+  history: History,
 
   rng: StdRng,
+
+  errors: ErrorPolicy,
+  // Where the current instruction started, for warnings.
+  instruction_pc: usize,
+  // Whether the current instruction took its branch, if it has one.
+  branch_taken: Option<bool>,
+
+  debugger: Option<Debugger>,
+  // What has happened so far, for the debugger to go back to.
+  timeline: Option<Timeline>,
+  watches: Watchpoints,
+  watch_log: Option<PathBuf>,
+  // Names from the debugging information file, if there is one.
+  names: Rc<DebugInfo>,
+  tracer: Option<Tracer<BufWriter<File>>>,
+  profiler: Option<Profiler>,
+  profile_report: bool,
+  profile_folded: Option<PathBuf>,
+  coverage: Option<Coverage>,
+  coverage_path: Option<PathBuf>,
 }
 
 impl ZMachine {
-  pub fn from_memory<T>(memory: Memory, config: &T) -> ZMachine
+  pub fn from_memory<T>(mut memory: Memory, config: &T) -> Result<ZMachine>
     where T: ZConfig {
+    let dict = Dictionary::new(&memory)?;
+    if config.debug() {
+      // For watchpoints the debugger adds.
+      memory.record_writes();
+    }
     let memory_rc = Rc::new(RefCell::new(memory));
     let pc = PC::new(memory_rc.borrow().starting_pc(), memory_rc.clone());
     let rng = StdRng::new().unwrap();
     let mut zmachine = ZMachine {
       memory: memory_rc.clone(),
       pc: pc,
-      stack: Stack::new(config.stack_size().unwrap()),
-      dict: Dictionary::new(&memory_rc.borrow()),
-      status_window: None,
-      main_window: None,
-      num_rows: 0,
-      num_cols: 0,
+      stack: Stack::new(config.stack_size().or(ZDefaults::new().stack_size()).unwrap()),
+      dict,
+      output: Output::new(Screen::new(config.default_foreground(), config.default_background()),
+                          Transcript::new(config.transcript_path(), config.transcript_markup())),
+      history: History::new(),
       rng: rng,
+      errors: ErrorPolicy::new(config.error_mode()),
+      instruction_pc: 0,
+      branch_taken: None,
+      debugger: if config.debug() {
+        Some(Debugger::new())
+      } else {
+        None
+      },
+      timeline: if config.debug() {
+        Some(Timeline::new(config.snapshot_interval()?.unwrap_or(DEFAULT_SNAPSHOT_INTERVAL)))
+      } else {
+        None
+      },
+      watches: Watchpoints::new(),
+      watch_log: config.watch_log_path(),
+      names: Rc::new(DebugInfo::new()),
+      tracer: None,
+      profiler: if config.profile() || config.profile_folded_path().is_some() {
+        Some(Profiler::new())
+      } else {
+        None
+      },
+      profile_report: config.profile(),
+      profile_folded: config.profile_folded_path(),
+      coverage: None,
+      coverage_path: config.coverage_path(),
     };
     zmachine.reset_interpreter_flags();
-    zmachine
+    Ok(zmachine)
   }
 
-  pub fn from_reader<T: Read>(mut reader: T) -> Result<ZMachine> {
+  pub fn from_reader<T, C>(mut reader: T, config: &C) -> Result<ZMachine>
+    where T: Read,
+          C: ZConfig {
     let mut zbytes = Vec::<u8>::new();
     let bytes_read = reader.read_to_end(&mut zbytes)?;
     if bytes_read < HEADER_SIZE {
       return Err(Error::CouldNotReadHeader);
     }
 
-    let config = ZDefaults::new();
     let memory = Memory::from(zbytes);
-    let zmachine = ZMachine::from_memory(memory, &config);
+    // v4 and v5 are a work in progress. Nothing has been done for v6-8.
+    match memory.version() {
+      1..=5 => {}
+      version => return Err(Error::UnsupportedVersion(version)),
+    }
+    if !memory.is_complete() {
+      return Err(Error::ZFileTooShort);
+    }
+    let mut zmachine = ZMachine::from_memory(memory, config)?;
 
     let expected_file_length = zmachine.memory.borrow().file_length();
     if expected_file_length != 0 && expected_file_length > bytes_read as u32 {
@@ -72,76 +143,533 @@ impl ZMachine {
       return Err(Error::ZFileTooShort);
     }
 
+    if let Some(path) = config.debug_info_path() {
+      let globals = RawPtr::from(zmachine.memory.borrow().global_base_ptr()).ptr();
+      zmachine.names = Rc::new(DebugInfo::from_file(path, globals)?);
+    }
+    if let Some(path) = config.trace_path() {
+      zmachine.tracer = Some(Tracer::create(path, config.trace_format())?);
+    }
+    if let Some(path) = config.coverage_path() {
+      // Add to what earlier runs recorded.
+      let story = zmachine.story_id();
+      zmachine.coverage = Some(if path.exists() {
+        Coverage::from_file(path, &story)?
+      } else {
+        Coverage::new(story)
+      });
+    }
+
     Ok(zmachine)
   }
 
   fn reset_interpreter_flags(&mut self) {
     // The interpreter sets flags in the header to express its capabilities to the game.
-    let flag1_mask = !0b01110000;  // no status line, no split screen, fixed-width font
+    // The meaning of Flags 1 changed completely in v4.
+    let version = self.memory.borrow().version();
     let old_val = self.memory.borrow().flag1();
-    self.memory.borrow_mut().set_flag1(old_val & flag1_mask);
+    let new_val = if version <= 3 {
+      // status line, split screen, fixed-width font
+      (old_val & !0b01010000) | 0b00100000
+    } else {
+      // bold, italic and fixed-space styles, but no timed input
+      let val = (old_val | 0b00011100) & !0b10000000;
+      // Colour is only known once the screen is up.
+      if version >= 5 && self.output.screen().has_colours() {
+        val | 0b00000001
+      } else {
+        val & !0b00000001
+      }
+    };
+    self.memory.borrow_mut().set_flag1(new_val);
+
+    update_screen_size(&mut self.memory.borrow_mut(), self.output.screen());
+
+    if version >= 5 {
+      let defaults = self.output.screen().default_colours();
+      self.memory.borrow_mut().set_default_colours(defaults.foreground as u8,
+                                                   defaults.background as u8);
+    }
   }
 
-  pub fn init_windows(&mut self) {
-    initscr();
-    raw();
-    noecho();
-    refresh();
+  /// Write a listing of the game's code, starting from the main routine.
+  pub fn disassemble<W>(&mut self, out: &mut W) -> Result<()>
+    where W: Write {
+    let start_pc = RawPtr::from(self.memory.borrow().starting_pc()).ptr();
+    let names = self.names.clone();
+    disasm::disassemble(self, start_pc, &names, out)
+  }
 
-    getmaxyx(stdscr(), &mut self.num_rows, &mut self.num_cols);
+  /// Merge the coverage files at `paths` and report how much of the game's
+  /// code they cover.
+  pub fn coverage_report<P, W>(&mut self, paths: &[P], out: &mut W) -> Result<()>
+    where P: AsRef<Path>,
+          W: Write {
+    let story = self.story_id();
+    let mut total = Coverage::new(story.clone());
+    for path in paths {
+      total.merge(&Coverage::from_file(path, &story)?);
+    }
+    let start_pc = RawPtr::from(self.memory.borrow().starting_pc()).ptr();
+    let names = self.names.clone();
+    total.write_report(self, start_pc, &names, out)
+  }
 
-    let win = newwin(1, self.num_cols, 0, 0);
-    scrollok(win, false);
-    wattron(win, A_REVERSE());
-    self.status_window = Some(win);
-    wrefresh(win);
+  fn story_id(&self) -> String {
+    let memory = self.memory.borrow();
+    coverage::story_id(memory.release(), &memory.serial(), memory.checksum())
+  }
 
-    let win = newwin(self.num_rows - 1, self.num_cols, 1, 0);
-    scrollok(win, true);
-    wmove(win, self.num_rows - 2, 0);
-    self.main_window = Some(win);
-    wrefresh(win);
+  /// Gather up what's in the story file, for `rzm info`.
+  pub fn info(&mut self, sections: Sections) -> Result<Info> {
+    let dict = self.dict.clone();
+    Ok(Info {
+      header: if sections.header {
+        Some(info::header(&self.memory.borrow()))
+      } else {
+        None
+      },
+      objects: if sections.objects {
+        Some(info::objects(self)?)
+      } else {
+        None
+      },
+      dictionary: if sections.dictionary {
+        Some((dict.separators().to_vec(), info::dictionary(self, &dict)?))
+      } else {
+        None
+      },
+      abbreviations: if sections.abbreviations {
+        Some(info::abbreviations(self)?)
+      } else {
+        None
+      },
+    })
   }
 
-  pub fn tear_down_windows(&mut self) {
-    self.status_window = None;
-    self.main_window = None;
-    endwin();
+  pub fn init_windows(&mut self) {
+    let version = self.version();
+    self.output.screen_mut().init(version);
+    self.reset_interpreter_flags();
+  }
+
+  pub fn tear_down_windows(&mut self) -> Result<()> {
+    self.output.close()
   }
 
   pub fn run(&mut self, start_pc: Option<usize>) -> Result<()> {
     start_pc.map(|new_pc| self.pc.set_raw_pc(new_pc));
     self.init_windows();
 
-    // TODO: check version number
     loop {
-      let r = self.process_opcode();
+      let r = self.check_debugger()
+        .and_then(|_| self.run_instruction())
+        .and_then(|_| self.check_watches());
       match r {
         Err(Error::Quitting) => break,
-        Err(Error::Restart) => {
-          self.memory.borrow_mut().restore_dynamic_bytes();
-          self.pc.set_pc(self.memory.borrow().starting_pc());
+        Err(Error::Restart) => self.restart(),
+        Err(_) => {
+          // Put the terminal back before anyone tries to report the error.
+          self.tear_down_windows()?;
+          let _ = self.write_backtrace(&mut io::stderr());
+          let _ = self.write_profile();
+          let _ = self.write_coverage();
+          return r;
         }
-        Err(_) => return r,
         _ => {}
       }
     }
 
-    self.tear_down_windows();
+    self.tear_down_windows()?;
+    self.write_profile()?;
+    self.write_coverage()
+  }
+
+  fn restart(&mut self) {
+    self.memory.borrow_mut().restore_dynamic_bytes();
+    self.reset_interpreter_flags();
+    self.pc.set_pc(self.memory.borrow().starting_pc());
+  }
+
+  // Report the profile, if there is one, now that the game is over.
+  fn write_profile(&self) -> Result<()> {
+    let profiler = match self.profiler.as_ref() {
+      Some(profiler) => profiler,
+      None => return Ok(()),
+    };
+    if self.profile_report {
+      profiler.write_report(&self.names, &mut io::stderr())?;
+    }
+    if let Some(ref path) = self.profile_folded {
+      let mut out = BufWriter::new(File::create(path)?);
+      profiler.write_folded(&self.names, &mut out)?;
+      out.flush()?;
+    }
+    Ok(())
+  }
+
+  // Save the coverage, if it is being recorded, now that the game is over.
+  fn write_coverage(&self) -> Result<()> {
+    if let (Some(coverage), Some(path)) = (self.coverage.as_ref(), self.coverage_path.as_ref()) {
+      let mut out = BufWriter::new(File::create(path)?);
+      coverage.write(&mut out)?;
+      out.flush()?;
+    }
+    Ok(())
+  }
+
+  // Give the debugger, if there is one, a chance to stop before the next
+  // instruction. The screen is put away while it asks what to do.
+  fn check_debugger(&mut self) -> Result<()> {
+    let mut debugger = match self.debugger.take() {
+      Some(debugger) => debugger,
+      None => return Ok(()),
+    };
+    let result = self.prompt_debugger(&mut debugger);
+    self.debugger = Some(debugger);
+    result
+  }
+
+  // After going back in time, the debugger stops again where it went to.
+  fn prompt_debugger(&mut self, debugger: &mut Debugger) -> Result<()> {
+    loop {
+      let pc = usize::from(self.pc.pc());
+      if !debugger.should_stop(pc, self.stack.depth()) {
+        return Ok(());
+      }
+      let frames = self.stack.frames().collect::<Vec<_>>();
+      self.output.screen_mut().suspend();
+      let stdin = io::stdin();
+      let stdout = io::stdout();
+      let mut watches = mem::replace(&mut self.watches, Watchpoints::new());
+      let timeline = self.timeline
+        .take()
+        .unwrap_or_else(|| Timeline::new(DEFAULT_SNAPSHOT_INTERVAL));
+      let names = self.names.clone();
+      let result = debugger.prompt(&mut Context {
+                                     vm: self,
+                                     pc,
+                                     frames: &frames,
+                                     watches: &mut watches,
+                                     timeline: &timeline,
+                                     names: &names,
+                                   },
+                                   &mut stdin.lock(),
+                                   &mut stdout.lock());
+      self.watches = watches;
+      self.timeline = Some(timeline);
+      let rewind = debugger.take_rewind();
+      let result = result.and_then(|_| match rewind {
+        Some(instruction) => self.rewind(instruction),
+        None => Ok(()),
+      });
+      self.output.screen_mut().resume();
+      result?;
+      match (rewind, self.timeline.as_ref()) {
+        (Some(_), Some(timeline)) => {
+          debugger.report(format!("Now at instruction {}, in turn {}\n",
+                                  timeline.now(),
+                                  timeline.turn()))
+        }
+        _ => return Ok(()),
+      }
+    }
+  }
+
+  // Put back the latest snapshot before `instruction`, then run the game up
+  // to it, replaying the input it had before.
+  fn rewind(&mut self, instruction: u64) -> Result<()> {
+    match self.timeline.as_mut().and_then(|timeline| timeline.rewind(instruction)) {
+      Some(snapshot) => {
+        self.memory.borrow_mut().set_dynamic_bytes(&snapshot.dynamic_bytes);
+        self.stack = snapshot.stack.clone();
+        self.pc.set_raw_pc(snapshot.pc);
+        self.rng = snapshot.rng;
+      }
+      None => return Ok(()),
+    }
+    // Going over the game again doesn't count for the profile.
+    let mut profiler = self.profiler.take();
+    let result = self.replay(instruction);
+    if let Some(profiler) = profiler.as_mut() {
+      profiler.set_call_stack(self.stack.routines());
+    }
+    self.profiler = profiler;
+    result?;
+    // Watchpoints compare with what things were just now, not back then.
+    let writes = self.memory.borrow_mut().take_writes();
+    let mut watches = mem::replace(&mut self.watches, Watchpoints::new());
+    let result = watches.check(self, &writes);
+    self.watches = watches;
+    result.map(|_| ())
+  }
+
+  // Run the game until the timeline gets to `instruction`.
+  fn replay(&mut self, instruction: u64) -> Result<()> {
+    while self.timeline.as_ref().is_some_and(|timeline| timeline.now() < instruction) {
+      self.take_snapshot();
+      let result = self.process_opcode();
+      self.instruction_ran();
+      match result {
+        Err(Error::Restart) => self.restart(),
+        Err(err) => return Err(err),
+        Ok(()) => {}
+      }
+    }
     Ok(())
   }
 
+  // Copy the machine, if the timeline wants a copy before the next
+  // instruction. This is also where replaying starts and stops.
+  fn take_snapshot(&mut self) {
+    let timeline = match self.timeline.as_mut() {
+      Some(timeline) => timeline,
+      None => return,
+    };
+    self.output.set_replaying(timeline.replaying());
+    if timeline.wants_snapshot() {
+      timeline.add_snapshot(usize::from(self.pc.pc()),
+                            self.memory.borrow().dynamic_slice().to_vec(),
+                            self.stack.clone(),
+                            self.rng);
+    }
+  }
+
+  fn instruction_ran(&mut self) {
+    if let Some(timeline) = self.timeline.as_mut() {
+      timeline.ran();
+    }
+  }
+
+  // The input the current instruction had before, if the game is going over
+  // it again.
+  fn replay_line(&mut self) -> Result<Option<(String, u16)>> {
+    self.timeline.as_mut().map_or(Ok(None), |timeline| timeline.replay_line())
+  }
+
+  fn replay_key(&mut self) -> Result<Option<u16>> {
+    self.timeline.as_mut().map_or(Ok(None), |timeline| timeline.replay_key())
+  }
+
+  fn replay_seed(&mut self) -> Result<Option<usize>> {
+    self.timeline.as_mut().map_or(Ok(None), |timeline| timeline.replay_seed())
+  }
+
+  fn record_input(&mut self, input: Input) {
+    let pc = self.instruction_pc;
+    if let Some(timeline) = self.timeline.as_mut() {
+      timeline.record(pc, input);
+    }
+  }
+
+  /// Watch for writes to a global, memory or an object, as written for
+  /// Watch::parse().
+  pub fn add_watch(&mut self, spec: &str) -> Result<usize> {
+    let watch = Watch::parse(spec, &self.names)
+      .map_err(|why| Error::BadWatchpoint(spec.to_string(), why))?;
+    let mut watches = mem::replace(&mut self.watches, Watchpoints::new());
+    let result = watches.add(self, watch);
+    self.watches = watches;
+    self.memory.borrow_mut().record_writes();
+    result.map_err(|err| match err {
+      Error::BadWatchpoint(..) => err,
+      err => Error::BadWatchpoint(spec.to_string(), err.to_string()),
+    })
+  }
+
+  // See whether the last instruction changed anything being watched. The
+  // debugger stops for it. Otherwise, it goes in the log.
+  fn check_watches(&mut self) -> Result<()> {
+    let writes = self.memory.borrow_mut().take_writes();
+    if self.watches.is_empty() {
+      return Ok(());
+    }
+    let mut watches = mem::replace(&mut self.watches, Watchpoints::new());
+    let hits = watches.check(self, &writes);
+    self.watches = watches;
+    let hits = hits?;
+    if hits.is_empty() {
+      return Ok(());
+    }
+
+    let frames = self.stack.frames().collect::<Vec<_>>();
+    let mut report = Vec::new();
+    for hit in &hits {
+      hit.write(self.instruction_pc, &frames, &self.names, &mut report)?;
+    }
+    match (self.debugger.as_mut(), self.watch_log.as_ref()) {
+      (Some(debugger), _) => debugger.report(String::from_utf8_lossy(&report).into_owned()),
+      (None, Some(path)) => {
+        OpenOptions::new().create(true).append(true).open(path)?.write_all(&report)?
+      }
+      (None, None) => io::stderr().write_all(&report)?,
+    }
+    Ok(())
+  }
+
+  /// Only trace the instructions in an address range, written as for
+  /// TraceFilter::parse_range(). Ranges and routines add up.
+  pub fn add_trace_range(&mut self, spec: &str) -> Result<()> {
+    let filter = TraceFilter::parse_range(spec)
+      .map_err(|why| Error::BadTraceFilter(spec.to_string(), why))?;
+    if let Some(tracer) = self.tracer.as_mut() {
+      tracer.add_filter(filter);
+    }
+    Ok(())
+  }
+
+  /// Only trace the instructions of a routine, given by address or by name.
+  pub fn add_trace_routine(&mut self, spec: &str) -> Result<()> {
+    let filter = TraceFilter::parse_routine(spec, &self.names)
+      .map_err(|why| Error::BadTraceFilter(spec.to_string(), why))?;
+    if let Some(tracer) = self.tracer.as_mut() {
+      tracer.add_filter(filter);
+    }
+    Ok(())
+  }
+
+  // Run the next instruction, tracing it and recording its coverage if either
+  // of those is wanted. (process_opcode() counts it for the profile.)
+  fn run_instruction(&mut self) -> Result<()> {
+    self.take_snapshot();
+    let result = if self.tracer.is_none() && self.coverage.is_none() {
+      self.process_opcode()
+    } else {
+      let mut tracer = self.tracer.take();
+      let result = self.recorded_instruction(tracer.as_mut());
+      self.tracer = tracer;
+      result
+    };
+    self.instruction_ran();
+    result
+  }
+
+  // Count the instruction that starts with `first_byte` for the profile.
+  fn profile_opcode(&mut self, first_byte: u8) {
+    let version = self.version();
+    let next_byte = self.memory.borrow().read_u8(self.pc.pc()).unwrap_or(0);
+    let (count, opcode) = decoder::opcode_number(version, first_byte, next_byte);
+    // One that doesn't exist stops the game, but it still ran (or tried to).
+    let name = decoder::opcode_info(version, count, opcode)
+      .map_or("(bad instruction)", |info| info.name);
+    if let Some(profiler) = self.profiler.as_mut() {
+      profiler.count(name);
+    }
+  }
+
+  // Everything is read without changing anything (the stack isn't popped), so
+  // the instruction runs just as it would without the trace.
+  fn recorded_instruction(&mut self,
+                          tracer: Option<&mut Tracer<BufWriter<File>>>)
+                          -> Result<()> {
+    let pc = usize::from(self.pc.pc());
+    let routine = self.stack.routine();
+    let tracer = tracer.and_then(|tracer| if tracer.wants(pc, routine) {
+      Some(tracer)
+    } else {
+      None
+    });
+    if tracer.is_none() && self.coverage.is_none() {
+      return self.process_opcode();
+    }
+    let instruction = match decoder::decode(self, pc) {
+      Ok(instruction) => instruction,
+      // process_opcode() will report it.
+      Err(_) => return self.process_opcode(),
+    };
+    let operands = if tracer.is_some() {
+      self.peek_operands(&instruction.operands)
+    } else {
+      Vec::new()
+    };
+    let depth = self.stack.depth();
+
+    self.branch_taken = None;
+    let result = self.process_opcode();
+
+    let finished = result.is_ok();
+    let called = finished && self.stack.depth() > depth;
+    let branch_taken = if finished { self.branch_taken } else { None };
+    if let Some(coverage) = self.coverage.as_mut() {
+      coverage.ran(pc, branch_taken);
+      if called {
+        coverage.entered(self.stack.routine());
+      }
+    }
+
+    if let Some(tracer) = tracer {
+      let mut record = Record {
+        routine,
+        operands,
+        stored: None,
+        branch_taken,
+        instruction,
+      };
+      // A call stores its result when the routine returns.
+      if let Some(variable) = record.instruction.store {
+        if finished && !called {
+          record.stored = self.peek_variable(variable, 0);
+        }
+      }
+      tracer.write(&record, &self.names)?;
+    }
+    result
+  }
+
+  // The values of an instruction's operands. Each sp is the next value down
+  // the stack, as if they had been popped.
+  fn peek_operands(&self, operands: &[Operand]) -> Vec<Option<u16>> {
+    let mut popped = 0;
+    operands.iter()
+      .filter_map(|operand| match *operand {
+        Operand::LargeConstant(value) => Some(Some(value)),
+        Operand::SmallConstant(value) => Some(Some(u16::from(value))),
+        Operand::Variable(VariableRef::Stack) => {
+          popped += 1;
+          Some(self.peek_variable(VariableRef::Stack, popped - 1))
+        }
+        Operand::Variable(variable) => Some(self.peek_variable(variable, 0)),
+        Operand::Omitted => None,
+      })
+      .collect()
+  }
+
+  // A variable's value, or None if there is no such variable. For sp, `depth`
+  // is how far down the stack to look.
+  fn peek_variable(&self, variable: VariableRef, depth: usize) -> Option<u16> {
+    match variable {
+      VariableRef::Stack => self.stack.peek_u16(depth),
+      VariableRef::Local(idx) => self.stack.read_local(idx).ok(),
+      VariableRef::Global(idx) => self.read_global(idx).ok(),
+    }
+  }
+
+  /// Write the routines on the call stack, from the one running the current
+  /// instruction out to the main routine.
+  pub fn write_backtrace<W>(&self, out: &mut W) -> Result<()>
+    where W: Write {
+    let frames = self.stack.frames().collect::<Vec<_>>();
+    writeln!(out, "Call stack:")?;
+    debugger::write_backtrace(self.instruction_pc, &frames, &self.names, out)
+  }
+
   fn process_opcode(&mut self) -> Result<()> {
-    info!(target: "pctrace", "PC: {}", usize::from(self.pc.pc()));
+    let start_pc = usize::from(self.pc.pc());
+    info!(target: "pctrace", "PC: {}", start_pc);
+    self.instruction_pc = start_pc;
 
-    let first_byte = self.read_pc_byte();
+    let first_byte = self.read_pc_byte()?;
+    if self.profiler.is_some() {
+      self.profile_opcode(first_byte);
+    }
     let top_two_bits = first_byte & 0b11000000;
 
-    match top_two_bits {
+    let result = match top_two_bits {
       0b11000000 => self.process_variable_opcode(first_byte),
       0b10000000 => self.process_short_opcode(first_byte),
       _ => self.process_long_opcode(first_byte),
-    }
+    };
+    result.map_err(|err| in_instruction(err, start_pc, first_byte))
   }
 
   fn process_variable_opcode(&mut self, first_byte: u8) -> Result<()> {
@@ -150,10 +678,10 @@ impl ZMachine {
     let start_pc = start_pc - 1usize;
     //    println!("var opcode number: {:x} @{:x}", opcode_number, start_pc);
     if (first_byte & 0b00100000) == 0 {
-      let operands = self.read_var_operands();
+      let operands = decoder::var_operands(self)?;
       self.dispatch_2op(start_pc, opcode_number, operands)
     } else {
-      let operands = self.read_var_operands();
+      let operands = decoder::var_operands(self)?;
       match opcode_number {
         0x00 => ops::varops::call_0x00(self, operands),
         0x01 => ops::varops::storew_0x01(self, operands),
@@ -163,45 +691,38 @@ impl ZMachine {
         0x05 => ops::varops::print_char_0x05(self, operands),
         0x06 => ops::varops::print_num_0x06(self, operands),
         0x07 => {
-          let encoded = self.read_pc_byte();
+          let encoded = self.read_pc_byte()?;
           let variable = VariableRef::decode(encoded);
           ops::varops::random_0x07(self, operands, variable)
         }
         0x08 => ops::varops::push_0x08(self, operands),
         0x09 => ops::varops::pull_0x09(self, operands),
+        0x0a => ops::varops::split_window_0x0a(self, operands),
+        0x0b => ops::varops::set_window_0x0b(self, operands),
+        0x0d => ops::varops::erase_window_0x0d(self, operands),
+        0x0e => ops::varops::erase_line_0x0e(self, operands),
+        0x0f => ops::varops::set_cursor_0x0f(self, operands),
+        0x10 => ops::varops::get_cursor_0x10(self, operands),
+        0x11 => ops::varops::set_text_style_0x11(self, operands),
+        0x12 => ops::varops::buffer_mode_0x12(self, operands),
+        0x13 => ops::varops::output_stream_0x13(self, operands),
+        0x16 => {
+          let encoded = self.read_pc_byte()?;
+          let variable = VariableRef::decode(encoded);
+          ops::varops::read_char_0x16(self, operands, variable)
+        }
         _ => Err(Error::UnknownOpcode("VAR", opcode_number, start_pc)),
       }
     }
   }
 
-  fn read_var_operands(&mut self) -> [Operand; 4] {
-    let operand_types = self.read_pc_byte();
-    let operand1 = self.read_operand_of_type((operand_types & 0b11000000) >> 6);
-    let operand2 = self.read_operand_of_type((operand_types & 0b00110000) >> 4);
-    let operand3 = self.read_operand_of_type((operand_types & 0b00001100) >> 2);
-    let operand4 = self.read_operand_of_type((operand_types & 0b00000011) >> 0);
-    [operand1, operand2, operand3, operand4]
-  }
-
-  fn read_operand_of_type(&mut self, operand_type: u8) -> Operand {
-    let operand = match operand_type {
-      0b00 => Operand::LargeConstant(self.read_pc_word()),
-      0b01 => Operand::SmallConstant(self.read_pc_byte()),
-      0b10 => Operand::Variable(VariableRef::decode(self.read_pc_byte())),
-      0b11 => Operand::Omitted,
-      _ => panic!("Unknown operand type: {:?}", operand_type),
-    };
-    operand
-  }
-
   fn process_short_opcode(&mut self, first_byte: u8) -> Result<()> {
     let op = first_byte & 0b00001111;
     // TODO: figure out how to write this better.
     let start_pc: usize = self.pc.pc().into();
     let start_pc = start_pc - 1usize;
     //    println!("short opcode number: {:x} @{:x}", op, start_pc);
-    let operand_type = (first_byte & 0b00110000) >> 4;
-    let operand = self.read_operand_of_type(operand_type);
+    let operand = decoder::short_operand(self, first_byte)?;
     match operand {
       Operand::Omitted => self.process_0op(start_pc, op),
       _ => self.process_1op(start_pc, op, operand),
@@ -221,9 +742,18 @@ impl ZMachine {
       0x09 => ops::zeroops::pop_0x09(self),
       0x0a => ops::zeroops::quit_0x0a(self),
       0x0b => ops::zeroops::new_line_0x0b(self),
-      _ => {
-        panic!("Unknown short 0op opcode: {:x} @{:x}", op, start_pc);
-      }
+      0x0c => ops::zeroops::show_status_0x0c(self),
+      0x0e if self.version() >= 5 => self.process_extended_opcode(start_pc),
+      _ => Err(Error::UnknownOpcode("0OP", op, start_pc)),
+    }
+  }
+
+  fn process_extended_opcode(&mut self, start_pc: usize) -> Result<()> {
+    let opcode_number = self.read_pc_byte()?;
+    let operands = decoder::var_operands(self)?;
+    match opcode_number {
+      0x0d => ops::extops::set_true_colour_0x0d(self, operands),
+      _ => Err(Error::UnknownOpcode("EXT", opcode_number, start_pc)),
     }
   }
 
@@ -231,7 +761,7 @@ impl ZMachine {
                              operand: Operand,
                              op_func: &Fn(&mut Self, Operand, VariableRef) -> Result<()>)
                              -> Result<()> {
-    let encoded = self.read_pc_byte();
+    let encoded = self.read_pc_byte()?;
     let variable = VariableRef::decode(encoded);
     op_func(self, operand, variable)
   }
@@ -252,9 +782,7 @@ impl ZMachine {
       0x0c => ops::oneops::jump_0x0c(self, operand),
       0x0d => ops::oneops::print_paddr_0x0d(self, operand),
       0x0e => self.process_1op_with_return(operand, &ops::oneops::load_0x0e),
-      _ => {
-        panic!("Unknown short 1op opcode: {:#x} @{:#x}", op, start_pc);
-      }
+      _ => Err(Error::UnknownOpcode("1OP", op, start_pc)),
     }
   }
 
@@ -263,17 +791,7 @@ impl ZMachine {
     let start_pc = start_pc - 1;
     let opcode_number = first_byte & 0b00011111;
     //    println!("long opcode number: {:x} @{:x}", opcode_number, start_pc);
-    let first = self.read_operand_of_type(if first_byte & 0b01000000 == 0 {
-      0b01
-    } else {
-      0b10
-    });
-    let second = self.read_operand_of_type(if first_byte & 0b00100000 == 0 {
-      0b01
-    } else {
-      0b10
-    });
-    let operands = [first, second, Operand::Omitted, Operand::Omitted];
+    let operands = decoder::long_operands(self, first_byte)?;
     self.dispatch_2op(start_pc, opcode_number, operands)
   }
 
@@ -291,7 +809,7 @@ impl ZMachine {
                               operands: [Operand; 4],
                               op_func: &Fn(&mut Self, Operand, Operand, VariableRef) -> Result<()>)
                               -> Result<()> {
-    let encoded = self.read_pc_byte();
+    let encoded = self.read_pc_byte()?;
     let variable = VariableRef::decode(encoded);
     // TODO: add checking that there are no extra operands.
     op_func(self, operands[0], operands[1], variable)
@@ -323,21 +841,58 @@ impl ZMachine {
       0x16 => self.dispatch_2op_with_return(operands, &ops::twoops::mul_0x16),
       0x17 => self.dispatch_2op_with_return(operands, &ops::twoops::div_0x17),
       0x18 => self.dispatch_2op_with_return(operands, &ops::twoops::mod_0x18),
+      0x1b => self.dispatch_basic_2op(operands, &ops::twoops::set_colour_0x1b),
 
-      _ => panic!("Unknown long opcode: {:#x} @{:#x}", opcode, start_pc),
+      _ => Err(Error::UnknownOpcode("2OP", opcode, start_pc)),
     }
   }
 }
 
+// Say where an error happened, unless it already does (or isn't really an
+// error).
+fn in_instruction(err: Error, pc: usize, first_byte: u8) -> Error {
+  match err {
+    Error::Quitting |
+    Error::Restart |
+    Error::UnknownOpcode(..) |
+    Error::Instruction(..) => err,
+    _ => Error::Instruction(pc, first_byte, Box::new(err)),
+  }
+}
+
+// Tell the game how big the screen is. Before the screen is set up, we
+// don't know, so the header is left alone.
+fn update_screen_size(memory: &mut Memory, screen: &Screen) {
+  let (rows, cols) = screen.size();
+  if rows > 0 && cols > 0 {
+    memory.set_screen_size(rows, cols);
+  }
+}
+
+// The interpreter decodes instructions as it runs them, from the PC.
+impl InstructionBytes for ZMachine {
+  fn next_byte(&mut self) -> Result<u8> {
+    self.read_pc_byte()
+  }
+
+  fn next_word(&mut self) -> Result<u16> {
+    self.read_pc_word()
+  }
+}
+
 impl VM for ZMachine {
   type ObjTable = MemoryMappedObjectTable;
   type PropertyTable = MemoryMappedPropertyTable;
 
-  fn read_pc_byte(&mut self) -> u8 {
+  fn version(&self) -> u8 {
+    self.memory.borrow().version()
+  }
+
+  fn read_pc_byte(&mut self) -> Result<u8> {
     self.pc.next_byte()
   }
 
-  fn read_pc_word(&mut self) -> u16 {
+  fn read_pc_word(&mut self) -> Result<u16> {
     self.pc.next_word()
   }
 
@@ -358,66 +913,80 @@ impl VM for ZMachine {
     Ok(())
   }
 
+  fn set_branch_taken(&mut self, taken: bool) {
+    self.branch_taken = Some(taken);
+  }
+
   fn new_frame(&mut self,
                ret_pc: usize,
+               routine: usize,
                num_locals: u8,
                result_location: VariableRef)
                -> Result<()> {
-    self.stack.new_frame(ret_pc, num_locals, result_location);
+    self.stack.new_frame(ret_pc, routine, num_locals, result_location)?;
+    if let Some(profiler) = self.profiler.as_mut() {
+      profiler.called(routine);
+    }
     Ok(())
   }
 
   fn pop_frame(&mut self) -> Result<(usize, VariableRef)> {
-    Ok(self.stack.pop_frame())
+    let popped = self.stack.pop_frame()?;
+    if let Some(profiler) = self.profiler.as_mut() {
+      profiler.returned();
+    }
+    Ok(popped)
   }
 
   fn pop_stack(&mut self) -> Result<u16> {
-    Ok(self.stack.pop_u16())
+    self.stack.pop_u16()
   }
 
   fn push_stack(&mut self, val: u16) -> Result<()> {
-    self.stack.push_u16(val);
-    Ok(())
+    self.stack.push_u16(val)
   }
 
   fn read_local(&self, local_idx: u8) -> Result<u16> {
-    Ok(self.stack.read_local(local_idx))
+    self.stack.read_local(local_idx)
   }
 
   fn write_local(&mut self, local_idx: u8, val: u16) -> Result<()> {
-    self.stack.write_local(local_idx, val);
-    Ok(())
+    self.stack.write_local(local_idx, val)
   }
 
   fn read_global(&self, global_idx: u8) -> Result<u16> {
-    Ok(self.memory.borrow().read_global(global_idx))
+    self.memory.borrow().read_global(global_idx)
   }
 
   fn write_global(&mut self, global_idx: u8, val: u16) -> Result<()> {
-    self.memory.borrow_mut().write_global(global_idx, val);
-    Ok(())
+    self.memory.borrow_mut().write_global(global_idx, val)
+  }
+
+  fn global_addr(&self, global_idx: u8) -> Result<usize> {
+    let ptr = self.memory.borrow().global_ptr(global_idx)?;
+    Ok(usize::from(RawPtr::from(ptr)))
   }
 
   fn read_memory<T>(&self, ptr: T) -> Result<u16>
     where T: Into<RawPtr> {
-    Ok(self.memory.borrow().u16_at(ptr))
+    self.memory.borrow().read_u16(ptr)
   }
 
   fn write_memory<T>(&mut self, ptr: T, val: u16) -> Result<()>
     where T: Into<RawPtr> {
-    self.memory.borrow_mut().set_u16_at(val, ptr);
-    Ok(())
+    let result = self.memory.borrow_mut().write_u16(val, ptr);
+    result.or_else(|err| self.report_error(err))
   }
 
   fn read_memory_u8<T>(&self, ptr: T) -> Result<u8>
     where T: Into<RawPtr> {
-    Ok(self.memory.borrow().u8_at(ptr))
+    self.memory.borrow().read_u8(ptr)
   }
 
   fn write_memory_u8<T>(&mut self, ptr: T, val: u8) -> Result<()>
     where T: Into<RawPtr> {
-    self.memory.borrow_mut().set_u8_at(val, ptr);
-    Ok(())
+    let result = self.memory.borrow_mut().write_u8(val, ptr);
+    result.or_else(|err| self.report_error(err))
   }
 
   fn object_table(&self) -> Result<MemoryMappedObjectTable> {
@@ -425,6 +994,10 @@ impl VM for ZMachine {
     Ok(MemoryMappedObjectTable::new(ptr, self.memory.clone()))
   }
 
+  fn terminating_chars(&self) -> Result<Vec<u16>> {
+    self.memory.borrow().terminating_chars()
+  }
+
   fn num_dict_entries(&self) -> u16 {
     self.dict.num_entries()
   }
@@ -433,63 +1006,201 @@ impl VM for ZMachine {
     self.dict.entry_ptr(number)
   }
 
-  fn rand(&mut self, range: u16) -> u16 {
+  fn rand(&mut self, range: u16) -> Result<u16> {
     let range = range as i16;
     if range < 0 {
       // Seed with -range.
       let new_seed = [-range as usize];
       self.rng.reseed(&new_seed);
-      0
+      Ok(0)
     } else if range == 0 {
-      // Seed as randomly as possible.
-      self.rng = StdRng::new().unwrap();
-      0
+      // Seed as randomly as possible, but the same way again if the debugger
+      // goes back in time.
+      if self.timeline.is_some() {
+        let seed = match self.replay_seed()? {
+          Some(seed) => seed,
+          None => {
+            let seed = rand::random::<usize>();
+            self.record_input(Input::Seed(seed));
+            seed
+          }
+        };
+        self.rng.reseed(&[seed]);
+      } else {
+        self.rng = StdRng::new().unwrap();
+      }
+      Ok(0)
     } else {
       // Return a random number in 1..range.
-      (self.rng.next_u32() as u16) % range as u16 + 1
+      Ok((self.rng.next_u32() as u16) % range as u16 + 1)
     }
   }
 
-  fn write_status_line(&self, str: &str) {
-    self.status_window.map(|w| {
-      self.main_window.map(|mw| {
-        let mut x = 0i32;
-        let mut y = 0i32;
-        getyx(mw, &mut y, &mut x);
+  fn report_error(&mut self, err: Error) -> Result<()> {
+    let err = self.errors.check(err)?;
+    // It was reported the first time round.
+    if self.output.replaying() {
+      return Ok(());
+    }
+    if let Some(err) = err {
+      warn!("{} (PC = {:#x})", err, self.instruction_pc);
+      let once = if self.errors.mode() == ErrorMode::WarnOnce {
+        " (will ignore further occurrences)"
+      } else {
+        ""
+      };
+      let message = format!("\n[Warning: {} (PC = {:#x}){}]\n",
+                            err,
+                            self.instruction_pc,
+                            once);
+      self.output.screen_mut().print_message(&message);
+    }
+    Ok(())
+  }
 
-        mvwprintw(w, 0, 0, str);
-        wmove(mw, y, x);
-        wrefresh(w);
-        wrefresh(mw);
-      })
-    });
+  fn write_status_line(&mut self, str: &str) -> Result<()> {
+    if self.output.replaying() {
+      return Ok(());
+    }
+    self.output.screen_mut().write_status_line(str);
+    Ok(())
   }
 
-  fn write_main_window_char(&self, ch: u16) {
-    self.main_window.map(|w| {
-      waddch(w, ch as u32);
-      wrefresh(w);
-    });
+  fn write_main_window_char(&mut self, ch: u16) -> Result<()> {
+    let mut buf = [0u8; 4];
+    let text = ::std::char::from_u32(ch as u32).unwrap_or('?').encode_utf8(&mut buf);
+    self.write_main_window(text)
   }
 
-  fn write_main_window(&self, str: &str) {
-    self.main_window.map(|w| {
-      wprintw(w, str);
-      wrefresh(w);
-    });
+  fn write_main_window(&mut self, str: &str) -> Result<()> {
+    let flag2 = self.memory.borrow().flag2();
+    self.output.print(str, flag2)
   }
 
-  fn read_line(&self) -> Result<String> {
-    self.main_window
-      .map(|w| {
-        let rl = ZReadline::new(w);
-        Ok(rl.readline())
-      })
-      .unwrap()
+  fn set_text_style(&mut self, style: u16) -> Result<()> {
+    self.output.set_text_style(style);
+    Ok(())
+  }
+
+  fn set_buffer_mode(&mut self, buffered: bool) -> Result<()> {
+    self.output.screen_mut().set_buffer_mode(buffered);
+    Ok(())
+  }
+
+  fn split_window(&mut self, lines: u16) -> Result<()> {
+    self.output.screen_mut().split_window(lines);
+    Ok(())
+  }
+
+  fn set_window(&mut self, window: u16) -> Result<()> {
+    match Window::from_number(window) {
+      Some(window) => self.output.screen_mut().set_window(window),
+      None => warn!("Cannot select window {}.", window),
+    }
+    Ok(())
+  }
+
+  fn erase_window(&mut self, window: i16) -> Result<()> {
+    self.output.screen_mut().erase_window(window);
+    Ok(())
+  }
+
+  fn erase_line(&mut self) -> Result<()> {
+    self.output.screen_mut().erase_line();
+    Ok(())
+  }
+
+  fn set_cursor(&mut self, line: u16, column: u16) -> Result<()> {
+    self.output.screen_mut().set_cursor(line, column);
+    Ok(())
+  }
+
+  fn cursor(&self) -> Result<(u16, u16)> {
+    Ok(self.output.screen().cursor())
+  }
+
+  fn set_colour(&mut self, foreground: u16, background: u16) -> Result<()> {
+    self.output.set_colour(foreground, background);
+    Ok(())
+  }
+
+  fn select_output_stream(&mut self, stream: i16, table: u16) -> Result<()> {
+    match stream {
+      1 | -1 => self.output.set_screen_enabled(stream > 0),
+      2 | -2 => {
+        // The transcript bit lives in the header, where the game can also flip it.
+        let flag2 = self.memory.borrow().flag2();
+        let flag2 = if stream > 0 {
+          flag2 | FLAG2_TRANSCRIPT
+        } else {
+          flag2 & !FLAG2_TRANSCRIPT
+        };
+        self.memory.borrow_mut().set_flag2(flag2);
+      }
+      3 => self.output.open_memory_stream(table)?,
+      -3 => {
+        // The table gets the length, then the text.
+        if let Some((table, zscii)) = self.output.close_memory_stream() {
+          let mut memory = self.memory.borrow_mut();
+          memory.write_u16(zscii.len() as u16, BytePtr::new(table))?;
+          for (idx, code) in zscii.iter().enumerate() {
+            memory.write_u8(*code, RawPtr::new(table as usize + 2 + idx))?;
+          }
+        }
+      }
+      _ => warn!("Output stream {} is not supported.", stream),
+    }
+    Ok(())
+  }
+
+  fn read_line(&mut self, max_len: usize, terminators: &[u16]) -> Result<(String, u16)> {
+    if let Some(line) = self.replay_line()? {
+      return Ok(line);
+    }
+    let (text, terminator) = {
+      let memory = &self.memory;
+      let screen = self.output.screen_mut();
+      if screen.prepare_for_input() {
+        update_screen_size(&mut memory.borrow_mut(), screen);
+      }
+      let window = screen.main_window().ok_or(Error::NoScreen)?;
+      let mut on_resize = || {
+        screen.resize();
+        update_screen_size(&mut memory.borrow_mut(), screen);
+      };
+      let rl = ZReadline::new(window, &mut self.history, &mut on_resize);
+      rl.readline(max_len, terminators)?
+    };
+    self.record_input(Input::Line(text.clone(), terminator));
+    let flag2 = self.memory.borrow().flag2();
+    self.output.echo_input(&text, terminator, flag2)?;
+    Ok((text, terminator))
+  }
+
+  fn read_char(&mut self) -> Result<u16> {
+    if let Some(key) = self.replay_key()? {
+      return Ok(key);
+    }
+    let key = {
+      let memory = &self.memory;
+      let screen = self.output.screen_mut();
+      if screen.prepare_for_input() {
+        update_screen_size(&mut memory.borrow_mut(), screen);
+      }
+      let window = screen.main_window().ok_or(Error::NoScreen)?;
+      let mut on_resize = || {
+        screen.resize();
+        update_screen_size(&mut memory.borrow_mut(), screen);
+      };
+      let rl = ZReadline::new(window, &mut self.history, &mut on_resize);
+      rl.read_key()?
+    };
+    self.record_input(Input::Key(key));
+    Ok(key)
   }
 
   fn screen_width(&self) -> u16 {
-    self.num_cols as u16
+    self.output.screen().width()
   }
 
   fn save(&self) -> Result<()> {
@@ -504,7 +1215,117 @@ impl VM for ZMachine {
     let abbrev_table_ptr = self.memory.borrow().abbrev_table_ptr();
     let abbrev_entry_ptr =
       abbrev_table_ptr.inc_by((32 * (abbrev_table as u16 - 1) + abbrev_index as u16) * 2);
-    let abbrev_addr = self.memory.borrow().u16_at(abbrev_entry_ptr);
+    let abbrev_addr = self.memory.borrow().read_u16(abbrev_entry_ptr)?;
     Ok(WordPtr::new(abbrev_addr))
   }
 }
+
+#[cfg(test)]
+mod test {
+  use byteorder::{BigEndian, ByteOrder};
+  use result::{Error, Result};
+  use super::ZMachine;
+  use zmachine::vm::VM;
+  use zmachine::vm::object_table::{ZObject, ZObjectTable};
+  use zmachine::zconfig::ZDefaults;
+
+  // A story with a 4 byte dictionary at 0x40, an object table at 0x44 with
+  // one object, and `code` at 0x90, where dynamic memory ends.
+  fn story(version: u8, code: &[u8]) -> Vec<u8> {
+    let mut bytes = vec![0; 0x90];
+    bytes[0x00] = version;
+    bytes[0x07] = 0x90;
+    bytes[0x09] = 0x40;
+    bytes[0x0b] = 0x44;
+    bytes[0x0d] = 0x40;
+    bytes[0x0f] = 0x90;
+    bytes[0x41] = 7;
+    // Object 1's (empty) property table is straight after it.
+    bytes[0x44 + 62 + 8] = 0x8b;
+    bytes.extend_from_slice(code);
+    bytes
+  }
+
+  fn run_one(bytes: Vec<u8>) -> Result<()> {
+    let mut zmachine = ZMachine::from_reader(&bytes[..], &ZDefaults::new())?;
+    zmachine.process_opcode()
+  }
+
+  // The address that was out of range, if that's what went wrong.
+  fn out_of_range<T>(result: Result<T>) -> Option<usize> {
+    match result {
+      Err(Error::AddressOutOfRange(addr)) => Some(addr),
+      Err(Error::Instruction(_, _, err)) => out_of_range::<()>(Err(*err)),
+      _ => None,
+    }
+  }
+
+  #[test]
+  fn test_truncated_story() {
+    // A story that is all there runs.
+    let bytes = story(3, &[0x93, 0x01, 0x00]);
+    assert!(run_one(bytes.clone()).is_ok());
+
+    // add 1 2 -> (missing store byte)
+    let truncated = story(3, &[0x14, 0x01, 0x02]);
+    assert_eq!(Some(0x93), out_of_range(run_one(truncated)));
+
+    // get_parent 1 -> sp, where object 1's parent is past the end.
+    let mut truncated = bytes.clone();
+    BigEndian::write_u16(&mut truncated[0x0a..], 0x93 - 62 - 4);
+    assert_eq!(Some(0x93), out_of_range(run_one(truncated)));
+
+    // The dictionary is read when the story is loaded.
+    let mut truncated = bytes.clone();
+    BigEndian::write_u16(&mut truncated[0x08..], 0x93);
+    assert_eq!(Some(0x93), out_of_range(run_one(truncated)));
+
+    // print, with abbreviation 5 from a table that's cut short.
+    let mut truncated = story(3, &[0xb2, 0x84, 0xa5]);
+    BigEndian::write_u16(&mut truncated[0x18..], 0x93 - 10);
+    assert_eq!(Some(0x93), out_of_range(run_one(truncated)));
+
+    // read (v5), with a terminating characters table past the end.
+    let mut truncated = story(5, &[0xe4, 0x5f, 0x50, 0x00, 0x00]);
+    BigEndian::write_u16(&mut truncated[0x2e..], 0x95);
+    assert_eq!(Some(0x95), out_of_range(run_one(truncated)));
+  }
+
+  // The error underneath the instruction that went wrong.
+  fn cause(result: Result<()>) -> Option<Error> {
+    match result {
+      Err(Error::Instruction(_, _, err)) => Some(*err),
+      Err(err) => Some(err),
+      Ok(()) => None,
+    }
+  }
+
+  #[test]
+  fn test_bad_stack() {
+    // pop, with nothing on the stack.
+    assert!(matches!(cause(run_one(story(3, &[0xb9]))), Some(Error::StackUnderflow)));
+    // rtrue, from the main routine.
+    assert!(matches!(cause(run_one(story(3, &[0xb0]))), Some(Error::StackUnderflow)));
+    // inc L00, in the main routine, which has no locals.
+    assert!(matches!(cause(run_one(story(3, &[0x95, 0x01]))), Some(Error::BadLocal(0))));
+
+    // push 1, until the stack is full.
+    let bytes = story(5, &[0xe8, 0x7f, 0x01, 0x8c, 0xff, 0xfc]);
+    let mut zmachine = ZMachine::from_reader(&bytes[..], &ZDefaults::new()).unwrap();
+    let result = (0..0x10000).map(|_| zmachine.process_opcode()).find(|result| result.is_err());
+    assert!(matches!(result.map(cause), Some(Some(Error::StackOverflow))));
+  }
+
+  #[test]
+  fn test_truncated_objects() {
+    let mut bytes = story(3, &[]);
+    BigEndian::write_u16(&mut bytes[0x0a..], 0x90 - 62 - 4);
+    let zmachine = ZMachine::from_reader(&bytes[..], &ZDefaults::new()).unwrap();
+    let object = zmachine.object_table().unwrap().object_with_number(1);
+    assert!(object.attributes().is_ok());
+    assert_eq!(Some(0x90), out_of_range(object.parent()));
+    assert_eq!(Some(0x93), out_of_range(object.property_table().map(|_| ())));
+    // Writes past the end are outside dynamic memory too.
+    assert!(matches!(object.set_child(2), Err(Error::ReadOnlyAddress(0x92))));
+  }
+}
@@ -0,0 +1,407 @@
+use result::{Error, Result};
+use std::fmt;
+use std::io::Write;
+use zmachine::debug_info::DebugInfo;
+use zmachine::debugger::write_backtrace;
+use zmachine::disasm;
+use zmachine::vm::{Frame, RawPtr, VM, ZObject, ZObjectTable, attribute_mask, num_attributes};
+
+// Watchpoints. Each one remembers the value of what it watches, and after each
+// instruction the values are read again to see what changed. Memory keeps a
+// list of the game's writes too, so that writing the value that is already
+// there is noticed as well.
+
+/// What to watch.
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum Watch {
+  Global(u8),
+  // A number of bytes, starting from an address.
+  Memory(usize, usize),
+  Attribute(u16, u8),
+  Parent(u16),
+}
+
+impl Watch {
+  /// Read a watch from how it is written: G10 for a global, 4f05 or 4f05+4
+  /// for bytes of memory, attr:12:3 for an object's attribute and parent:12
+  /// for an object's parent. Addresses and globals are in hex, as they are in
+  /// listings. Globals, objects and attributes can also be given by the names
+  /// in `names`.
+  pub fn parse(spec: &str, names: &DebugInfo) -> ::std::result::Result<Watch, String> {
+    let parts = spec.split(':').collect::<Vec<_>>();
+    match parts.as_slice() {
+      [global] if global.starts_with('G') || global.starts_with('g') => {
+        disasm::parse_global(global, names).map(Watch::Global)
+      }
+      [memory] => {
+        if let Some(idx) = names.global_named(memory) {
+          return Ok(Watch::Global(idx));
+        }
+        let (addr, len) = match memory.find('+') {
+          Some(plus) => (&memory[..plus], &memory[plus + 1..]),
+          None => (*memory, "1"),
+        };
+        let addr = disasm::parse_addr(addr)?;
+        match len.parse::<usize>() {
+          Ok(len) if len > 0 => Ok(Watch::Memory(addr, len)),
+          _ => Err(format!("\"{}\" is not a number of bytes", len)),
+        }
+      }
+      ["attr", object, attr] => {
+        let object = parse_object(object, names)?;
+        let number = attr.parse::<u16>().ok().or_else(|| names.attribute_named(attr));
+        match number {
+          Some(number) if number < 48 => Ok(Watch::Attribute(object, number as u8)),
+          _ => Err(format!("\"{}\" is not an attribute (0-47)", attr)),
+        }
+      }
+      ["parent", object] => parse_object(object, names).map(Watch::Parent),
+      _ => {
+        Err(format!("\"{}\" is not a watchpoint (G10, 4f05, 4f05+4, attr:12:3 or parent:12)",
+                    spec))
+      }
+    }
+  }
+
+  /// How the watch is written, with names where there are any.
+  pub fn describe(&self, names: &DebugInfo) -> String {
+    match *self {
+      Watch::Global(idx) => names.global_name(idx).map(String::from).unwrap_or(self.to_string()),
+      Watch::Memory(..) => self.to_string(),
+      Watch::Attribute(object, attr) => {
+        format!("attr:{}:{}",
+                object_text(object, names),
+                names.attribute_name(u16::from(attr)).map(String::from).unwrap_or(attr.to_string()))
+      }
+      Watch::Parent(object) => format!("parent:{}", object_text(object, names)),
+    }
+  }
+
+  // Bytes of memory are one value each. Everything else is one value.
+  fn read<T>(&self, vm: &T) -> Result<Vec<u16>>
+    where T: VM {
+    match *self {
+      Watch::Global(idx) => Ok(vec![vm.read_global(idx)?]),
+      Watch::Memory(addr, len) => {
+        (addr..addr + len)
+          .map(|addr| vm.read_memory_u8(RawPtr::new(addr)).map(u16::from))
+          .collect()
+      }
+      Watch::Attribute(object, attr) => {
+        let attributes = vm.object_table()?.object_with_number(object).attributes()?;
+        Ok(vec![if attributes & attribute_mask(u16::from(attr)) != 0 { 1 } else { 0 }])
+      }
+      Watch::Parent(object) => Ok(vec![vm.object_table()?.object_with_number(object).parent()?]),
+    }
+  }
+
+  // Where each value is kept in memory, as (address, length).
+  fn spans<T>(&self, vm: &T) -> Vec<(usize, usize)>
+    where T: VM {
+    match *self {
+      Watch::Global(idx) => vm.global_addr(idx).map(|addr| vec![(addr, 2)]).unwrap_or_default(),
+      Watch::Memory(addr, len) => (addr..addr + len).map(|addr| (addr, 1)).collect(),
+      Watch::Attribute(object, attr) => {
+        vm.object_table()
+          .map(|table| vec![table.object_with_number(object).attribute_span(u16::from(attr))])
+          .unwrap_or_default()
+      }
+      Watch::Parent(object) => {
+        vm.object_table()
+          .map(|table| vec![table.object_with_number(object).parent_span()])
+          .unwrap_or_default()
+      }
+    }
+  }
+
+  fn object(&self) -> Option<u16> {
+    match *self {
+      Watch::Attribute(object, _) |
+      Watch::Parent(object) => Some(object),
+      _ => None,
+    }
+  }
+}
+
+fn parse_object(word: &str, names: &DebugInfo) -> ::std::result::Result<u16, String> {
+  match word.parse::<u16>().ok().or_else(|| names.object_named(word)) {
+    Some(object) if object > 0 => Ok(object),
+    _ => Err(format!("\"{}\" is not an object", word)),
+  }
+}
+
+fn object_text(object: u16, names: &DebugInfo) -> String {
+  names.object_name(object).map(String::from).unwrap_or(object.to_string())
+}
+
+impl fmt::Display for Watch {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      Watch::Global(idx) => write!(f, "G{:02x}", idx),
+      Watch::Memory(addr, 1) => write!(f, "{:04x}", addr),
+      Watch::Memory(addr, len) => write!(f, "{:04x}+{}", addr, len),
+      Watch::Attribute(object, attr) => write!(f, "attr:{}:{}", object, attr),
+      Watch::Parent(object) => write!(f, "parent:{}", object),
+    }
+  }
+}
+
+/// A watchpoint whose value changed.
+pub struct Hit {
+  pub number: usize,
+  pub watch: Watch,
+  // The index of each value that was written or changed, with its old and
+  // new values.
+  changes: Vec<(usize, u16, u16)>,
+}
+
+impl Hit {
+  /// Write what changed. `pc` is the instruction that changed it and `frames`
+  /// is the call stack, innermost first.
+  pub fn write<W>(&self, pc: usize, frames: &[Frame], names: &DebugInfo, out: &mut W) -> Result<()>
+    where W: Write {
+    let watch = self.watch.describe(names);
+    let what = if self.changes.iter().any(|&(_, old, new)| old != new) {
+      "changed"
+    } else {
+      "written"
+    };
+    writeln!(out, "Watchpoint {} ({}) {} at {:x}", self.number, watch, what, pc)?;
+    for &(idx, old, new) in &self.changes {
+      match self.watch {
+        Watch::Global(_) => writeln!(out, "  {}: {:04x} -> {:04x}", watch, old, new)?,
+        Watch::Memory(addr, _) => {
+          writeln!(out, "  {:04x}: {:02x} -> {:02x}", addr + idx, old, new)?
+        }
+        Watch::Attribute(..) => {
+          writeln!(out,
+                   "  {}: {} -> {}",
+                   watch,
+                   if old != 0 { "set" } else { "clear" },
+                   if new != 0 { "set" } else { "clear" })?
+        }
+        Watch::Parent(_) => {
+          writeln!(out,
+                   "  {}: {} -> {}",
+                   watch,
+                   object_text(old, names),
+                   object_text(new, names))?
+        }
+      }
+    }
+    writeln!(out, "  Call stack:")?;
+    write_backtrace(pc, frames, names, out)
+  }
+}
+
+pub struct Watchpoints {
+  next_number: usize,
+  // Each watch with its number and the values it had last time.
+  watches: Vec<(usize, Watch, Vec<u16>)>,
+}
+
+impl Watchpoints {
+  pub fn new() -> Watchpoints {
+    Watchpoints {
+      next_number: 1,
+      watches: Vec::new(),
+    }
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.watches.is_empty()
+  }
+
+  /// Start watching, and return the new watchpoint's number.
+  pub fn add<T>(&mut self, vm: &T, watch: Watch) -> Result<usize>
+    where T: VM {
+    if let Some(object) = watch.object() {
+      let num_objects = vm.object_table()?.num_objects();
+      if object > num_objects {
+        return Err(Error::BadWatchpoint(watch.to_string(),
+                                        format!("there are only {} objects", num_objects)));
+      }
+    }
+    if let Watch::Attribute(_, attr) = watch {
+      let num_attributes = num_attributes(vm.version());
+      if u16::from(attr) >= num_attributes {
+        return Err(Error::BadWatchpoint(watch.to_string(),
+                                        format!("objects only have {} attributes",
+                                                num_attributes)));
+      }
+    }
+    let values = watch.read(vm)?;
+    let number = self.next_number;
+    self.next_number += 1;
+    self.watches.push((number, watch, values));
+    Ok(number)
+  }
+
+  /// Stop watching. Returns false if there was no such watchpoint.
+  pub fn remove(&mut self, number: usize) -> bool {
+    let len = self.watches.len();
+    self.watches.retain(|&(n, _, _)| n != number);
+    self.watches.len() != len
+  }
+
+  pub fn list(&self) -> Vec<(usize, Watch)> {
+    self.watches.iter().map(|&(number, watch, _)| (number, watch)).collect()
+  }
+
+  /// Find the watchpoints whose values have been written or changed since
+  /// last time. `writes` are the game's writes since then, as (address,
+  /// length).
+  pub fn check<T>(&mut self, vm: &T, writes: &[(usize, usize)]) -> Result<Vec<Hit>>
+    where T: VM {
+    let mut hits = Vec::new();
+    for &mut (number, watch, ref mut values) in &mut self.watches {
+      let new_values = watch.read(vm)?;
+      let spans = watch.spans(vm);
+      let written = |idx: usize| match spans.get(idx) {
+        Some(&(start, len)) => {
+          writes.iter().any(|&(addr, count)| addr < start + len && start < addr + count)
+        }
+        None => false,
+      };
+      let changes = values.iter()
+        .zip(new_values.iter())
+        .enumerate()
+        .filter(|&(idx, (old, new))| old != new || written(idx))
+        .map(|(idx, (old, new))| (idx, *old, *new))
+        .collect::<Vec<_>>();
+      if !changes.is_empty() {
+        hits.push(Hit {
+          number,
+          watch,
+          changes,
+        });
+      }
+      *values = new_values;
+    }
+    Ok(hits)
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::{Watch, Watchpoints};
+  use zmachine::debug_info::DebugInfo;
+  use zmachine::ops::testvm::TestVM;
+  use zmachine::vm::{Frame, VM, VariableRef, ZObject, ZObjectTable};
+
+  #[test]
+  fn test_parse() {
+    let names = DebugInfo::new();
+    assert_eq!(Ok(Watch::Global(0x10)), Watch::parse("G10", &names));
+    assert_eq!(Ok(Watch::Memory(0x4f05, 1)), Watch::parse("4f05", &names));
+    assert_eq!(Ok(Watch::Memory(0x4f05, 4)), Watch::parse("0x4f05+4", &names));
+    assert_eq!(Ok(Watch::Attribute(12, 3)), Watch::parse("attr:12:3", &names));
+    assert_eq!(Ok(Watch::Parent(12)), Watch::parse("parent:12", &names));
+
+    assert!(Watch::parse("Gf0", &names).is_err());
+    assert!(Watch::parse("4f05+0", &names).is_err());
+    assert!(Watch::parse("attr:0:3", &names).is_err());
+    assert!(Watch::parse("attr:12:48", &names).is_err());
+    assert!(Watch::parse("child:12", &names).is_err());
+
+    for spec in &["G10", "4f05", "4f05+4", "attr:12:3", "parent:12"] {
+      assert_eq!(*spec, Watch::parse(spec, &names).unwrap().to_string());
+    }
+  }
+
+  #[test]
+  fn test_parse_names() {
+    let xml = "<inform-story-file>\
+               <global-variable><identifier>location</identifier>\
+               <address>32</address></global-variable>\
+               <object><identifier>lamp</identifier><value>12</value></object>\
+               <attribute><identifier>light</identifier><value>3</value></attribute>\
+               </inform-story-file>";
+    let names = DebugInfo::from_bytes(xml.as_bytes(), 0).unwrap();
+    assert_eq!(Ok(Watch::Global(0x10)), Watch::parse("location", &names));
+    assert_eq!(Ok(Watch::Attribute(12, 3)), Watch::parse("attr:lamp:light", &names));
+    assert_eq!(Ok(Watch::Parent(12)), Watch::parse("parent:LAMP", &names));
+    assert!(Watch::parse("parent:torch", &names).is_err());
+
+    for spec in &["location", "attr:lamp:light", "parent:lamp", "4f05"] {
+      assert_eq!(*spec, Watch::parse(spec, &names).unwrap().describe(&names));
+    }
+  }
+
+  #[test]
+  fn test_check() {
+    let mut vm = TestVM::new();
+    for _ in 0..3 {
+      vm.object_storage.borrow_mut().add_mock_object(0, 0, 0, 0);
+    }
+    let mut watches = Watchpoints::new();
+    assert_eq!(1, watches.add(&vm, Watch::Global(3)).unwrap());
+    assert_eq!(2, watches.add(&vm, Watch::Memory(0x200, 4)).unwrap());
+    assert_eq!(3, watches.add(&vm, Watch::Parent(2)).unwrap());
+    assert_eq!(4, watches.add(&vm, Watch::Attribute(3, 1)).unwrap());
+    assert!(watches.add(&vm, Watch::Parent(4)).is_err());
+    // Attributes 32-47 are only in v4+.
+    assert!(watches.add(&vm, Watch::Attribute(3, 32)).is_err());
+    assert!(watches.check(&vm, &[]).unwrap().is_empty());
+
+    vm.globals[3] = 0x0102;
+    vm.heap[0x202] = 0x7f;
+    vm.object_table().unwrap().object_with_number(2).set_parent(1).unwrap();
+    vm.object_table().unwrap().object_with_number(3).set_attributes(0x400000000000).unwrap();
+    let hits = watches.check(&vm, &[]).unwrap();
+    assert_eq!(vec![1, 2, 3, 4], hits.iter().map(|hit| hit.number).collect::<Vec<_>>());
+
+    let mut out = Vec::new();
+    let frames = [Frame {
+                    return_pc: 0,
+                    routine: 0,
+                    locals: vec![],
+                    eval_stack: vec![],
+                    result_location: VariableRef::Stack,
+                  }];
+    hits[1].write(0x4f05, &frames, &DebugInfo::new(), &mut out).unwrap();
+    assert_eq!("Watchpoint 2 (0200+4) changed at 4f05\n  \
+                0202: 00 -> 7f\n  \
+                Call stack:\n  \
+                #0   4f05 in the main routine\n",
+               String::from_utf8(out).unwrap());
+
+    // Only changes count, so nothing has happened since.
+    assert!(watches.check(&vm, &[]).unwrap().is_empty());
+
+    assert!(watches.remove(1));
+    assert!(!watches.remove(1));
+    vm.globals[3] = 0;
+    assert!(watches.check(&vm, &[]).unwrap().is_empty());
+  }
+
+  #[test]
+  fn test_check_writes() {
+    let mut vm = TestVM::new();
+    let mut watches = Watchpoints::new();
+    watches.add(&vm, Watch::Global(3)).unwrap();
+    watches.add(&vm, Watch::Memory(0x200, 4)).unwrap();
+    vm.heap[0x203] = 0x7f;
+
+    // Writing the value that is already there counts, but only where it was
+    // written.
+    let global = vm.global_addr(3).unwrap();
+    let hits = watches.check(&vm, &[(global, 2), (0x1ff, 2), (0x300, 1)]).unwrap();
+    assert_eq!(vec![1, 2], hits.iter().map(|hit| hit.number).collect::<Vec<_>>());
+    let mut out = Vec::new();
+    hits[1].write(0x4f05, &[], &DebugInfo::new(), &mut out).unwrap();
+    assert_eq!("Watchpoint 2 (0200+4) changed at 4f05\n  \
+                0200: 00 -> 00\n  \
+                0203: 00 -> 7f\n  \
+                Call stack:\n",
+               String::from_utf8(out).unwrap());
+    let mut out = Vec::new();
+    hits[0].write(0x4f05, &[], &DebugInfo::new(), &mut out).unwrap();
+    assert_eq!("Watchpoint 1 (G03) written at 4f05\n  \
+                G03: 0000 -> 0000\n  \
+                Call stack:\n",
+               String::from_utf8(out).unwrap());
+
+    assert!(watches.check(&vm, &[(0x204, 4)]).unwrap().is_empty());
+  }
+}
@@ -1,7 +1,86 @@
+use result::Result;
+use std::path::PathBuf;
+use zmachine::trace::TraceFormat;
+use zmachine::vm::ErrorMode;
+
 pub trait ZConfig {
   fn stack_size(&self) -> Option<usize> {
     None
   }
+
+  /// Where to write the transcript (output stream 2).
+  fn transcript_path(&self) -> Option<PathBuf> {
+    None
+  }
+
+  /// Whether to record text styles in the transcript.
+  fn transcript_markup(&self) -> bool {
+    false
+  }
+
+  /// The default foreground colour (a set_colour colour number). None uses
+  /// the terminal's default.
+  fn default_foreground(&self) -> Option<u16> {
+    None
+  }
+
+  /// The default background colour (a set_colour colour number). None uses
+  /// the terminal's default.
+  fn default_background(&self) -> Option<u16> {
+    None
+  }
+
+  /// What to do about errors in the story file that we can recover from.
+  fn error_mode(&self) -> ErrorMode {
+    ErrorMode::WarnOnce
+  }
+
+  /// Whether to run the game under the debugger.
+  fn debug(&self) -> bool {
+    false
+  }
+
+  /// Where to log watchpoint hits when not debugging. None means stderr.
+  fn watch_log_path(&self) -> Option<PathBuf> {
+    None
+  }
+
+  /// An Inform debugging information file with the game's names, for the
+  /// debugger and listings.
+  fn debug_info_path(&self) -> Option<PathBuf> {
+    None
+  }
+
+  /// Where to write a trace of the instructions run, if anywhere.
+  fn trace_path(&self) -> Option<PathBuf> {
+    None
+  }
+
+  fn trace_format(&self) -> TraceFormat {
+    TraceFormat::Text
+  }
+
+  /// Whether to count what the game runs, and report it when it's over.
+  fn profile(&self) -> bool {
+    false
+  }
+
+  /// Where to write the profile's call stacks for a flame graph, if anywhere.
+  /// The game is profiled if this is given, even without profile().
+  fn profile_folded_path(&self) -> Option<PathBuf> {
+    None
+  }
+
+  /// How many instructions the debugger runs between snapshots, for going
+  /// back in time.
+  fn snapshot_interval(&self) -> Result<Option<u64>> {
+    Ok(None)
+  }
+
+  /// A file to record code coverage in, if any. What is in it already is kept.
+  fn coverage_path(&self) -> Option<PathBuf> {
+    None
+  }
 }
 
 // pub struct ZConfigStack<'a> {